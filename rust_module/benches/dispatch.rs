@@ -0,0 +1,35 @@
+// リクエストごとにServiceを取得する際のオーバーヘッドを、
+// リクエストごとにDIコンテナを組み立てていた従来の方式と、共有のServiceContainerを使う現在の方式とで比較する
+//
+// $ cargo bench --bench dispatch --features bench
+use std::time::{Duration, Instant};
+
+use skyway::bench::{build_per_request, lookup_shared, Request};
+
+const ITERATIONS: u32 = 100_000;
+const WARM_UP: u32 = 1_000;
+
+// 種類の異なるServiceに振り分けられるリクエストを順に処理し、1リクエストあたりの時間を返す
+fn measure(requests: &[Request], f: fn(&Request)) -> Duration {
+    for i in 0..WARM_UP {
+        f(&requests[i as usize % requests.len()]);
+    }
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        f(&requests[i as usize % requests.len()]);
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let requests = [
+        Request::parse(r#"{"request_type": "PEER", "command": "LIST"}"#),
+        Request::parse(r#"{"request_type": "EVENT", "command": "SUBSCRIBE"}"#),
+        Request::parse(r#"{"request_type": "SYSTEM", "command": "PING"}"#),
+    ];
+
+    let per_request = measure(&requests, build_per_request);
+    let shared = measure(&requests, lookup_shared);
+    println!("build per request: {:?}/request", per_request);
+    println!("shared container : {:?}/request", shared);
+}
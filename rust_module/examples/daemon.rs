@@ -0,0 +1,36 @@
+// ROSを使わずに、Rust側モジュールを通常のLinuxのデーモンとして動かす例
+// 環境変数SKYWAY_API_KEY, SKYWAY_PEER_IDが与えられた場合はPeerを生成し、
+// 設定ファイルのdata_connection_policy, media_connection_policyに従って着信を処理する
+// SIGINT/SIGTERMを受信すると、全てのリソースを開放して終了する
+//
+// $ SKYWAY_API_KEY=xxx SKYWAY_PEER_ID=my_peer_id cargo run --example daemon --no-default-features
+use skyway::client::{CreatePeerParams, EventFilter, PeerId, SkyWayClient};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let client = SkyWayClient::builder()
+        .shutdown_on_signal(true)
+        .start()
+        .await?;
+
+    if let (Ok(key), Ok(peer_id)) = (
+        std::env::var("SKYWAY_API_KEY"),
+        std::env::var("SKYWAY_PEER_ID"),
+    ) {
+        let params = CreatePeerParams {
+            key,
+            domain: std::env::var("SKYWAY_DOMAIN").unwrap_or_else(|_| "localhost".to_string()),
+            peer_id: PeerId::new(peer_id),
+            turn: false,
+        };
+        let peer_info = client.create_peer(params).await?;
+        eprintln!("peer {} is created", peer_info.peer_id().as_str());
+    }
+
+    // シグナルによる終了処理が完了すると、Noneが返る
+    let mut events = client.events(EventFilter::default());
+    while let Some(event) = events.next().await {
+        eprintln!("{:?}", event);
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+/// リクエストの処理を終えるべき期限
+/// 期限はtask localに保持し、WebRTC Gatewayの応答を待つ際に、残り時間を超えて待たないようにする
+/// 期限はcall_serviceのtimeout_msと、複数の手順からなるUseCaseの全体の期限のうち、早い方が有効になる
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+#[cfg(any(feature = "ffi", test))]
+use serde::Deserialize;
+
+use crate::error;
+use crate::error::ErrorCode;
+
+tokio::task_local! {
+    static DEADLINE: Option<Instant>;
+}
+
+/// リクエストのJSONからtimeout_msを取り出す
+#[cfg(any(feature = "ffi", test))]
+pub(crate) fn from_message(message: &str) -> Option<Duration> {
+    #[derive(Deserialize)]
+    struct TimeoutDto {
+        timeout_ms: Option<u64>,
+    }
+
+    serde_json::from_str::<TimeoutDto>(message)
+        .ok()
+        .and_then(|dto| dto.timeout_ms)
+        .map(Duration::from_millis)
+}
+
+/// 期限を設定した状態でfutureを実行する。Noneの場合は期限を設けない
+pub(crate) async fn scope<F: Future>(deadline: Option<Instant>, future: F) -> F::Output {
+    DEADLINE.scope(deadline, future).await
+}
+
+/// 現在の期限を返す。期限がない場合はNone
+pub(crate) fn current() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok().flatten()
+}
+
+/// 既定の待ち時間を、期限までの残り時間で切り詰める
+pub(crate) fn timeout_for(default: Duration) -> Duration {
+    match current() {
+        Some(deadline) => default.min(deadline.saturating_duration_since(Instant::now())),
+        None => default,
+    }
+}
+
+/// timeout以内に完了しなければTIMEOUTエラーを返す
+/// 外側で既により早い期限が設定されている場合は、そちらを優先する
+/// futureの中で行われるWebRTC Gatewayへのリクエストも、この期限に従う
+pub(crate) async fn within<T>(
+    name: &str,
+    timeout: Duration,
+    future: impl Future<Output = Result<T, error::Error>>,
+) -> Result<T, error::Error> {
+    let deadline = Instant::now() + timeout;
+    let deadline = current().map_or(deadline, |current| current.min(deadline));
+    let timeout_at = tokio::time::Instant::from_std(deadline);
+    match tokio::time::timeout_at(timeout_at, scope(Some(deadline), future)).await {
+        Ok(result) => result,
+        Err(_) => {
+            let message = format!("{} did not complete before the deadline", name);
+            Err(error::Error::create_error(ErrorCode::Timeout, &message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod deadline_test {
+    use super::*;
+
+    #[test]
+    fn from_message() {
+        let message = r#"{"request_type":"SYSTEM","command":"PING","timeout_ms":500}"#;
+        assert_eq!(
+            super::from_message(message),
+            Some(Duration::from_millis(500))
+        );
+
+        let message = r#"{"request_type":"SYSTEM","command":"PING"}"#;
+        assert_eq!(super::from_message(message), None);
+    }
+
+    #[tokio::test]
+    async fn timeout_for() {
+        let default = Duration::from_secs(10);
+        assert_eq!(super::timeout_for(default), default);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        scope(Some(deadline), async {
+            assert!(super::timeout_for(default) <= Duration::from_secs(1));
+            // 期限を外した内側では既定の待ち時間に戻る
+            scope(None, async {
+                assert_eq!(super::timeout_for(default), default);
+            })
+            .await;
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn within() {
+        let result = super::within("DATA CONNECT", Duration::from_secs(1), async {
+            Ok::<_, error::Error>(current().is_some())
+        })
+        .await;
+        assert!(result.unwrap());
+
+        let result = super::within("DATA CONNECT", Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            Ok(())
+        })
+        .await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Timeout);
+        assert_eq!(
+            error.message(),
+            "DATA CONNECT did not complete before the deadline"
+        );
+
+        // 外側の期限の方が早い場合は、そちらで打ち切られる
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let result = scope(Some(deadline), async {
+            super::within("MEDIA CALL", Duration::from_secs(3600), async {
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+                Ok(())
+            })
+            .await
+        })
+        .await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::Timeout);
+    }
+}
@@ -1,11 +1,13 @@
 pub(crate) mod request;
 pub(crate) mod response;
 
-use crate::application::dto::request::{DataRequestDto, MediaRequestDto, RequestDto};
+use crate::application::dto::request::{
+    DataRequestDto, MediaRequestDto, PeerRequestDto, RequestDto,
+};
 use crate::application::dto::response::{
     DataResponseDto, MediaResponseDto, PeerResponseDto, ResponseDto, ResponseDtoResult,
 };
-use crate::domain::entity::request::{DataRequest, MediaRequest, Request};
+use crate::domain::entity::request::{DataRequest, MediaRequest, PeerRequest, Request};
 use crate::domain::entity::response::{
     DataResponse, MediaResponse, PeerResponse, Response, ResponseResult,
 };
@@ -19,7 +21,15 @@ pub(crate) trait Command {
 /// Dto objectからDomain objectへの変換
 pub(crate) fn dto_to_request(dto: RequestDto) -> Result<Request, error::Error> {
     match dto {
-        RequestDto::Peer(parameter) => Ok(Request::Peer(parameter)),
+        RequestDto::Peer(PeerRequestDto::Create { params, .. }) => {
+            Ok(Request::Peer(PeerRequest::Create { params }))
+        }
+        RequestDto::Peer(PeerRequestDto::Status { params }) => {
+            Ok(Request::Peer(PeerRequest::Status { params }))
+        }
+        RequestDto::Peer(PeerRequestDto::Delete { params }) => {
+            Ok(Request::Peer(PeerRequest::Delete { params }))
+        }
         RequestDto::Data(DataRequestDto::Create) => {
             Ok(Request::Data(DataRequest::Create { params: true }))
         }
@@ -86,9 +96,11 @@ pub(crate) fn result_to_dto(response: ResponseResult) -> Result<ResponseDtoResul
         ResponseResult::Success(Response::Data(DataResponse::Delete(params))) => Ok(
             ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Delete(params))),
         ),
-        ResponseResult::Success(Response::Data(DataResponse::Disconnect(params))) => Ok(
-            ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Disconnect(params))),
-        ),
+        ResponseResult::Success(Response::Data(DataResponse::Disconnect(params))) => {
+            Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                DataResponseDto::Disconnect(params.into()),
+            )))
+        }
         ResponseResult::Success(Response::Data(DataResponse::Status(params))) => Ok(
             ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Status(params))),
         ),
@@ -110,6 +122,7 @@ pub(crate) fn result_to_dto(response: ResponseResult) -> Result<ResponseDtoResul
         ResponseResult::Success(Response::Media(MediaResponse::Disconnect(params))) => Ok(
             ResponseDtoResult::Success(ResponseDto::Media(MediaResponseDto::Disconnect(params))),
         ),
+        ResponseResult::Error(message) => Err(error::Error::create_gateway_error(&message)),
         param => {
             let message = format!("invalid response for GeneralService {:?}", param);
             Err(error::Error::create_local_error(&message))
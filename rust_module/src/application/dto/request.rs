@@ -1,31 +1,101 @@
-pub(crate) use crate::domain::entity::request::PeerRequest as PeerRequestDto;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::application::dto::Command;
+use crate::application::usecase::event::hub::EventFilter;
 use crate::domain::entity::request::IsVideo;
 use crate::domain::entity::{
-    ConnectQueryOption, DataConnectionId, DataConnectionIdWrapper, DataIdWrapper,
-    MediaConnectionId, MediaConnectionIdWrapper, MediaIdWrapper, PeerId, PhantomId,
+    ConnectQueryOption, CreatePeerParams, DataConnectionId, DataConnectionIdWrapper, DataIdWrapper,
+    MediaConnectionId, MediaConnectionIdWrapper, MediaIdWrapper, PeerId, PeerInfo, PhantomId,
     RedirectParameters, RtcpIdWrapper, SocketInfo, Token,
 };
 use crate::error;
 
 //========== System ==========
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub(crate) struct SystemRequestDto {
-    pub(crate) command: String,
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command")]
+pub(crate) enum SystemRequestDto {
+    #[serde(rename = "PING")]
+    Ping,
+    #[serde(rename = "VERSION")]
+    Version,
+    #[serde(rename = "STATE")]
+    State,
+    #[serde(rename = "SHUTDOWN")]
+    Shutdown,
+}
+
+impl Command for SystemRequestDto {
+    fn command(&self) -> String {
+        match self {
+            SystemRequestDto::Ping => "PING".to_string(),
+            SystemRequestDto::Version => "VERSION".to_string(),
+            SystemRequestDto::State => "STATE".to_string(),
+            SystemRequestDto::Shutdown => "SHUTDOWN".to_string(),
+        }
+    }
 }
 
 //========== Peer ==========
+/// PEER CREATEで生成したPeer ObjectがCLOSE, ERRORとなった場合に、同じpeer_idで再生成するための設定
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PeerRecoveryPolicyDto {
+    /// 再生成を試みる最大回数
+    pub max_retries: u32,
+    /// 1回目の再生成までの待機時間。以降は試行のたびに2倍になる
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// 待機時間の上限
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30000
+}
+
+impl PeerRecoveryPolicyDto {
+    /// attempt回目(1始まり)の再生成の前に待機する時間
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self.initial_backoff_ms.saturating_mul(factor);
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command")]
+pub(crate) enum PeerRequestDto {
+    #[serde(rename = "CREATE")]
+    Create {
+        params: CreatePeerParams,
+        /// 省略した場合は再生成を行わない
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        recovery: Option<PeerRecoveryPolicyDto>,
+    },
+    #[serde(rename = "STATUS")]
+    Status { params: PeerInfo },
+    #[serde(rename = "DELETE")]
+    Delete { params: PeerInfo },
+    /// PEER CREATEで生成した全てのPeer Objectを返す
+    #[serde(rename = "LIST")]
+    List,
+}
+
 impl Command for PeerRequestDto {
     fn command(&self) -> String {
         match self {
-            PeerRequestDto::Create { params: ref _p } => "CREATE".to_string(),
+            PeerRequestDto::Create { .. } => "CREATE".to_string(),
             PeerRequestDto::Delete { params: ref _p } => "DELETE".to_string(),
             PeerRequestDto::Status { params: ref _p } => "STATUS".to_string(),
+            PeerRequestDto::List => "LIST".to_string(),
         }
     }
 }
@@ -95,6 +165,47 @@ pub(crate) struct AnswerParametersDto {
     pub answer_query: AnswerQueryDto,
 }
 
+/// 着信したMediaConnectionに自動的にANSWERするための規則
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct MediaPolicyRuleDto {
+    /// 発信側のpeer_idに対する条件。省略した場合は全てのPeerに一致する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<StringMatcherDto>,
+    /// 発信側がCALL時に指定したmetadataに対する条件。省略した場合は全てのmetadataに一致する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<StringMatcherDto>,
+    /// 一致した場合にANSWERで利用するパラメータ
+    /// redirect_paramsで、受信したメディアの転送先(GStreamerのパイプラインなど)を指定する
+    pub answer_query: AnswerQueryDto,
+}
+
+impl MediaPolicyRuleDto {
+    pub fn is_match(&self, remote_id: &str, metadata: &str) -> bool {
+        is_match_connection(&self.peer_id, &self.metadata, remote_id, metadata)
+    }
+}
+
+/// 着信したMediaConnectionの扱いを決めるpolicy
+/// 先頭から順に評価し、最初に一致した規則でANSWERする。どの規則にも一致しない場合は切断する
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct MediaPolicyDto {
+    pub rules: Vec<MediaPolicyRuleDto>,
+}
+
+impl MediaPolicyDto {
+    /// 一致した規則と、その位置を返す
+    pub fn find_rule(
+        &self,
+        remote_id: &str,
+        metadata: &str,
+    ) -> Option<(usize, &MediaPolicyRuleDto)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.is_match(remote_id, metadata))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "command")]
 pub(crate) enum MediaRequestDto {
@@ -104,7 +215,7 @@ pub(crate) enum MediaRequestDto {
     ContentDelete { params: MediaIdWrapper },
     #[serde(rename = "RTCP_CREATE")]
     RtcpCreate { params: Option<()> },
-    #[serde(rename = "RTCP_CREATE")]
+    #[serde(rename = "RTCP_DELETE")]
     RtcpDelete { params: RtcpIdWrapper },
     #[serde(rename = "CALL")]
     Call { params: CallQueryDto },
@@ -114,6 +225,12 @@ pub(crate) enum MediaRequestDto {
     Answer { params: AnswerParametersDto },
     #[serde(rename = "DISCONNECT")]
     Disconnect { params: MediaConnectionIdWrapper },
+    /// paramsを省略した場合はpolicyを解除し、着信したMediaConnectionを自動的に処理しない
+    #[serde(rename = "POLICY")]
+    Policy {
+        #[serde(default)]
+        params: Option<MediaPolicyDto>,
+    },
 }
 
 impl Command for MediaRequestDto {
@@ -127,6 +244,7 @@ impl Command for MediaRequestDto {
             MediaRequestDto::Status { .. } => "STATUS".to_string(),
             MediaRequestDto::Answer { .. } => "ANSWER".to_string(),
             MediaRequestDto::Disconnect { .. } => "DISCONNECT".to_string(),
+            MediaRequestDto::Policy { .. } => "POLICY".to_string(),
         }
     }
 }
@@ -147,7 +265,7 @@ pub(crate) struct ConnectDtoParams {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub(crate) struct PluginInfo {
+pub struct PluginInfo {
     pub r#type: String,
     pub plugins: Vec<Value>,
 }
@@ -158,6 +276,107 @@ pub(crate) struct RedirectDtoParams {
     pub plugin_info: PluginInfo,
 }
 
+/// 相手側のpeer_idやmetadataなど、文字列に対する条件
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StringMatcherDto {
+    Exact(String),
+    Prefix(String),
+    /// `*`は任意の文字列、`?`は任意の1文字に一致する
+    Glob(String),
+}
+
+impl StringMatcherDto {
+    pub fn is_match(&self, value: &str) -> bool {
+        match self {
+            StringMatcherDto::Exact(pattern) => pattern == value,
+            StringMatcherDto::Prefix(prefix) => value.starts_with(prefix.as_str()),
+            StringMatcherDto::Glob(pattern) => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                let value: Vec<char> = value.chars().collect();
+                glob_match(&pattern, &value)
+            }
+        }
+    }
+}
+
+// 最後に現れた`*`の位置まで戻りながら照合する
+fn glob_match(pattern: &[char], value: &[char]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = star {
+            p = star_p + 1;
+            v = star_v + 1;
+            star = Some((star_p, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// 着信したDataConnectionを自動的にREDIRECTするための規則
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct DataPolicyRuleDto {
+    /// 相手側のpeer_idに対する条件。省略した場合は全てのPeerに一致する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<StringMatcherDto>,
+    /// 相手側がCONNECT時に指定したmetadataに対する条件。省略した場合は全てのmetadataに一致する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<StringMatcherDto>,
+    /// 一致した場合にREDIRECTで利用するPlugin
+    pub plugin_info: PluginInfo,
+}
+
+impl DataPolicyRuleDto {
+    pub fn is_match(&self, remote_id: &str, metadata: &str) -> bool {
+        is_match_connection(&self.peer_id, &self.metadata, remote_id, metadata)
+    }
+}
+
+// 省略された条件は全ての値に一致する
+fn is_match_connection(
+    peer_id: &Option<StringMatcherDto>,
+    metadata: &Option<StringMatcherDto>,
+    remote_id: &str,
+    remote_metadata: &str,
+) -> bool {
+    peer_id
+        .as_ref()
+        .is_none_or(|matcher| matcher.is_match(remote_id))
+        && metadata
+            .as_ref()
+            .is_none_or(|matcher| matcher.is_match(remote_metadata))
+}
+
+/// 着信したDataConnectionの扱いを決めるpolicy
+/// 先頭から順に評価し、最初に一致した規則でREDIRECTする。どの規則にも一致しない場合は切断する
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct DataPolicyDto {
+    pub rules: Vec<DataPolicyRuleDto>,
+}
+
+impl DataPolicyDto {
+    /// 一致した規則と、その位置を返す
+    pub fn find_rule(
+        &self,
+        remote_id: &str,
+        metadata: &str,
+    ) -> Option<(usize, &DataPolicyRuleDto)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.is_match(remote_id, metadata))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "command")]
 pub(crate) enum DataRequestDto {
@@ -173,6 +392,12 @@ pub(crate) enum DataRequestDto {
     Disconnect { params: DataConnectionIdWrapper },
     #[serde(rename = "STATUS")]
     Status { params: DataConnectionIdWrapper },
+    /// paramsを省略した場合はpolicyを解除し、着信したDataConnectionを自動的に処理しない
+    #[serde(rename = "POLICY")]
+    Policy {
+        #[serde(default)]
+        params: Option<DataPolicyDto>,
+    },
 }
 
 impl Command for DataRequestDto {
@@ -184,6 +409,58 @@ impl Command for DataRequestDto {
             DataRequestDto::Redirect { .. } => "REDIRECT".to_string(),
             DataRequestDto::Disconnect { .. } => "DISCONNECT".to_string(),
             DataRequestDto::Status { .. } => "STATUS".to_string(),
+            DataRequestDto::Policy { .. } => "POLICY".to_string(),
+        }
+    }
+}
+
+//========== Event ==========
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct SubscribeParamsDto {
+    /// 受け取るイベントの条件。省略した場合は全てのイベントを受け取る
+    #[serde(default)]
+    pub filter: EventFilter,
+    /// 購読者ごとのキューの容量。溢れた場合は古いイベントから破棄される
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PollParamsDto {
+    pub subscriber_id: u64,
+    /// 一度に取り出すイベントの最大数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_events: Option<usize>,
+    /// キューが空の場合にイベントを待つ時間。省略した場合は待たずに返す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct SubscriberIdDto {
+    pub subscriber_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command")]
+pub(crate) enum EventRequestDto {
+    #[serde(rename = "SUBSCRIBE")]
+    Subscribe {
+        #[serde(default)]
+        params: SubscribeParamsDto,
+    },
+    #[serde(rename = "POLL")]
+    Poll { params: PollParamsDto },
+    #[serde(rename = "UNSUBSCRIBE")]
+    Unsubscribe { params: SubscriberIdDto },
+}
+
+impl Command for EventRequestDto {
+    fn command(&self) -> String {
+        match self {
+            EventRequestDto::Subscribe { .. } => "SUBSCRIBE".to_string(),
+            EventRequestDto::Poll { .. } => "POLL".to_string(),
+            EventRequestDto::Unsubscribe { .. } => "UNSUBSCRIBE".to_string(),
         }
     }
 }
@@ -201,11 +478,14 @@ pub(crate) enum RequestDto {
     Media(MediaRequestDto),
     #[serde(rename = "SYSTEM")]
     System(SystemRequestDto),
+    #[serde(rename = "EVENT")]
+    Event(EventRequestDto),
     #[cfg(test)]
     Test,
 }
 
 impl RequestDto {
+    #[cfg(any(feature = "ffi", test))]
     pub fn from_str(json: &str) -> Result<Self, error::Error> {
         serde_json::from_str::<RequestDto>(json).map_err(|e| error::Error::SerdeError { error: e })
     }
@@ -216,6 +496,7 @@ impl RequestDto {
             RequestDto::Data(ref _d) => "DATA".to_string(),
             RequestDto::Media(ref _m) => "MEDIA".to_string(),
             RequestDto::System(ref _m) => "SYSTEM".to_string(),
+            RequestDto::Event(ref _e) => "EVENT".to_string(),
             #[cfg(test)]
             _ => "TEST".to_string(),
         }
@@ -233,7 +514,8 @@ impl Command for RequestDto {
             RequestDto::Peer(ref peer) => peer.command(),
             RequestDto::Data(ref data) => data.command(),
             RequestDto::Media(ref media) => media.command(),
-            RequestDto::System(_) => "SYSTEM".to_string(),
+            RequestDto::System(ref system) => system.command(),
+            RequestDto::Event(ref event) => event.command(),
             #[cfg(test)]
             RequestDto::Test => {
                 unreachable!()
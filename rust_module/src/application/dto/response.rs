@@ -1,19 +1,71 @@
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
 
+use crate::application::dto::request::{
+    DataPolicyDto, MediaPolicyDto, PeerRecoveryPolicyDto, SubscriberIdDto,
+};
 use crate::domain::entity::response::{DataResponse, MediaResponse, PeerResponse};
 use crate::domain::entity::{
-    AnswerResult, DataConnectionId, DataConnectionIdWrapper, DataConnectionStatus, DataId,
-    DataIdWrapper, MediaConnectionId, MediaConnectionIdWrapper, MediaConnectionStatus, MediaId,
-    MediaIdWrapper, PeerCallEvent, PeerCloseEvent, PeerErrorEvent, PeerInfo, PeerOpenEvent,
-    PeerStatusMessage, RedirectParameters, RtcpId, RtcpIdWrapper, SerializableId, SocketInfo,
+    AnswerResult, CreatePeerParams, DataConnectionId, DataConnectionIdWrapper,
+    DataConnectionStatus, DataId, DataIdWrapper, MediaConnectionId, MediaConnectionIdWrapper,
+    MediaConnectionStatus, MediaId, MediaIdWrapper, PeerCallEvent, PeerCloseEvent, PeerErrorEvent,
+    PeerId, PeerInfo, PeerOpenEvent, PeerStatusMessage, RedirectParameters, RtcpId, RtcpIdWrapper,
+    SerializableId, SocketInfo, Token,
 };
 use crate::error;
+use crate::ffi::rust_to_c_bridge::c_functions_wrapper::DataPipeInfo;
 
 //========== System ==========
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PingResponseDto {
+    pub gateway_url: String,
+    /// WebRTC Gatewayとの往復にかかった時間
+    pub round_trip_ms: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct VersionResponseDto {
+    /// rust_moduleのバージョン
+    pub version: String,
+    pub gateway_url: String,
+    /// WebRTC Gatewayのバージョン。取得できなかった場合は含まれない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway_version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct StateResponseDto {
+    /// CONNECT, REDIRECT時に保存したDataConnectionの情報
+    pub data_connections: Vec<DataPipeInfo>,
+    /// CALL, ANSWER時に保存したMediaConnectionの情報
+    pub media_connections: Vec<CallResponseDto>,
+}
+
+/// 終了処理の結果
+/// 各手順は"DATA DISCONNECT dc-xxx"のように、リクエストの種類と対象のIDで表す
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ShutdownSummaryDto {
+    /// 完了した手順
+    pub completed: Vec<String>,
+    /// 失敗した手順とその理由
+    pub failed: Vec<String>,
+    /// 期限内にWebRTC Gatewayから応答がなかった手順
+    pub timed_out: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub struct SystemResponseDto {
-    pub(crate) is_success: bool,
+#[serde(tag = "command")]
+pub(crate) enum SystemResponseDto {
+    #[serde(rename = "PING")]
+    Ping(PingResponseDto),
+    #[serde(rename = "VERSION")]
+    Version(VersionResponseDto),
+    #[serde(rename = "STATE")]
+    State(StateResponseDto),
+    #[serde(rename = "SHUTDOWN")]
+    Shutdown,
 }
 
 //========== Peer ==========
@@ -38,8 +90,77 @@ pub struct PeerConnectionEventDto {
     pub status: DataConnectionStatus,
 }
 
+/// DATA POLICY, MEDIA POLICYに従って着信したConnectionを処理した結果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action")]
+pub enum PolicyResultDto {
+    /// rule番目の規則に一致し、REDIRECTもしくはANSWERした
+    #[serde(rename = "ACCEPTED")]
+    Accepted { rule: usize },
+    /// どの規則にも一致しなかったため切断した
+    #[serde(rename = "REJECTED")]
+    Rejected,
+    /// rule番目の規則に一致したが、REDIRECTもしくはANSWERに失敗したため切断した
+    #[serde(rename = "FAILED")]
+    Failed { rule: usize, error: ErrorDto },
+}
+
+impl std::fmt::Display for PolicyResultDto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyResultDto::Accepted { rule } => write!(f, "accepted by rule {}", rule),
+            PolicyResultDto::Rejected => write!(f, "no rule matched"),
+            PolicyResultDto::Failed { rule, error } => write!(
+                f,
+                "rule {} matched but failed: {} ({})",
+                rule, error.message, error.code
+            ),
+        }
+    }
+}
+
+/// MEDIA POLICYの結果として、設定後のpolicyを返す
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct MediaPolicyResponseDto {
+    pub policy: Option<MediaPolicyDto>,
+}
+
+/// DATA POLICYの結果として、設定後のpolicyを返す
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct DataPolicyResponseDto {
+    pub policy: Option<DataPolicyDto>,
+}
+
+/// 再生成を試みる前に通知する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerRecoveringEventDto {
+    /// CLOSE, ERRORとなったPeer Object
+    pub params: PeerInfo,
+    /// 何回目の試行か(1始まり)
+    pub attempt: u32,
+    pub max_retries: u32,
+    /// 試行までの待機時間
+    pub backoff_ms: u64,
+}
+
+/// 再生成に成功したことを通知する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerRecoveredEventDto {
+    /// 再生成したPeer Object。tokenは新しいものに変わる
+    pub params: PeerInfo,
+    pub attempt: u32,
+}
+
+/// 全ての試行が失敗し、再生成を諦めたことを通知する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerRecoveryFailedEventDto {
+    pub params: PeerInfo,
+    pub attempts: u32,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "event")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum PeerEventEnumDto {
     OPEN(PeerOpenEvent),
     CLOSE(PeerCloseEvent),
@@ -47,6 +168,63 @@ pub enum PeerEventEnumDto {
     CALL(PeerCallEventDto),
     ERROR(PeerErrorEvent),
     TIMEOUT,
+    RECOVERING(PeerRecoveringEventDto),
+    RECOVERED(PeerRecoveredEventDto),
+    #[allow(non_camel_case_types)]
+    RECOVERY_FAILED(PeerRecoveryFailedEventDto),
+}
+
+/// Rust側で把握しているPeer Objectの状態
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PeerRecordStatusDto {
+    /// OPENイベントを受信し、利用可能な状態
+    #[serde(rename = "OPEN")]
+    Open,
+    /// CLOSEイベントを受信し、WebRTC Gateway上では既に削除されている状態
+    #[serde(rename = "CLOSED")]
+    Closed,
+    /// CLOSE, ERRORイベントを受信し、再生成を試みている状態
+    #[serde(rename = "RECOVERING")]
+    Recovering,
+}
+
+/// PEER CREATEで生成したPeer Objectと、そのPeerが確立したConnectionの一覧
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PeerRecordDto {
+    pub peer_id: PeerId,
+    pub token: Token,
+    pub status: PeerRecordStatusDto,
+    pub data_connections: Vec<DataConnectionId>,
+    pub media_connections: Vec<MediaConnectionId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<PeerRecoveryPolicyDto>,
+    /// 再生成時に利用するPEER CREATEのパラメータ
+    /// API Keyを含むため、PEER LISTでは返さない
+    #[serde(skip)]
+    pub create_params: Option<CreatePeerParams>,
+}
+
+impl PeerRecordDto {
+    pub(crate) fn new(peer_info: &PeerInfo) -> Self {
+        PeerRecordDto {
+            peer_id: peer_info.peer_id(),
+            token: peer_info.token(),
+            status: PeerRecordStatusDto::Open,
+            data_connections: vec![],
+            media_connections: vec![],
+            recovery: None,
+            create_params: None,
+        }
+    }
+
+    pub(crate) fn peer_info(&self) -> PeerInfo {
+        PeerInfo::new(self.peer_id.clone(), self.token.clone())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PeerListResponseDto {
+    pub peers: Vec<PeerRecordDto>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -58,6 +236,8 @@ pub(crate) enum PeerResponseDto {
     Status(PeerStatusMessage),
     #[serde(rename = "DELETE")]
     Delete(PeerInfo),
+    #[serde(rename = "LIST")]
+    List(PeerListResponseDto),
     #[serde(rename = "EVENT")]
     Event(PeerEventEnumDto),
 }
@@ -76,9 +256,35 @@ impl PeerResponseDto {
 
 //========== Media ==========
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MediaConnectionErrorEventDto {
+    pub media_connection_id: MediaConnectionId,
+    pub error_message: String,
+}
+
+/// MediaConnectionの終了後に、保持していた情報とポートを開放したことを通知する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MediaConnectionReleasedEventDto {
+    pub media_connection_id: MediaConnectionId,
+    /// 削除したMedia, RTCPポートのID
+    pub released: Vec<String>,
+    /// 削除に失敗したポートのIDとエラー内容
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// MEDIA POLICYに従って、着信したMediaConnectionを処理した結果を通知する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MediaConnectionPolicyEventDto {
+    pub media_connection_id: MediaConnectionId,
+    /// 接続してきた相手側のpeer_id
+    pub remote_id: PeerId,
+    pub policy: PolicyResultDto,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "event")]
-pub(crate) enum MediaConnectionEventEnumDto {
+pub enum MediaConnectionEventEnumDto {
     #[serde(rename = "READY")]
     Ready(CallResponseDto),
     #[serde(rename = "STREAM")]
@@ -86,11 +292,17 @@ pub(crate) enum MediaConnectionEventEnumDto {
     #[serde(rename = "CLOSE")]
     Close(MediaConnectionIdWrapper),
     #[serde(rename = "ERROR")]
-    Error((MediaConnectionId, String)),
+    Error(MediaConnectionErrorEventDto),
+    #[serde(rename = "TIMEOUT")]
+    Timeout,
+    #[serde(rename = "RELEASED")]
+    Released(MediaConnectionReleasedEventDto),
+    #[serde(rename = "POLICY")]
+    Policy(Box<MediaConnectionPolicyEventDto>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub(crate) struct CallResponseDto {
+pub struct CallResponseDto {
     pub send_params: SendParams,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub redirect_params: Option<RedirectParameters>,
@@ -98,15 +310,19 @@ pub(crate) struct CallResponseDto {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub(crate) struct MediaPair<M: SerializableId, R: SerializableId> {
+pub struct MediaPair<M: SerializableId, R: SerializableId> {
     pub media: SocketInfo<M>,
     pub rtcp: SocketInfo<R>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub(crate) struct SendParams {
-    pub video: MediaPair<MediaId, RtcpId>,
-    pub audio: MediaPair<MediaId, RtcpId>,
+pub struct SendParams {
+    /// Videoを送信しない場合はNone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<MediaPair<MediaId, RtcpId>>,
+    /// Audioを送信しない場合はNone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<MediaPair<MediaId, RtcpId>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -130,6 +346,8 @@ pub(crate) enum MediaResponseDto {
     Disconnect(Option<()>),
     #[serde(rename = "STATUS")]
     Status(MediaConnectionStatus),
+    #[serde(rename = "POLICY")]
+    Policy(MediaPolicyResponseDto),
 }
 
 impl MediaResponseDto {
@@ -151,12 +369,55 @@ impl MediaResponseDto {
 
 //========== Data ==========
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DataConnectionErrorEventDto {
+    pub data_connection_id: DataConnectionId,
+    pub error_message: String,
+}
+
+/// DATA POLICYに従って、着信したDataConnectionを処理した結果を通知する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DataConnectionPolicyEventDto {
+    pub data_connection_id: DataConnectionId,
+    /// 接続してきた相手側のpeer_id
+    pub remote_id: String,
+    pub policy: PolicyResultDto,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "event")]
-pub(crate) enum DataConnectionEventDto {
+pub enum DataConnectionEventDto {
     OPEN(DataConnectionIdWrapper),
     CLOSE(DataConnectionIdWrapper),
-    ERROR((DataConnectionId, String)),
+    ERROR(DataConnectionErrorEventDto),
+    TIMEOUT,
+    POLICY(Box<DataConnectionPolicyEventDto>),
+}
+
+/// DATA DISCONNECTで切断したDataConnectionと、開放したリソースの情報
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DataDisconnectDto {
+    pub data_connection_id: DataConnectionId,
+    /// 削除したDataポートのID。DataConnectionの情報を保持していなかった場合は含まれない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_id: Option<DataId>,
+    /// アンロードしたPluginのポート番号。DataConnectionの情報を保持していなかった場合は含まれない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plugin_port: Option<u16>,
+    /// リソースの開放に失敗した場合のエラー内容
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl From<DataConnectionIdWrapper> for DataDisconnectDto {
+    fn from(item: DataConnectionIdWrapper) -> Self {
+        DataDisconnectDto {
+            data_connection_id: item.data_connection_id,
+            data_id: None,
+            plugin_port: None,
+            errors: vec![],
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -169,13 +430,15 @@ pub(crate) enum DataResponseDto {
     #[serde(rename = "DELETE")]
     Delete(DataIdWrapper),
     #[serde(rename = "DISCONNECT")]
-    Disconnect(DataConnectionIdWrapper),
+    Disconnect(DataDisconnectDto),
     #[serde(rename = "REDIRECT")]
     Redirect(DataConnectionIdWrapper),
     #[serde(rename = "EVENT")]
     Event(DataConnectionEventDto),
     #[serde(rename = "STATUS")]
     Status(DataConnectionStatus),
+    #[serde(rename = "POLICY")]
+    Policy(DataPolicyResponseDto),
 }
 
 impl DataResponseDto {
@@ -185,7 +448,7 @@ impl DataResponseDto {
             DataResponse::Create(item) => DataResponseDto::Create(item),
             DataResponse::Connect(item) => DataResponseDto::Connect(item),
             DataResponse::Delete(item) => DataResponseDto::Delete(item),
-            DataResponse::Disconnect(item) => DataResponseDto::Disconnect(item),
+            DataResponse::Disconnect(item) => DataResponseDto::Disconnect(item.into()),
             DataResponse::Redirect(item) => DataResponseDto::Redirect(item),
             DataResponse::Event(_) => unreachable!(),
             DataResponse::Status(item) => DataResponseDto::Status(item),
@@ -193,6 +456,34 @@ impl DataResponseDto {
     }
 }
 
+//========== Event ==========
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PolledEventsDto {
+    pub subscriber_id: u64,
+    /// receive_eventsが返すものと同じ形式のイベント
+    pub events: Vec<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command")]
+pub(crate) enum EventResponseDto {
+    #[serde(rename = "SUBSCRIBE")]
+    Subscribe(SubscriberIdDto),
+    #[serde(rename = "POLL")]
+    Poll(PolledEventsDto),
+    #[serde(rename = "UNSUBSCRIBE")]
+    Unsubscribe(SubscriberIdDto),
+}
+
+//========== Unknown ==========
+
+/// 想定していないイベントを受け取った場合に、内容をそのままユーザに通知するためのDTO
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct UnknownEventDto {
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "request_type")]
 pub(crate) enum ResponseDto {
@@ -204,12 +495,51 @@ pub(crate) enum ResponseDto {
     Data(DataResponseDto),
     #[serde(rename = "SYSTEM")]
     System(SystemResponseDto),
+    #[serde(rename = "EVENT")]
+    Event(EventResponseDto),
+    #[serde(rename = "UNKNOWN")]
+    Unknown(UnknownEventDto),
+}
+
+/// ユーザに返すエラーの内容
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorDto {
+    pub code: error::ErrorCode,
+    pub message: String,
+    /// 複数の手順からなるUseCaseで、失敗した手順
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    /// WebRTC Gatewayが返したエラー
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway_error: Option<Value>,
+}
+
+impl From<&error::Error> for ErrorDto {
+    fn from(error: &error::Error) -> Self {
+        ErrorDto {
+            code: error.code(),
+            message: error.message(),
+            step: error.step().map(|step| step.to_string()),
+            gateway_error: error.gateway_error().cloned(),
+        }
+    }
+}
+
+impl From<ErrorDto> for error::Error {
+    fn from(error: ErrorDto) -> Self {
+        error::Error::Failure {
+            code: error.code,
+            message: error.message,
+            step: error.step,
+            gateway_error: error.gateway_error,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub(crate) enum ResponseDtoResult {
     Success(ResponseDto),
-    Error(String),
+    Error(ErrorDto),
 }
 
 #[allow(dead_code)]
@@ -230,7 +560,7 @@ impl ResponseDtoResult {
                 Ok(ResponseDtoResult::Success(content))
             }
             _ => {
-                let content: String = serde_json::from_value(value.result)
+                let content: ErrorDto = serde_json::from_value(value.result)
                     .map_err(|e| error::Error::SerdeError { error: e })?;
                 Ok(ResponseDtoResult::Error(content))
             }
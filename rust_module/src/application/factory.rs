@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use once_cell::sync::OnceCell;
 use shaku::{Component, HasComponent, Interface};
 
 use crate::application::dto::request::{
@@ -12,6 +14,10 @@ use crate::di::*;
 #[cfg(test)]
 use mockall::automock;
 
+// 全てのリクエストで共有するServiceの一覧
+// 初回の参照時、もしくはrun()の起動時に一度だけ生成する
+static SERVICE_CONTAINER: OnceCell<ServiceContainer> = OnceCell::new();
+
 #[async_trait]
 #[cfg_attr(test, automock)]
 pub(crate) trait Factory: Interface {
@@ -22,46 +28,158 @@ pub(crate) trait Factory: Interface {
 #[shaku(interface = Factory)]
 pub(crate) struct FactoryImpl {}
 
-/// 各UseCaseのインスタンスを生成し、サービスとして返す
+/// 各UseCaseのインスタンスを、ServiceContainerから取得して返す
 impl Factory for FactoryImpl {
     fn create_service(&self, request: &RequestDto) -> Arc<dyn Service> {
+        ServiceContainer::global().service(request)
+    }
+}
+
+/// リクエストを処理するServiceの種類
+/// 同じ種類のリクエストは、同じServiceのインスタンスで処理される
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ServiceKind {
+    PeerCreate,
+    PeerRegistry,
+    DataConnect,
+    DataRedirect,
+    DataDisconnect,
+    DataPolicy,
+    MediaCall,
+    MediaAnswer,
+    MediaDisconnect,
+    MediaPolicy,
+    System,
+    EventSubscription,
+    General,
+}
+
+impl ServiceKind {
+    const ALL: [ServiceKind; 13] = [
+        ServiceKind::PeerCreate,
+        ServiceKind::PeerRegistry,
+        ServiceKind::DataConnect,
+        ServiceKind::DataRedirect,
+        ServiceKind::DataDisconnect,
+        ServiceKind::DataPolicy,
+        ServiceKind::MediaCall,
+        ServiceKind::MediaAnswer,
+        ServiceKind::MediaDisconnect,
+        ServiceKind::MediaPolicy,
+        ServiceKind::System,
+        ServiceKind::EventSubscription,
+        ServiceKind::General,
+    ];
+
+    /// リクエストを処理するServiceの種類を返す
+    pub(crate) fn from_request(request: &RequestDto) -> Self {
         match request {
-            RequestDto::Peer(PeerRequestDto::Create { params: _ }) => {
-                let module = PeerCreateService::builder().build();
-                module.resolve()
-            }
-            RequestDto::Data(DataRequestDto::Connect { params: _ }) => {
-                let module = DataConnectService::builder().build();
-                module.resolve()
-            }
-            RequestDto::Data(DataRequestDto::Redirect { params: _ }) => {
-                let module = DataRedirectService::builder().build();
-                module.resolve()
+            RequestDto::Peer(PeerRequestDto::Create { .. }) => ServiceKind::PeerCreate,
+            RequestDto::Peer(PeerRequestDto::Delete { params: _ }) => ServiceKind::PeerRegistry,
+            RequestDto::Peer(PeerRequestDto::List) => ServiceKind::PeerRegistry,
+            RequestDto::Data(DataRequestDto::Connect { params: _ }) => ServiceKind::DataConnect,
+            RequestDto::Data(DataRequestDto::Redirect { params: _ }) => ServiceKind::DataRedirect,
+            RequestDto::Data(DataRequestDto::Disconnect { params: _ }) => {
+                ServiceKind::DataDisconnect
             }
-            RequestDto::Data(DataRequestDto::Create) => {
-                let module = GeneralService::builder().build();
-                module.resolve()
-            }
-            RequestDto::Data(DataRequestDto::Status { params: _ }) => {
-                let module = GeneralService::builder().build();
-                module.resolve()
-            }
-            RequestDto::Media(MediaRequestDto::Call { params: _ }) => {
-                let module = MediaCallService::builder().build();
-                module.resolve()
-            }
-            RequestDto::Media(MediaRequestDto::Answer { params: _ }) => {
-                let module = MediaAnswerService::builder().build();
-                module.resolve()
-            }
-            RequestDto::System(_) => {
-                let module = SystemService::builder().build();
-                module.resolve()
-            }
-            _ => {
-                let module = GeneralService::builder().build();
-                module.resolve()
+            RequestDto::Data(DataRequestDto::Policy { .. }) => ServiceKind::DataPolicy,
+            RequestDto::Data(DataRequestDto::Create) => ServiceKind::General,
+            RequestDto::Data(DataRequestDto::Status { params: _ }) => ServiceKind::General,
+            RequestDto::Media(MediaRequestDto::Call { params: _ }) => ServiceKind::MediaCall,
+            RequestDto::Media(MediaRequestDto::Answer { params: _ }) => ServiceKind::MediaAnswer,
+            RequestDto::Media(MediaRequestDto::Disconnect { params: _ }) => {
+                ServiceKind::MediaDisconnect
             }
+            RequestDto::Media(MediaRequestDto::Policy { .. }) => ServiceKind::MediaPolicy,
+            RequestDto::System(_) => ServiceKind::System,
+            RequestDto::Event(_) => ServiceKind::EventSubscription,
+            _ => ServiceKind::General,
         }
     }
+
+    /// DIコンテナを組み立て、Serviceのインスタンスを生成する
+    pub(crate) fn build(self) -> Arc<dyn Service> {
+        match self {
+            ServiceKind::PeerCreate => PeerCreateService::builder().build().resolve(),
+            ServiceKind::PeerRegistry => PeerRegistryService::builder().build().resolve(),
+            ServiceKind::DataConnect => DataConnectService::builder().build().resolve(),
+            ServiceKind::DataRedirect => DataRedirectService::builder().build().resolve(),
+            ServiceKind::DataDisconnect => DataDisconnectService::builder().build().resolve(),
+            ServiceKind::DataPolicy => DataPolicyService::builder().build().resolve(),
+            ServiceKind::MediaCall => MediaCallService::builder().build().resolve(),
+            ServiceKind::MediaAnswer => MediaAnswerService::builder().build().resolve(),
+            ServiceKind::MediaDisconnect => MediaDisconnectService::builder().build().resolve(),
+            ServiceKind::MediaPolicy => MediaPolicyService::builder().build().resolve(),
+            ServiceKind::System => SystemService::builder().build().resolve(),
+            ServiceKind::EventSubscription => EventSubscriptionService::builder().build().resolve(),
+            ServiceKind::General => GeneralService::builder().build().resolve(),
+        }
+    }
+}
+
+/// 全てのServiceを一度だけ生成して保持し、リクエストの種類ごとに振り分ける
+/// 各Serviceは状態をGlobalState側に持つため、インスタンスは全てのリクエストで共有できる
+pub(crate) struct ServiceContainer {
+    services: HashMap<ServiceKind, Arc<dyn Service>>,
+}
+
+impl ServiceContainer {
+    fn new() -> Self {
+        let services = ServiceKind::ALL
+            .iter()
+            .map(|kind| (*kind, kind.build()))
+            .collect();
+        ServiceContainer { services }
+    }
+
+    /// 共有のServiceContainerを返す。まだ生成されていなければ生成する
+    pub(crate) fn global() -> &'static ServiceContainer {
+        SERVICE_CONTAINER.get_or_init(ServiceContainer::new)
+    }
+
+    /// リクエストを処理するServiceを返す
+    pub(crate) fn service(&self, request: &RequestDto) -> Arc<dyn Service> {
+        // 全てのServiceKindを生成済みなので、取得に失敗することはない
+        self.services[&ServiceKind::from_request(request)].clone()
+    }
+}
+
+#[cfg(test)]
+mod factory_test {
+    use super::*;
+
+    fn request(message: &str) -> RequestDto {
+        RequestDto::from_str(message).unwrap()
+    }
+
+    #[test]
+    fn same_instance_for_same_kind() {
+        // 同じ種類のリクエストには、同じインスタンスを返す
+        let container = ServiceContainer::global();
+        let list = request(r#"{"request_type": "PEER", "command": "LIST"}"#);
+        let delete = request(
+            r#"{"request_type": "PEER", "command": "DELETE", "params": {"peer_id": "peer_1", "token": "pt-9749250e-d157-4f80-9ee2-359ce8524308"}}"#,
+        );
+        assert!(Arc::ptr_eq(
+            &container.service(&list),
+            &container.service(&delete)
+        ));
+        assert!(Arc::ptr_eq(
+            &container.service(&list),
+            &FactoryImpl {}.create_service(&list)
+        ));
+
+        // 種類が異なれば別のインスタンスを返す
+        let subscribe = request(r#"{"request_type": "EVENT", "command": "SUBSCRIBE"}"#);
+        assert!(!Arc::ptr_eq(
+            &container.service(&list),
+            &container.service(&subscribe)
+        ));
+    }
+
+    #[test]
+    fn all_kinds_are_built() {
+        let container = ServiceContainer::global();
+        assert_eq!(container.services.len(), ServiceKind::ALL.len());
+    }
 }
@@ -1,20 +1,36 @@
 /// Rust側の処理の大元となるモジュール
-/// 全ての処理はcall_serviceとreceive_eventの2つを経由してC++側と連携される
+/// 全ての処理はcall_serviceとreceive_event(及びイベントの購読用の関数)を経由してC++側と連携される
+pub(crate) mod deadline;
 pub(crate) mod dto;
 pub(crate) mod factory;
+pub(crate) mod request_id;
 pub(crate) mod usecase;
 
+use std::time::{Duration, Instant};
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use shaku::HasComponent;
 
 use crate::application::dto::request::RequestDto;
+use crate::application::dto::response::{
+    ErrorDto, ResponseDto, ResponseDtoResult, ShutdownSummaryDto,
+};
+#[cfg(feature = "ffi")]
 use crate::application::dto::Command;
-use crate::application::factory::Factory;
+use crate::application::factory::ServiceContainer;
+use crate::application::usecase::event::hub::{
+    EventFilter, DEFAULT_QUEUE_CAPACITY, DEFAULT_SUBSCRIBER_ID,
+};
 use crate::application::usecase::event::EventReceive;
+use crate::application::usecase::system::shutdown::ShutdownProcess;
 use crate::di::*;
-use crate::domain::entity::Stringify;
+use crate::domain::entity::{PeerInfo, Stringify};
 use crate::error;
+#[cfg(feature = "ffi")]
+use crate::error::ErrorCode;
 use crate::ffi::rust_to_c_bridge::c_functions_wrapper::LoggerHolder;
+use crate::ffi::rust_to_c_bridge::state_objects::GlobalState;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct ErrorMessage {
@@ -28,20 +44,63 @@ impl Stringify for ErrorMessage {
     }
 }
 
+// 全てのエラーはこの形式でユーザに返す
+// リクエストに紐付かないエラーの場合、request_typeとcommandはnullになる
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct ErrorMessageInternal {
     request_type: Option<String>,
     command: Option<String>,
-    error: String,
+    #[serde(flatten)]
+    error: ErrorDto,
 }
 
+fn create_error_message(
+    request_type: Option<String>,
+    command: Option<String>,
+    error: ErrorDto,
+) -> ErrorMessage {
+    ErrorMessage {
+        is_success: false,
+        result: ErrorMessageInternal {
+            request_type,
+            command,
+            error,
+        },
+    }
+}
+
+#[cfg(feature = "ffi")]
+/// 特定のリクエストに紐付かないエラーメッセージを生成する
+pub(crate) fn error_message(error: &error::Error) -> String {
+    // ErrorMessageはto_stringでエラーを出すことはない
+    create_error_message(None, None, error.into())
+        .to_string()
+        .unwrap()
+}
+
+#[cfg(feature = "ffi")]
 /// called from ffi::call_service
 /// 能動的にWebRTC GatewayのAPIを呼ぶために使用される
 /// 取得した結果は、そのままの形ではなく、C++側/End Userが必要とする形に変換される。
 /// また、処理によってはRust側のEventListenerが内部的な処理を行うものもある
 /// 特別な処理を行うものは、usecase内のdata, media, peer moduleの中で実装される。
 /// その他のものはgeneral moduleの中で処理される。
+/// リクエストの処理はrequest_idを保持した状態で行い、レスポンスにも同じrequest_idを付与して返す
+/// timeout_msが指定された場合は、それを処理全体の期限とする
 pub(crate) async fn call_service(message: String) -> String {
+    let request_id = request_id::from_message(&message);
+    let deadline = deadline::from_message(&message).map(|timeout| Instant::now() + timeout);
+    let response = deadline::scope(deadline, execute_request(message));
+    let mut response = request_id::scope(request_id.clone(), response).await;
+    // レスポンスは成功時もエラー時もresultにobjectを持つ
+    if let Some(result) = response.get_mut("result").and_then(Value::as_object_mut) {
+        result.insert("request_id".into(), Value::String(request_id));
+    }
+    response.to_string()
+}
+
+#[cfg(feature = "ffi")]
+async fn execute_request(message: String) -> Value {
     // 正常にparseできなかった場合に、request_typeとcommandをユーザに返すために取得を試みる
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub(crate) struct RequestTypeAndCommand {
@@ -51,32 +110,22 @@ pub(crate) async fn call_service(message: String) -> String {
 
     match RequestDto::from_str(&message) {
         Ok(dto) => {
-            let module = GeneralFactory::builder().build();
-            let factory: &dyn Factory = module.resolve_ref();
-            let service = factory.create_service(&dto);
+            let service = ServiceContainer::global().service(&dto);
 
             // errorメッセージを生成する際に必要なので確保しておく
             let command = dto.command();
             let dto_type = dto.dto_type();
 
-            match service.execute(dto).await {
-                Ok(response) => {
-                    // ResponseMessageはto_stringでエラーを出すことはない
-                    response.to_string().unwrap()
-                }
-                Err(e) => {
-                    let internal = ErrorMessageInternal {
-                        request_type: Some(dto_type),
-                        command: Some(command),
-                        error: format!("{:?}", e),
-                    };
-                    let error_message = ErrorMessage {
-                        is_success: false,
-                        result: internal,
-                    };
-                    error_message.to_string().unwrap()
+            let error = match service.execute(dto).await {
+                Ok(ResponseDtoResult::Success(response)) => {
+                    // ResponseMessageはシリアライズでエラーを出すことはない
+                    return serde_json::to_value(ResponseDtoResult::Success(response)).unwrap();
                 }
-            }
+                Ok(ResponseDtoResult::Error(error)) => error,
+                Err(e) => ErrorDto::from(&e),
+            };
+            serde_json::to_value(create_error_message(Some(dto_type), Some(command), error))
+                .unwrap()
         }
         Err(_e) => {
             let type_and_command = match serde_json::from_str::<RequestTypeAndCommand>(&message) {
@@ -87,46 +136,167 @@ pub(crate) async fn call_service(message: String) -> String {
                 Err(_e) => (None, None),
             };
 
-            let internal = ErrorMessageInternal {
-                request_type: type_and_command.0,
-                command: type_and_command.1,
-                error: format!("invalid message in call_service: {}", message),
-            };
-            let error_message = ErrorMessage {
-                is_success: false,
-                result: internal,
+            let error = ErrorDto {
+                code: ErrorCode::InvalidRequest,
+                message: format!("invalid message in call_service: {}", message),
+                step: None,
+                gateway_error: None,
             };
-            let message = error_message.to_string().unwrap();
-            LoggerHolder::global().error(message.as_str());
+            let message = create_error_message(type_and_command.0, type_and_command.1, error);
+            let message = serde_json::to_value(message).unwrap();
+            LoggerHolder::global().error(message.to_string());
             message
         }
     }
 }
 
+/// called from client::SkyWayClient
+/// JSONを経由せずにリクエストを処理する。エラーレスポンスはErrに変換して返す
+/// call_serviceと同様に、処理中はrequest_idを保持し、timeoutが指定された場合はそれを処理全体の期限とする
+pub(crate) async fn execute(
+    request: RequestDto,
+    timeout: Option<Duration>,
+) -> Result<ResponseDto, error::Error> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let response = deadline::scope(deadline, async move {
+        let service = ServiceContainer::global().service(&request);
+        service.execute(request).await
+    });
+    match request_id::scope(request_id::generate(), response).await? {
+        ResponseDtoResult::Success(response) => Ok(response),
+        ResponseDtoResult::Error(error) => Err(error.into()),
+    }
+}
+
+/// rust_mainから起動され、ROSが終了するまで動き続ける
+/// EventListenerが受信したイベントを一度だけ処理し、EventHubを介して全ての購読者に配信する
+/// イベントの処理は購読者の有無に関わらず行われる
+pub(crate) async fn publish_events() {
+    let module = EventReceiveService::builder().build();
+    let service: &dyn EventReceive = module.resolve_ref();
+    let state: &dyn GlobalState = module.resolve_ref();
+    let hub = state.event_hub();
+    let interval = Duration::from_millis(state.config().event_poll_interval_ms);
+
+    while !state.program_state().is_shutting_down() {
+        let dropped = match service.execute().await {
+            Ok(event) => service.deliver(&event),
+            Err(_) if state.program_state().is_shutting_down() => break,
+            Err(error) => {
+                let message = format!("invalid message in receive_events: {}", error.message());
+                LoggerHolder::global().error(message.as_str());
+                // 同じエラーが続く場合に備えて、少し待ってから次のイベントを待つ
+                tokio::time::sleep(interval).await;
+                let error = error.with_message(message);
+                hub.publish(
+                    &serde_json::to_value(create_error_message(None, None, (&error).into()))
+                        .unwrap(),
+                )
+            }
+        };
+
+        for subscriber_id in dropped {
+            let message = format!(
+                "event queue of subscriber {} is full. the oldest event is dropped",
+                subscriber_id
+            );
+            LoggerHolder::global().debug(message);
+        }
+    }
+}
+
+#[cfg(feature = "ffi")]
 /// called from ffi::receive_events
 /// 起動時に開始されたEventListenerが常時WebRTC Gatewayのイベントを監視している。
 /// この関数を通してC++側のプログラムがイベントを取得する。
 /// 取得したイベントはそのままの形ではなく、C++側/End Userが必要とする形に変換される。
 /// また、イベントによってはRust側のEventListenerが受信時に処理を行うものもある
 pub async fn receive_events() -> String {
-    let module = EventReceiveService::builder().build();
-    let service: &dyn EventReceive = module.resolve_ref();
-    let event = service.execute().await;
-    match event {
-        Ok(event) => serde_json::to_string(&event).unwrap(),
-        Err(error) => {
-            let internal = ErrorMessageInternal {
-                request_type: None,
-                command: None,
-                error: format!("invalid message in receive_events: {:?}", error),
-            };
-            let error_message = ErrorMessage {
-                is_success: false,
-                result: internal,
-            };
-            let message = error_message.to_string().unwrap();
-            LoggerHolder::global().error(message.as_str());
-            return message;
+    poll_events(DEFAULT_SUBSCRIBER_ID).await
+}
+
+#[cfg(feature = "ffi")]
+/// called from ffi::subscribe_events
+/// filterはEVENT SUBSCRIBEのfilterと同じ形式のJSON
+pub(crate) fn subscribe_events(filter: &str) -> Result<u64, error::Error> {
+    let filter = serde_json::from_str::<EventFilter>(filter)
+        .map_err(|e| error::Error::SerdeError { error: e })?;
+    Ok(subscribe(filter))
+}
+
+/// called from subscribe_events, client::SkyWayClient::events
+pub(crate) fn subscribe(filter: EventFilter) -> u64 {
+    let module = CppObjctsModule::builder().build();
+    let state: &dyn GlobalState = module.resolve_ref();
+    state.event_hub().subscribe(filter, DEFAULT_QUEUE_CAPACITY)
+}
+
+#[cfg(feature = "ffi")]
+/// called from ffi::poll_events, receive_events
+/// 購読者のキューからイベントを1つ取り出す。イベントが届くかROSが終了するまで待機する
+pub(crate) async fn poll_events(subscriber_id: u64) -> String {
+    let error = match next_event(subscriber_id).await {
+        Ok(event) => return event.to_string(),
+        Err(error) => error,
+    };
+
+    let caller = match subscriber_id {
+        DEFAULT_SUBSCRIBER_ID => "receive_events",
+        _ => "poll_events",
+    };
+    let message = format!("invalid message in {}: {}", caller, error.message());
+    let error = error.with_message(message);
+    let message = error_message(&error);
+    LoggerHolder::global().error(message.as_str());
+    message
+}
+
+#[cfg(feature = "ffi")]
+/// called from ffi::subscribe_events_async
+/// 購読者のキューに届いたイベントを、届くたびにon_eventに渡す
+/// 購読が解除されるか、ROSが終了するか、on_eventがfalseを返すまで続ける
+pub(crate) async fn push_events(subscriber_id: u64, on_event: impl Fn(String) -> bool) {
+    while let Ok(event) = next_event(subscriber_id).await {
+        if !on_event(event.to_string()) {
+            break;
+        }
+    }
+}
+
+/// called from poll_events, push_events, client::EventStream
+/// 購読者のキューからイベントを1つ取り出す
+/// 購読者が存在しない場合やROSが終了した場合はエラーを返す
+pub(crate) async fn next_event(subscriber_id: u64) -> Result<Value, error::Error> {
+    let module = CppObjctsModule::builder().build();
+    let state: &dyn GlobalState = module.resolve_ref();
+    let interval = Duration::from_millis(state.config().event_poll_interval_ms);
+
+    loop {
+        if state.program_state().is_shutting_down() {
+            return Err(error::Error::create_local_error("ros has been shut down"));
         }
+        let mut events = state.event_hub().poll(subscriber_id, 1, interval).await?;
+        if let Some(event) = events.pop() {
+            return Ok(event);
+        }
+    }
+}
+
+/// called from ffi::unsubscribe_events
+pub(crate) fn unsubscribe_events(subscriber_id: u64) -> bool {
+    // receive_events用の購読者は解除させない
+    if subscriber_id == DEFAULT_SUBSCRIBER_ID {
+        return false;
     }
+    let module = CppObjctsModule::builder().build();
+    let state: &dyn GlobalState = module.resolve_ref();
+    state.event_hub().unsubscribe(subscriber_id)
+}
+
+/// called from ffi::shutdown_service
+/// 保持している全てのConnectionと、与えられたPeerを開放する
+pub(crate) async fn shutdown(peers: Vec<PeerInfo>) -> ShutdownSummaryDto {
+    let module = ShutdownModule::builder().build();
+    let process: &dyn ShutdownProcess = module.resolve_ref();
+    process.shutdown(peers).await
 }
@@ -0,0 +1,78 @@
+/// call_serviceで受け付けたリクエストを、ログやイベントと対応付けるためのID
+/// リクエストの処理中はtask localに保持し、ログの出力時やConnectionの情報を保存する際に参照する
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(any(feature = "ffi", test))]
+use serde::Deserialize;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// ユーザがrequest_idを指定しなかった場合に割り当てるIDを生成する
+pub(crate) fn generate() -> String {
+    format!("req-{}", NEXT_REQUEST_ID.fetch_add(1, Ordering::SeqCst))
+}
+
+/// リクエストのJSONからrequest_idを取り出す。指定されていない場合は生成する
+#[cfg(any(feature = "ffi", test))]
+pub(crate) fn from_message(message: &str) -> String {
+    #[derive(Deserialize)]
+    struct RequestIdDto {
+        request_id: Option<String>,
+    }
+
+    serde_json::from_str::<RequestIdDto>(message)
+        .ok()
+        .and_then(|dto| dto.request_id)
+        .unwrap_or_else(generate)
+}
+
+/// request_idを保持した状態でfutureを実行する
+pub(crate) async fn scope<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// 処理中のリクエストのIDを返す。リクエストの処理中でない場合はNone
+pub(crate) fn current() -> Option<String> {
+    REQUEST_ID.try_with(|request_id| request_id.clone()).ok()
+}
+
+/// 処理中のリクエストがある場合は、ログメッセージの先頭にrequest_idを付与する
+pub(crate) fn tag_message(message: String) -> String {
+    match current() {
+        Some(request_id) => format!("[{}] {}", request_id, message),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod request_id_test {
+    use super::*;
+
+    #[test]
+    fn from_message() {
+        let message = r#"{"request_type":"SYSTEM","command":"PING","request_id":"my-request"}"#;
+        assert_eq!(super::from_message(message), "my-request");
+
+        let message = r#"{"request_type":"SYSTEM","command":"PING"}"#;
+        assert!(super::from_message(message).starts_with("req-"));
+    }
+
+    #[tokio::test]
+    async fn scope() {
+        assert_eq!(current(), None);
+        assert_eq!(tag_message("message".into()), "message");
+
+        super::scope("req-test".into(), async {
+            assert_eq!(current(), Some("req-test".to_string()));
+            assert_eq!(tag_message("message".into()), "[req-test] message");
+        })
+        .await;
+
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,248 @@
+/// 複数の手順からなるUseCaseで、完了した手順の取り消し処理を記録しておくためのモジュール
+/// 途中の手順が失敗した場合は、記録した取り消し処理を逆順に全て実行する
+/// 取り消し処理自体が失敗した場合も残りの取り消し処理は続行し、
+/// 元のエラーのメッセージに、取り消した内容と取り消し時のエラーを加えて返す
+use crate::application::deadline;
+use crate::application::dto::request::{DataRequestDto, MediaRequestDto, RequestDto};
+use crate::application::dto::response::ResponseDtoResult;
+use crate::application::dto::Command;
+use crate::application::factory::Factory;
+use crate::domain::entity::SerializableId;
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::CallbackFunctions;
+
+/// 完了した手順を取り消すための処理
+#[derive(Debug, Clone)]
+pub(crate) enum CompensationStep {
+    /// WebRTC Gatewayにリクエストを送り、確保したリソースを開放する
+    Request(Box<RequestDto>),
+    /// C++側でロードしたPluginを開放する。値はPluginに割り当てられたポート番号
+    UnloadPlugin(u16),
+}
+
+impl CompensationStep {
+    // エラーメッセージに含めるため、取り消し処理の内容と対象のIDを文字列にする
+    fn name(&self) -> String {
+        match self {
+            CompensationStep::Request(request) => {
+                let name = format!("{} {}", request.dto_type(), request.command());
+                match request.as_ref() {
+                    RequestDto::Data(DataRequestDto::Delete { params }) => {
+                        format!("{} {}", name, params.data_id.as_str())
+                    }
+                    RequestDto::Media(MediaRequestDto::ContentDelete { params }) => {
+                        format!("{} {}", name, params.media_id.as_str())
+                    }
+                    RequestDto::Media(MediaRequestDto::RtcpDelete { params }) => {
+                        format!("{} {}", name, params.rtcp_id.as_str())
+                    }
+                    _ => name,
+                }
+            }
+            CompensationStep::UnloadPlugin(port) => format!("UNLOAD_PLUGIN {}", port),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Compensation {
+    steps: Vec<CompensationStep>,
+}
+
+impl Compensation {
+    pub fn new() -> Self {
+        Compensation { steps: vec![] }
+    }
+
+    /// 手順が完了したら、その手順を取り消す処理を記録する
+    pub fn push(&mut self, step: CompensationStep) {
+        self.steps.push(step);
+    }
+
+    /// 記録した取り消し処理を逆順に実行し、元のエラーに取り消した内容と取り消し時のエラーを加えて返す
+    /// 取り消すものがなかった場合は、元のエラーをそのまま返す
+    pub async fn rollback(
+        self,
+        error: error::Error,
+        factory: &dyn Factory,
+        callback: &dyn CallbackFunctions,
+    ) -> error::Error {
+        if self.steps.is_empty() {
+            return error;
+        }
+
+        let mut rolled_back = vec![];
+        let mut cleanup_errors = vec![];
+        for step in self.steps.into_iter().rev() {
+            let name = step.name();
+            // 期限切れで中断された場合でも開放できるよう、取り消し処理には期限を引き継がない
+            let undo = Compensation::undo(step, factory, callback);
+            match deadline::scope(None, undo).await {
+                Ok(_) => rolled_back.push(name),
+                Err(e) => cleanup_errors.push(format!("{}: {}", name, e.message())),
+            }
+        }
+
+        let mut message = format!(
+            "{}. rolled back: [{}]",
+            error.message(),
+            rolled_back.join(", ")
+        );
+        if !cleanup_errors.is_empty() {
+            message = format!(
+                "{}. cleanup failed: [{}]",
+                message,
+                cleanup_errors.join(", ")
+            );
+        }
+        // 失敗した手順と分類は元のエラーのものを引き継ぐ
+        error.with_message(message)
+    }
+
+    async fn undo(
+        step: CompensationStep,
+        factory: &dyn Factory,
+        callback: &dyn CallbackFunctions,
+    ) -> Result<(), error::Error> {
+        match step {
+            CompensationStep::Request(request) => {
+                let service = factory.create_service(&request);
+                match service.execute(*request).await? {
+                    ResponseDtoResult::Success(_) => Ok(()),
+                    ResponseDtoResult::Error(error) => Err(error.into()),
+                }
+            }
+            CompensationStep::UnloadPlugin(port) => {
+                callback.data_connection_deleted_callback(port);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod compensation_test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::application::dto::request::DataRequestDto;
+    use crate::application::dto::response::{DataResponseDto, ErrorDto, ResponseDto};
+    use crate::application::factory::MockFactory;
+    use crate::application::usecase::MockService;
+    use crate::domain::entity::{DataId, DataIdWrapper, SerializableId};
+    use crate::ffi::rust_to_c_bridge::state_objects::MockCallbackFunctions;
+
+    fn delete_data_step(data_id: &str) -> CompensationStep {
+        CompensationStep::Request(Box::new(RequestDto::Data(DataRequestDto::Delete {
+            params: DataIdWrapper {
+                data_id: DataId::try_create(data_id).unwrap(),
+            },
+        })))
+    }
+
+    #[tokio::test]
+    async fn rollback_in_reverse_order() {
+        // 取り消し処理の実行順を記録する
+        let history = Arc::new(Mutex::new(vec![]));
+
+        let factory_history = history.clone();
+        let mut factory = MockFactory::new();
+        factory
+            .expect_create_service()
+            .times(1)
+            .returning(move |_| {
+                let history = factory_history.clone();
+                let mut service = MockService::new();
+                service.expect_execute().returning(move |request| {
+                    history.lock().unwrap().push("DELETE".to_string());
+                    if let RequestDto::Data(DataRequestDto::Delete { params }) = request {
+                        return Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                            DataResponseDto::Delete(params),
+                        )));
+                    }
+                    unreachable!()
+                });
+                Arc::new(service)
+            });
+
+        let callback_history = history.clone();
+        let mut callback = MockCallbackFunctions::new();
+        callback
+            .expect_data_connection_deleted_callback()
+            .times(1)
+            .returning(move |port| {
+                assert_eq!(port, 60000);
+                callback_history.lock().unwrap().push("UNLOAD".to_string());
+            });
+
+        let mut compensation = Compensation::new();
+        compensation.push(delete_data_step("da-50a32bab-b3d9-4913-8e20-f79c90a6a211"));
+        compensation.push(CompensationStep::UnloadPlugin(60000));
+
+        let error = compensation
+            .rollback(
+                error::Error::create_local_error("connect failed"),
+                &factory,
+                &callback,
+            )
+            .await;
+
+        // 取り消した内容が実行順に列挙される
+        if let error::Error::LocalError(message) = error {
+            assert_eq!(
+                message,
+                "connect failed. rolled back: [UNLOAD_PLUGIN 60000, DATA DELETE da-50a32bab-b3d9-4913-8e20-f79c90a6a211]"
+            );
+        } else {
+            unreachable!();
+        }
+        assert_eq!(*history.lock().unwrap(), vec!["UNLOAD", "DELETE"]);
+    }
+
+    #[tokio::test]
+    async fn report_cleanup_errors() {
+        // 1つ目の取り消しに失敗しても、2つ目の取り消しは実行される
+        let mut factory = MockFactory::new();
+        factory
+            .expect_create_service()
+            .times(2)
+            .returning(|request| {
+                let is_first = match request {
+                    RequestDto::Data(DataRequestDto::Delete { params }) => {
+                        params.data_id.as_str() == "da-50a32bab-b3d9-4913-8e20-f79c90a6a212"
+                    }
+                    _ => unreachable!(),
+                };
+                let mut service = MockService::new();
+                service.expect_execute().returning(move |_| match is_first {
+                    true => Err(error::Error::create_local_error("gateway is down")),
+                    false => Ok(ResponseDtoResult::Error(ErrorDto::from(
+                        &error::Error::create_gateway_error("data not found"),
+                    ))),
+                });
+                Arc::new(service)
+            });
+        let callback = MockCallbackFunctions::new();
+
+        let mut compensation = Compensation::new();
+        compensation.push(delete_data_step("da-50a32bab-b3d9-4913-8e20-f79c90a6a211"));
+        compensation.push(delete_data_step("da-50a32bab-b3d9-4913-8e20-f79c90a6a212"));
+
+        let error = compensation
+            .rollback(
+                error::Error::create_local_error("connect failed"),
+                &factory,
+                &callback,
+            )
+            .await;
+
+        if let error::Error::LocalError(message) = error {
+            assert_eq!(
+                message,
+                "connect failed. rolled back: []. cleanup failed: [DATA DELETE da-50a32bab-b3d9-4913-8e20-f79c90a6a212: gateway is down, DATA DELETE da-50a32bab-b3d9-4913-8e20-f79c90a6a211: data not found]"
+            );
+        } else {
+            unreachable!();
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// DATA POLICYが設定されている場合に、着信したDataConnectionを外部からのDATA REDIRECTを待たずに処理する
+// 責務は以下の通りである
+// 1. 相手側のpeer_idとmetadataから、policyの規則を先頭から順に評価する
+// 2. 一致した規則があれば、その規則のPluginでRedirect UseCaseを実行する
+// 3. 一致する規則がない場合や、REDIRECTに失敗した場合はDataConnectionを切断する
+//
+// policyが設定されていない場合は何もせず、従来通り外部からのDATA REDIRECTを待つ
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::{Component, Interface};
+
+use crate::application::dto::request::{DataRequestDto, RedirectDtoParams, RequestDto};
+use crate::application::dto::response::{ErrorDto, PolicyResultDto, ResponseDtoResult};
+use crate::application::factory::Factory;
+use crate::domain::entity::{DataConnectionId, DataConnectionIdWrapper, DataConnectionStatus};
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::{GlobalState, Logger};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub(crate) trait DataAutoAccept: Interface {
+    /// policyに従ってDataConnectionを処理し、その結果を返す
+    /// policyが設定されていない場合はNoneを返す
+    async fn apply(
+        &self,
+        data_connection_id: &DataConnectionId,
+        status: &DataConnectionStatus,
+    ) -> Option<PolicyResultDto>;
+}
+
+#[derive(Component)]
+#[shaku(interface = DataAutoAccept)]
+pub(crate) struct DataAutoAcceptImpl {
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+    #[shaku(inject)]
+    factory: Arc<dyn Factory>,
+    #[shaku(inject)]
+    logger: Arc<dyn Logger>,
+}
+
+#[async_trait]
+impl DataAutoAccept for DataAutoAcceptImpl {
+    async fn apply(
+        &self,
+        data_connection_id: &DataConnectionId,
+        status: &DataConnectionStatus,
+    ) -> Option<PolicyResultDto> {
+        let policy = self.state.data_policy()?;
+
+        let result = match policy.find_rule(&status.remote_id, &status.metadata) {
+            Some((index, rule)) => {
+                let request = RequestDto::Data(DataRequestDto::Redirect {
+                    params: RedirectDtoParams {
+                        data_connection_id: data_connection_id.clone(),
+                        plugin_info: rule.plugin_info.clone(),
+                    },
+                });
+                match self.execute(request).await {
+                    Ok(()) => {
+                        let message = format!(
+                            "DataConnection {} from {} is accepted by rule {}",
+                            data_connection_id.as_str(),
+                            status.remote_id,
+                            index
+                        );
+                        self.logger.info(&message);
+                        return Some(PolicyResultDto::Accepted { rule: index });
+                    }
+                    Err(e) => PolicyResultDto::Failed {
+                        rule: index,
+                        error: ErrorDto::from(&e),
+                    },
+                }
+            }
+            None => PolicyResultDto::Rejected,
+        };
+
+        let message = format!(
+            "DataConnection {} from {} is closed by policy: {}",
+            data_connection_id.as_str(),
+            status.remote_id,
+            result
+        );
+        self.logger.warn(&message);
+
+        let request = RequestDto::Data(DataRequestDto::Disconnect {
+            params: DataConnectionIdWrapper {
+                data_connection_id: data_connection_id.clone(),
+            },
+        });
+        if let Err(e) = self.execute(request).await {
+            let message = format!(
+                "failed to disconnect DataConnection {}: {} ({})",
+                data_connection_id.as_str(),
+                e.message(),
+                e.code()
+            );
+            self.logger.error(&message);
+        }
+
+        Some(result)
+    }
+}
+
+impl DataAutoAcceptImpl {
+    async fn execute(&self, request: RequestDto) -> Result<(), error::Error> {
+        let service = self.factory.create_service(&request);
+        match service.execute(request).await? {
+            ResponseDtoResult::Success(_) => Ok(()),
+            ResponseDtoResult::Error(error) => Err(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod data_auto_accept_test {
+    use serde_json::json;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::application::dto::request::DataPolicyDto;
+    use crate::application::dto::response::{DataResponseDto, ResponseDto};
+    use crate::application::factory::MockFactory;
+    use crate::application::usecase::MockService;
+    use crate::di::DataAutoAcceptModule;
+    use crate::ffi::rust_to_c_bridge::state_objects::{MockGlobalState, MockLogger};
+
+    const DATA_CONNECTION_ID: &str = "dc-8bdef7a1-65c8-46be-a82e-37d51c776309";
+
+    fn policy() -> DataPolicyDto {
+        serde_json::from_value(json!({
+            "rules": [
+                {
+                    "peer_id": {"exact": "operator"},
+                    "plugin_info": {"type": "string", "plugins": []}
+                },
+                {
+                    "peer_id": {"prefix": "robot_"},
+                    "metadata": {"glob": "*camera*"},
+                    "plugin_info": {"type": "binary", "plugins": []}
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn status(remote_id: &str, metadata: &str) -> DataConnectionStatus {
+        serde_json::from_value(json!({
+            "remote_id": remote_id,
+            "buffersize": 0,
+            "label": "",
+            "metadata": metadata,
+            "open": true,
+            "reliable": true,
+            "serialization": "NONE",
+            "type": "DATA"
+        }))
+        .unwrap()
+    }
+
+    // 受け取ったリクエストを記録し、redirect_resultの結果を返すFactoryを生成する
+    fn factory(
+        redirect_result: Result<(), String>,
+        requests: Arc<std::sync::Mutex<Vec<RequestDto>>>,
+    ) -> MockFactory {
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().returning(move |_| {
+            let redirect_result = redirect_result.clone();
+            let requests = requests.clone();
+            let mut service = MockService::new();
+            service.expect_execute().returning(move |request| {
+                requests.lock().unwrap().push(request.clone());
+                match request {
+                    RequestDto::Data(DataRequestDto::Redirect { params }) => redirect_result
+                        .clone()
+                        .map(|_| {
+                            ResponseDtoResult::Success(ResponseDto::Data(
+                                DataResponseDto::Redirect(DataConnectionIdWrapper {
+                                    data_connection_id: params.data_connection_id,
+                                }),
+                            ))
+                        })
+                        .map_err(|message| error::Error::create_local_error(&message)),
+                    RequestDto::Data(DataRequestDto::Disconnect { .. }) => {
+                        Ok(ResponseDtoResult::Error(ErrorDto::from(
+                            &error::Error::create_gateway_error("already closed"),
+                        )))
+                    }
+                    _ => unreachable!(),
+                }
+            });
+            Arc::new(service)
+        });
+        factory
+    }
+
+    async fn apply(
+        policy: Option<DataPolicyDto>,
+        factory: MockFactory,
+        remote_id: &str,
+        metadata: &str,
+    ) -> Option<PolicyResultDto> {
+        let mut state = MockGlobalState::new();
+        state.expect_data_policy().return_const(policy);
+        let mut logger = MockLogger::new();
+        logger.expect_info().returning(|_| ());
+        logger.expect_warn().returning(|_| ());
+        logger.expect_error().returning(|_| ());
+
+        let module = DataAutoAcceptModule::builder()
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .build();
+        let auto_accept: &dyn DataAutoAccept = module.resolve_ref();
+        let data_connection_id = DataConnectionId::try_create(DATA_CONNECTION_ID).unwrap();
+        auto_accept
+            .apply(&data_connection_id, &status(remote_id, metadata))
+            .await
+    }
+
+    #[tokio::test]
+    async fn accepted() {
+        // peer_idとmetadataの両方に一致した規則のPluginでREDIRECTする
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let factory = factory(Ok(()), requests.clone());
+        let result = apply(Some(policy()), factory, "robot_1", "front_camera").await;
+        assert_eq!(result, Some(PolicyResultDto::Accepted { rule: 1 }));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        if let RequestDto::Data(DataRequestDto::Redirect { params }) = &requests[0] {
+            assert_eq!(params.data_connection_id.as_str(), DATA_CONNECTION_ID);
+            assert_eq!(params.plugin_info.r#type, "binary");
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected() {
+        // peer_idは一致するがmetadataが一致しないので、REDIRECTせずに切断する
+        // 切断に失敗しても結果は変わらない
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let factory = factory(Ok(()), requests.clone());
+        let result = apply(Some(policy()), factory, "robot_1", "lidar").await;
+        assert_eq!(result, Some(PolicyResultDto::Rejected));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(matches!(
+            requests[0],
+            RequestDto::Data(DataRequestDto::Disconnect { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn redirect_failed() {
+        // REDIRECTに失敗した場合は切断する
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let factory = factory(Err("plugin not found".to_string()), requests.clone());
+        let result = apply(Some(policy()), factory, "operator", "").await;
+        assert_eq!(
+            result,
+            Some(PolicyResultDto::Failed {
+                rule: 0,
+                error: ErrorDto {
+                    code: error::ErrorCode::Internal,
+                    message: "plugin not found".to_string(),
+                    step: None,
+                    gateway_error: None,
+                }
+            })
+        );
+        // 切断時のログには、エラーのメッセージとcodeを出力する
+        assert_eq!(
+            result.unwrap().to_string(),
+            "rule 0 matched but failed: plugin not found (INTERNAL)"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn no_policy() {
+        // policyが設定されていなければ何もしない
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().times(0);
+        let result = apply(None, factory, "robot_1", "front_camera").await;
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn matcher() {
+        use crate::application::dto::request::StringMatcherDto;
+
+        assert!(StringMatcherDto::Exact("robot".into()).is_match("robot"));
+        assert!(!StringMatcherDto::Exact("robot".into()).is_match("robot_1"));
+        assert!(StringMatcherDto::Prefix("robot_".into()).is_match("robot_1"));
+        assert!(!StringMatcherDto::Prefix("robot_".into()).is_match("robot"));
+        let glob = StringMatcherDto::Glob("robot_?_*cam*".into());
+        assert!(glob.is_match("robot_1_front_camera"));
+        assert!(glob.is_match("robot_2_cam"));
+        assert!(!glob.is_match("robot_10_camera"));
+        assert!(!glob.is_match("robot_1_lidar"));
+        assert!(StringMatcherDto::Glob("*".into()).is_match(""));
+    }
+}
@@ -7,24 +7,28 @@
 ///    ロードエラーが出たら、Dataポートを閉じてエラーを返して終了。
 ///    ロードエラーが発生しない場合、この時点でC++側は送受信の準備ができている
 /// 3. C++側で開放したポート番号を戻り値から取得し、CONNECT APIをcallし、戻り値を返す
-use std::ffi::CStr;
+///    CONNECTに失敗した場合は、Pluginを開放し、Dataポートを閉じてエラーを返す
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use serde_json::{json, Value};
 use shaku::Component;
 
-use crate::application::dto::request::{DataRequestDto, RequestDto};
+use crate::application::deadline;
+use crate::application::dto::request::{ConnectDtoParams, DataRequestDto, RequestDto};
 use crate::application::dto::response::{DataResponseDto, ResponseDto, ResponseDtoResult};
 use crate::application::factory::Factory;
-use crate::application::usecase::Service;
+use crate::application::usecase::compensation::{Compensation, CompensationStep};
+use crate::application::usecase::{execute_step, Service};
 use crate::domain::entity::request::{DataRequest, Request};
 use crate::domain::entity::response::{DataResponse, Response, ResponseResult};
 use crate::domain::entity::{
-    ConnectQuery, ConnectQueryOption, DataIdWrapper, PhantomId, SerializableSocket, SocketInfo,
+    ConnectQuery, DataIdWrapper, PhantomId, SerializableSocket, SocketInfo,
 };
 use crate::domain::repository::Repository;
 use crate::error;
+use crate::error::ErrorCode;
+use crate::ffi::ownership::take_cpp_string;
 use crate::ffi::rust_to_c_bridge::c_functions_wrapper::DataPipeInfo;
 use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState};
 
@@ -48,96 +52,140 @@ impl Service for Connect {
             params: connect_params,
         }) = request
         {
-            // 1.は単独で実施可能なので最初に行う
-            let (data_id, address, port) = {
-                let create_data_param = RequestDto::Data(DataRequestDto::Create);
-                let service = self.factory.create_service(&create_data_param);
-                let result = service.execute(create_data_param).await?;
-                if let ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Create(
-                    socket,
-                ))) = result
-                {
-                    (
-                        socket.get_id().expect("failed to open data port"),
-                        socket.ip(),
-                        socket.port(),
-                    )
-                } else {
-                    let message = format!("create data failed {:?}", result);
-                    return Err(error::Error::create_local_error(&message));
-                }
+            // 完了した手順の取り消し処理を記録し、途中で失敗した場合は逆順に実行する
+            let mut compensation = Compensation::new();
+            // 全体の期限を超えた場合も、確保したリソースを開放する
+            let timeout = Duration::from_millis(self.state.config().composite_timeout_ms);
+            let result = self.connect(connect_params, &mut compensation);
+            return match deadline::within("DATA CONNECT", timeout, result).await {
+                Ok(result) => Ok(result),
+                Err(e) => Err(compensation
+                    .rollback(e, self.factory.as_ref(), self.callback.as_ref())
+                    .await),
             };
+        }
 
-            // 2. C++側でRos Pluginをロードさせる。
-            // ここでserializeが失敗するケースはRustの型システムにより発生しないので、テストはしていない
-            let plugin_params = serde_json::to_string(&connect_params.plugin_info.plugins).unwrap();
+        return Err(error::Error::create_local_error("invalid parameters"));
+    }
+}
 
-            let (flag, port, error_message) = {
-                let result = self.callback.data_callback(
-                    &address.to_string(),
-                    port,
-                    &connect_params.plugin_info.r#type,
-                    &plugin_params,
-                );
+impl Connect {
+    async fn connect(
+        &self,
+        connect_params: ConnectDtoParams,
+        compensation: &mut Compensation,
+    ) -> Result<ResponseDtoResult, error::Error> {
+        // 1.は単独で実施可能なので最初に行う
+        let (data_id, address, port) = {
+            let create_data_param = RequestDto::Data(DataRequestDto::Create);
+            let result = execute_step(self.factory.as_ref(), create_data_param).await?;
+            if let ResponseDto::Data(DataResponseDto::Create(socket)) = result {
                 (
-                    result.is_success,
-                    result.port,
-                    unsafe { CStr::from_ptr(result.error_message) }
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
+                    socket.get_id().expect("failed to open data port"),
+                    socket.ip(),
+                    socket.port(),
                 )
-            };
-
-            if !flag {
-                let delete_data_param = RequestDto::Data(DataRequestDto::Delete {
-                    params: DataIdWrapper { data_id },
-                });
-                let _ = self.factory.create_service(&delete_data_param);
-                return Err(error::Error::create_local_error(&error_message));
+            } else {
+                let message = format!("create data failed {:?}", result);
+                return Err(error::Error::create_local_error(&message));
             }
+        };
+        compensation.push(CompensationStep::Request(Box::new(RequestDto::Data(
+            DataRequestDto::Delete {
+                params: DataIdWrapper {
+                    data_id: data_id.clone(),
+                },
+            },
+        ))));
+
+        // 2. C++側でRos Pluginをロードさせる。
+        // ここでserializeが失敗するケースはRustの型システムにより発生しないので、テストはしていない
+        let plugin_params = serde_json::to_string(&connect_params.plugin_info.plugins).unwrap();
+
+        let (flag, port, error_message) = {
+            let result = self.callback.data_callback(
+                &address.to_string(),
+                port,
+                &connect_params.plugin_info.r#type,
+                &plugin_params,
+            );
+
+            let error_message = match result.is_success {
+                true => "".to_string(),
+                // error_messageはC++側の持ち物なので、コピーした後にC++側で開放させる
+                false => unsafe {
+                    take_cpp_string(result.error_message, |message| {
+                        self.callback.release_string_callback(message)
+                    })
+                },
+            };
 
-            // 3. C++側で開放したポート番号を戻り値から取得し、CONNECT APIをcallし、戻り値を返す
-            // Connect APIを呼ぶためのパラメータ生成
-            // Dest ObjectのUDPソケット情報が必要なので、このタイミングで実施する
-            let params = {
-                let params = ConnectQuery {
-                    peer_id: connect_params.peer_id,
-                    token: connect_params.token,
-                    options: connect_params.options,
-                    target_id: connect_params.target_id,
-                    params: Some(DataIdWrapper {
-                        data_id: data_id.clone(),
-                    }),
-                    redirect_params: Some(
-                        SocketInfo::<PhantomId>::try_create(None, "127.0.0.1", port).unwrap(),
-                    ),
-                };
+            (result.is_success, result.port, error_message)
+        };
 
-                Request::Data(DataRequest::Connect { params })
+        if !flag {
+            return Err(
+                error::Error::create_error(ErrorCode::PluginLoadFailed, &error_message)
+                    .with_step("LOAD_PLUGIN"),
+            );
+        }
+        compensation.push(CompensationStep::UnloadPlugin(port));
+
+        // 3. C++側で開放したポート番号を戻り値から取得し、CONNECT APIをcallし、戻り値を返す
+        // Connect APIを呼ぶためのパラメータ生成
+        // Dest ObjectのUDPソケット情報が必要なので、このタイミングで実施する
+        let params = {
+            let params = ConnectQuery {
+                peer_id: connect_params.peer_id.clone(),
+                token: connect_params.token,
+                options: connect_params.options,
+                target_id: connect_params.target_id,
+                params: Some(DataIdWrapper {
+                    data_id: data_id.clone(),
+                }),
+                redirect_params: Some(SocketInfo::<PhantomId>::try_create(
+                    None,
+                    &self.state.config().data_redirect_address,
+                    port,
+                )?),
             };
 
-            let result = self.repository.register(params).await?;
-            match result {
-                // Connectに成功した場合
-                ResponseResult::Success(Response::Data(DataResponse::Connect(params))) => {
-                    // Topicの情報を保管
-                    let response = DataPipeInfo {
-                        data_connection_id: params.data_connection_id.clone(),
-                        data_pipe_port_num: port,
-                    };
-                    self.state
-                        .store_topic(params.data_connection_id.clone(), response);
-
-                    return Ok(ResponseDtoResult::Success(ResponseDto::Data(
-                        DataResponseDto::Connect(params),
-                    )));
-                }
-                _ => unreachable!(),
+            Request::Data(DataRequest::Connect { params })
+        };
+
+        let result = self
+            .repository
+            .register(params)
+            .await
+            .map_err(|e| e.with_step("DATA CONNECT"))?;
+        match result {
+            // Connectに成功した場合
+            ResponseResult::Success(Response::Data(DataResponse::Connect(params))) => {
+                // Topicの情報を保管
+                let response = DataPipeInfo {
+                    data_connection_id: params.data_connection_id.clone(),
+                    data_id,
+                    data_pipe_port_num: port,
+                };
+                self.state
+                    .store_topic(params.data_connection_id.clone(), response);
+                self.state.attach_data_connection(
+                    &connect_params.peer_id,
+                    params.data_connection_id.clone(),
+                );
+
+                Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                    DataResponseDto::Connect(params),
+                )))
+            }
+            ResponseResult::Error(message) => {
+                Err(error::Error::create_gateway_error(&message).with_step("DATA CONNECT"))
+            }
+            result => {
+                let message = format!("unexpected response for CONNECT: {:?}", result);
+                Err(error::Error::create_local_error(&message))
             }
         }
-
-        return Err(error::Error::create_local_error("invalid parameters"));
     }
 }
 
@@ -145,14 +193,18 @@ impl Service for Connect {
 mod connect_data_test {
     use std::ffi::CString;
 
+    use once_cell::sync::OnceCell;
     use shaku::HasComponent;
 
     use super::*;
     use crate::application::factory::MockFactory;
     use crate::application::usecase::MockService;
+    use crate::config::Config;
     use crate::di::*;
     use crate::domain::entity::response::{DataResponse, ResponseResult};
-    use crate::domain::entity::{DataConnectionId, DataConnectionIdWrapper, DataId, SocketInfo};
+    use crate::domain::entity::{
+        DataConnectionId, DataConnectionIdWrapper, DataId, SerializableId, SocketInfo,
+    };
     use crate::domain::repository::MockRepository;
     use crate::ffi::rust_to_c_bridge::c_functions_wrapper::PluginLoadResult;
     use crate::ffi::rust_to_c_bridge::state_objects::{MockCallbackFunctions, MockGlobalState};
@@ -182,7 +234,12 @@ mod connect_data_test {
             .expect_data_callback()
             .times(0)
             .returning(|_, _, _, _| unreachable!());
+        static CONFIG: OnceCell<Config> = OnceCell::new();
         let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(|| CONFIG.get_or_init(Config::default));
         state
             .expect_store_topic()
             .times(0)
@@ -216,9 +273,11 @@ mod connect_data_test {
         };
 
         let result = service.execute(request).await;
-        if let Err(error::Error::LocalError(e)) = result {
-            assert_eq!(e, "failed to open data port");
-        }
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.step(), Some("DATA CREATE"));
+        let e = error.message();
+        assert_eq!(e, "failed to open data port");
     }
 
     #[tokio::test]
@@ -261,6 +320,10 @@ mod connect_data_test {
                 port: 0,
                 error_message: CString::new("plugin_router load error").unwrap().into_raw(),
             });
+        caller
+            .expect_release_string_callback()
+            .times(1)
+            .returning(|_| ());
 
         // 以下のMockはこのテストでは呼ばれない
         let mut repository = MockRepository::new();
@@ -268,7 +331,12 @@ mod connect_data_test {
             .expect_register()
             .times(0)
             .returning(|_| unreachable!());
+        static CONFIG: OnceCell<Config> = OnceCell::new();
         let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(|| CONFIG.get_or_init(Config::default));
         state
             .expect_store_topic()
             .times(0)
@@ -302,9 +370,14 @@ mod connect_data_test {
         };
 
         let result = service.execute(request).await;
-        if let Err(error::Error::LocalError(e)) = result {
-            assert_eq!(e, "plugin_router load error");
-        }
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::PluginLoadFailed);
+        assert_eq!(error.step(), Some("LOAD_PLUGIN"));
+        let e = error.message();
+        assert_eq!(
+            e,
+            "plugin_router load error. rolled back: [DATA DELETE da-06cf1d26-0ef0-4b03-aca6-933027d434c2]"
+        );
     }
 
     #[tokio::test]
@@ -364,16 +437,36 @@ mod connect_data_test {
                 error_message: CString::new("").unwrap().into_raw(),
             });
 
+        static CONFIG: OnceCell<Config> = OnceCell::new();
         let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(2)
+            .returning(|| CONFIG.get_or_init(Config::default));
         state.expect_store_topic().times(1).returning(
             |data_connection_id: DataConnectionId, info: DataPipeInfo| {
                 assert_eq!(
                     data_connection_id.as_str(),
                     "dc-8bdef7a1-65c8-46be-a82e-37d51c776309"
                 );
+                assert_eq!(
+                    info.data_id.as_str(),
+                    "da-06cf1d26-0ef0-4b03-aca6-933027d434c2"
+                );
                 assert_eq!(info.data_pipe_port_num, 60000);
             },
         );
+        // 確立したDataConnectionはCONNECTしたPeerの持ち物として記録される
+        state
+            .expect_attach_data_connection()
+            .times(1)
+            .returning(|peer_id, data_connection_id| {
+                assert_eq!(peer_id.as_str(), "peer_id");
+                assert_eq!(
+                    data_connection_id.as_str(),
+                    "dc-8bdef7a1-65c8-46be-a82e-37d51c776309"
+                );
+            });
 
         // サービスの生成
         let module = DataConnectService::builder()
@@ -405,4 +498,110 @@ mod connect_data_test {
         let result = service.execute(request).await;
         assert_eq!(result.unwrap(), expected);
     }
+
+    #[tokio::test]
+    // CONNECTに失敗した場合は、Pluginを開放し、Dataポートを閉じたあとエラーを返す
+    async fn connect_failed() {
+        // mockのsetup
+        // Dataポートの開放と、失敗後のDataポートの削除で2回呼ばれる
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().times(2).returning(|_| {
+            let mut mock_service = MockService::new();
+            mock_service
+                .expect_execute()
+                .returning(|request| match request {
+                    RequestDto::Data(DataRequestDto::Create) => {
+                        let socket = SocketInfo::<DataId>::try_create(
+                            Some("da-06cf1d26-0ef0-4b03-aca6-933027d434c2".to_string()),
+                            "127.0.0.1",
+                            10000,
+                        )
+                        .unwrap();
+                        Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                            DataResponseDto::Create(socket),
+                        )))
+                    }
+                    RequestDto::Data(DataRequestDto::Delete { params }) => {
+                        assert_eq!(
+                            params.data_id.as_str(),
+                            "da-06cf1d26-0ef0-4b03-aca6-933027d434c2"
+                        );
+                        Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                            DataResponseDto::Delete(params),
+                        )))
+                    }
+                    _ => unreachable!(),
+                });
+            Arc::new(mock_service)
+        });
+
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(1)
+            .returning(|_| Err(error::Error::create_local_error("connect failed")));
+
+        let mut caller = MockCallbackFunctions::new();
+        caller
+            .expect_data_callback()
+            .times(1)
+            .returning(|_, _, _, _| PluginLoadResult {
+                is_success: true,
+                port: 60000,
+                error_message: CString::new("").unwrap().into_raw(),
+            });
+        // ロードしたPluginは開放される
+        caller
+            .expect_data_connection_deleted_callback()
+            .times(1)
+            .returning(|port| assert_eq!(port, 60000));
+
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(2)
+            .returning(|| CONFIG.get_or_init(Config::default));
+        state
+            .expect_store_topic()
+            .times(0)
+            .returning(|_, _| unreachable!());
+
+        // サービスの生成
+        let module = DataConnectService::builder()
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(caller))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+
+        let request = {
+            let message = r#"{
+                   "request_type":"DATA",
+                   "command":"CONNECT",
+                   "params":{
+                       "peer_id": "peer_id",
+                       "token": "pt-06cf1d26-0ef0-4b03-aca6-933027d434c2",
+                       "target_id":"target_id",
+                       "plugin_info": {
+                            "type": "binary",
+                            "plugins": []
+                       }
+                   }
+               }"#;
+
+            RequestDto::from_str(message).unwrap()
+        };
+
+        let result = service.execute(request).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.step(), Some("DATA CONNECT"));
+        let e = error.message();
+        assert_eq!(
+            e,
+            "connect failed. rolled back: [UNLOAD_PLUGIN 60000, DATA DELETE da-06cf1d26-0ef0-4b03-aca6-933027d434c2]"
+        );
+    }
 }
@@ -0,0 +1,311 @@
+/// DataChannelの切断要求に対し、以下の内容を実施する
+/// 1. DISCONNECT APIをcallし、DataConnectionを切断する。失敗した場合はエラーを返して終了
+/// 2. CONNECT, REDIRECT時に保存したDataConnectionの情報を削除する
+/// 3. DataChannelのSourceとして開放したDataポートを削除する
+/// 4. C++側でロードしたPluginを開放する
+///
+/// Dataポートの削除に失敗してもPluginの開放は行い、失敗した内容はレスポンスに含めて返す
+/// CLOSEイベントを待たずに同期的にPluginを開放するため、イベントを監視していなくてもPluginは開放される
+/// 切断後に届くCLOSEイベントでは、既に情報が削除されているため二重に開放されることはない
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::Component;
+
+use crate::application::dto::request::{DataRequestDto, RequestDto};
+use crate::application::dto::response::{
+    DataDisconnectDto, DataResponseDto, ResponseDto, ResponseDtoResult,
+};
+use crate::application::usecase::Service;
+use crate::domain::entity::request::{DataRequest, Request};
+use crate::domain::entity::response::{DataResponse, Response, ResponseResult};
+use crate::domain::entity::{DataConnectionIdWrapper, DataIdWrapper, SerializableId};
+use crate::domain::repository::Repository;
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState};
+
+#[derive(Component)]
+#[shaku(interface = Service)]
+pub(crate) struct Disconnect {
+    #[shaku(inject)]
+    repository: Arc<dyn Repository>,
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+    #[shaku(inject)]
+    callback: Arc<dyn CallbackFunctions>,
+}
+
+#[async_trait]
+impl Service for Disconnect {
+    async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
+        if let RequestDto::Data(DataRequestDto::Disconnect { params }) = request {
+            return self.disconnect(params).await;
+        }
+
+        return Err(error::Error::create_local_error("invalid parameters"));
+    }
+}
+
+impl Disconnect {
+    async fn disconnect(
+        &self,
+        params: DataConnectionIdWrapper,
+    ) -> Result<ResponseDtoResult, error::Error> {
+        // 1. DISCONNECT APIをcallする
+        let request = Request::Data(DataRequest::Disconnect { params });
+        let mut response: DataDisconnectDto = match self.repository.register(request).await? {
+            ResponseResult::Success(Response::Data(DataResponse::Disconnect(params))) => {
+                params.into()
+            }
+            ResponseResult::Error(message) => {
+                return Err(error::Error::create_gateway_error(&message))
+            }
+            result => {
+                let message = format!(
+                    "unexpected response for DISCONNECT: {}",
+                    serde_json::to_string(&result).unwrap()
+                );
+                return Err(error::Error::create_local_error(&message));
+            }
+        };
+
+        // 2. 保存した情報を削除する
+        // CLOSEイベントで既に開放済みの場合は、これ以上行うことはない
+        self.state
+            .detach_data_connection(&response.data_connection_id);
+        let data_pipe_info = match self.state.remove_topic(&response.data_connection_id) {
+            Some(info) => info,
+            None => {
+                return Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                    DataResponseDto::Disconnect(response),
+                )))
+            }
+        };
+
+        // 3. Dataポートを削除する
+        let request = Request::Data(DataRequest::Delete {
+            params: DataIdWrapper {
+                data_id: data_pipe_info.data_id.clone(),
+            },
+        });
+        match self.repository.register(request).await {
+            Ok(ResponseResult::Success(_)) => response.data_id = Some(data_pipe_info.data_id),
+            Ok(ResponseResult::Error(message)) => response.errors.push(format!(
+                "DATA DELETE {}: {}",
+                data_pipe_info.data_id.as_str(),
+                message
+            )),
+            Err(e) => response.errors.push(format!(
+                "DATA DELETE {}: {}",
+                data_pipe_info.data_id.as_str(),
+                e.message()
+            )),
+        }
+
+        // 4. Pluginを開放する
+        self.callback
+            .data_connection_deleted_callback(data_pipe_info.data_pipe_port_num);
+        response.plugin_port = Some(data_pipe_info.data_pipe_port_num);
+
+        Ok(ResponseDtoResult::Success(ResponseDto::Data(
+            DataResponseDto::Disconnect(response),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod disconnect_data_test {
+    use serde_json::json;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::di::DataDisconnectService;
+    use crate::domain::entity::{DataConnectionId, DataId};
+    use crate::domain::repository::MockRepository;
+    use crate::error::ErrorCode;
+    use crate::ffi::rust_to_c_bridge::c_functions_wrapper::DataPipeInfo;
+    use crate::ffi::rust_to_c_bridge::state_objects::{MockCallbackFunctions, MockGlobalState};
+
+    fn create_request() -> RequestDto {
+        RequestDto::Data(DataRequestDto::Disconnect {
+            params: DataConnectionIdWrapper {
+                data_connection_id: DataConnectionId::try_create(
+                    "dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+                )
+                .unwrap(),
+            },
+        })
+    }
+
+    fn create_state() -> MockGlobalState {
+        let mut state = MockGlobalState::new();
+        state
+            .expect_detach_data_connection()
+            .times(1)
+            .return_const(());
+        state
+            .expect_remove_topic()
+            .times(1)
+            .returning(|data_connection_id| {
+                Some(DataPipeInfo {
+                    data_connection_id: data_connection_id.clone(),
+                    data_id: DataId::try_create("da-06cf1d26-0ef0-4b03-aca6-933027d434c2").unwrap(),
+                    data_pipe_port_num: 60000,
+                })
+            });
+        state
+    }
+
+    async fn execute(
+        repository: MockRepository,
+        state: MockGlobalState,
+        callback: MockCallbackFunctions,
+    ) -> Result<ResponseDtoResult, error::Error> {
+        let module = DataDisconnectService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(callback))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+        service.execute(create_request()).await
+    }
+
+    #[tokio::test]
+    async fn success() {
+        // DISCONNECTとDataポートの削除が行われる
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(2)
+            .returning(|request| match request {
+                Request::Data(DataRequest::Disconnect { params }) => Ok(ResponseResult::Success(
+                    Response::Data(DataResponse::Disconnect(params)),
+                )),
+                Request::Data(DataRequest::Delete { params }) => {
+                    assert_eq!(
+                        params.data_id.as_str(),
+                        "da-06cf1d26-0ef0-4b03-aca6-933027d434c2"
+                    );
+                    Ok(ResponseResult::Success(Response::Data(
+                        DataResponse::Delete(params),
+                    )))
+                }
+                _ => unreachable!(),
+            });
+
+        // Pluginが同期的に開放される
+        let mut callback = MockCallbackFunctions::new();
+        callback
+            .expect_data_connection_deleted_callback()
+            .times(1)
+            .returning(|port| assert_eq!(port, 60000));
+
+        let result = execute(repository, create_state(), callback).await.unwrap();
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "DISCONNECT",
+                "data_connection_id": "dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+                "data_id": "da-06cf1d26-0ef0-4b03-aca6-933027d434c2",
+                "plugin_port": 60000
+            }
+        });
+        assert_eq!(serde_json::to_value(&result).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn delete_data_failed() {
+        // Dataポートの削除に失敗しても、Pluginは開放される
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(2)
+            .returning(|request| match request {
+                Request::Data(DataRequest::Disconnect { params }) => Ok(ResponseResult::Success(
+                    Response::Data(DataResponse::Disconnect(params)),
+                )),
+                Request::Data(DataRequest::Delete { .. }) => {
+                    Ok(ResponseResult::Error("data not found".to_string()))
+                }
+                _ => unreachable!(),
+            });
+        let mut callback = MockCallbackFunctions::new();
+        callback
+            .expect_data_connection_deleted_callback()
+            .times(1)
+            .returning(|_| ());
+
+        let result = execute(repository, create_state(), callback).await.unwrap();
+        if let ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Disconnect(dto))) =
+            result
+        {
+            assert_eq!(dto.data_id, None);
+            assert_eq!(dto.plugin_port, Some(60000));
+            assert_eq!(
+                dto.errors,
+                vec!["DATA DELETE da-06cf1d26-0ef0-4b03-aca6-933027d434c2: data not found"]
+            );
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[tokio::test]
+    async fn already_closed() {
+        // CLOSEイベントで既に開放済みの場合は、DISCONNECTのみ行う
+        let mut repository = MockRepository::new();
+        repository.expect_register().times(1).returning(|request| {
+            if let Request::Data(DataRequest::Disconnect { params }) = request {
+                return Ok(ResponseResult::Success(Response::Data(
+                    DataResponse::Disconnect(params),
+                )));
+            }
+            unreachable!()
+        });
+        let mut state = MockGlobalState::new();
+        state
+            .expect_detach_data_connection()
+            .times(1)
+            .return_const(());
+        state.expect_remove_topic().times(1).returning(|_| None);
+        let mut callback = MockCallbackFunctions::new();
+        callback.expect_data_connection_deleted_callback().times(0);
+
+        let result = execute(repository, state, callback).await.unwrap();
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "DISCONNECT",
+                "data_connection_id": "dc-8bdef7a1-65c8-46be-a82e-37d51c776309"
+            }
+        });
+        assert_eq!(serde_json::to_value(&result).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn disconnect_failed() {
+        // 切断に失敗した場合は、保存した情報もPluginもそのまま残す
+        let mut repository = MockRepository::new();
+        repository.expect_register().times(1).returning(|_| {
+            Ok(ResponseResult::Error(
+                "data_connection_id is not found".to_string(),
+            ))
+        });
+        let mut state = MockGlobalState::new();
+        state
+            .expect_detach_data_connection()
+            .times(0)
+            .return_const(());
+        state.expect_remove_topic().times(0);
+        let mut callback = MockCallbackFunctions::new();
+        callback.expect_data_connection_deleted_callback().times(0);
+
+        let result = execute(repository, state, callback).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::GatewayRejected);
+        let message = error.message();
+        assert_eq!(message, "data_connection_id is not found");
+    }
+}
@@ -1,3 +1,6 @@
 /// /data系のAPIのうち、特別な内部処理を必要とするものはここで実装する
+pub(crate) mod auto_accept;
 pub(crate) mod connect;
+pub(crate) mod disconnect;
+pub(crate) mod policy;
 pub(crate) mod redirect;
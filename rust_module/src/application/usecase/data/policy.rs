@@ -0,0 +1,108 @@
+/// 着信したDataConnectionを自動的に処理するためのpolicyを設定する
+/// 設定したpolicyはCONNECTIONイベントの受信時に参照され、以降に着信したDataConnectionに適用される
+/// paramsを省略した場合はpolicyを解除する
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::Component;
+
+use crate::application::dto::request::{DataRequestDto, RequestDto};
+use crate::application::dto::response::{
+    DataPolicyResponseDto, DataResponseDto, ResponseDto, ResponseDtoResult,
+};
+use crate::application::usecase::Service;
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::GlobalState;
+
+#[derive(Component)]
+#[shaku(interface = Service)]
+pub(crate) struct Policy {
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+}
+
+#[async_trait]
+impl Service for Policy {
+    async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
+        if let RequestDto::Data(DataRequestDto::Policy { params }) = request {
+            self.state.set_data_policy(params.clone());
+            return Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                DataResponseDto::Policy(DataPolicyResponseDto { policy: params }),
+            )));
+        }
+
+        Err(error::Error::create_local_error("invalid parameters"))
+    }
+}
+
+#[cfg(test)]
+mod data_policy_test {
+    use serde_json::json;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::di::DataPolicyService;
+    use crate::ffi::rust_to_c_bridge::state_objects::MockGlobalState;
+
+    async fn execute(state: MockGlobalState, message: &str) -> serde_json::Value {
+        let module = DataPolicyService::builder()
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+        let result = service
+            .execute(RequestDto::from_str(message).unwrap())
+            .await
+            .unwrap();
+        serde_json::to_value(&result).unwrap()
+    }
+
+    #[tokio::test]
+    async fn set_policy() {
+        let mut state = MockGlobalState::new();
+        state.expect_set_data_policy().times(1).returning(|policy| {
+            let policy = policy.unwrap();
+            assert_eq!(policy.rules.len(), 1);
+            assert!(policy.rules[0].is_match("robot_1", ""));
+        });
+
+        let message = r#"{
+            "request_type": "DATA",
+            "command": "POLICY",
+            "params": {
+                "rules": [{
+                    "peer_id": {"prefix": "robot_"},
+                    "plugin_info": {"type": "string", "plugins": []}
+                }]
+            }
+        }"#;
+        let result = execute(state, message).await;
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "POLICY",
+                "policy": {
+                    "rules": [{
+                        "peer_id": {"prefix": "robot_"},
+                        "plugin_info": {"type": "string", "plugins": []}
+                    }]
+                }
+            }
+        });
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test]
+    async fn clear_policy() {
+        // paramsを省略するとpolicyを解除する
+        let mut state = MockGlobalState::new();
+        state
+            .expect_set_data_policy()
+            .times(1)
+            .returning(|policy| assert!(policy.is_none()));
+
+        let message = r#"{"request_type": "DATA", "command": "POLICY"}"#;
+        let result = execute(state, message).await;
+        assert_eq!(result["result"]["policy"], serde_json::Value::Null);
+    }
+}
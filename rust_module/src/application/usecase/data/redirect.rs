@@ -7,16 +7,19 @@
 ///    ロードエラーが出たら、Dataポートを閉じてエラーを返して終了。
 ///    ロードエラーが発生しない場合、この時点でC++側は送受信の準備ができている
 /// 3. C++側で開放したポート番号を戻り値から取得し、Redirect APIをcallし、戻り値を返す
-use std::ffi::CStr;
+///    REDIRECTに失敗した場合は、Pluginを開放し、Dataポートを閉じてエラーを返す
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use shaku::Component;
 
-use crate::application::dto::request::{DataRequestDto, RequestDto};
+use crate::application::deadline;
+use crate::application::dto::request::{DataRequestDto, RedirectDtoParams, RequestDto};
 use crate::application::dto::response::{DataResponseDto, ResponseDto, ResponseDtoResult};
 use crate::application::factory::Factory;
-use crate::application::usecase::Service;
+use crate::application::usecase::compensation::{Compensation, CompensationStep};
+use crate::application::usecase::{execute_step, Service};
 use crate::domain::entity::request::{DataRequest, Request};
 use crate::domain::entity::response::{DataResponse, Response, ResponseResult};
 use crate::domain::entity::{
@@ -24,6 +27,8 @@ use crate::domain::entity::{
 };
 use crate::domain::repository::Repository;
 use crate::error;
+use crate::error::ErrorCode;
+use crate::ffi::ownership::take_cpp_string;
 use crate::ffi::rust_to_c_bridge::c_functions_wrapper::DataPipeInfo;
 use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState};
 
@@ -47,97 +52,132 @@ impl Service for Redirect {
             params: redirect_params,
         }) = request
         {
-            // 1. Dataポートを開放させ、DataChannelへのSourceとして利用する
-            let (data_id, address, port) = {
-                let create_data_param = RequestDto::Data(DataRequestDto::Create);
-                let service = self.factory.create_service(&create_data_param);
-                let result = service.execute(create_data_param).await?;
-                if let ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Create(
-                    socket,
-                ))) = result
-                {
-                    (
-                        socket.get_id().expect("failed to open data port"),
-                        socket.ip(),
-                        socket.port(),
-                    )
-                } else {
-                    let message = format!("create data failed {:?}", result);
-                    return Err(error::Error::create_local_error(&message));
-                }
+            // 完了した手順の取り消し処理を記録し、途中で失敗した場合は逆順に実行する
+            let mut compensation = Compensation::new();
+            // 全体の期限を超えた場合も、確保したリソースを開放する
+            let timeout = Duration::from_millis(self.state.config().composite_timeout_ms);
+            let result = self.redirect(redirect_params, &mut compensation);
+            return match deadline::within("DATA REDIRECT", timeout, result).await {
+                Ok(result) => Ok(result),
+                Err(e) => Err(compensation
+                    .rollback(e, self.factory.as_ref(), self.callback.as_ref())
+                    .await),
             };
+        }
 
-            // 2. C++側でRos Pluginをロードさせる。
-            // ここでserializeが失敗するケースはRustの型システムにより発生しないので、テストはしていない
-            let plugin_params =
-                serde_json::to_string(&redirect_params.plugin_info.plugins).unwrap();
+        return Err(error::Error::create_local_error("invalid parameters"));
+    }
+}
 
-            let (flag, port, error_message) = {
-                let result = self.callback.data_callback(
-                    &address.to_string(),
-                    port,
-                    &redirect_params.plugin_info.r#type,
-                    &plugin_params,
-                );
+impl Redirect {
+    async fn redirect(
+        &self,
+        redirect_params: RedirectDtoParams,
+        compensation: &mut Compensation,
+    ) -> Result<ResponseDtoResult, error::Error> {
+        // 1. Dataポートを開放させ、DataChannelへのSourceとして利用する
+        let (data_id, address, port) = {
+            let create_data_param = RequestDto::Data(DataRequestDto::Create);
+            let result = execute_step(self.factory.as_ref(), create_data_param).await?;
+            if let ResponseDto::Data(DataResponseDto::Create(socket)) = result {
+                (
+                    socket.get_id().expect("failed to open data port"),
+                    socket.ip(),
+                    socket.port(),
+                )
+            } else {
+                let message = format!("create data failed {:?}", result);
+                return Err(error::Error::create_local_error(&message));
+            }
+        };
+        compensation.push(CompensationStep::Request(Box::new(RequestDto::Data(
+            DataRequestDto::Delete {
+                params: DataIdWrapper {
+                    data_id: data_id.clone(),
+                },
+            },
+        ))));
+
+        // 2. C++側でRos Pluginをロードさせる。
+        // ここでserializeが失敗するケースはRustの型システムにより発生しないので、テストはしていない
+        let plugin_params = serde_json::to_string(&redirect_params.plugin_info.plugins).unwrap();
+
+        let (flag, port, error_message) = {
+            let result = self.callback.data_callback(
+                &address.to_string(),
+                port,
+                &redirect_params.plugin_info.r#type,
+                &plugin_params,
+            );
+
+            let error_message = match result.is_success {
+                true => "".to_string(),
+                // error_messageはC++側の持ち物なので、コピーした後にC++側で開放させる
+                false => unsafe {
+                    take_cpp_string(result.error_message, |message| {
+                        self.callback.release_string_callback(message)
+                    })
+                },
+            };
 
-                let error_message = match result.is_success {
-                    true => "".to_string(),
-                    false => {
-                        let error_message = unsafe { CStr::from_ptr(result.error_message) }
-                            .to_str()
-                            .unwrap()
-                            .to_string();
-                        self.callback.release_string_callback(result.error_message);
-                        error_message
-                    }
-                };
+            (result.is_success, result.port, error_message)
+        };
 
-                (result.is_success, result.port, error_message)
+        if !flag {
+            return Err(
+                error::Error::create_error(ErrorCode::PluginLoadFailed, &error_message)
+                    .with_step("LOAD_PLUGIN"),
+            );
+        }
+        compensation.push(CompensationStep::UnloadPlugin(port));
+
+        // 3. C++側で開放したポート番号を戻り値から取得し、Redirect APIをcallし、戻り値を返す
+        // REDIRECT APIを呼ぶためのパラメータ生成
+        // Dest ObjectのUDPソケット情報が必要なので、このタイミングで実施する
+        let params = {
+            let params = RedirectParams {
+                data_connection_id: redirect_params.data_connection_id,
+                feed_params: Some(DataIdWrapper {
+                    data_id: data_id.clone(),
+                }),
+                redirect_params: Some(SocketInfo::<PhantomId>::try_create(
+                    None,
+                    &self.state.config().data_redirect_address,
+                    port,
+                )?),
             };
+            Request::Data(DataRequest::Redirect { params })
+        };
 
-            if !flag {
-                let delete_data_param = RequestDto::Data(DataRequestDto::Delete {
-                    params: DataIdWrapper { data_id },
-                });
-                let _ = self.factory.create_service(&delete_data_param);
-                return Err(error::Error::create_local_error(&error_message));
-            }
-
-            // 3. C++側で開放したポート番号を戻り値から取得し、Redirect APIをcallし、戻り値を返す
-            // REDIRECT APIを呼ぶためのパラメータ生成
-            // Dest ObjectのUDPソケット情報が必要なので、このタイミングで実施する
-            let params = {
-                let params = RedirectParams {
-                    data_connection_id: redirect_params.data_connection_id,
-                    feed_params: Some(DataIdWrapper { data_id }),
-                    redirect_params: Some(
-                        SocketInfo::<PhantomId>::try_create(None, "127.0.0.1", port).unwrap(),
-                    ),
+        let result = self
+            .repository
+            .register(params)
+            .await
+            .map_err(|e| e.with_step("DATA REDIRECT"))?;
+        match result {
+            // Redirectに成功した場合
+            ResponseResult::Success(Response::Data(DataResponse::Redirect(params))) => {
+                // Topicの情報を保管
+                let response = DataPipeInfo {
+                    data_connection_id: params.data_connection_id.clone(),
+                    data_id,
+                    data_pipe_port_num: port,
                 };
-                Request::Data(DataRequest::Redirect { params })
-            };
+                self.state
+                    .store_topic(params.data_connection_id.clone(), response);
 
-            let result = self.repository.register(params).await?;
-            match result {
-                // Redirectに成功した場合
-                ResponseResult::Success(Response::Data(DataResponse::Redirect(params))) => {
-                    // Topicの情報を保管
-                    let response = DataPipeInfo {
-                        data_connection_id: params.data_connection_id.clone(),
-                        data_pipe_port_num: port,
-                    };
-                    self.state
-                        .store_topic(params.data_connection_id.clone(), response);
-
-                    return Ok(ResponseDtoResult::Success(ResponseDto::Data(
-                        DataResponseDto::Redirect(params),
-                    )));
-                }
-                _ => unreachable!(),
+                Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                    DataResponseDto::Redirect(params),
+                )))
+            }
+            ResponseResult::Error(message) => {
+                Err(error::Error::create_gateway_error(&message).with_step("DATA REDIRECT"))
+            }
+            result => {
+                let message = format!("unexpected response for REDIRECT: {:?}", result);
+                Err(error::Error::create_local_error(&message))
             }
         }
-
-        return Err(error::Error::create_local_error("invalid parameters"));
     }
 }
 
@@ -145,14 +185,18 @@ impl Service for Redirect {
 mod redirect_data_test {
     use std::ffi::CString;
 
+    use once_cell::sync::OnceCell;
     use shaku::HasComponent;
 
     use super::*;
     use crate::application::factory::MockFactory;
     use crate::application::usecase::MockService;
+    use crate::config::Config;
     use crate::di::*;
     use crate::domain::entity::response::{DataResponse, ResponseResult};
-    use crate::domain::entity::{DataConnectionId, DataConnectionIdWrapper, DataId, SocketInfo};
+    use crate::domain::entity::{
+        DataConnectionId, DataConnectionIdWrapper, DataId, SerializableId, SocketInfo,
+    };
     use crate::domain::repository::MockRepository;
     use crate::ffi::rust_to_c_bridge::c_functions_wrapper::PluginLoadResult;
     use crate::ffi::rust_to_c_bridge::state_objects::{MockCallbackFunctions, MockGlobalState};
@@ -182,7 +226,12 @@ mod redirect_data_test {
             .expect_data_callback()
             .times(0)
             .returning(|_, _, _, _| unreachable!());
+        static CONFIG: OnceCell<Config> = OnceCell::new();
         let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(|| CONFIG.get_or_init(Config::default));
         state
             .expect_store_topic()
             .times(0)
@@ -215,9 +264,11 @@ mod redirect_data_test {
         };
 
         let result = service.execute(request).await;
-        if let Err(error::Error::LocalError(e)) = result {
-            assert_eq!(e, "failed to open data port");
-        }
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.step(), Some("DATA CREATE"));
+        let e = error.message();
+        assert_eq!(e, "failed to open data port");
     }
 
     #[tokio::test]
@@ -271,7 +322,12 @@ mod redirect_data_test {
             .expect_register()
             .times(0)
             .returning(|_| unreachable!());
+        static CONFIG: OnceCell<Config> = OnceCell::new();
         let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(|| CONFIG.get_or_init(Config::default));
         state
             .expect_store_topic()
             .times(0)
@@ -304,9 +360,14 @@ mod redirect_data_test {
         };
 
         let result = service.execute(request).await;
-        if let Err(error::Error::LocalError(e)) = result {
-            assert_eq!(e, "plugin_router load error");
-        }
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::PluginLoadFailed);
+        assert_eq!(error.step(), Some("LOAD_PLUGIN"));
+        let e = error.message();
+        assert_eq!(
+            e,
+            "plugin_router load error. rolled back: [DATA DELETE da-06cf1d26-0ef0-4b03-aca6-933027d434c2]"
+        );
     }
 
     #[tokio::test]
@@ -370,13 +431,22 @@ mod redirect_data_test {
             .times(0)
             .returning(|_| ());
 
+        static CONFIG: OnceCell<Config> = OnceCell::new();
         let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(2)
+            .returning(|| CONFIG.get_or_init(Config::default));
         state.expect_store_topic().times(1).returning(
             |data_connection_id: DataConnectionId, info: DataPipeInfo| {
                 assert_eq!(
                     data_connection_id.as_str(),
                     "dc-8bdef7a1-65c8-46be-a82e-37d51c776309"
                 );
+                assert_eq!(
+                    info.data_id.as_str(),
+                    "da-06cf1d26-0ef0-4b03-aca6-933027d434c2"
+                );
                 assert_eq!(info.data_pipe_port_num, 60000);
             },
         );
@@ -410,4 +480,108 @@ mod redirect_data_test {
         let result = service.execute(request).await;
         assert_eq!(result.unwrap(), expected);
     }
+
+    #[tokio::test]
+    // REDIRECTに失敗した場合は、Pluginを開放し、Dataポートを閉じたあとエラーを返す
+    async fn redirect_failed() {
+        // mockのsetup
+        // Dataポートの開放と、失敗後のDataポートの削除で2回呼ばれる
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().times(2).returning(|_| {
+            let mut mock_service = MockService::new();
+            mock_service
+                .expect_execute()
+                .returning(|request| match request {
+                    RequestDto::Data(DataRequestDto::Create) => {
+                        let socket = SocketInfo::<DataId>::try_create(
+                            Some("da-06cf1d26-0ef0-4b03-aca6-933027d434c2".to_string()),
+                            "127.0.0.1",
+                            10000,
+                        )
+                        .unwrap();
+                        Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                            DataResponseDto::Create(socket),
+                        )))
+                    }
+                    RequestDto::Data(DataRequestDto::Delete { params }) => {
+                        assert_eq!(
+                            params.data_id.as_str(),
+                            "da-06cf1d26-0ef0-4b03-aca6-933027d434c2"
+                        );
+                        Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                            DataResponseDto::Delete(params),
+                        )))
+                    }
+                    _ => unreachable!(),
+                });
+            Arc::new(mock_service)
+        });
+
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(1)
+            .returning(|_| Err(error::Error::create_local_error("redirect failed")));
+
+        let mut caller = MockCallbackFunctions::new();
+        caller
+            .expect_data_callback()
+            .times(1)
+            .returning(|_, _, _, _| PluginLoadResult {
+                is_success: true,
+                port: 60000,
+                error_message: CString::new("").unwrap().into_raw(),
+            });
+        // ロードしたPluginは開放される
+        caller
+            .expect_data_connection_deleted_callback()
+            .times(1)
+            .returning(|port| assert_eq!(port, 60000));
+
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(2)
+            .returning(|| CONFIG.get_or_init(Config::default));
+        state
+            .expect_store_topic()
+            .times(0)
+            .returning(|_, _| unreachable!());
+
+        // サービスの生成
+        let module = DataRedirectService::builder()
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(caller))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+
+        let request = {
+            let message = r#"{
+                   "request_type":"DATA",
+                   "command":"REDIRECT",
+                   "params":{
+                       "data_connection_id":"dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+                       "plugin_info": {
+                            "type": "binary",
+                            "plugins": []
+                       }
+                   }
+               }"#;
+
+            RequestDto::from_str(message).unwrap()
+        };
+
+        let result = service.execute(request).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.step(), Some("DATA REDIRECT"));
+        let e = error.message();
+        assert_eq!(
+            e,
+            "redirect failed. rolled back: [UNLOAD_PLUGIN 60000, DATA DELETE da-06cf1d26-0ef0-4b03-aca6-933027d434c2]"
+        );
+    }
 }
@@ -1,16 +1,18 @@
 use super::EventReceiveImpl;
-use crate::application::dto::response::{DataConnectionEventDto, DataResponseDto};
-use crate::domain::entity::response::DataResponse;
+use crate::application::dto::response::{
+    DataConnectionErrorEventDto, DataConnectionEventDto, DataResponseDto,
+};
 use crate::domain::entity::{DataConnectionEventEnum, DataConnectionIdWrapper};
 use crate::error;
+use crate::error::ErrorCode;
 
 impl EventReceiveImpl {
     pub(crate) async fn process_data_event(
         &self,
-        response: DataResponse,
+        event: DataConnectionEventEnum,
     ) -> Result<DataResponseDto, error::Error> {
-        match response {
-            DataResponse::Event(DataConnectionEventEnum::OPEN(open)) => {
+        match event {
+            DataConnectionEventEnum::OPEN(open) => {
                 if let Some(item) = self.state.find_topic(&open.data_connection_id) {
                     Ok(DataResponseDto::Event(DataConnectionEventDto::OPEN(
                         DataConnectionIdWrapper {
@@ -22,27 +24,35 @@ impl EventReceiveImpl {
                         "no info about DataConnectionId {:?}",
                         open.data_connection_id.as_str()
                     );
-                    Err(error::Error::create_local_error(&message))
+                    Err(error::Error::create_error(ErrorCode::NotFound, &message))
                 }
             }
-            DataResponse::Event(DataConnectionEventEnum::CLOSE(close)) => {
+            DataConnectionEventEnum::CLOSE(close) => {
                 let data_info = self.state.remove_topic(&close.data_connection_id);
-                data_info.map(|item| {
+                self.state.detach_data_connection(&close.data_connection_id);
+                if let Some(item) = data_info {
                     self.callback
                         .data_connection_deleted_callback(item.data_pipe_port_num);
-                });
+                }
 
                 Ok(DataResponseDto::Event(DataConnectionEventDto::CLOSE(close)))
             }
-            DataResponse::Event(event) => {
-                let message = format!("This event is not processed {:?}", event);
+            DataConnectionEventEnum::ERROR((data_connection_id, error_message)) => {
+                let message = format!(
+                    "DataConnection {} error: {}",
+                    data_connection_id.as_str(),
+                    error_message
+                );
                 self.logger.error(&message);
-                todo!()
+                Ok(DataResponseDto::Event(DataConnectionEventDto::ERROR(
+                    DataConnectionErrorEventDto {
+                        data_connection_id,
+                        error_message,
+                    },
+                )))
             }
-            _ => {
-                let message = format!("Non-Event object is processed in EventReceiveImpl as Data");
-                self.logger.error(&message);
-                unreachable!()
+            DataConnectionEventEnum::TIMEOUT => {
+                Ok(DataResponseDto::Event(DataConnectionEventDto::TIMEOUT))
             }
         }
     }
@@ -0,0 +1,436 @@
+/// 処理済みのイベントを複数の購読者に配信する
+/// 購読者ごとにIDとフィルタ、上限付きのキューを持ち、フィルタに合致したイベントだけがキューに積まれる
+/// キューが溢れた場合は古いイベントから破棄する
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+
+use crate::error;
+use crate::error::ErrorCode;
+
+/// receive_eventsのために予約された購読者のID
+pub(crate) const DEFAULT_SUBSCRIBER_ID: u64 = 0;
+// receive_events用のキューの容量
+const DEFAULT_SUBSCRIBER_QUEUE_CAPACITY: usize = 1000;
+/// キューの容量が指定されなかった場合の値
+pub(crate) const DEFAULT_QUEUE_CAPACITY: usize = 100;
+/// キューの容量の上限
+pub(crate) const MAX_QUEUE_CAPACITY: usize = 10000;
+// イベントの起因となったrequest_idを覚えておくIDの数の上限。超えた場合は古いものから忘れる
+const MAX_REQUEST_ORIGINS: usize = 1000;
+
+/// 購読するイベントの条件
+/// 指定された項目は全て一致する必要がある。何も指定しない場合は全てのイベントを受け取る
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_connection_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_connection_id: Option<String>,
+}
+
+impl EventFilter {
+    /// receive_eventsが返すJSONと同じ形式のイベントが条件に合致するか判定する
+    pub fn is_match(&self, event: &Value) -> bool {
+        let result = &event["result"];
+        let ids = EventIds::from_event(event);
+
+        is_match_field(&self.request_type, result["request_type"].as_str())
+            && is_match_field(&self.event, result["event"].as_str())
+            && is_match_field(&self.peer_id, ids.peer_id)
+            && is_match_field(&self.data_connection_id, ids.data_connection_id)
+            && is_match_field(&self.media_connection_id, ids.media_connection_id)
+    }
+}
+
+// イベントに含まれる、Peer, Connectionを特定するためのID
+struct EventIds<'a> {
+    peer_id: Option<&'a str>,
+    data_connection_id: Option<&'a str>,
+    media_connection_id: Option<&'a str>,
+}
+
+impl<'a> EventIds<'a> {
+    fn from_event(event: &'a Value) -> Self {
+        let result = &event["result"];
+        EventIds {
+            peer_id: result["params"]["peer_id"].as_str(),
+            data_connection_id: first_string(&[
+                &result["data_connection_id"],
+                &result["data_params"]["data_connection_id"],
+            ]),
+            media_connection_id: first_string(&[
+                &result["media_connection_id"],
+                &result["call_params"]["media_connection_id"],
+            ]),
+        }
+    }
+
+    // イベントの対象を最も具体的に表すID
+    // CONNECTION, CALLイベントのようにConnectionのIDを含む場合は、Peerではなくそちらを対象とする
+    fn target(&self) -> Option<&'a str> {
+        match (self.data_connection_id, self.media_connection_id) {
+            (None, None) => self.peer_id,
+            (data_connection_id, media_connection_id) => data_connection_id.or(media_connection_id),
+        }
+    }
+}
+
+fn first_string<'a>(values: &[&'a Value]) -> Option<&'a str> {
+    values.iter().find_map(|value| value.as_str())
+}
+
+fn is_match_field(expected: &Option<String>, actual: Option<&str>) -> bool {
+    match expected {
+        None => true,
+        Some(expected) => actual == Some(expected.as_str()),
+    }
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    capacity: usize,
+    queue: Mutex<VecDeque<Value>>,
+    notify: Notify,
+}
+
+impl Subscriber {
+    fn new(filter: EventFilter, capacity: usize) -> Self {
+        Subscriber {
+            filter,
+            capacity,
+            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            notify: Notify::new(),
+        }
+    }
+
+    // キューに積み、溢れた場合は古いイベントを破棄する
+    // 破棄した場合はtrueを返す
+    fn push(&self, event: Value) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let is_overflowed = queue.len() >= self.capacity;
+        if is_overflowed {
+            queue.pop_front();
+        }
+        queue.push_back(event);
+        drop(queue);
+        self.notify.notify_one();
+        is_overflowed
+    }
+
+    fn pop(&self, max_events: usize) -> Vec<Value> {
+        let mut queue = self.queue.lock().unwrap();
+        let len = max_events.min(queue.len());
+        queue.drain(..len).collect()
+    }
+}
+
+// Peer, Connectionを生成したリクエストのrequest_id
+#[derive(Default)]
+struct RequestOrigins {
+    request_ids: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+impl RequestOrigins {
+    fn record(&mut self, id: &str, request_id: &str) {
+        if self
+            .request_ids
+            .insert(id.to_string(), request_id.to_string())
+            .is_none()
+        {
+            self.order.push_back(id.to_string());
+        }
+        while self.order.len() > MAX_REQUEST_ORIGINS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.request_ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+pub(crate) struct EventHub {
+    next_id: AtomicU64,
+    subscribers: Mutex<HashMap<u64, Arc<Subscriber>>>,
+    origins: Mutex<RequestOrigins>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        EventHub::new()
+    }
+}
+
+impl EventHub {
+    /// receive_events用の購読者を登録した状態で生成する
+    pub fn new() -> Self {
+        let mut subscribers = HashMap::new();
+        subscribers.insert(
+            DEFAULT_SUBSCRIBER_ID,
+            Arc::new(Subscriber::new(
+                EventFilter::default(),
+                DEFAULT_SUBSCRIBER_QUEUE_CAPACITY,
+            )),
+        );
+        EventHub {
+            next_id: AtomicU64::new(DEFAULT_SUBSCRIBER_ID + 1),
+            subscribers: Mutex::new(subscribers),
+            origins: Mutex::new(RequestOrigins::default()),
+        }
+    }
+
+    pub fn subscribe(&self, filter: EventFilter, capacity: usize) -> u64 {
+        let capacity = capacity.clamp(1, MAX_QUEUE_CAPACITY);
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .insert(id, Arc::new(Subscriber::new(filter, capacity)));
+        id
+    }
+
+    pub fn unsubscribe(&self, subscriber_id: u64) -> bool {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .remove(&subscriber_id)
+            .is_some()
+    }
+
+    /// Peer, Connectionを生成したリクエストのrequest_idを記録する
+    /// 以降、そのPeer, Connectionに関するイベントにはrequest_idが付与される
+    pub fn record_origin(&self, id: &str, request_id: &str) {
+        self.origins.lock().unwrap().record(id, request_id);
+    }
+
+    /// 条件に合致する全ての購読者にイベントを配信する
+    /// キューが溢れてイベントを破棄した購読者のIDを返す
+    pub fn publish(&self, event: &Value) -> Vec<u64> {
+        let event = self.attach_origin(event);
+        let subscribers = self.subscribers.lock().unwrap();
+        subscribers
+            .iter()
+            .filter(|(_, subscriber)| subscriber.filter.is_match(&event))
+            .filter_map(|(id, subscriber)| subscriber.push(event.clone()).then_some(*id))
+            .collect()
+    }
+
+    // イベントの対象を生成したリクエストが分かっている場合は、resultにrequest_idを付与する
+    fn attach_origin(&self, event: &Value) -> Value {
+        let mut event = event.clone();
+        let request_id = EventIds::from_event(&event).target().and_then(|id| {
+            let origins = self.origins.lock().unwrap();
+            origins.request_ids.get(id).cloned()
+        });
+        if let (Some(request_id), Some(result)) = (
+            request_id,
+            event.get_mut("result").and_then(Value::as_object_mut),
+        ) {
+            result
+                .entry("request_id")
+                .or_insert(Value::String(request_id));
+        }
+        event
+    }
+
+    /// 最大max_events個のイベントを取り出す
+    /// キューが空の場合は、イベントが届くかtimeoutが経過するまで待機する
+    pub async fn poll(
+        &self,
+        subscriber_id: u64,
+        max_events: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Value>, error::Error> {
+        let subscriber = self
+            .subscribers
+            .lock()
+            .unwrap()
+            .get(&subscriber_id)
+            .cloned()
+            .ok_or_else(|| {
+                let message = format!("subscriber {} is not found", subscriber_id);
+                error::Error::create_error(ErrorCode::NotFound, &message)
+            })?;
+
+        let events = subscriber.pop(max_events);
+        if !events.is_empty() || timeout.is_zero() {
+            return Ok(events);
+        }
+
+        let _ = tokio::time::timeout(timeout, subscriber.notify.notified()).await;
+        Ok(subscriber.pop(max_events))
+    }
+}
+
+#[cfg(test)]
+mod event_hub_test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn data_open_event(data_connection_id: &str) -> Value {
+        json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "EVENT",
+                "event": "OPEN",
+                "data_connection_id": data_connection_id
+            }
+        })
+    }
+
+    fn peer_connection_event(peer_id: &str, data_connection_id: &str) -> Value {
+        json!({
+            "is_success": true,
+            "result": {
+                "request_type": "PEER",
+                "command": "EVENT",
+                "event": "CONNECTION",
+                "params": {
+                    "peer_id": peer_id,
+                    "token": "pt-06cf1d26-0ef0-4b03-aca6-933027d434c2"
+                },
+                "data_params": {
+                    "data_connection_id": data_connection_id
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn filter() {
+        let event = peer_connection_event("peer_id", "dc-1");
+
+        assert!(EventFilter::default().is_match(&event));
+
+        let filter = EventFilter {
+            request_type: Some("PEER".into()),
+            event: Some("CONNECTION".into()),
+            peer_id: Some("peer_id".into()),
+            data_connection_id: Some("dc-1".into()),
+            media_connection_id: None,
+        };
+        assert!(filter.is_match(&event));
+
+        let filter = EventFilter {
+            peer_id: Some("other_peer".into()),
+            ..Default::default()
+        };
+        assert!(!filter.is_match(&event));
+
+        // イベントに含まれない項目を指定した場合は合致しない
+        let filter = EventFilter {
+            media_connection_id: Some("mc-1".into()),
+            ..Default::default()
+        };
+        assert!(!filter.is_match(&event));
+    }
+
+    #[tokio::test]
+    async fn every_subscriber_receives_event() {
+        let hub = EventHub::new();
+        let data_only = hub.subscribe(
+            EventFilter {
+                request_type: Some("DATA".into()),
+                ..Default::default()
+            },
+            10,
+        );
+        let all = hub.subscribe(EventFilter::default(), 10);
+
+        hub.publish(&data_open_event("dc-1"));
+        hub.publish(&peer_connection_event("peer_id", "dc-2"));
+
+        let zero = Duration::from_millis(0);
+        assert_eq!(hub.poll(data_only, 10, zero).await.unwrap().len(), 1);
+        assert_eq!(hub.poll(all, 10, zero).await.unwrap().len(), 2);
+        // receive_events用の購読者も全てのイベントを受け取る
+        assert_eq!(
+            hub.poll(DEFAULT_SUBSCRIBER_ID, 10, zero)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_event() {
+        let hub = EventHub::new();
+        let id = hub.subscribe(EventFilter::default(), 2);
+
+        assert!(hub.publish(&data_open_event("dc-1")).is_empty());
+        assert!(hub.publish(&data_open_event("dc-2")).is_empty());
+        assert!(hub.publish(&data_open_event("dc-3")).contains(&id));
+
+        let events = hub.poll(id, 10, Duration::from_millis(0)).await.unwrap();
+        assert_eq!(
+            events,
+            vec![data_open_event("dc-2"), data_open_event("dc-3")]
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_waits_for_event() {
+        let hub = Arc::new(EventHub::new());
+        let id = hub.subscribe(EventFilter::default(), 10);
+
+        let hub_clone = hub.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            hub_clone.publish(&data_open_event("dc-1"));
+        });
+
+        let events = hub.poll(id, 10, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(events, vec![data_open_event("dc-1")]);
+
+        // イベントが来ない場合はtimeoutで空のリストを返す
+        let events = hub.poll(id, 10, Duration::from_millis(10)).await.unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn attach_request_id() {
+        let hub = EventHub::new();
+        let id = hub.subscribe(EventFilter::default(), 10);
+        hub.record_origin("dc-1", "req-1");
+        hub.record_origin("peer_id", "req-2");
+
+        hub.publish(&data_open_event("dc-1"));
+        hub.publish(&data_open_event("dc-2"));
+        // Connectionを含むイベントは、Peerを生成したリクエストとは対応付けない
+        hub.publish(&peer_connection_event("peer_id", "dc-3"));
+
+        let events = hub.poll(id, 10, Duration::from_millis(0)).await.unwrap();
+        assert_eq!(events[0]["result"]["request_id"], "req-1");
+        assert!(events[1]["result"].get("request_id").is_none());
+        assert!(events[2]["result"].get("request_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn unsubscribe() {
+        let hub = EventHub::new();
+        let id = hub.subscribe(EventFilter::default(), 10);
+
+        assert!(hub.unsubscribe(id));
+        assert!(!hub.unsubscribe(id));
+
+        let result = hub.poll(id, 10, Duration::from_millis(0)).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::NotFound);
+        let message = error.message();
+        assert_eq!(message, format!("subscriber {} is not found", id));
+    }
+}
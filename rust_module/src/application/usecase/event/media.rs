@@ -1,59 +1,70 @@
 use super::EventReceiveImpl;
 use crate::application::dto::response::{
-    CallResponseDto, MediaConnectionEventEnumDto, MediaResponseDto,
+    CallResponseDto, MediaConnectionErrorEventDto, MediaConnectionEventEnumDto, MediaResponseDto,
 };
-use crate::domain::entity::response::MediaResponse;
-use crate::domain::entity::MediaConnectionEventEnum;
+use crate::domain::entity::{MediaConnectionEventEnum, MediaConnectionId};
 use crate::error;
+use crate::error::ErrorCode;
 
 impl EventReceiveImpl {
     pub(crate) async fn process_media_event(
         &self,
-        response: MediaResponse,
+        event: MediaConnectionEventEnum,
     ) -> Result<MediaResponseDto, error::Error> {
-        match response {
-            MediaResponse::Event(MediaConnectionEventEnum::STREAM(stream)) => {
-                let response = self
-                    .state
-                    .find_call_response(&stream.media_connection_id)
-                    .expect("call response info is not stored");
-
-                let call_response_dto = CallResponseDto {
-                    send_params: response.send_params,
-                    redirect_params: response.redirect_params,
-                    media_connection_id: stream.media_connection_id,
-                };
+        match event {
+            MediaConnectionEventEnum::STREAM(stream) => {
+                let call_response_dto = self.find_call_response(stream.media_connection_id)?;
                 Ok(MediaResponseDto::Event(
                     MediaConnectionEventEnumDto::Stream(call_response_dto),
                 ))
             }
-            MediaResponse::Event(MediaConnectionEventEnum::READY(stream)) => {
-                let response = self
-                    .state
-                    .find_call_response(&stream.media_connection_id)
-                    .expect("call response info is not stored");
-
-                let call_response_dto = CallResponseDto {
-                    send_params: response.send_params,
-                    redirect_params: response.redirect_params,
-                    media_connection_id: stream.media_connection_id,
-                };
+            MediaConnectionEventEnum::READY(ready) => {
+                let call_response_dto = self.find_call_response(ready.media_connection_id)?;
                 Ok(MediaResponseDto::Event(MediaConnectionEventEnumDto::Ready(
                     call_response_dto,
                 )))
             }
-            MediaResponse::Event(MediaConnectionEventEnum::CLOSE(id_wrapper)) => Ok(
-                MediaResponseDto::Event(MediaConnectionEventEnumDto::Close(id_wrapper)),
-            ),
-            MediaResponse::Event(event) => {
-                let message = format!("This event is not processed {:?}", event);
+            // CALL, ANSWER時に確保したリソースは、CLOSEイベントの配信後に開放する
+            MediaConnectionEventEnum::CLOSE(id_wrapper) => Ok(MediaResponseDto::Event(
+                MediaConnectionEventEnumDto::Close(id_wrapper),
+            )),
+            MediaConnectionEventEnum::ERROR((media_connection_id, error_message)) => {
+                let message = format!(
+                    "MediaConnection {} error: {}",
+                    media_connection_id.as_str(),
+                    error_message
+                );
                 self.logger.error(&message);
-                todo!()
+                Ok(MediaResponseDto::Event(MediaConnectionEventEnumDto::Error(
+                    MediaConnectionErrorEventDto {
+                        media_connection_id,
+                        error_message,
+                    },
+                )))
             }
-            _ => {
-                let message = format!("Non-Event object is processed in EventReceiveImpl as Media");
-                self.logger.error(&message);
-                unreachable!()
+            MediaConnectionEventEnum::TIMEOUT => Ok(MediaResponseDto::Event(
+                MediaConnectionEventEnumDto::Timeout,
+            )),
+        }
+    }
+
+    // CALL, ANSWER時に保存した情報を取り出す
+    fn find_call_response(
+        &self,
+        media_connection_id: MediaConnectionId,
+    ) -> Result<CallResponseDto, error::Error> {
+        match self.state.find_call_response(&media_connection_id) {
+            Some(response) => Ok(CallResponseDto {
+                send_params: response.send_params,
+                redirect_params: response.redirect_params,
+                media_connection_id,
+            }),
+            None => {
+                let message = format!(
+                    "no info about MediaConnectionId {:?}",
+                    media_connection_id.as_str()
+                );
+                Err(error::Error::create_error(ErrorCode::NotFound, &message))
             }
         }
     }
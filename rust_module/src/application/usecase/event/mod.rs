@@ -1,14 +1,26 @@
 pub(crate) mod data;
+pub(crate) mod hub;
 pub(crate) mod media;
 pub(crate) mod peer;
+pub(crate) mod subscription;
 
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use shaku::{Component, Interface};
 
-use crate::application::dto::response::{ResponseDto, ResponseDtoResult};
-use crate::domain::entity::response::{Response, ResponseResult};
+use crate::application::dto::response::{
+    MediaConnectionEventEnumDto, MediaResponseDto, PeerEventEnumDto, PeerResponseDto, ResponseDto,
+    ResponseDtoResult, UnknownEventDto,
+};
+use crate::application::factory::Factory;
+use crate::application::usecase::data::auto_accept::DataAutoAccept;
+use crate::application::usecase::media::auto_answer::MediaAutoAnswer;
+use crate::application::usecase::media::lifecycle::MediaLifecycle;
+use crate::application::usecase::peer::recovery::PeerRecovery;
+use crate::domain::entity::response::{
+    DataResponse, MediaResponse, PeerResponse, Response, ResponseResult,
+};
 use crate::domain::repository::Repository;
 use crate::error;
 use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState, Logger};
@@ -26,6 +38,9 @@ pub(crate) trait OnEvent: Interface {
 #[cfg_attr(test, automock)]
 pub(crate) trait EventReceive: Interface {
     async fn execute(&self) -> Result<ResponseDtoResult, error::Error>;
+    /// executeが返したイベントを全ての購読者に配信し、配信後に行う処理を別タスクで開始する
+    /// キューが溢れて古いイベントを破棄した購読者のIDを返す
+    fn deliver(&self, event: &ResponseDtoResult) -> Vec<u64>;
 }
 
 #[derive(Component)]
@@ -39,13 +54,38 @@ pub(crate) struct EventReceiveImpl {
     state: Arc<dyn GlobalState>,
     #[shaku(inject)]
     callback: Arc<dyn CallbackFunctions>,
+    #[shaku(inject)]
+    factory: Arc<dyn Factory>,
+    #[shaku(inject)]
+    lifecycle: Arc<dyn MediaLifecycle>,
+    #[shaku(inject)]
+    recovery: Arc<dyn PeerRecovery>,
+    #[shaku(inject)]
+    auto_accept: Arc<dyn DataAutoAccept>,
+    #[shaku(inject)]
+    auto_answer: Arc<dyn MediaAutoAnswer>,
 }
 
 #[async_trait]
 impl EventReceive for EventReceiveImpl {
     async fn execute(&self) -> Result<ResponseDtoResult, error::Error> {
-        let event = self.repository.receive_event().await?;
-        self.process_event(event).await
+        match self.repository.receive_event().await {
+            Ok(event) => self.process_event(event).await,
+            // パースできないイベントも、処理を止めずにユーザに通知する
+            Err(error::Error::SerdeError { error }) => {
+                Ok(self.unknown_event(format!("failed to parse event: {}", error)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn deliver(&self, event: &ResponseDtoResult) -> Vec<u64> {
+        let dropped = self
+            .state
+            .event_hub()
+            .publish(&serde_json::to_value(event).unwrap());
+        self.after_delivery(event);
+        dropped
     }
 }
 
@@ -55,19 +95,710 @@ impl EventReceiveImpl {
         response: ResponseResult,
     ) -> Result<ResponseDtoResult, error::Error> {
         match response {
-            ResponseResult::Success(Response::Peer(response)) => Ok(ResponseDtoResult::Success(
-                ResponseDto::Peer(self.process_peer_event(response).await?),
-            )),
-            ResponseResult::Success(Response::Data(response)) => Ok(ResponseDtoResult::Success(
-                ResponseDto::Data(self.process_data_event(response).await?),
-            )),
-            ResponseResult::Success(Response::Media(response)) => Ok(ResponseDtoResult::Success(
-                ResponseDto::Media(self.process_media_event(response).await?),
-            )),
+            ResponseResult::Success(Response::Peer(PeerResponse::Event(event))) => {
+                Ok(ResponseDtoResult::Success(ResponseDto::Peer(
+                    self.process_peer_event(event).await?,
+                )))
+            }
+            ResponseResult::Success(Response::Data(DataResponse::Event(event))) => {
+                Ok(ResponseDtoResult::Success(ResponseDto::Data(
+                    self.process_data_event(event).await?,
+                )))
+            }
+            ResponseResult::Success(Response::Media(MediaResponse::Event(event))) => {
+                Ok(ResponseDtoResult::Success(ResponseDto::Media(
+                    self.process_media_event(event).await?,
+                )))
+            }
+            // Event以外のオブジェクトがイベントとして届いた場合
+            ResponseResult::Success(response) => {
+                Ok(self.unknown_event(format!("non-event object is received: {:?}", response)))
+            }
             ResponseResult::Error(e) => {
                 let message = format!("EventReceiveImpl receives error message {}", e);
-                Err(error::Error::create_local_error(&message))
+                Err(error::Error::create_gateway_error(&e).with_message(message))
+            }
+        }
+    }
+
+    // 購読者にイベントを配信した後に行う処理を、イベントの配信を止めないよう別タスクで開始する
+    // 処理の結果は、それぞれ独立したイベントとして配信される
+    fn after_delivery(&self, event: &ResponseDtoResult) {
+        match event {
+            ResponseDtoResult::Success(ResponseDto::Peer(PeerResponseDto::Event(
+                PeerEventEnumDto::CONNECTION(event),
+            ))) => self.start_auto_accept(event),
+            ResponseDtoResult::Success(ResponseDto::Peer(PeerResponseDto::Event(
+                PeerEventEnumDto::CALL(event),
+            ))) => self.start_auto_answer(event),
+            ResponseDtoResult::Success(ResponseDto::Media(MediaResponseDto::Event(
+                MediaConnectionEventEnumDto::Close(id_wrapper),
+            ))) => {
+                // CALL, ANSWER時に確保したリソースを開放し、RELEASEDイベントを配信する
+                let lifecycle = self.lifecycle.clone();
+                let media_connection_id = id_wrapper.media_connection_id.clone();
+                tokio::spawn(async move { lifecycle.release(&media_connection_id).await });
+            }
+            _ => {}
+        }
+    }
+
+    // 想定していないイベントはログに残した上で、UNKNOWNイベントとしてユーザに返す
+    fn unknown_event(&self, message: String) -> ResponseDtoResult {
+        self.logger.warn(&format!(
+            "EventReceiveImpl receives unknown event: {}",
+            message
+        ));
+        ResponseDtoResult::Success(ResponseDto::Unknown(UnknownEventDto { message }))
+    }
+}
+
+#[cfg(test)]
+mod event_receive_test {
+    use serde_json::{json, Value};
+    use shaku::HasComponent;
+
+    use super::*;
+    use std::time::Duration;
+
+    use crate::application::dto::request::{DataRequestDto, MediaRequestDto, RequestDto};
+    use crate::application::dto::response::{DataResponseDto, PolicyResultDto};
+    use crate::application::dto::response::{
+        MediaConnectionReleasedEventDto, PeerRecordDto, PeerRecordStatusDto,
+    };
+    use crate::application::factory::MockFactory;
+    use crate::application::usecase::data::auto_accept::MockDataAutoAccept;
+    use crate::application::usecase::event::hub::EventFilter;
+    use crate::application::usecase::event::hub::EventHub;
+    use crate::application::usecase::media::auto_answer::MockMediaAutoAnswer;
+    use crate::application::usecase::media::lifecycle::MockMediaLifecycle;
+    use crate::application::usecase::peer::recovery::MockPeerRecovery;
+    use crate::application::usecase::MockService;
+    use crate::di::EventReceiveService;
+    use crate::domain::entity::{
+        DataConnectionEventEnum, DataConnectionId, DataConnectionIdWrapper, DataId,
+        MediaConnectionEventEnum, MediaConnectionId, MediaConnectionIdWrapper, PeerCallEvent,
+        PeerCloseEvent, PeerConnectionEvent, PeerErrorEvent, PeerEventEnum, PeerInfo,
+        SerializableSocket, SocketInfo,
+    };
+    use crate::domain::repository::MockRepository;
+    use crate::error::ErrorCode;
+    use crate::ffi::rust_to_c_bridge::state_objects::{
+        MockCallbackFunctions, MockGlobalState, MockLogger,
+    };
+
+    // repositoryが指定されたイベントを返すケースでサービスを実行する
+    async fn execute(
+        event: Result<ResponseResult, error::Error>,
+        state: MockGlobalState,
+    ) -> Result<Value, error::Error> {
+        let mut repository = MockRepository::new();
+        repository
+            .expect_receive_event()
+            .times(1)
+            .return_once(move || event);
+        let mut logger = MockLogger::new();
+        logger.expect_warn().returning(|_| ());
+        logger.expect_error().returning(|_| ());
+
+        let module = EventReceiveService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn CallbackFunctions>(
+                Box::new(MockCallbackFunctions::new()),
+            )
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .build();
+        let service: &dyn EventReceive = module.resolve_ref();
+
+        let result = service.execute().await?;
+        Ok(serde_json::to_value(&result).unwrap())
+    }
+
+    // 購読者にcount個のイベントが届くまで待ち、届いた順に返す
+    async fn received_events(hub: &EventHub, subscriber_id: u64, count: usize) -> Vec<Value> {
+        let mut events = vec![];
+        let receive = async {
+            while events.len() < count {
+                let received = hub
+                    .poll(subscriber_id, count - events.len(), Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                events.extend(received);
             }
+        };
+        if tokio::time::timeout(Duration::from_secs(1), receive)
+            .await
+            .is_err()
+        {
+            panic!("only {} events are received", events.len());
         }
+        events
+    }
+
+    #[tokio::test]
+    async fn peer_timeout() {
+        let event =
+            ResponseResult::Success(Response::Peer(PeerResponse::Event(PeerEventEnum::TIMEOUT)));
+        let result = execute(Ok(event), MockGlobalState::new()).await;
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "PEER",
+                "command": "EVENT",
+                "event": "TIMEOUT"
+            }
+        });
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn peer_close() {
+        // recoveryを指定せずに生成したPeerは、CLOSEイベントを受信すると一覧上でCLOSEDとして扱う
+        let peer_info =
+            PeerInfo::try_create("peer_id", "pt-9749250e-d157-4f80-9ee2-359ce8524308").unwrap();
+        let event = ResponseResult::Success(Response::Peer(PeerResponse::Event(
+            PeerEventEnum::CLOSE(PeerCloseEvent {
+                params: peer_info.clone(),
+            }),
+        )));
+        let mut state = MockGlobalState::new();
+        state
+            .expect_find_peer()
+            .returning(move |_| Some(PeerRecordDto::new(&peer_info)));
+        state
+            .expect_set_peer_status()
+            .times(1)
+            .returning(|peer_id, status| {
+                assert_eq!(peer_id.as_str(), "peer_id");
+                assert_eq!(status, PeerRecordStatusDto::Closed);
+            });
+
+        let result = execute(Ok(event), state).await.unwrap();
+        assert_eq!(result["result"]["event"], "CLOSE");
+    }
+
+    #[tokio::test]
+    async fn peer_error_starts_recovery() {
+        // 再生成の対象であれば、ERRORイベントを返した後に別タスクで再生成が行われる
+        let event = ResponseResult::Success(Response::Peer(PeerResponse::Event(
+            PeerEventEnum::ERROR(PeerErrorEvent {
+                params: PeerInfo::try_create("peer_id", "pt-9749250e-d157-4f80-9ee2-359ce8524308")
+                    .unwrap(),
+                error_message: "SOCKET_ERROR".to_string(),
+            }),
+        )));
+        let mut repository = MockRepository::new();
+        repository
+            .expect_receive_event()
+            .times(1)
+            .return_once(move || Ok(event));
+        let mut logger = MockLogger::new();
+        logger.expect_error().returning(|_| ());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut recovery = MockPeerRecovery::new();
+        recovery
+            .expect_on_peer_lost()
+            .times(1)
+            .returning(|_, closed| !closed);
+        recovery
+            .expect_recover()
+            .times(1)
+            .returning(move |peer_id| {
+                tx.send(peer_id).unwrap();
+            });
+
+        let module = EventReceiveService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .with_component_override::<dyn PeerRecovery>(Box::new(recovery))
+            .build();
+        let service: &dyn EventReceive = module.resolve_ref();
+
+        let result = service.execute().await.unwrap();
+        let result = serde_json::to_value(&result).unwrap();
+        assert_eq!(result["result"]["event"], "ERROR");
+        assert_eq!(rx.recv().await.unwrap().as_str(), "peer_id");
+    }
+
+    const PEER_TOKEN: &str = "pt-9749250e-d157-4f80-9ee2-359ce8524308";
+    const DATA_CONNECTION_ID: &str = "dc-8bdef7a1-65c8-46be-a82e-37d51c776309";
+    const MEDIA_CONNECTION_ID: &str = "mc-102127d9-30de-413b-93f7-41a33e39d82b";
+
+    // STATUSリクエストを受け取り、responseを返すFactoryを生成する
+    // responseがNoneの場合はSTATUSの取得に失敗する
+    fn status_factory(response: Option<ResponseDto>) -> MockFactory {
+        let mut factory = MockFactory::new();
+        factory
+            .expect_create_service()
+            .times(1)
+            .return_once(move |request| {
+                assert!(matches!(
+                    request,
+                    RequestDto::Data(DataRequestDto::Status { .. })
+                        | RequestDto::Media(MediaRequestDto::Status { .. })
+                ));
+                let mut service = MockService::new();
+                service
+                    .expect_execute()
+                    .times(1)
+                    .return_once(move |_| match response {
+                        Some(response) => Ok(ResponseDtoResult::Success(response)),
+                        None => Err(error::Error::create_local_error("recv Not Found")),
+                    });
+                Arc::new(service)
+            });
+        factory
+    }
+
+    // CONNECTION, CALLイベントの処理で利用するコンポーネントを差し替えてサービスを実行する
+    // policyは設定されていないものとする
+    async fn execute_with_factory(
+        event: ResponseResult,
+        state: MockGlobalState,
+        factory: MockFactory,
+    ) -> Result<Value, error::Error> {
+        let mut repository = MockRepository::new();
+        repository
+            .expect_receive_event()
+            .times(1)
+            .return_once(move || Ok(event));
+        let mut auto_accept = MockDataAutoAccept::new();
+        auto_accept.expect_apply().returning(|_, _| None);
+        let mut auto_answer = MockMediaAutoAnswer::new();
+        auto_answer.expect_apply().returning(|_, _| None);
+
+        let module = EventReceiveService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn DataAutoAccept>(Box::new(auto_accept))
+            .with_component_override::<dyn MediaAutoAnswer>(Box::new(auto_answer))
+            .build();
+        let service: &dyn EventReceive = module.resolve_ref();
+
+        let result = service.execute().await?;
+        Ok(serde_json::to_value(&result).unwrap())
+    }
+
+    fn connection_event() -> ResponseResult {
+        ResponseResult::Success(Response::Peer(PeerResponse::Event(
+            PeerEventEnum::CONNECTION(PeerConnectionEvent {
+                params: PeerInfo::try_create("peer_id", PEER_TOKEN).unwrap(),
+                data_params: DataConnectionIdWrapper {
+                    data_connection_id: DataConnectionId::try_create(DATA_CONNECTION_ID).unwrap(),
+                },
+            }),
+        )))
+    }
+
+    fn call_event() -> ResponseResult {
+        ResponseResult::Success(Response::Peer(PeerResponse::Event(PeerEventEnum::CALL(
+            PeerCallEvent {
+                params: PeerInfo::try_create("peer_id", PEER_TOKEN).unwrap(),
+                call_params: MediaConnectionIdWrapper {
+                    media_connection_id: MediaConnectionId::try_create(MEDIA_CONNECTION_ID)
+                        .unwrap(),
+                },
+            },
+        ))))
+    }
+
+    #[tokio::test]
+    async fn peer_connection() {
+        // 着信したDataConnectionはPeerの持ち物として記録し、STATUSを付与して返す
+        let mut state = MockGlobalState::new();
+        state
+            .expect_attach_data_connection()
+            .times(1)
+            .returning(|peer_id, data_connection_id| {
+                assert_eq!(peer_id.as_str(), "peer_id");
+                assert_eq!(data_connection_id.as_str(), DATA_CONNECTION_ID);
+            });
+        let status = serde_json::from_value(json!({
+            "remote_id": "robot_1",
+            "buffersize": 0,
+            "label": "",
+            "metadata": "front_camera",
+            "open": false,
+            "reliable": false,
+            "serialization": "NONE",
+            "type": "DATA"
+        }))
+        .unwrap();
+        let factory = status_factory(Some(ResponseDto::Data(DataResponseDto::Status(status))));
+
+        let result = execute_with_factory(connection_event(), state, factory)
+            .await
+            .unwrap();
+        assert_eq!(result["result"]["event"], "CONNECTION");
+        assert_eq!(
+            result["result"]["data_params"]["data_connection_id"],
+            DATA_CONNECTION_ID
+        );
+        assert_eq!(result["result"]["status"]["remote_id"], "robot_1");
+    }
+
+    #[tokio::test]
+    async fn peer_connection_policy() {
+        // DATA POLICYによる処理はCONNECTIONイベントの配信後に別タスクで行い、結果をPOLICYイベントとして配信する
+        let mut repository = MockRepository::new();
+        repository
+            .expect_receive_event()
+            .times(1)
+            .return_once(move || Ok(connection_event()));
+        let hub: &'static EventHub = Box::leak(Box::new(EventHub::new()));
+        let subscriber_id = hub.subscribe(EventFilter::default(), 10);
+        let mut state = MockGlobalState::new();
+        state.expect_attach_data_connection().returning(|_, _| ());
+        state.expect_event_hub().return_const(hub);
+        let status = serde_json::from_value(json!({
+            "remote_id": "robot_1",
+            "buffersize": 0,
+            "label": "",
+            "metadata": "front_camera",
+            "open": false,
+            "reliable": false,
+            "serialization": "NONE",
+            "type": "DATA"
+        }))
+        .unwrap();
+        let factory = status_factory(Some(ResponseDto::Data(DataResponseDto::Status(status))));
+        let mut auto_accept = MockDataAutoAccept::new();
+        auto_accept
+            .expect_apply()
+            .times(1)
+            .returning(|data_connection_id, status| {
+                assert_eq!(data_connection_id.as_str(), DATA_CONNECTION_ID);
+                assert_eq!(status.remote_id, "robot_1");
+                Some(PolicyResultDto::Accepted { rule: 0 })
+            });
+
+        let module = EventReceiveService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn DataAutoAccept>(Box::new(auto_accept))
+            .build();
+        let service: &dyn EventReceive = module.resolve_ref();
+
+        let result = service.execute().await.unwrap();
+        service.deliver(&result);
+
+        let events = received_events(hub, subscriber_id, 2).await;
+        assert_eq!(events[0]["result"]["event"], "CONNECTION");
+        assert_eq!(events[0]["result"].get("policy"), None);
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "EVENT",
+                "event": "POLICY",
+                "data_connection_id": DATA_CONNECTION_ID,
+                "remote_id": "robot_1",
+                "policy": {
+                    "action": "ACCEPTED",
+                    "rule": 0
+                }
+            }
+        });
+        assert_eq!(events[1], expected);
+    }
+
+    #[tokio::test]
+    async fn peer_connection_without_status() {
+        // STATUSを取得できない場合はエラーを返す
+        let mut state = MockGlobalState::new();
+        state
+            .expect_attach_data_connection()
+            .times(1)
+            .returning(|_, _| ());
+
+        let result = execute_with_factory(connection_event(), state, status_factory(None)).await;
+        assert_eq!(
+            result.unwrap_err().message(),
+            "connection request is received from peer_id. But failed to get DataConnection Status."
+        );
+    }
+
+    #[tokio::test]
+    async fn peer_call() {
+        // 着信したMediaConnectionはPeerの持ち物として記録し、STATUSを付与して返す
+        let mut state = MockGlobalState::new();
+        state.expect_attach_media_connection().times(1).returning(
+            |peer_id, media_connection_id| {
+                assert_eq!(peer_id.as_str(), "peer_id");
+                assert_eq!(media_connection_id.as_str(), MEDIA_CONNECTION_ID);
+            },
+        );
+        let status = serde_json::from_value(json!({
+            "metadata": "",
+            "open": false,
+            "remote_id": "camera_1"
+        }))
+        .unwrap();
+        let factory = status_factory(Some(ResponseDto::Media(MediaResponseDto::Status(status))));
+
+        let result = execute_with_factory(call_event(), state, factory)
+            .await
+            .unwrap();
+        assert_eq!(result["result"]["event"], "CALL");
+        assert_eq!(
+            result["result"]["call_params"]["media_connection_id"],
+            MEDIA_CONNECTION_ID
+        );
+        assert_eq!(result["result"]["status"]["remote_id"], "camera_1");
+    }
+
+    #[tokio::test]
+    async fn peer_call_policy() {
+        // MEDIA POLICYによる処理はCALLイベントの配信後に別タスクで行い、結果をPOLICYイベントとして配信する
+        let mut repository = MockRepository::new();
+        repository
+            .expect_receive_event()
+            .times(1)
+            .return_once(move || Ok(call_event()));
+        let hub: &'static EventHub = Box::leak(Box::new(EventHub::new()));
+        let subscriber_id = hub.subscribe(EventFilter::default(), 10);
+        let mut state = MockGlobalState::new();
+        state.expect_attach_media_connection().returning(|_, _| ());
+        state.expect_event_hub().return_const(hub);
+        let status = serde_json::from_value(json!({
+            "metadata": "",
+            "open": false,
+            "remote_id": "camera_1"
+        }))
+        .unwrap();
+        let factory = status_factory(Some(ResponseDto::Media(MediaResponseDto::Status(status))));
+        let mut auto_answer = MockMediaAutoAnswer::new();
+        auto_answer
+            .expect_apply()
+            .times(1)
+            .returning(|media_connection_id, status| {
+                assert_eq!(media_connection_id.as_str(), MEDIA_CONNECTION_ID);
+                assert_eq!(status.remote_id.as_str(), "camera_1");
+                Some(PolicyResultDto::Rejected)
+            });
+
+        let module = EventReceiveService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn MediaAutoAnswer>(Box::new(auto_answer))
+            .build();
+        let service: &dyn EventReceive = module.resolve_ref();
+
+        let result = service.execute().await.unwrap();
+        service.deliver(&result);
+
+        let events = received_events(hub, subscriber_id, 2).await;
+        assert_eq!(events[0]["result"]["event"], "CALL");
+        assert_eq!(events[0]["result"].get("policy"), None);
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "MEDIA",
+                "command": "EVENT",
+                "event": "POLICY",
+                "media_connection_id": MEDIA_CONNECTION_ID,
+                "remote_id": "camera_1",
+                "policy": {
+                    "action": "REJECTED"
+                }
+            }
+        });
+        assert_eq!(events[1], expected);
+    }
+
+    #[tokio::test]
+    async fn peer_call_without_status() {
+        // STATUSを取得できない場合はエラーを返す
+        let mut state = MockGlobalState::new();
+        state
+            .expect_attach_media_connection()
+            .times(1)
+            .returning(|_, _| ());
+
+        let result = execute_with_factory(call_event(), state, status_factory(None)).await;
+        assert_eq!(
+            result.unwrap_err().message(),
+            "call request is received from peer_id. But failed to get MediaConnection Status."
+        );
+    }
+
+    #[tokio::test]
+    async fn data_error() {
+        let data_connection_id =
+            DataConnectionId::try_create("dc-8bdef7a1-65c8-46be-a82e-37d51c776309").unwrap();
+        let event = ResponseResult::Success(Response::Data(DataResponse::Event(
+            DataConnectionEventEnum::ERROR((data_connection_id, "error".to_string())),
+        )));
+        let result = execute(Ok(event), MockGlobalState::new()).await;
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "EVENT",
+                "event": "ERROR",
+                "data_connection_id": "dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+                "error_message": "error"
+            }
+        });
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn data_close() {
+        // CLOSEイベントを受信したDataConnectionは、Peerの一覧からも外す
+        let data_connection_id =
+            DataConnectionId::try_create("dc-8bdef7a1-65c8-46be-a82e-37d51c776309").unwrap();
+        let event = ResponseResult::Success(Response::Data(DataResponse::Event(
+            DataConnectionEventEnum::CLOSE(DataConnectionIdWrapper {
+                data_connection_id: data_connection_id.clone(),
+            }),
+        )));
+        let mut state = MockGlobalState::new();
+        state.expect_remove_topic().times(1).returning(|_| None);
+        state
+            .expect_detach_data_connection()
+            .times(1)
+            .returning(move |id| assert_eq!(id, &data_connection_id));
+
+        let result = execute(Ok(event), state).await.unwrap();
+        assert_eq!(result["result"]["event"], "CLOSE");
+    }
+
+    #[tokio::test]
+    async fn media_error_and_timeout() {
+        let media_connection_id =
+            MediaConnectionId::try_create("mc-102127d9-30de-413b-93f7-41a33e39d82b").unwrap();
+        let event = ResponseResult::Success(Response::Media(MediaResponse::Event(
+            MediaConnectionEventEnum::ERROR((media_connection_id, "error".to_string())),
+        )));
+        let result = execute(Ok(event), MockGlobalState::new()).await;
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "MEDIA",
+                "command": "EVENT",
+                "event": "ERROR",
+                "media_connection_id": "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+                "error_message": "error"
+            }
+        });
+        assert_eq!(result.unwrap(), expected);
+
+        let event = ResponseResult::Success(Response::Media(MediaResponse::Event(
+            MediaConnectionEventEnum::TIMEOUT,
+        )));
+        let result = execute(Ok(event), MockGlobalState::new()).await;
+        assert_eq!(result.unwrap()["result"]["event"], "TIMEOUT");
+    }
+
+    #[tokio::test]
+    async fn media_stream_without_call_response() {
+        // CALL, ANSWERの情報がない場合もpanicせずエラーを返す
+        let event = ResponseResult::Success(Response::Media(MediaResponse::Event(
+            MediaConnectionEventEnum::STREAM(MediaConnectionIdWrapper {
+                media_connection_id: MediaConnectionId::try_create(
+                    "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+                )
+                .unwrap(),
+            }),
+        )));
+        let mut state = MockGlobalState::new();
+        state
+            .expect_find_call_response()
+            .times(1)
+            .returning(|_| None);
+
+        let result = execute(Ok(event), state).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::NotFound);
+        let message = error.message();
+        assert_eq!(
+            message,
+            "no info about MediaConnectionId \"mc-102127d9-30de-413b-93f7-41a33e39d82b\""
+        );
+    }
+
+    #[tokio::test]
+    async fn media_close() {
+        // CLOSEイベントを配信した後に、CALL, ANSWER時に確保したリソースを開放し、RELEASEDイベントを配信する
+        let media_connection_id =
+            MediaConnectionId::try_create("mc-102127d9-30de-413b-93f7-41a33e39d82b").unwrap();
+        let event = ResponseResult::Success(Response::Media(MediaResponse::Event(
+            MediaConnectionEventEnum::CLOSE(MediaConnectionIdWrapper {
+                media_connection_id: media_connection_id.clone(),
+            }),
+        )));
+        let mut repository = MockRepository::new();
+        repository
+            .expect_receive_event()
+            .times(1)
+            .return_once(move || Ok(event));
+        let hub: &'static EventHub = Box::leak(Box::new(EventHub::new()));
+        let subscriber_id = hub.subscribe(EventFilter::default(), 10);
+        let mut state = MockGlobalState::new();
+        state.expect_event_hub().return_const(hub);
+        let mut lifecycle = MockMediaLifecycle::new();
+        lifecycle.expect_release().times(1).returning(move |id| {
+            let event = MediaConnectionReleasedEventDto {
+                media_connection_id: id.clone(),
+                released: vec![],
+                errors: vec![],
+            };
+            let result = ResponseDtoResult::Success(ResponseDto::Media(MediaResponseDto::Event(
+                MediaConnectionEventEnumDto::Released(event.clone()),
+            )));
+            hub.publish(&serde_json::to_value(&result).unwrap());
+            Some(event)
+        });
+
+        let module = EventReceiveService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn MediaLifecycle>(Box::new(lifecycle))
+            .build();
+        let service: &dyn EventReceive = module.resolve_ref();
+
+        let result = service.execute().await.unwrap();
+        service.deliver(&result);
+
+        let events = received_events(hub, subscriber_id, 2).await;
+        assert_eq!(events[0]["result"]["event"], "CLOSE");
+        assert_eq!(
+            events[0]["result"]["media_connection_id"],
+            media_connection_id.as_str()
+        );
+        assert_eq!(events[1]["result"]["event"], "RELEASED");
+    }
+
+    #[tokio::test]
+    async fn non_event_object() {
+        // Event以外のオブジェクトはUNKNOWNイベントとして返す
+        let socket = SocketInfo::<DataId>::try_create(
+            Some("da-06cf1d26-0ef0-4b03-aca6-933027d434c2".to_string()),
+            "127.0.0.1",
+            10000,
+        )
+        .unwrap();
+        let event = ResponseResult::Success(Response::Data(DataResponse::Create(socket)));
+        let result = execute(Ok(event), MockGlobalState::new()).await.unwrap();
+        assert_eq!(result["is_success"], true);
+        assert_eq!(result["result"]["request_type"], "UNKNOWN");
+    }
+
+    #[tokio::test]
+    async fn invalid_json() {
+        // パースできないイベントもUNKNOWNイベントとして返す
+        let error = serde_json::from_str::<Value>("invalid json").unwrap_err();
+        let result = execute(
+            Err(error::Error::SerdeError { error }),
+            MockGlobalState::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["result"]["request_type"], "UNKNOWN");
     }
 }
@@ -1,47 +1,43 @@
-use shaku::HasComponent;
-
 use super::EventReceiveImpl;
-use crate::application::dto::request::{MediaRequestDto, RequestDto};
+use crate::application::dto::request::RequestDto;
 use crate::application::dto::response::{
-    DataResponseDto, MediaResponseDto, PeerCallEventDto, PeerConnectionEventDto, PeerEventEnumDto,
-    PeerResponseDto, ResponseDto, ResponseDtoResult,
+    DataConnectionEventDto, DataConnectionPolicyEventDto, DataResponseDto,
+    MediaConnectionEventEnumDto, MediaConnectionPolicyEventDto, MediaResponseDto, PeerCallEventDto,
+    PeerConnectionEventDto, PeerEventEnumDto, PeerResponseDto, ResponseDto, ResponseDtoResult,
 };
-use crate::di::*;
-use crate::domain::entity::response::PeerResponse;
-use crate::domain::entity::PeerEventEnum;
+use crate::domain::entity::{PeerEventEnum, PeerInfo};
 use crate::error;
 
 impl EventReceiveImpl {
     pub(crate) async fn process_peer_event(
         &self,
-        response: PeerResponse,
+        event: PeerEventEnum,
     ) -> Result<PeerResponseDto, error::Error> {
-        match response {
-            PeerResponse::Event(PeerEventEnum::OPEN(event)) => {
-                Ok(PeerResponseDto::Event(PeerEventEnumDto::OPEN(event)))
-            }
-            PeerResponse::Event(PeerEventEnum::CLOSE(close)) => {
+        match event {
+            PeerEventEnum::OPEN(event) => Ok(PeerResponseDto::Event(PeerEventEnumDto::OPEN(event))),
+            PeerEventEnum::CLOSE(close) => {
+                self.start_recovery(&close.params, true);
                 Ok(PeerResponseDto::Event(PeerEventEnumDto::CLOSE(close)))
             }
-            PeerResponse::Event(PeerEventEnum::CONNECTION(connection)) => {
-                use serde::{Deserialize, Serialize};
-
+            PeerEventEnum::CONNECTION(connection) => {
                 use crate::application::dto::request::DataRequestDto;
-                use crate::application::Factory;
 
-                let module = GeneralFactory::builder().build();
-                let factory: &dyn Factory = module.resolve_ref();
+                // 着信したDataConnectionは、着信したPeerの持ち物として記録する
+                self.state.attach_data_connection(
+                    &connection.params.peer_id(),
+                    connection.data_params.data_connection_id.clone(),
+                );
 
                 let request_dto = RequestDto::Data(DataRequestDto::Status {
                     params: connection.data_params.clone(),
                 });
-                let service = factory.create_service(&request_dto);
+                let service = self.factory.create_service(&request_dto);
                 let result = service.execute(request_dto).await;
                 if let Ok(ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Status(
                     status,
                 )))) = result
                 {
-                    let message = serde_json::to_string(&status).unwrap();
+                    // DATA POLICYによる処理は、CONNECTIONイベントの配信後に行う
                     let event_dto = PeerConnectionEventDto {
                         params: connection.params,
                         data_params: connection.data_params,
@@ -55,26 +51,26 @@ impl EventReceiveImpl {
                     Err(error::Error::create_local_error(&message))
                 }
             }
-            PeerResponse::Event(PeerEventEnum::CALL(event)) => {
-                use serde::{Deserialize, Serialize};
-
+            PeerEventEnum::CALL(event) => {
                 use crate::application::dto::request::MediaRequestDto;
-                use crate::application::Factory;
 
-                let module = GeneralFactory::builder().build();
-                let factory: &dyn Factory = module.resolve_ref();
+                // 着信したMediaConnectionは、着信したPeerの持ち物として記録する
+                self.state.attach_media_connection(
+                    &event.params.peer_id(),
+                    event.call_params.media_connection_id.clone(),
+                );
 
                 let request_dto = RequestDto::Media(MediaRequestDto::Status {
                     params: event.call_params.clone(),
                 });
-                let service = factory.create_service(&request_dto);
+                let service = self.factory.create_service(&request_dto);
                 let result = service.execute(request_dto).await;
 
                 if let Ok(ResponseDtoResult::Success(ResponseDto::Media(
                     MediaResponseDto::Status(status),
                 ))) = result
                 {
-                    let message = serde_json::to_string(&status).unwrap();
+                    // MEDIA POLICYによる処理は、CALLイベントの配信後に行う
                     let event_dto = PeerCallEventDto {
                         params: event.params,
                         call_params: event.call_params,
@@ -86,12 +82,73 @@ impl EventReceiveImpl {
                     Err(error::Error::create_local_error(&message))
                 }
             }
-            PeerResponse::Event(PeerEventEnum::TIMEOUT) => unreachable!(),
-            _ => {
-                let message = format!("Non-Event object is processed in EventReceiveImpl as Peer");
+            PeerEventEnum::ERROR(error) => {
+                let message = format!(
+                    "Peer {} error: {}",
+                    error.params.peer_id().as_str(),
+                    error.error_message
+                );
                 self.logger.error(&message);
-                unreachable!()
+                self.start_recovery(&error.params, false);
+                Ok(PeerResponseDto::Event(PeerEventEnumDto::ERROR(error)))
             }
+            PeerEventEnum::TIMEOUT => Ok(PeerResponseDto::Event(PeerEventEnumDto::TIMEOUT)),
+        }
+    }
+
+    // DATA POLICYが設定されていれば、REDIRECTもしくは切断までを別タスクで行い、結果をPOLICYイベントとして配信する
+    // REDIRECTとPluginのロードには時間がかかるため、その間もイベントの配信を止めない
+    pub(crate) fn start_auto_accept(&self, event: &PeerConnectionEventDto) {
+        let auto_accept = self.auto_accept.clone();
+        let state = self.state.clone();
+        let data_connection_id = event.data_params.data_connection_id.clone();
+        let status = event.status.clone();
+        tokio::spawn(async move {
+            if let Some(policy) = auto_accept.apply(&data_connection_id, &status).await {
+                let event =
+                    DataConnectionEventDto::POLICY(Box::new(DataConnectionPolicyEventDto {
+                        data_connection_id,
+                        remote_id: status.remote_id,
+                        policy,
+                    }));
+                let result =
+                    ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Event(event)));
+                state
+                    .event_hub()
+                    .publish(&serde_json::to_value(&result).unwrap());
+            }
+        });
+    }
+
+    // MEDIA POLICYが設定されていれば、ANSWERもしくは切断までを別タスクで行い、結果をPOLICYイベントとして配信する
+    pub(crate) fn start_auto_answer(&self, event: &PeerCallEventDto) {
+        let auto_answer = self.auto_answer.clone();
+        let state = self.state.clone();
+        let media_connection_id = event.call_params.media_connection_id.clone();
+        let status = event.status.clone();
+        tokio::spawn(async move {
+            if let Some(policy) = auto_answer.apply(&media_connection_id, &status).await {
+                let event =
+                    MediaConnectionEventEnumDto::Policy(Box::new(MediaConnectionPolicyEventDto {
+                        media_connection_id,
+                        remote_id: status.remote_id,
+                        policy,
+                    }));
+                let result =
+                    ResponseDtoResult::Success(ResponseDto::Media(MediaResponseDto::Event(event)));
+                state
+                    .event_hub()
+                    .publish(&serde_json::to_value(&result).unwrap());
+            }
+        });
+    }
+
+    // recoveryが指定されたPeerであれば、イベントの処理を止めないよう別タスクで再生成を行う
+    fn start_recovery(&self, peer_info: &PeerInfo, closed: bool) {
+        if self.recovery.on_peer_lost(peer_info, closed) {
+            let recovery = self.recovery.clone();
+            let peer_id = peer_info.peer_id();
+            tokio::spawn(async move { recovery.recover(peer_id).await });
         }
     }
 }
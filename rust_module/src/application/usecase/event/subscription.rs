@@ -0,0 +1,230 @@
+/// EventHubに対する購読の登録・イベントの取得・購読の解除を行うService
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use shaku::Component;
+
+use crate::application::dto::request::{EventRequestDto, RequestDto, SubscriberIdDto};
+use crate::application::dto::response::{
+    EventResponseDto, PolledEventsDto, ResponseDto, ResponseDtoResult,
+};
+use crate::application::usecase::event::hub::{DEFAULT_QUEUE_CAPACITY, DEFAULT_SUBSCRIBER_ID};
+use crate::application::usecase::Service;
+use crate::error;
+use crate::error::ErrorCode;
+use crate::ffi::rust_to_c_bridge::state_objects::GlobalState;
+
+// POLLでmax_eventsが指定されなかった場合に取り出すイベントの最大数
+const DEFAULT_MAX_EVENTS: usize = 100;
+
+#[derive(Component)]
+#[shaku(interface = Service)]
+pub(crate) struct Subscription {
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+}
+
+#[async_trait]
+impl Service for Subscription {
+    async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
+        let hub = self.state.event_hub();
+        let response = match request {
+            RequestDto::Event(EventRequestDto::Subscribe { params }) => {
+                let capacity = params.capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+                let subscriber_id = hub.subscribe(params.filter, capacity);
+                EventResponseDto::Subscribe(SubscriberIdDto { subscriber_id })
+            }
+            RequestDto::Event(EventRequestDto::Poll { params }) => {
+                check_subscriber_id(params.subscriber_id)?;
+                let max_events = params.max_events.unwrap_or(DEFAULT_MAX_EVENTS);
+                let timeout = Duration::from_millis(params.timeout_ms.unwrap_or(0));
+                let events = hub.poll(params.subscriber_id, max_events, timeout).await?;
+                EventResponseDto::Poll(PolledEventsDto {
+                    subscriber_id: params.subscriber_id,
+                    events,
+                })
+            }
+            RequestDto::Event(EventRequestDto::Unsubscribe { params }) => {
+                check_subscriber_id(params.subscriber_id)?;
+                if !hub.unsubscribe(params.subscriber_id) {
+                    let message = format!("subscriber {} is not found", params.subscriber_id);
+                    return Err(error::Error::create_error(ErrorCode::NotFound, &message));
+                }
+                EventResponseDto::Unsubscribe(params)
+            }
+            _ => {
+                return Err(error::Error::create_local_error(
+                    "invalid parameter for Subscription",
+                ));
+            }
+        };
+
+        Ok(ResponseDtoResult::Success(ResponseDto::Event(response)))
+    }
+}
+
+// receive_events用の購読者をユーザが操作しないようにする
+fn check_subscriber_id(subscriber_id: u64) -> Result<(), error::Error> {
+    if subscriber_id == DEFAULT_SUBSCRIBER_ID {
+        let message = format!(
+            "subscriber {} is reserved for receive_events",
+            DEFAULT_SUBSCRIBER_ID
+        );
+        return Err(error::Error::create_error(
+            ErrorCode::InvalidRequest,
+            &message,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod subscription_test {
+    use once_cell::sync::OnceCell;
+    use serde_json::json;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::application::usecase::event::hub::EventHub;
+    use crate::di::EventSubscriptionService;
+    use crate::ffi::rust_to_c_bridge::state_objects::MockGlobalState;
+
+    fn create_service(hub: &'static EventHub) -> EventSubscriptionService {
+        let mut state = MockGlobalState::new();
+        state.expect_event_hub().return_const(hub);
+        EventSubscriptionService::builder()
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn subscribe_poll_unsubscribe() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        let hub = HUB.get_or_init(EventHub::new);
+        let module = create_service(hub);
+        let service: &dyn Service = module.resolve_ref();
+
+        // DATAのイベントだけを購読する
+        let message = r#"{
+            "request_type": "EVENT",
+            "command": "SUBSCRIBE",
+            "params": {
+                "filter": {
+                    "request_type": "DATA"
+                }
+            }
+        }"#;
+        let result = service
+            .execute(RequestDto::from_str(message).unwrap())
+            .await
+            .unwrap();
+        let subscriber_id = match result {
+            ResponseDtoResult::Success(ResponseDto::Event(EventResponseDto::Subscribe(
+                SubscriberIdDto { subscriber_id },
+            ))) => subscriber_id,
+            _ => unreachable!(),
+        };
+
+        let data_event = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "EVENT",
+                "event": "OPEN",
+                "data_connection_id": "dc-4995f372-fb6a-4196-b30a-ce11e5c7f56c"
+            }
+        });
+        let peer_event = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "PEER",
+                "command": "EVENT",
+                "event": "TIMEOUT"
+            }
+        });
+        hub.publish(&peer_event);
+        hub.publish(&data_event);
+
+        let message = format!(
+            r#"{{
+                "request_type": "EVENT",
+                "command": "POLL",
+                "params": {{
+                    "subscriber_id": {}
+                }}
+            }}"#,
+            subscriber_id
+        );
+        let result = service
+            .execute(RequestDto::from_str(&message).unwrap())
+            .await
+            .unwrap();
+        let expected = ResponseDtoResult::Success(ResponseDto::Event(EventResponseDto::Poll(
+            PolledEventsDto {
+                subscriber_id,
+                events: vec![data_event],
+            },
+        )));
+        assert_eq!(result, expected);
+
+        let message = format!(
+            r#"{{
+                "request_type": "EVENT",
+                "command": "UNSUBSCRIBE",
+                "params": {{
+                    "subscriber_id": {}
+                }}
+            }}"#,
+            subscriber_id
+        );
+        let dto = RequestDto::from_str(&message).unwrap();
+        assert!(service.execute(dto.clone()).await.is_ok());
+
+        // 解除済みの購読者は見つからない
+        let result = service.execute(dto).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::NotFound);
+        let message = error.message();
+        assert_eq!(
+            message,
+            format!("subscriber {} is not found", subscriber_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn reserved_subscriber() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        let module = create_service(HUB.get_or_init(EventHub::new));
+        let service: &dyn Service = module.resolve_ref();
+
+        let message = r#"{
+            "request_type": "EVENT",
+            "command": "POLL",
+            "params": {
+                "subscriber_id": 0
+            }
+        }"#;
+        let result = service
+            .execute(RequestDto::from_str(message).unwrap())
+            .await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
+        let message = error.message();
+        assert_eq!(message, "subscriber 0 is reserved for receive_events");
+    }
+
+    #[tokio::test]
+    async fn invalid_parameter() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        let module = create_service(HUB.get_or_init(EventHub::new));
+        let service: &dyn Service = module.resolve_ref();
+
+        let result = service.execute(RequestDto::Test).await;
+        if let Err(error::Error::LocalError(message)) = result {
+            assert_eq!(message, "invalid parameter for Subscription");
+        } else {
+            unreachable!();
+        }
+    }
+}
@@ -83,7 +83,7 @@ mod create_peer_test {
                         "token":"pt-06cf1d26-0ef0-4b03-aca6-933027d434c2"
                     }
                 }"#;
-            ResponseResult::from_str(message)
+            ResponseResult::from_str(message).map_err(error::Error::from)
         });
 
         // サービスの生成
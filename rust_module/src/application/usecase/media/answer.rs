@@ -1,6 +1,7 @@
 // このサービスでは、End-User-Programの指示を受けて、MediaConnectionの確立要求を行う
 // 責務は以下の通りである
-// 1. GWにMedia Portを開放させる。これはVideo, Audioともに行う
+// 1. GWにMedia Portを開放させる。これはConstraintsで送信パラメータが指定されたVideo, Audioについてのみ行う
+//    以降の処理が失敗した場合は、開放したポートを逆順に全て削除してからエラーを返す
 // 2. CALL APIをコールし、MediaConnectionの確立を開始する
 //
 // WebRTC GWの仕様により、確立は受信側でAnswerが行われたタイミングである。
@@ -8,24 +9,26 @@
 // 実際にMediaConnectionが確立されたかどうか知るために、End-User-ProgramはCONNECT Eventを監視する必要がある
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use shaku::Component;
 
-use crate::application::dto::request::{ConstraintsDto, MediaRequestDto, RequestDto};
+use crate::application::deadline;
+use crate::application::dto::request::{AnswerParametersDto, MediaRequestDto, RequestDto};
 use crate::application::dto::response::{
-    CallResponseDto, MediaPair, MediaResponseDto, ResponseDto, ResponseDtoResult, SendParams,
+    CallResponseDto, MediaResponseDto, ResponseDto, ResponseDtoResult,
 };
 use crate::application::factory::Factory;
+use crate::application::usecase::compensation::Compensation;
+use crate::application::usecase::media::{create_constraint, create_send_params};
 use crate::application::usecase::Service;
-use crate::domain::entity::request::{AnswerParameters, IsVideo, MediaRequest, Request};
+use crate::domain::entity::request::{AnswerParameters, MediaRequest, Request};
 use crate::domain::entity::response::{MediaResponse, Response, ResponseResult};
-use crate::domain::entity::{
-    AnswerQuery, Constraints, MediaId, MediaParams, RedirectParameters, RtcpId, SerializableSocket,
-};
+use crate::domain::entity::AnswerQuery;
 use crate::domain::repository::Repository;
 use crate::error;
-use crate::ffi::rust_to_c_bridge::state_objects::GlobalState;
+use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState};
 
 #[derive(Component)]
 #[shaku(interface = Service)]
@@ -36,127 +39,25 @@ pub(crate) struct AnswerService {
     state: Arc<dyn GlobalState>,
     #[shaku(inject)]
     factory: Arc<dyn Factory>,
+    #[shaku(inject)]
+    callback: Arc<dyn CallbackFunctions>,
 }
 
 #[async_trait]
 impl Service for AnswerService {
     async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
         if let RequestDto::Media(MediaRequestDto::Answer { params }) = request {
-            let video_socket = {
-                let param = RequestDto::Media(MediaRequestDto::ContentCreate {
-                    params: IsVideo { is_video: true },
-                });
-                let service = self.factory.create_service(&param);
-                let result = service.execute(param).await?;
-                if let ResponseDtoResult::Success(ResponseDto::Media(
-                    MediaResponseDto::ContentCreate(socket),
-                )) = result
-                {
-                    socket
-                } else {
-                    let message = format!("create data failed {:?}", result);
-                    return Err(error::Error::create_local_error(&message));
-                }
-            };
-
-            let video_rtcp_socket = {
-                let param = RequestDto::Media(MediaRequestDto::RtcpCreate { params: None });
-                let service = self.factory.create_service(&param);
-                let result = service.execute(param).await?;
-                if let ResponseDtoResult::Success(ResponseDto::Media(
-                    MediaResponseDto::RtcpCreate(socket),
-                )) = result
-                {
-                    socket
-                } else {
-                    let message = format!("create data failed {:?}", result);
-                    return Err(error::Error::create_local_error(&message));
-                }
-            };
-
-            let audio_socket = {
-                let param = RequestDto::Media(MediaRequestDto::ContentCreate {
-                    params: IsVideo { is_video: false },
-                });
-                let service = self.factory.create_service(&param);
-                let result = service.execute(param).await?;
-                if let ResponseDtoResult::Success(ResponseDto::Media(
-                    MediaResponseDto::ContentCreate(socket),
-                )) = result
-                {
-                    socket
-                } else {
-                    let message = format!("create data failed {:?}", result);
-                    return Err(error::Error::create_local_error(&message));
-                }
+            // 開放したポートを記録し、途中で失敗した場合は逆順に削除する
+            let mut compensation = Compensation::new();
+            // 全体の期限を超えた場合も、確保したリソースを開放する
+            let timeout = Duration::from_millis(self.state.config().composite_timeout_ms);
+            let result = self.answer(params, &mut compensation);
+            return match deadline::within("MEDIA ANSWER", timeout, result).await {
+                Ok(result) => Ok(result),
+                Err(e) => Err(compensation
+                    .rollback(e, self.factory.as_ref(), self.callback.as_ref())
+                    .await),
             };
-
-            let audio_rtcp_socket = {
-                let param = RequestDto::Media(MediaRequestDto::RtcpCreate { params: None });
-                let service = self.factory.create_service(&param);
-                let result = service.execute(param).await?;
-                if let ResponseDtoResult::Success(ResponseDto::Media(
-                    MediaResponseDto::RtcpCreate(socket),
-                )) = result
-                {
-                    socket
-                } else {
-                    let message = format!("create data failed {:?}", result);
-                    return Err(error::Error::create_local_error(&message));
-                }
-            };
-
-            // Readyイベントでユーザに返すために保持
-            let send_params = SendParams {
-                video: MediaPair {
-                    media: video_socket.clone(),
-                    rtcp: video_rtcp_socket.clone(),
-                },
-                audio: MediaPair {
-                    media: audio_socket.clone(),
-                    rtcp: audio_rtcp_socket.clone(),
-                },
-            };
-            let redirect_params = params.answer_query.redirect_params.clone();
-            let constraints = create_constraint(
-                video_socket.get_id().unwrap(),
-                video_rtcp_socket.get_id().unwrap(),
-                audio_socket.get_id().unwrap(),
-                audio_rtcp_socket.get_id().unwrap(),
-                &Some(params.answer_query.constraints),
-                &params.answer_query.redirect_params,
-            );
-
-            let params = AnswerParameters {
-                media_connection_id: params.media_connection_id.clone(),
-                answer_query: AnswerQuery {
-                    constraints,
-                    redirect_params: redirect_params.clone(),
-                },
-            };
-            let request = Request::Media(MediaRequest::Answer { params });
-            let result = self.repository.register(request).await?;
-            match result {
-                ResponseResult::Success(Response::Media(MediaResponse::Answer(answer_result))) => {
-                    let call_response = CallResponseDto {
-                        send_params,
-                        redirect_params,
-                        media_connection_id: answer_result.media_connection_id.clone(),
-                    };
-                    self.state.store_call_response(
-                        answer_result.media_connection_id.clone(),
-                        call_response,
-                    );
-
-                    return Ok(ResponseDtoResult::Success(ResponseDto::Media(
-                        MediaResponseDto::Answer(answer_result),
-                    )));
-                }
-                ResponseResult::Error(message) => return Ok(ResponseDtoResult::Error(message)),
-                _ => {
-                    unreachable!()
-                }
-            }
         }
 
         return Err(error::Error::create_local_error(
@@ -165,107 +66,100 @@ impl Service for AnswerService {
     }
 }
 
-pub(crate) fn create_constraint(
-    video_id: MediaId,
-    video_rtcp_id: RtcpId,
-    audio_id: MediaId,
-    audio_rtcp_id: RtcpId,
-    constraint_dto: &Option<ConstraintsDto>,
-    redirect_params: &Option<RedirectParameters>,
-) -> Constraints {
-    let video_receive_enabled = if let Some(RedirectParameters {
-        video: Some(ref _video),
-        ..
-    }) = redirect_params
-    {
-        Some(true)
-    } else {
-        None
-    };
-
-    let audio_receive_enabled = if let Some(RedirectParameters {
-        audio: Some(ref _audio),
-        ..
-    }) = redirect_params
-    {
-        Some(true)
-    } else {
-        None
-    };
-
-    let video_params = if let Some(ConstraintsDto {
-        video_params: Some(ref params),
-        ..
-    }) = constraint_dto
-    {
-        Some(MediaParams {
-            band_width: params.band_width,
-            codec: params.codec.clone(),
-            media_id: video_id,
-            rtcp_id: Some(video_rtcp_id),
-            payload_type: params.payload_type,
-            sampling_rate: params.sampling_rate,
-        })
-    } else {
-        None
-    };
-
-    let audio_params = if let Some(ConstraintsDto {
-        audio_params: Some(ref params),
-        ..
-    }) = constraint_dto
-    {
-        Some(MediaParams {
-            band_width: params.band_width,
-            codec: params.codec.clone(),
-            media_id: audio_id,
-            rtcp_id: Some(audio_rtcp_id),
-            payload_type: params.payload_type,
-            sampling_rate: params.sampling_rate,
-        })
-    } else {
-        None
-    };
-
-    let metadata = if let Some(ConstraintsDto {
-        metadata: Some(ref metadata),
-        ..
-    }) = constraint_dto
-    {
-        Some(metadata.clone())
-    } else {
-        None
-    };
+impl AnswerService {
+    async fn answer(
+        &self,
+        params: AnswerParametersDto,
+        compensation: &mut Compensation,
+    ) -> Result<ResponseDtoResult, error::Error> {
+        // 送信を要求された種類のMediaのポートだけを開放する
+        // Readyイベントでユーザに返すために保持
+        let constraints_dto = Some(params.answer_query.constraints);
+        let send_params =
+            create_send_params(self.factory.as_ref(), &constraints_dto, compensation).await?;
+        let redirect_params = params.answer_query.redirect_params.clone();
+        let constraints = create_constraint(
+            &send_params,
+            &constraints_dto,
+            &params.answer_query.redirect_params,
+        );
+
+        let params = AnswerParameters {
+            media_connection_id: params.media_connection_id.clone(),
+            answer_query: AnswerQuery {
+                constraints,
+                redirect_params: redirect_params.clone(),
+            },
+        };
+        let request = Request::Media(MediaRequest::Answer { params });
+        let result = self
+            .repository
+            .register(request)
+            .await
+            .map_err(|e| e.with_step("MEDIA ANSWER"))?;
+        match result {
+            ResponseResult::Success(Response::Media(MediaResponse::Answer(answer_result))) => {
+                let call_response = CallResponseDto {
+                    send_params,
+                    redirect_params,
+                    media_connection_id: answer_result.media_connection_id.clone(),
+                };
+                self.state
+                    .store_call_response(call_response.media_connection_id.clone(), call_response);
 
-    Constraints {
-        video: true,
-        videoReceiveEnabled: video_receive_enabled,
-        audio: true,
-        audioReceiveEnabled: audio_receive_enabled,
-        video_params,
-        audio_params,
-        metadata: metadata,
+                Ok(ResponseDtoResult::Success(ResponseDto::Media(
+                    MediaResponseDto::Answer(answer_result),
+                )))
+            }
+            ResponseResult::Error(message) => {
+                Err(error::Error::create_gateway_error(&message).with_step("MEDIA ANSWER"))
+            }
+            result => {
+                let message = format!("unexpected response for ANSWER: {:?}", result);
+                Err(error::Error::create_local_error(&message))
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod answer_media_test {
+    use once_cell::sync::OnceCell;
     use shaku::HasComponent;
 
     use super::*;
     use crate::application::dto::request::{
-        AnswerParametersDto, AnswerQueryDto, ConstraintsDto, MediaRequestDto,
+        AnswerParametersDto, AnswerQueryDto, ConstraintsDto, MediaParamsDto, MediaRequestDto,
     };
-    use crate::application::dto::response::CallResponseDto;
+    use crate::application::dto::response::{CallResponseDto, MediaPair, SendParams};
     use crate::application::factory::MockFactory;
     use crate::application::usecase::MockService;
+    use crate::config::Config;
     use crate::di::MediaAnswerService;
     use crate::domain::entity::request::{MediaRequest, Request};
     use crate::domain::entity::response::ResponseResult;
-    use crate::domain::entity::{AnswerResult, MediaConnectionId, SocketInfo};
+    use crate::domain::entity::{
+        AnswerResult, MediaConnectionId, MediaId, RtcpId, SerializableSocket, SocketInfo,
+    };
     use crate::domain::repository::MockRepository;
+    use crate::error::ErrorCode;
     use crate::ffi::rust_to_c_bridge::state_objects::MockGlobalState;
 
+    // Video, Audioの両方の送信パラメータを持つConstraintsDtoを生成する
+    fn create_constraints_dto() -> ConstraintsDto {
+        let params = |codec: &str| MediaParamsDto {
+            band_width: 1500,
+            codec: codec.to_string(),
+            payload_type: None,
+            sampling_rate: None,
+        };
+        ConstraintsDto {
+            video_params: Some(params("H264")),
+            audio_params: Some(params("OPUS")),
+            metadata: None,
+        }
+    }
+
     #[tokio::test]
     async fn success() {
         // 正解データの生成
@@ -304,7 +198,10 @@ mod answer_media_test {
                     MediaPair { media, rtcp }
                 };
 
-                SendParams { video, audio }
+                SendParams {
+                    video: Some(video),
+                    audio: Some(audio),
+                }
             };
 
             let media_connection_id =
@@ -340,16 +237,17 @@ mod answer_media_test {
         let params = AnswerParametersDto {
             media_connection_id: dto.media_connection_id.clone(),
             answer_query: AnswerQueryDto {
-                constraints: ConstraintsDto {
-                    video_params: None,
-                    audio_params: None,
-                    metadata: None,
-                },
+                constraints: create_constraints_dto(),
                 redirect_params: None,
             },
         };
 
+        static CONFIG: OnceCell<Config> = OnceCell::new();
         let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(|| CONFIG.get_or_init(Config::default));
         state
             .expect_store_call_response()
             .times(1)
@@ -422,4 +320,182 @@ mod answer_media_test {
 
         assert_eq!(result.unwrap(), answer);
     }
+
+    #[tokio::test]
+    // Audioポートの開放に失敗した場合は、開放済みのVideo, Video RTCPポートを削除してエラーを返す
+    async fn create_socket_failed() {
+        // ANSWERまで到達しない
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(0)
+            .returning(|_| unreachable!());
+
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(|| CONFIG.get_or_init(Config::default));
+        state
+            .expect_store_call_response()
+            .times(0)
+            .returning(|_, _| unreachable!());
+
+        // Video, Video RTCP, Audioのポートの開放と、2つのポートの削除で5回呼ばれる
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().times(5).returning(|_| {
+            let mut mock_service = MockService::new();
+            mock_service
+                .expect_execute()
+                .returning(|request| match request {
+                    RequestDto::Media(MediaRequestDto::ContentCreate { params }) => {
+                        let (media_id, port) = match params.is_video {
+                            true => ("vi-06cf1d26-0ef0-4b03-aca6-933027d434c2", 10000),
+                            false => return Err(error::Error::create_local_error("no more ports")),
+                        };
+                        let socket = SocketInfo::<MediaId>::try_create(
+                            Some(media_id.to_string()),
+                            "127.0.0.1",
+                            port,
+                        )
+                        .unwrap();
+                        Ok(ResponseDtoResult::Success(ResponseDto::Media(
+                            MediaResponseDto::ContentCreate(socket),
+                        )))
+                    }
+                    RequestDto::Media(MediaRequestDto::RtcpCreate { params: _ }) => {
+                        let socket = SocketInfo::<RtcpId>::try_create(
+                            Some("rc-06cf1d26-0ef0-4b03-aca6-933027d434c2".to_string()),
+                            "127.0.0.1",
+                            10010,
+                        )
+                        .unwrap();
+                        Ok(ResponseDtoResult::Success(ResponseDto::Media(
+                            MediaResponseDto::RtcpCreate(socket),
+                        )))
+                    }
+                    // 失敗時には開放したポートが削除される
+                    RequestDto::Media(MediaRequestDto::ContentDelete { params }) => {
+                        Ok(ResponseDtoResult::Success(ResponseDto::Media(
+                            MediaResponseDto::ContentDelete(params),
+                        )))
+                    }
+                    RequestDto::Media(MediaRequestDto::RtcpDelete { params }) => {
+                        Ok(ResponseDtoResult::Success(ResponseDto::Media(
+                            MediaResponseDto::RtcpDelete(params),
+                        )))
+                    }
+                    _ => {
+                        unreachable!()
+                    }
+                });
+            Arc::new(mock_service)
+        });
+
+        let module = MediaAnswerService::builder()
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+
+        let params = AnswerParametersDto {
+            media_connection_id: MediaConnectionId::try_create(
+                "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+            )
+            .unwrap(),
+            answer_query: AnswerQueryDto {
+                constraints: create_constraints_dto(),
+                redirect_params: None,
+            },
+        };
+        let result = service
+            .execute(RequestDto::Media(MediaRequestDto::Answer { params }))
+            .await;
+
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.step(), Some("MEDIA CONTENT_CREATE"));
+        let message = error.message();
+        assert_eq!(
+            message,
+            "no more ports. rolled back: [\
+             MEDIA RTCP_DELETE rc-06cf1d26-0ef0-4b03-aca6-933027d434c2, \
+             MEDIA CONTENT_DELETE vi-06cf1d26-0ef0-4b03-aca6-933027d434c2]"
+        );
+    }
+
+    #[tokio::test]
+    // 送信パラメータが指定されない場合は受信専用となり、ポートを開放しない
+    async fn recv_only() {
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(1)
+            .returning(|request| match request {
+                Request::Media(MediaRequest::Answer { params }) => {
+                    let constraints = params.answer_query.constraints;
+                    assert!(!constraints.video);
+                    assert!(!constraints.audio);
+                    Ok(ResponseResult::Success(Response::Media(
+                        MediaResponse::Answer(AnswerResult {
+                            media_connection_id: params.media_connection_id,
+                            send_sockets: None,
+                            recv_sockets: None,
+                        }),
+                    )))
+                }
+                _ => unreachable!(),
+            });
+
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(|| CONFIG.get_or_init(Config::default));
+        state
+            .expect_store_call_response()
+            .times(1)
+            .returning(|_, response| {
+                assert_eq!(
+                    response.send_params,
+                    SendParams {
+                        video: None,
+                        audio: None
+                    }
+                );
+            });
+
+        // ポートを開放しないので呼ばれない
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().times(0);
+
+        let module = MediaAnswerService::builder()
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+
+        let params = AnswerParametersDto {
+            media_connection_id: MediaConnectionId::try_create(
+                "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+            )
+            .unwrap(),
+            answer_query: AnswerQueryDto {
+                constraints: ConstraintsDto {
+                    video_params: None,
+                    audio_params: None,
+                    metadata: None,
+                },
+                redirect_params: None,
+            },
+        };
+        let result = service
+            .execute(RequestDto::Media(MediaRequestDto::Answer { params }))
+            .await;
+        assert!(result.is_ok());
+    }
 }
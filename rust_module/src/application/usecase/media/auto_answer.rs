@@ -0,0 +1,284 @@
+// MEDIA POLICYが設定されている場合に、着信したMediaConnectionを外部からのMEDIA ANSWERを待たずに処理する
+// 責務は以下の通りである
+// 1. 発信側のpeer_idとmetadataから、policyの規則を先頭から順に評価する
+// 2. 一致した規則があれば、その規則のanswer_queryでAnswerServiceを実行する
+// 3. 一致する規則がない場合や、ANSWERに失敗した場合はMediaConnectionを切断する
+//
+// policyが設定されていない場合は何もせず、従来通り外部からのMEDIA ANSWERを待つ
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::{Component, Interface};
+
+use crate::application::dto::request::{AnswerParametersDto, MediaRequestDto, RequestDto};
+use crate::application::dto::response::{ErrorDto, PolicyResultDto, ResponseDtoResult};
+use crate::application::factory::Factory;
+use crate::domain::entity::{MediaConnectionId, MediaConnectionIdWrapper, MediaConnectionStatus};
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::{GlobalState, Logger};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub(crate) trait MediaAutoAnswer: Interface {
+    /// policyに従ってMediaConnectionを処理し、その結果を返す
+    /// policyが設定されていない場合はNoneを返す
+    async fn apply(
+        &self,
+        media_connection_id: &MediaConnectionId,
+        status: &MediaConnectionStatus,
+    ) -> Option<PolicyResultDto>;
+}
+
+#[derive(Component)]
+#[shaku(interface = MediaAutoAnswer)]
+pub(crate) struct MediaAutoAnswerImpl {
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+    #[shaku(inject)]
+    factory: Arc<dyn Factory>,
+    #[shaku(inject)]
+    logger: Arc<dyn Logger>,
+}
+
+#[async_trait]
+impl MediaAutoAnswer for MediaAutoAnswerImpl {
+    async fn apply(
+        &self,
+        media_connection_id: &MediaConnectionId,
+        status: &MediaConnectionStatus,
+    ) -> Option<PolicyResultDto> {
+        let policy = self.state.media_policy()?;
+        let remote_id = status.remote_id.as_str();
+
+        let result = match policy.find_rule(remote_id, &status.metadata) {
+            Some((index, rule)) => {
+                let request = RequestDto::Media(MediaRequestDto::Answer {
+                    params: AnswerParametersDto {
+                        media_connection_id: media_connection_id.clone(),
+                        answer_query: rule.answer_query.clone(),
+                    },
+                });
+                match self.execute(request).await {
+                    Ok(()) => {
+                        let message = format!(
+                            "MediaConnection {} from {} is answered by rule {}",
+                            media_connection_id.as_str(),
+                            remote_id,
+                            index
+                        );
+                        self.logger.info(&message);
+                        return Some(PolicyResultDto::Accepted { rule: index });
+                    }
+                    Err(e) => PolicyResultDto::Failed {
+                        rule: index,
+                        error: ErrorDto::from(&e),
+                    },
+                }
+            }
+            None => PolicyResultDto::Rejected,
+        };
+
+        let message = format!(
+            "MediaConnection {} from {} is closed by policy: {}",
+            media_connection_id.as_str(),
+            remote_id,
+            result
+        );
+        self.logger.warn(&message);
+
+        let request = RequestDto::Media(MediaRequestDto::Disconnect {
+            params: MediaConnectionIdWrapper {
+                media_connection_id: media_connection_id.clone(),
+            },
+        });
+        if let Err(e) = self.execute(request).await {
+            let message = format!(
+                "failed to disconnect MediaConnection {}: {} ({})",
+                media_connection_id.as_str(),
+                e.message(),
+                e.code()
+            );
+            self.logger.error(&message);
+        }
+
+        Some(result)
+    }
+}
+
+impl MediaAutoAnswerImpl {
+    async fn execute(&self, request: RequestDto) -> Result<(), error::Error> {
+        let service = self.factory.create_service(&request);
+        match service.execute(request).await? {
+            ResponseDtoResult::Success(_) => Ok(()),
+            ResponseDtoResult::Error(error) => Err(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod media_auto_answer_test {
+    use std::sync::Mutex;
+
+    use serde_json::json;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::application::dto::request::MediaPolicyDto;
+    use crate::application::dto::response::{MediaResponseDto, ResponseDto};
+    use crate::application::factory::MockFactory;
+    use crate::application::usecase::MockService;
+    use crate::di::MediaAutoAnswerModule;
+    use crate::ffi::rust_to_c_bridge::state_objects::{MockGlobalState, MockLogger};
+
+    const MEDIA_CONNECTION_ID: &str = "mc-102127d9-30de-413b-93f7-41a33e39d82b";
+
+    fn policy() -> MediaPolicyDto {
+        serde_json::from_value(json!({
+            "rules": [{
+                "peer_id": {"prefix": "camera_"},
+                "answer_query": {
+                    "constraints": {},
+                    "redirect_params": {
+                        "video": {"ip_v4": "127.0.0.1", "port": 20000}
+                    }
+                }
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn status(remote_id: &str) -> MediaConnectionStatus {
+        serde_json::from_value(json!({
+            "metadata": "",
+            "open": false,
+            "remote_id": remote_id
+        }))
+        .unwrap()
+    }
+
+    // 受け取ったリクエストを記録し、ANSWERにはanswer_resultの結果を返すFactoryを生成する
+    fn factory(
+        answer_result: Result<(), String>,
+        requests: Arc<Mutex<Vec<RequestDto>>>,
+    ) -> MockFactory {
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().returning(move |_| {
+            let answer_result = answer_result.clone();
+            let requests = requests.clone();
+            let mut service = MockService::new();
+            service.expect_execute().returning(move |request| {
+                requests.lock().unwrap().push(request.clone());
+                match request {
+                    RequestDto::Media(MediaRequestDto::Answer { .. }) => answer_result
+                        .clone()
+                        .map(|_| {
+                            ResponseDtoResult::Success(ResponseDto::Media(
+                                MediaResponseDto::Disconnect(None),
+                            ))
+                        })
+                        .map_err(|message| error::Error::create_local_error(&message)),
+                    RequestDto::Media(MediaRequestDto::Disconnect { .. }) => {
+                        Ok(ResponseDtoResult::Success(ResponseDto::Media(
+                            MediaResponseDto::Disconnect(None),
+                        )))
+                    }
+                    _ => unreachable!(),
+                }
+            });
+            Arc::new(service)
+        });
+        factory
+    }
+
+    async fn apply(
+        policy: Option<MediaPolicyDto>,
+        factory: MockFactory,
+        remote_id: &str,
+    ) -> Option<PolicyResultDto> {
+        let mut state = MockGlobalState::new();
+        state.expect_media_policy().return_const(policy);
+        let mut logger = MockLogger::new();
+        logger.expect_info().returning(|_| ());
+        logger.expect_warn().returning(|_| ());
+        logger.expect_error().returning(|_| ());
+
+        let module = MediaAutoAnswerModule::builder()
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn Factory>(Box::new(factory))
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .build();
+        let auto_answer: &dyn MediaAutoAnswer = module.resolve_ref();
+        let media_connection_id = MediaConnectionId::try_create(MEDIA_CONNECTION_ID).unwrap();
+        auto_answer
+            .apply(&media_connection_id, &status(remote_id))
+            .await
+    }
+
+    #[tokio::test]
+    async fn answered() {
+        // 一致した規則のanswer_queryでANSWERする
+        let requests = Arc::new(Mutex::new(vec![]));
+        let factory = factory(Ok(()), requests.clone());
+        let result = apply(Some(policy()), factory, "camera_1").await;
+        assert_eq!(result, Some(PolicyResultDto::Accepted { rule: 0 }));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        if let RequestDto::Media(MediaRequestDto::Answer { params }) = &requests[0] {
+            assert_eq!(params.media_connection_id.as_str(), MEDIA_CONNECTION_ID);
+            assert_eq!(params.answer_query, policy().rules[0].answer_query);
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected() {
+        // どの規則にも一致しない場合は、ANSWERせずに切断する
+        let requests = Arc::new(Mutex::new(vec![]));
+        let factory = factory(Ok(()), requests.clone());
+        let result = apply(Some(policy()), factory, "unknown").await;
+        assert_eq!(result, Some(PolicyResultDto::Rejected));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(matches!(
+            requests[0],
+            RequestDto::Media(MediaRequestDto::Disconnect { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn answer_failed() {
+        // ANSWERに失敗した場合は切断する
+        let requests = Arc::new(Mutex::new(vec![]));
+        let factory = factory(Err("port is in use".to_string()), requests.clone());
+        let result = apply(Some(policy()), factory, "camera_1").await;
+        assert_eq!(
+            result,
+            Some(PolicyResultDto::Failed {
+                rule: 0,
+                error: ErrorDto {
+                    code: error::ErrorCode::Internal,
+                    message: "port is in use".to_string(),
+                    step: None,
+                    gateway_error: None,
+                }
+            })
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn no_policy() {
+        // policyが設定されていなければ何もしない
+        let mut factory = MockFactory::new();
+        factory.expect_create_service().times(0);
+        let result = apply(None, factory, "camera_1").await;
+        assert_eq!(result, None);
+    }
+}
@@ -0,0 +1,135 @@
+// このサービスでは、End-User-Programの指示を受けて、MediaConnectionを切断する
+// 責務は以下の通りである
+// 1. DISCONNECT APIをコールし、MediaConnectionを切断する
+// 2. 切断に成功した場合は、CALL, ANSWER時に確保したリソースを開放する
+//
+// 切断後にWebRTC GatewayからCLOSEイベントが届くが、リソースは既に開放済みのため二重には開放されない
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::Component;
+
+use crate::application::dto;
+use crate::application::dto::request::{MediaRequestDto, RequestDto};
+use crate::application::dto::response::ResponseDtoResult;
+use crate::application::usecase::media::lifecycle::MediaLifecycle;
+use crate::application::usecase::Service;
+use crate::domain::entity::request::{MediaRequest, Request};
+use crate::domain::entity::response::ResponseResult;
+use crate::domain::repository::Repository;
+use crate::error;
+
+#[derive(Component)]
+#[shaku(interface = Service)]
+pub(crate) struct DisconnectService {
+    #[shaku(inject)]
+    repository: Arc<dyn Repository>,
+    #[shaku(inject)]
+    lifecycle: Arc<dyn MediaLifecycle>,
+}
+
+#[async_trait]
+impl Service for DisconnectService {
+    async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
+        if let RequestDto::Media(MediaRequestDto::Disconnect { params }) = request {
+            let media_connection_id = params.media_connection_id.clone();
+            let request = Request::Media(MediaRequest::Disconnect { params });
+            return match self.repository.register(request).await? {
+                ResponseResult::Success(response) => {
+                    self.lifecycle.release(&media_connection_id).await;
+                    dto::result_to_dto(ResponseResult::Success(response))
+                }
+                ResponseResult::Error(message) => Err(error::Error::create_gateway_error(&message)),
+            };
+        }
+
+        return Err(error::Error::create_local_error(
+            "invalid message in disconnect service",
+        ));
+    }
+}
+
+#[cfg(test)]
+mod disconnect_media_test {
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::application::dto::response::{MediaResponseDto, ResponseDto};
+    use crate::application::usecase::media::lifecycle::MockMediaLifecycle;
+    use crate::di::MediaDisconnectService;
+    use crate::domain::entity::response::{MediaResponse, Response};
+    use crate::domain::entity::{MediaConnectionId, MediaConnectionIdWrapper};
+    use crate::domain::repository::MockRepository;
+    use crate::error::ErrorCode;
+
+    fn create_request() -> RequestDto {
+        RequestDto::Media(MediaRequestDto::Disconnect {
+            params: MediaConnectionIdWrapper {
+                media_connection_id: MediaConnectionId::try_create(
+                    "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+                )
+                .unwrap(),
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn success() {
+        let mut repository = MockRepository::new();
+        repository.expect_register().times(1).returning(|_| {
+            Ok(ResponseResult::Success(Response::Media(
+                MediaResponse::Disconnect(None),
+            )))
+        });
+
+        // 切断に成功したMediaConnectionのリソースが開放される
+        let mut lifecycle = MockMediaLifecycle::new();
+        lifecycle
+            .expect_release()
+            .times(1)
+            .returning(|media_connection_id| {
+                assert_eq!(
+                    media_connection_id.as_str(),
+                    "mc-102127d9-30de-413b-93f7-41a33e39d82b"
+                );
+                None
+            });
+
+        let module = MediaDisconnectService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn MediaLifecycle>(Box::new(lifecycle))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+        let result = service.execute(create_request()).await.unwrap();
+        assert_eq!(
+            result,
+            ResponseDtoResult::Success(ResponseDto::Media(MediaResponseDto::Disconnect(None)))
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnect_failed() {
+        let mut repository = MockRepository::new();
+        repository.expect_register().times(1).returning(|_| {
+            Ok(ResponseResult::Error(
+                "media_connection_id is not found".to_string(),
+            ))
+        });
+
+        // 切断に失敗した場合はリソースを開放しない
+        let mut lifecycle = MockMediaLifecycle::new();
+        lifecycle.expect_release().times(0);
+
+        let module = MediaDisconnectService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn MediaLifecycle>(Box::new(lifecycle))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+        let result = service.execute(create_request()).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::GatewayRejected);
+        let message = error.message();
+        assert_eq!(message, "media_connection_id is not found");
+    }
+}
@@ -0,0 +1,315 @@
+// MediaConnectionの終了時に、CALL, ANSWER時に確保したリソースを開放する
+// 責務は以下の通りである
+// 1. CALL, ANSWER時に保存したCallResponseDtoを削除する
+// 2. CallResponseDtoに含まれるMedia, RTCPポートを削除する
+// 3. 開放が完了したことをRELEASEDイベントとして購読者に配信する
+//
+// CLOSEイベントとMEDIA DISCONNECTの両方から呼ばれるため、同じMediaConnectionに対して複数回呼ばれることがある。
+// 保存した情報が既に削除されている場合は何もしない
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::{Component, Interface};
+
+use crate::application::dto::response::{
+    MediaConnectionEventEnumDto, MediaConnectionReleasedEventDto, MediaResponseDto, ResponseDto,
+    ResponseDtoResult,
+};
+use crate::domain::entity::request::{MediaRequest, Request};
+use crate::domain::entity::response::ResponseResult;
+use crate::domain::entity::{
+    MediaConnectionId, MediaIdWrapper, RtcpIdWrapper, SerializableId, SerializableSocket,
+};
+use crate::domain::repository::Repository;
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::{GlobalState, Logger};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub(crate) trait MediaLifecycle: Interface {
+    /// MediaConnectionに紐づくリソースを開放し、配信したRELEASEDイベントの内容を返す
+    /// 既に開放済みの場合はNoneを返す
+    async fn release(
+        &self,
+        media_connection_id: &MediaConnectionId,
+    ) -> Option<MediaConnectionReleasedEventDto>;
+}
+
+#[derive(Component)]
+#[shaku(interface = MediaLifecycle)]
+pub(crate) struct MediaLifecycleImpl {
+    #[shaku(inject)]
+    repository: Arc<dyn Repository>,
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+    #[shaku(inject)]
+    logger: Arc<dyn Logger>,
+}
+
+#[async_trait]
+impl MediaLifecycle for MediaLifecycleImpl {
+    async fn release(
+        &self,
+        media_connection_id: &MediaConnectionId,
+    ) -> Option<MediaConnectionReleasedEventDto> {
+        self.state.detach_media_connection(media_connection_id);
+        let call_response = self.state.remove_call_response(media_connection_id)?;
+
+        // 削除に失敗したポートがあっても、残りのポートの削除は続行する
+        let mut released = vec![];
+        let mut errors = vec![];
+        let pairs = [
+            call_response.send_params.video,
+            call_response.send_params.audio,
+        ];
+        for pair in pairs.into_iter().flatten() {
+            let media_id = pair.media.get_id().unwrap();
+            let rtcp_id = pair.rtcp.get_id().unwrap();
+            let requests = [
+                (
+                    media_id.as_str().to_string(),
+                    MediaRequest::ContentDelete {
+                        params: MediaIdWrapper { media_id },
+                    },
+                ),
+                (
+                    rtcp_id.as_str().to_string(),
+                    MediaRequest::RtcpDelete {
+                        params: RtcpIdWrapper { rtcp_id },
+                    },
+                ),
+            ];
+            for (id, request) in requests {
+                match self.delete(request).await {
+                    Ok(_) => released.push(id),
+                    Err(e) => errors.push(format!("{}: {}", id, e.message())),
+                }
+            }
+        }
+
+        let event = MediaConnectionReleasedEventDto {
+            media_connection_id: media_connection_id.clone(),
+            released,
+            errors,
+        };
+        if !event.errors.is_empty() {
+            let message = format!(
+                "failed to release MediaConnection {}: {:?}",
+                media_connection_id.as_str(),
+                event.errors
+            );
+            self.logger.warn(&message);
+        }
+
+        let result = ResponseDtoResult::Success(ResponseDto::Media(MediaResponseDto::Event(
+            MediaConnectionEventEnumDto::Released(event.clone()),
+        )));
+        self.state
+            .event_hub()
+            .publish(&serde_json::to_value(&result).unwrap());
+
+        Some(event)
+    }
+}
+
+impl MediaLifecycleImpl {
+    async fn delete(&self, request: MediaRequest) -> Result<(), error::Error> {
+        match self.repository.register(Request::Media(request)).await? {
+            ResponseResult::Success(_) => Ok(()),
+            ResponseResult::Error(message) => Err(error::Error::create_gateway_error(&message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod media_lifecycle_test {
+    use std::time::Duration;
+
+    use once_cell::sync::OnceCell;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::application::dto::response::{CallResponseDto, MediaPair, SendParams};
+    use crate::application::usecase::event::hub::{EventFilter, EventHub};
+    use crate::di::MediaLifecycleModule;
+    use crate::domain::entity::response::{MediaResponse, Response};
+    use crate::domain::entity::{MediaId, RtcpId, SocketInfo};
+    use crate::domain::repository::MockRepository;
+    use crate::ffi::rust_to_c_bridge::state_objects::{MockGlobalState, MockLogger};
+
+    fn create_call_response() -> CallResponseDto {
+        let pair = |media_id: &str, rtcp_id: &str| MediaPair {
+            media: SocketInfo::<MediaId>::try_create(
+                Some(media_id.to_string()),
+                "127.0.0.1",
+                10000,
+            )
+            .unwrap(),
+            rtcp: SocketInfo::<RtcpId>::try_create(Some(rtcp_id.to_string()), "127.0.0.1", 10001)
+                .unwrap(),
+        };
+        CallResponseDto {
+            send_params: SendParams {
+                video: Some(pair(
+                    "vi-4d053831-5dc2-461b-a358-d062d6115216",
+                    "rc-4d053831-5dc2-461b-a358-d062d6115216",
+                )),
+                audio: Some(pair(
+                    "au-4d053831-5dc2-461b-a358-d062d6115216",
+                    "rc-5d053831-5dc2-461b-a358-d062d6115216",
+                )),
+            },
+            redirect_params: None,
+            media_connection_id: MediaConnectionId::try_create(
+                "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+            )
+            .unwrap(),
+        }
+    }
+
+    fn create_module(repository: MockRepository, state: MockGlobalState) -> MediaLifecycleModule {
+        let mut logger = MockLogger::new();
+        logger.expect_warn().returning(|_| ());
+        MediaLifecycleModule::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn release_all_sockets() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        let hub = HUB.get_or_init(EventHub::new);
+        let subscriber_id = hub.subscribe(EventFilter::default(), 10);
+
+        // 4つのポートが全て削除される
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(4)
+            .returning(|request| match request {
+                Request::Media(MediaRequest::ContentDelete { params }) => Ok(
+                    ResponseResult::Success(Response::Media(MediaResponse::ContentDelete(params))),
+                ),
+                Request::Media(MediaRequest::RtcpDelete { params }) => Ok(ResponseResult::Success(
+                    Response::Media(MediaResponse::RtcpDelete(params)),
+                )),
+                _ => unreachable!(),
+            });
+
+        let mut state = MockGlobalState::new();
+        state
+            .expect_detach_media_connection()
+            .times(1)
+            .return_const(());
+        state
+            .expect_remove_call_response()
+            .times(1)
+            .returning(|_| Some(create_call_response()));
+        state.expect_event_hub().return_const(hub);
+
+        let module = create_module(repository, state);
+        let lifecycle: &dyn MediaLifecycle = module.resolve_ref();
+        let media_connection_id =
+            MediaConnectionId::try_create("mc-102127d9-30de-413b-93f7-41a33e39d82b").unwrap();
+        let event = lifecycle.release(&media_connection_id).await.unwrap();
+        assert_eq!(
+            event.released,
+            vec![
+                "vi-4d053831-5dc2-461b-a358-d062d6115216",
+                "rc-4d053831-5dc2-461b-a358-d062d6115216",
+                "au-4d053831-5dc2-461b-a358-d062d6115216",
+                "rc-5d053831-5dc2-461b-a358-d062d6115216",
+            ]
+        );
+        assert!(event.errors.is_empty());
+
+        // RELEASEDイベントが配信されている
+        let events = hub
+            .poll(subscriber_id, 10, Duration::from_millis(0))
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["result"]["event"], "RELEASED");
+        assert_eq!(
+            events[0]["result"]["media_connection_id"],
+            "mc-102127d9-30de-413b-93f7-41a33e39d82b"
+        );
+    }
+
+    #[tokio::test]
+    async fn report_delete_errors() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        let hub = HUB.get_or_init(EventHub::new);
+
+        // Videoのポートの削除に失敗しても、Audioのポートは削除される
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(4)
+            .returning(|request| match request {
+                Request::Media(MediaRequest::ContentDelete { params })
+                    if params.media_id.as_str().starts_with("vi") =>
+                {
+                    Ok(ResponseResult::Error("media not found".to_string()))
+                }
+                Request::Media(MediaRequest::ContentDelete { params }) => Ok(
+                    ResponseResult::Success(Response::Media(MediaResponse::ContentDelete(params))),
+                ),
+                Request::Media(MediaRequest::RtcpDelete { params }) => Ok(ResponseResult::Success(
+                    Response::Media(MediaResponse::RtcpDelete(params)),
+                )),
+                _ => unreachable!(),
+            });
+
+        let mut state = MockGlobalState::new();
+        state
+            .expect_detach_media_connection()
+            .times(1)
+            .return_const(());
+        state
+            .expect_remove_call_response()
+            .times(1)
+            .returning(|_| Some(create_call_response()));
+        state.expect_event_hub().return_const(hub);
+
+        let module = create_module(repository, state);
+        let lifecycle: &dyn MediaLifecycle = module.resolve_ref();
+        let media_connection_id =
+            MediaConnectionId::try_create("mc-102127d9-30de-413b-93f7-41a33e39d82b").unwrap();
+        let event = lifecycle.release(&media_connection_id).await.unwrap();
+        assert_eq!(event.released.len(), 3);
+        assert_eq!(
+            event.errors,
+            vec!["vi-4d053831-5dc2-461b-a358-d062d6115216: media not found".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn already_released() {
+        // 保存した情報がない場合は、ポートの削除もイベントの配信も行わない
+        let mut repository = MockRepository::new();
+        repository.expect_register().times(0);
+        let mut state = MockGlobalState::new();
+        state
+            .expect_detach_media_connection()
+            .times(1)
+            .return_const(());
+        state
+            .expect_remove_call_response()
+            .times(1)
+            .returning(|_| None);
+        state.expect_event_hub().times(0);
+
+        let module = create_module(repository, state);
+        let lifecycle: &dyn MediaLifecycle = module.resolve_ref();
+        let media_connection_id =
+            MediaConnectionId::try_create("mc-102127d9-30de-413b-93f7-41a33e39d82b").unwrap();
+        assert!(lifecycle.release(&media_connection_id).await.is_none());
+    }
+}
@@ -1,3 +1,184 @@
 /// /media系のAPIのうち、特別な内部処理を必要とするものはここで実装する
 pub(crate) mod answer;
+pub(crate) mod auto_answer;
 pub(crate) mod call;
+pub(crate) mod disconnect;
+pub(crate) mod lifecycle;
+pub(crate) mod policy;
+
+use crate::application::dto::request::{
+    ConstraintsDto, MediaParamsDto, MediaRequestDto, RequestDto,
+};
+use crate::application::dto::response::{MediaPair, MediaResponseDto, ResponseDto, SendParams};
+use crate::application::factory::Factory;
+use crate::application::usecase::compensation::{Compensation, CompensationStep};
+use crate::application::usecase::execute_step;
+use crate::domain::entity::request::IsVideo;
+use crate::domain::entity::{
+    Constraints, MediaId, MediaIdWrapper, MediaParams, RedirectParameters, RtcpId, RtcpIdWrapper,
+    SerializableSocket, SocketInfo,
+};
+use crate::error;
+
+/// WebRTC GatewayにMediaポートを開放させる
+/// 後続の処理が失敗した場合に削除できるよう、CONTENT_DELETEを取り消し処理として記録する
+pub(crate) async fn create_media_socket(
+    factory: &dyn Factory,
+    is_video: bool,
+    compensation: &mut Compensation,
+) -> Result<SocketInfo<MediaId>, error::Error> {
+    let param = RequestDto::Media(MediaRequestDto::ContentCreate {
+        params: IsVideo { is_video },
+    });
+    let result = execute_step(factory, param).await?;
+    if let ResponseDto::Media(MediaResponseDto::ContentCreate(socket)) = result {
+        compensation.push(CompensationStep::Request(Box::new(RequestDto::Media(
+            MediaRequestDto::ContentDelete {
+                params: MediaIdWrapper {
+                    media_id: socket.get_id().unwrap(),
+                },
+            },
+        ))));
+        Ok(socket)
+    } else {
+        let message = format!("create media failed {:?}", result);
+        Err(error::Error::create_local_error(&message))
+    }
+}
+
+/// WebRTC GatewayにRTCPポートを開放させる
+/// 後続の処理が失敗した場合に削除できるよう、RTCP_DELETEを取り消し処理として記録する
+pub(crate) async fn create_rtcp_socket(
+    factory: &dyn Factory,
+    compensation: &mut Compensation,
+) -> Result<SocketInfo<RtcpId>, error::Error> {
+    let param = RequestDto::Media(MediaRequestDto::RtcpCreate { params: None });
+    let result = execute_step(factory, param).await?;
+    if let ResponseDto::Media(MediaResponseDto::RtcpCreate(socket)) = result {
+        compensation.push(CompensationStep::Request(Box::new(RequestDto::Media(
+            MediaRequestDto::RtcpDelete {
+                params: RtcpIdWrapper {
+                    rtcp_id: socket.get_id().unwrap(),
+                },
+            },
+        ))));
+        Ok(socket)
+    } else {
+        let message = format!("create rtcp failed {:?}", result);
+        Err(error::Error::create_local_error(&message))
+    }
+}
+
+/// ConstraintsDtoで送信パラメータが指定された種類のMediaについてだけ、MediaとRTCPのポートを開放する
+/// 何も指定されていない場合は受信専用となり、ポートは開放しない
+pub(crate) async fn create_send_params(
+    factory: &dyn Factory,
+    constraints: &Option<ConstraintsDto>,
+    compensation: &mut Compensation,
+) -> Result<SendParams, error::Error> {
+    let (is_video_required, is_audio_required) = match constraints {
+        Some(constraints) => (
+            constraints.video_params.is_some(),
+            constraints.audio_params.is_some(),
+        ),
+        None => (false, false),
+    };
+
+    let video = match is_video_required {
+        true => Some(MediaPair {
+            media: create_media_socket(factory, true, compensation).await?,
+            rtcp: create_rtcp_socket(factory, compensation).await?,
+        }),
+        false => None,
+    };
+    let audio = match is_audio_required {
+        true => Some(MediaPair {
+            media: create_media_socket(factory, false, compensation).await?,
+            rtcp: create_rtcp_socket(factory, compensation).await?,
+        }),
+        false => None,
+    };
+
+    Ok(SendParams { video, audio })
+}
+
+/// 開放したポートとユーザの指定から、CALL, ANSWER APIに与えるConstraintsを生成する
+/// 送信はポートを開放した種類のMediaだけ、受信はRedirect先が指定された種類のMediaだけ有効にする
+pub(crate) fn create_constraint(
+    send_params: &SendParams,
+    constraint_dto: &Option<ConstraintsDto>,
+    redirect_params: &Option<RedirectParameters>,
+) -> Constraints {
+    let video_receive_enabled = if let Some(RedirectParameters {
+        video: Some(ref _video),
+        ..
+    }) = redirect_params
+    {
+        Some(true)
+    } else {
+        None
+    };
+
+    let audio_receive_enabled = if let Some(RedirectParameters {
+        audio: Some(ref _audio),
+        ..
+    }) = redirect_params
+    {
+        Some(true)
+    } else {
+        None
+    };
+
+    let video_params = match (constraint_dto, &send_params.video) {
+        (
+            Some(ConstraintsDto {
+                video_params: Some(ref params),
+                ..
+            }),
+            Some(pair),
+        ) => Some(create_media_params(params, pair)),
+        _ => None,
+    };
+
+    let audio_params = match (constraint_dto, &send_params.audio) {
+        (
+            Some(ConstraintsDto {
+                audio_params: Some(ref params),
+                ..
+            }),
+            Some(pair),
+        ) => Some(create_media_params(params, pair)),
+        _ => None,
+    };
+
+    let metadata = if let Some(ConstraintsDto {
+        metadata: Some(ref metadata),
+        ..
+    }) = constraint_dto
+    {
+        Some(metadata.clone())
+    } else {
+        None
+    };
+
+    Constraints {
+        video: video_params.is_some(),
+        videoReceiveEnabled: video_receive_enabled,
+        audio: audio_params.is_some(),
+        audioReceiveEnabled: audio_receive_enabled,
+        video_params,
+        audio_params,
+        metadata,
+    }
+}
+
+fn create_media_params(params: &MediaParamsDto, pair: &MediaPair<MediaId, RtcpId>) -> MediaParams {
+    MediaParams {
+        band_width: params.band_width,
+        codec: params.codec.clone(),
+        media_id: pair.media.get_id().unwrap(),
+        rtcp_id: Some(pair.rtcp.get_id().unwrap()),
+        payload_type: params.payload_type,
+        sampling_rate: params.sampling_rate,
+    }
+}
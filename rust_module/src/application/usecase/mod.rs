@@ -1,3 +1,4 @@
+pub(crate) mod compensation;
 pub(crate) mod data;
 pub(crate) mod event;
 pub(crate) mod general;
@@ -9,7 +10,9 @@ use async_trait::async_trait;
 use shaku::Interface;
 
 use crate::application::dto::request::RequestDto;
-use crate::application::dto::response::ResponseDtoResult;
+use crate::application::dto::response::{ResponseDto, ResponseDtoResult};
+use crate::application::dto::Command;
+use crate::application::factory::Factory;
 use crate::error;
 
 #[cfg(test)]
@@ -20,3 +23,18 @@ use mockall::automock;
 pub(crate) trait Service: Interface {
     async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error>;
 }
+
+/// 複数の手順からなるUseCaseの1手順として、他のServiceを実行する
+/// 失敗した場合は、エラーに手順の名前("DATA CREATE"等)を記録する
+pub(crate) async fn execute_step(
+    factory: &dyn Factory,
+    request: RequestDto,
+) -> Result<ResponseDto, error::Error> {
+    let step = format!("{} {}", request.dto_type(), request.command());
+    let service = factory.create_service(&request);
+    match service.execute(request).await {
+        Ok(ResponseDtoResult::Success(response)) => Ok(response),
+        Ok(ResponseDtoResult::Error(error)) => Err(error::Error::from(error).with_step(&step)),
+        Err(e) => Err(e.with_step(&step)),
+    }
+}
@@ -3,17 +3,23 @@
 /// ユーザにとってはPeer Objectは生成に完了して然るべきもので、Eventの監視をする積極的理由がないので、
 /// このUseCase内でEventの監視まで自動的に行い、Open完了時に結果を返す
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use shaku::Component;
 
-use crate::application::dto::request::RequestDto;
-use crate::application::dto::response::{PeerResponseDto, ResponseDto, ResponseDtoResult};
+use crate::application::deadline;
+use crate::application::dto::request::{PeerRequestDto, RequestDto};
+use crate::application::dto::response::{
+    ErrorDto, PeerRecordDto, PeerResponseDto, ResponseDto, ResponseDtoResult,
+};
 use crate::application::usecase::Service;
-use crate::domain::entity::request::Request;
+use crate::domain::entity::request::{PeerRequest, Request};
 use crate::domain::entity::response::{PeerResponse, Response, ResponseResult};
-use crate::domain::repository::Repository;
+use crate::domain::entity::{CreatePeerParams, PeerEventEnum, PeerId, PeerInfo};
+use crate::domain::repository::{EventSubscription, Repository};
 use crate::error;
+use crate::error::ErrorCode;
 use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState};
 
 #[allow(unused)]
@@ -31,13 +37,30 @@ pub(crate) struct Create {
 #[async_trait]
 impl Service for Create {
     async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
-        if let RequestDto::Peer(ref inner) = request {
-            let request = Request::Peer(inner.clone());
-            let result = self.repository.register(request).await?;
+        if let RequestDto::Peer(PeerRequestDto::Create {
+            ref params,
+            ref recovery,
+        }) = request
+        {
+            let result = create_and_wait_for_open(
+                self.repository.as_ref(),
+                self.state.as_ref(),
+                params.clone(),
+            )
+            .await?;
 
             // 成功した場合はC++側にpeer_id, tokenを渡す
             match result {
                 ResponseResult::Success(Response::Peer(PeerResponse::Create(ref peer_info))) => {
+                    // PEER LISTやshutdown時に参照できるよう、生成したPeerを登録しておく
+                    // 再生成を行う場合は、同じパラメータでCREATEし直すため保持しておく
+                    let record = PeerRecordDto {
+                        recovery: recovery.clone(),
+                        create_params: recovery.as_ref().map(|_| params.clone()),
+                        ..PeerRecordDto::new(peer_info)
+                    };
+                    self.state.store_peer(record);
+
                     let peer_id = peer_info.peer_id();
                     let token = peer_info.token();
                     // shutdown処理のためにpeer_id, tokenをC++側に通知
@@ -50,7 +73,8 @@ impl Service for Create {
                 }
                 // API Callには成功したが、内部処理に失敗したケース
                 ResponseResult::Error(message) => {
-                    return Ok(ResponseDtoResult::Error(message));
+                    let error = error::Error::create_gateway_error(&message);
+                    return Ok(ResponseDtoResult::Error(ErrorDto::from(&error)));
                 }
                 _ => {
                     unreachable!()
@@ -63,17 +87,207 @@ impl Service for Create {
     }
 }
 
+/// CREATEを送り、生成したPeer ObjectのOPENイベントをpeer_open_timeout_msまで待つ
+/// OPENしなかった場合は、WebRTC Gateway上に残ったPeer Objectは利用できないので削除した上でエラーを返す
+/// WebRTC GatewayがCREATEを受け付けなかった場合は、そのレスポンスをそのまま返す
+/// PEER CREATEと再生成の両方で、同じ待機と後始末の規則を使うために共通化している
+pub(crate) async fn create_and_wait_for_open(
+    repository: &dyn Repository,
+    state: &dyn GlobalState,
+    params: CreatePeerParams,
+) -> Result<ResponseResult, error::Error> {
+    // CREATE APIの呼び出し直後にOPENイベントが発火する可能性があるので、先に購読を開始しておく
+    let mut events = repository.subscribe_events();
+
+    let request = Request::Peer(PeerRequest::Create { params });
+    let result = repository.register(request).await?;
+    let peer_info = match result {
+        ResponseResult::Success(Response::Peer(PeerResponse::Create(ref peer_info))) => {
+            peer_info.clone()
+        }
+        _ => return Ok(result),
+    };
+
+    // OPENイベントが発火するまではPeer Objectは利用できないので、待機する
+    // リクエスト全体の期限が近い場合はそちらで打ち切り、終了処理が始まった場合は中断する
+    let mut cancelled = state.request_canceller().subscribe();
+    let timeout = Duration::from_millis(state.config().peer_open_timeout_ms);
+    let timeout = deadline::timeout_for(timeout);
+    let open_result = tokio::select! {
+        result = tokio::time::timeout(timeout, wait_for_open(&mut events, peer_info.peer_id())) => {
+            result.unwrap_or_else(|_| {
+                let message = format!(
+                    "timeout: peer {} did not open within {} ms",
+                    peer_info.peer_id().as_str(),
+                    timeout.as_millis()
+                );
+                Err(error::Error::create_error(ErrorCode::Timeout, &message))
+            })
+        }
+        _ = cancelled.changed() => {
+            let message = format!(
+                "waiting for peer {} to open is cancelled by shutdown",
+                peer_info.peer_id().as_str()
+            );
+            Err(error::Error::create_error(ErrorCode::Cancelled, &message))
+        }
+    };
+
+    if let Err(e) = open_result {
+        // 期限切れで中断された場合でも削除できるよう、削除には期限を引き継がない
+        let delete_request = Request::Peer(PeerRequest::Delete { params: peer_info });
+        let _ = deadline::scope(None, repository.register(delete_request)).await;
+        return Err(e);
+    }
+    Ok(result)
+}
+
+// 指定したPeerIdに対するOPENかERRORイベントが来るまで待機する
+// 他のPeerのイベントや、Peer以外のイベントは無視する
+async fn wait_for_open(
+    events: &mut EventSubscription,
+    peer_id: PeerId,
+) -> Result<PeerInfo, error::Error> {
+    loop {
+        // パースできないイベントは無関係なものとして読み飛ばす
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(error::Error::SerdeError { .. }) => continue,
+            Err(e) => return Err(e),
+        };
+        match event {
+            ResponseResult::Success(Response::Peer(PeerResponse::Event(PeerEventEnum::OPEN(
+                open,
+            )))) if open.params.peer_id() == peer_id => return Ok(open.params),
+            ResponseResult::Success(Response::Peer(PeerResponse::Event(PeerEventEnum::ERROR(
+                error,
+            )))) if error.params.peer_id() == peer_id => {
+                let message = format!(
+                    "peer {} reported an error before opening: {}",
+                    peer_id.as_str(),
+                    error.error_message
+                );
+                return Err(error::Error::create_error(
+                    ErrorCode::GatewayRejected,
+                    &message,
+                ));
+            }
+            _ => continue,
+        }
+    }
+}
+
 #[cfg(test)]
 mod create_peer_test {
+    use once_cell::sync::OnceCell;
     use shaku::HasComponent;
+    use tokio::sync::broadcast;
 
     use super::*;
     use crate::application::dto::request::RequestDto;
     use crate::application::dto::response::ResponseDtoResult;
+    use crate::config::Config;
     use crate::di::PeerCreateService;
     use crate::domain::entity::response::ResponseResult;
+    use crate::domain::entity::{PeerErrorEvent, PeerOpenEvent, Stringify};
     use crate::domain::repository::MockRepository;
-    use crate::ffi::rust_to_c_bridge::state_objects::MockCallbackFunctions;
+    use crate::ffi::rust_to_c_bridge::state_objects::{
+        MockCallbackFunctions, MockGlobalState, RequestCanceller,
+    };
+
+    const TOKEN: &str = "pt-06cf1d26-0ef0-4b03-aca6-933027d434c2";
+
+    fn create_request() -> RequestDto {
+        let message = r#"{
+            "request_type": "PEER",
+            "command": "CREATE",
+            "params": {
+                "key": "API_KEY",
+                "domain": "localhost",
+                "peer_id": "peer_id",
+                "turn": true
+            }
+        }"#;
+        RequestDto::from_str(message).unwrap()
+    }
+
+    fn create_request_params() -> CreatePeerParams {
+        match create_request() {
+            RequestDto::Peer(PeerRequestDto::Create { params, .. }) => params,
+            _ => unreachable!(),
+        }
+    }
+
+    fn create_response() -> Result<ResponseResult, error::Error> {
+        let message = r#"{
+                "is_success":true,
+                "result":{
+                    "request_type":"PEER",
+                    "command":"CREATE",
+                    "peer_id":"peer_id",
+                    "token":"pt-06cf1d26-0ef0-4b03-aca6-933027d434c2"
+                }
+            }"#;
+        ResponseResult::from_str(message).map_err(error::Error::from)
+    }
+
+    fn open_event(peer_id: &str) -> String {
+        let params = PeerInfo::try_create(peer_id, TOKEN).unwrap();
+        ResponseResult::Success(Response::Peer(PeerResponse::Event(PeerEventEnum::OPEN(
+            PeerOpenEvent { params },
+        ))))
+        .to_string()
+        .unwrap()
+    }
+
+    fn error_event(peer_id: &str) -> String {
+        let params = PeerInfo::try_create(peer_id, TOKEN).unwrap();
+        ResponseResult::Success(Response::Peer(PeerResponse::Event(PeerEventEnum::ERROR(
+            PeerErrorEvent {
+                params,
+                error_message: "BROWSER_INCOMPATIBLE".to_string(),
+            },
+        ))))
+        .to_string()
+        .unwrap()
+    }
+
+    // テストごとに独立して中断できるよう、RequestCancellerは毎回生成する
+    fn canceller() -> &'static RequestCanceller {
+        Box::leak(Box::new(RequestCanceller::new()))
+    }
+
+    // OPENしないPeerのCREATEと、その後始末のDELETEを受け付けるRepositoryを生成する
+    fn unopened_repository() -> MockRepository {
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(2)
+            .returning(|request| match request {
+                Request::Peer(PeerRequest::Create { .. }) => create_response(),
+                Request::Peer(PeerRequest::Delete { params }) => Ok(ResponseResult::Success(
+                    Response::Peer(PeerResponse::Delete(params)),
+                )),
+                _ => unreachable!(),
+            });
+        let (events, tx) = subscription(vec![open_event("other_peer")]);
+        // 待機中に購読が終了しないよう、送信側はテストの終了まで保持する
+        Box::leak(Box::new(tx));
+        repository
+            .expect_subscribe_events()
+            .times(1)
+            .return_once(move || events);
+        repository
+    }
+
+    // 与えたイベントを順に返すEventSubscriptionを生成する
+    fn subscription(events: Vec<String>) -> (EventSubscription, broadcast::Sender<String>) {
+        let (tx, rx) = broadcast::channel(10);
+        for event in events {
+            tx.send(event).unwrap();
+        }
+        (EventSubscription::new(rx), tx)
+    }
 
     #[tokio::test]
     async fn success() {
@@ -91,36 +305,19 @@ mod create_peer_test {
             ResponseDtoResult::from_str(message).unwrap()
         };
 
-        // CreatePeerのパラメータ生成
-        let dto = {
-            let message = r#"{
-                "request_type": "PEER",
-                "command": "CREATE",
-                "params": {
-                    "key": "API_KEY",
-                    "domain": "localhost",
-                    "peer_id": "peer_id",
-                    "turn": true
-                }
-            }"#;
-            RequestDto::from_str(message).unwrap()
-        };
-
         // repositoryのMockを生成
         // 呼び出しに成功するケース
         let mut repository = MockRepository::new();
-        repository.expect_register().times(1).returning(|_| {
-            let message = r#"{
-                    "is_success":true,
-                    "result":{
-                        "request_type":"PEER",
-                        "command":"CREATE",
-                        "peer_id":"peer_id",
-                        "token":"pt-06cf1d26-0ef0-4b03-aca6-933027d434c2"
-                    }
-                }"#;
-            ResponseResult::from_str(message)
-        });
+        repository
+            .expect_register()
+            .times(1)
+            .returning(|_| create_response());
+        // 他のPeerのOPENイベントは無視され、自身のOPENイベントで完了するはずである
+        let (events, _tx) = subscription(vec![open_event("other_peer"), open_event("peer_id")]);
+        repository
+            .expect_subscribe_events()
+            .times(1)
+            .return_once(move || events);
 
         let mut caller = MockCallbackFunctions::new();
         caller
@@ -128,37 +325,176 @@ mod create_peer_test {
             .times(1)
             .returning(|_, _| ());
 
+        // OPENしたPeerは一覧に登録される
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .returning(|| CONFIG.get_or_init(Config::default));
+        state.expect_request_canceller().return_const(canceller());
+        state.expect_store_peer().times(1).returning(|record| {
+            assert_eq!(record.peer_id.as_str(), "peer_id");
+            assert_eq!(record.token.as_str(), TOKEN);
+        });
+
         // サービスの生成
         let module = PeerCreateService::builder()
             .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
             .with_component_override::<dyn CallbackFunctions>(Box::new(caller))
             .build();
         let service: &dyn Service = module.resolve_ref();
 
         // 実行
-        let result = service.execute(dto).await;
+        let result = service.execute(create_request()).await;
         assert_eq!(result.unwrap(), expected);
     }
 
+    #[tokio::test]
+    async fn peer_error() {
+        // OPENの前にERRORイベントが発火するケース
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(2)
+            .returning(|request| match request {
+                Request::Peer(PeerRequest::Create { .. }) => create_response(),
+                // 利用できないPeer Objectは削除される
+                Request::Peer(PeerRequest::Delete { params }) => Ok(ResponseResult::Success(
+                    Response::Peer(PeerResponse::Delete(params)),
+                )),
+                _ => unreachable!(),
+            });
+        let (events, _tx) = subscription(vec![error_event("peer_id")]);
+        repository
+            .expect_subscribe_events()
+            .times(1)
+            .return_once(move || events);
+
+        // C++側には通知されない
+        let mut caller = MockCallbackFunctions::new();
+        caller.expect_create_peer_callback().times(0);
+
+        // サービスの生成
+        let module = PeerCreateService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(caller))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+
+        // 実行
+        let result = service.execute(create_request()).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::GatewayRejected);
+        let message = error.message();
+        assert_eq!(
+            message,
+            "peer peer_id reported an error before opening: BROWSER_INCOMPATIBLE"
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        // OPENイベントが発火しないケース
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(2)
+            .returning(|request| match request {
+                Request::Peer(PeerRequest::Create { .. }) => create_response(),
+                Request::Peer(PeerRequest::Delete { params }) => Ok(ResponseResult::Success(
+                    Response::Peer(PeerResponse::Delete(params)),
+                )),
+                _ => unreachable!(),
+            });
+        let (events, _tx) = subscription(vec![open_event("other_peer")]);
+        repository
+            .expect_subscribe_events()
+            .times(1)
+            .return_once(move || events);
+
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let mut state = MockGlobalState::new();
+        state.expect_config().times(1).returning(|| {
+            CONFIG.get_or_init(|| Config {
+                peer_open_timeout_ms: 10,
+                ..Config::default()
+            })
+        });
+        state.expect_request_canceller().return_const(canceller());
+
+        let mut caller = MockCallbackFunctions::new();
+        caller.expect_create_peer_callback().times(0);
+
+        // サービスの生成
+        let module = PeerCreateService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(caller))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+
+        // 実行
+        let result = service.execute(create_request()).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Timeout);
+        let message = error.message();
+        assert_eq!(message, "timeout: peer peer_id did not open within 10 ms");
+    }
+
+    #[tokio::test]
+    async fn request_deadline() {
+        // リクエスト全体の期限がpeer_open_timeout_msより早い場合は、期限で打ち切ってPeer Objectを削除する
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .returning(|| CONFIG.get_or_init(Config::default));
+        state.expect_request_canceller().return_const(canceller());
+
+        let repository = unopened_repository();
+        let result = deadline::scope(
+            Some(std::time::Instant::now() + Duration::from_millis(50)),
+            create_and_wait_for_open(&repository, &state, create_request_params()),
+        );
+        let result = tokio::time::timeout(Duration::from_secs(1), result)
+            .await
+            .expect("the request deadline is ignored");
+        assert_eq!(result.unwrap_err().code(), ErrorCode::Timeout);
+    }
+
+    #[tokio::test]
+    async fn cancelled() {
+        // 終了処理が始まった場合は、OPENを待たずに中断してPeer Objectを削除する
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let canceller = canceller();
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .returning(|| CONFIG.get_or_init(Config::default));
+        state.expect_request_canceller().return_const(canceller);
+
+        let repository = unopened_repository();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel_all();
+        });
+        let result = create_and_wait_for_open(&repository, &state, create_request_params());
+        let result = tokio::time::timeout(Duration::from_secs(1), result)
+            .await
+            .expect("shutdown does not cancel waiting for OPEN");
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Cancelled);
+        assert_eq!(
+            error.message(),
+            "waiting for peer peer_id to open is cancelled by shutdown"
+        );
+    }
+
     #[tokio::test]
     async fn fail() {
         // APIがエラーを返してくるケース
 
-        // CreatePeerのパラメータ生成
-        let dto = {
-            let message = r#"{
-                "request_type": "PEER",
-                "command": "CREATE",
-                "params": {
-                    "key": "API_KEY",
-                    "domain": "localhost",
-                    "peer_id": "peer_id",
-                    "turn": true
-                }
-            }"#;
-            RequestDto::from_str(message).unwrap()
-        };
-
         // repositoryのMockを生成
         // errorを返してくるケース
         let mut repository = MockRepository::new();
@@ -166,6 +502,11 @@ mod create_peer_test {
             let answer = error::Error::create_local_error("error");
             return Err(answer);
         });
+        let (events, _tx) = subscription(vec![]);
+        repository
+            .expect_subscribe_events()
+            .times(1)
+            .return_once(move || events);
 
         // サービスの生成
         let module = PeerCreateService::builder()
@@ -174,7 +515,7 @@ mod create_peer_test {
         let service: &dyn Service = module.resolve_ref();
 
         // 実行
-        let result = service.execute(dto).await;
+        let result = service.execute(create_request()).await;
         if let Err(error::Error::LocalError(message)) = result {
             assert_eq!(message, "error");
         }
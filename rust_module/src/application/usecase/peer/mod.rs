@@ -1,2 +1,4 @@
 /// /peer系のAPIのうち、特別な内部処理を必要とするものはここで実装する
 pub(crate) mod create;
+pub(crate) mod recovery;
+pub(crate) mod registry;
@@ -0,0 +1,537 @@
+// PEER CREATEでrecoveryを指定したPeer Objectが、CLOSE, ERRORとなった場合に同じpeer_idで再生成する
+// 責務は以下の通りである
+// 1. CLOSE, ERRORイベントを受けて、再生成の対象かどうかを判定し、RECOVERINGとして記録する
+// 2. 待機時間を指数的に伸ばしながらCREATEを再送し、OPENを待つ
+// 3. 試行の前にRECOVERING, 成功時にRECOVERED, 全ての試行に失敗した場合はRECOVERY_FAILEDイベントを配信する
+//
+// 再生成中にPEER DELETEやshutdownで一覧から削除された場合は、その時点で再生成を中止する
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::{Component, Interface};
+
+use crate::application::dto::response::{
+    PeerEventEnumDto, PeerRecordDto, PeerRecordStatusDto, PeerRecoveredEventDto,
+    PeerRecoveringEventDto, PeerRecoveryFailedEventDto, PeerResponseDto, ResponseDto,
+    ResponseDtoResult,
+};
+use crate::application::usecase::peer::create::create_and_wait_for_open;
+use crate::domain::entity::request::{PeerRequest, Request};
+use crate::domain::entity::response::{PeerResponse, Response, ResponseResult};
+use crate::domain::entity::{CreatePeerParams, PeerId, PeerInfo};
+use crate::domain::repository::Repository;
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState, Logger};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub(crate) trait PeerRecovery: Interface {
+    /// CLOSE, ERRORイベントを受けたときに呼ぶ
+    /// 再生成が必要な場合はRECOVERINGとして記録してtrueを返すので、呼び出し側でrecoverを実行する
+    /// 再生成しない場合、CLOSEであればCLOSEDとして記録する
+    fn on_peer_lost(&self, peer_info: &PeerInfo, closed: bool) -> bool;
+    /// RECOVERINGとして記録されたPeer Objectを再生成する
+    async fn recover(&self, peer_id: PeerId);
+}
+
+#[derive(Component)]
+#[shaku(interface = PeerRecovery)]
+pub(crate) struct PeerRecoveryImpl {
+    #[shaku(inject)]
+    repository: Arc<dyn Repository>,
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+    #[shaku(inject)]
+    callback: Arc<dyn CallbackFunctions>,
+    #[shaku(inject)]
+    logger: Arc<dyn Logger>,
+}
+
+#[async_trait]
+impl PeerRecovery for PeerRecoveryImpl {
+    fn on_peer_lost(&self, peer_info: &PeerInfo, closed: bool) -> bool {
+        let peer_id = peer_info.peer_id();
+        let record = match self.state.find_peer(&peer_id) {
+            Some(record) => record,
+            None => return false,
+        };
+        // 再生成前のPeer Objectや、再生成中に届いたイベントは無視する
+        if record.token != peer_info.token() || record.status == PeerRecordStatusDto::Recovering {
+            return false;
+        }
+
+        if record.recovery.is_some() && record.create_params.is_some() {
+            self.state
+                .set_peer_status(&peer_id, PeerRecordStatusDto::Recovering);
+            true
+        } else {
+            if closed {
+                // WebRTC Gateway上では削除済みなので、一覧上もCLOSEDとして扱う
+                self.state
+                    .set_peer_status(&peer_id, PeerRecordStatusDto::Closed);
+            }
+            false
+        }
+    }
+
+    async fn recover(&self, peer_id: PeerId) {
+        let record = match self.state.find_peer(&peer_id) {
+            Some(record) => record,
+            None => return,
+        };
+        let (policy, params) = match (record.recovery.clone(), record.create_params.clone()) {
+            (Some(policy), Some(params)) => (policy, params),
+            _ => return,
+        };
+        let old_peer_info = record.peer_info();
+
+        // ERRORの場合はWebRTC Gateway上にPeer Objectが残っているので、同じpeer_idで生成できるよう削除しておく
+        let delete_request = Request::Peer(PeerRequest::Delete {
+            params: old_peer_info.clone(),
+        });
+        let _ = self.repository.register(delete_request).await;
+
+        for attempt in 1..=policy.max_retries {
+            let backoff = policy.backoff(attempt);
+            self.publish(PeerEventEnumDto::RECOVERING(PeerRecoveringEventDto {
+                params: old_peer_info.clone(),
+                attempt,
+                max_retries: policy.max_retries,
+                backoff_ms: backoff.as_millis() as u64,
+            }));
+            tokio::time::sleep(backoff).await;
+
+            if !self.is_recovering(&peer_id) {
+                self.logger.info(&format!(
+                    "recovery of peer {} is cancelled",
+                    peer_id.as_str()
+                ));
+                return;
+            }
+
+            match self.create(params.clone()).await {
+                Ok(peer_info) => {
+                    // 待機中に削除された場合は、生成したPeer Objectも不要なので削除する
+                    if !self.is_recovering(&peer_id) {
+                        let delete_request =
+                            Request::Peer(PeerRequest::Delete { params: peer_info });
+                        let _ = self.repository.register(delete_request).await;
+                        return;
+                    }
+
+                    self.state.store_peer(PeerRecordDto {
+                        recovery: Some(policy.clone()),
+                        create_params: Some(params.clone()),
+                        ..PeerRecordDto::new(&peer_info)
+                    });
+                    self.callback
+                        .create_peer_callback(peer_id.as_str(), peer_info.token().as_str());
+                    self.logger.info(&format!(
+                        "peer {} is recovered at attempt {}",
+                        peer_id.as_str(),
+                        attempt
+                    ));
+                    self.publish(PeerEventEnumDto::RECOVERED(PeerRecoveredEventDto {
+                        params: peer_info,
+                        attempt,
+                    }));
+                    return;
+                }
+                Err(e) => {
+                    self.logger.warn(&format!(
+                        "failed to recover peer {} at attempt {}: {} ({})",
+                        peer_id.as_str(),
+                        attempt,
+                        e.message(),
+                        e.code()
+                    ));
+                }
+            }
+        }
+
+        if self.is_recovering(&peer_id) {
+            self.state
+                .set_peer_status(&peer_id, PeerRecordStatusDto::Closed);
+        }
+        self.logger.error(&format!(
+            "gave up recovering peer {} after {} attempts",
+            peer_id.as_str(),
+            policy.max_retries
+        ));
+        self.publish(PeerEventEnumDto::RECOVERY_FAILED(
+            PeerRecoveryFailedEventDto {
+                params: old_peer_info,
+                attempts: policy.max_retries,
+            },
+        ));
+    }
+}
+
+impl PeerRecoveryImpl {
+    fn is_recovering(&self, peer_id: &PeerId) -> bool {
+        matches!(
+            self.state.find_peer(peer_id),
+            Some(record) if record.status == PeerRecordStatusDto::Recovering
+        )
+    }
+
+    // CREATEを再送し、OPENイベントを待つ
+    // OPENしなかったPeer Objectは削除されるので、次の試行で同じpeer_idを利用できる
+    async fn create(&self, params: CreatePeerParams) -> Result<PeerInfo, error::Error> {
+        let result =
+            create_and_wait_for_open(self.repository.as_ref(), self.state.as_ref(), params).await?;
+        match result {
+            ResponseResult::Success(Response::Peer(PeerResponse::Create(peer_info))) => {
+                Ok(peer_info)
+            }
+            ResponseResult::Error(message) => Err(error::Error::create_gateway_error(&message)),
+            result => {
+                let message = format!(
+                    "unexpected response for CREATE: {}",
+                    serde_json::to_string(&result).unwrap()
+                );
+                Err(error::Error::create_local_error(&message))
+            }
+        }
+    }
+
+    fn publish(&self, event: PeerEventEnumDto) {
+        let result = ResponseDtoResult::Success(ResponseDto::Peer(PeerResponseDto::Event(event)));
+        self.state
+            .event_hub()
+            .publish(&serde_json::to_value(&result).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod peer_recovery_test {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use once_cell::sync::OnceCell;
+    use serde_json::json;
+    use shaku::HasComponent;
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::application::dto::request::PeerRecoveryPolicyDto;
+    use crate::application::usecase::event::hub::{EventFilter, EventHub};
+    use crate::config::Config;
+    use crate::di::PeerRecoveryModule;
+    use crate::domain::entity::{PeerEventEnum, PeerOpenEvent, Stringify};
+    use crate::domain::repository::{EventSubscription, MockRepository};
+    use crate::ffi::rust_to_c_bridge::state_objects::{
+        MockCallbackFunctions, MockGlobalState, MockLogger, RequestCanceller,
+    };
+
+    const OLD_TOKEN: &str = "pt-06cf1d26-0ef0-4b03-aca6-933027d434c2";
+    const NEW_TOKEN: &str = "pt-9749250e-d157-4f80-9ee2-359ce8524308";
+
+    fn policy(max_retries: u32) -> PeerRecoveryPolicyDto {
+        PeerRecoveryPolicyDto {
+            max_retries,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 2,
+        }
+    }
+
+    fn record(status: PeerRecordStatusDto, recovery: bool) -> PeerRecordDto {
+        let params: CreatePeerParams = serde_json::from_value(json!({
+            "key": "API_KEY",
+            "domain": "localhost",
+            "peer_id": "peer_id",
+            "turn": true
+        }))
+        .unwrap();
+        PeerRecordDto {
+            status,
+            recovery: recovery.then(|| policy(3)),
+            create_params: recovery.then_some(params),
+            ..PeerRecordDto::new(&PeerInfo::try_create("peer_id", OLD_TOKEN).unwrap())
+        }
+    }
+
+    fn open_event(token: &str) -> String {
+        let params = PeerInfo::try_create("peer_id", token).unwrap();
+        ResponseResult::Success(Response::Peer(PeerResponse::Event(PeerEventEnum::OPEN(
+            PeerOpenEvent { params },
+        ))))
+        .to_string()
+        .unwrap()
+    }
+
+    fn create_module(
+        repository: MockRepository,
+        state: MockGlobalState,
+        callback: MockCallbackFunctions,
+    ) -> PeerRecoveryModule {
+        let mut logger = MockLogger::new();
+        logger.expect_info().returning(|_| ());
+        logger.expect_warn().returning(|_| ());
+        logger.expect_error().returning(|_| ());
+        PeerRecoveryModule::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(callback))
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .build()
+    }
+
+    // find_peer, store_peer, set_peer_statusが同じ記録を参照するMockGlobalStateを生成する
+    fn stateful_mock(
+        record: PeerRecordDto,
+        hub: &'static EventHub,
+        config: &'static Config,
+    ) -> (MockGlobalState, Arc<Mutex<Option<PeerRecordDto>>>) {
+        let shared = Arc::new(Mutex::new(Some(record)));
+        let mut state = MockGlobalState::new();
+        let find = shared.clone();
+        state
+            .expect_find_peer()
+            .returning(move |_| find.lock().unwrap().clone());
+        let store = shared.clone();
+        state
+            .expect_store_peer()
+            .returning(move |record| *store.lock().unwrap() = Some(record));
+        let status = shared.clone();
+        state.expect_set_peer_status().returning(move |_, s| {
+            if let Some(record) = status.lock().unwrap().as_mut() {
+                record.status = s;
+            }
+        });
+        state.expect_event_hub().return_const(hub);
+        state.expect_config().return_const(config);
+        state
+            .expect_request_canceller()
+            .return_const(&*Box::leak(Box::new(RequestCanceller::new())));
+        (state, shared)
+    }
+
+    // 購読者に届いたイベントを全て取り出す
+    async fn received_events(hub: &EventHub, subscriber_id: u64) -> Vec<serde_json::Value> {
+        hub.poll(subscriber_id, 10, Duration::from_millis(0))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn backoff() {
+        // 試行のたびに待機時間は2倍になり、上限を超えない
+        let policy = PeerRecoveryPolicyDto {
+            max_retries: 5,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 5000,
+        };
+        let backoff: Vec<u128> = (1..=5).map(|i| policy.backoff(i).as_millis()).collect();
+        assert_eq!(backoff, vec![1000, 2000, 4000, 5000, 5000]);
+
+        // 省略した場合はデフォルト値を利用する
+        let policy: PeerRecoveryPolicyDto =
+            serde_json::from_value(json!({"max_retries": 3})).unwrap();
+        assert_eq!(policy.initial_backoff_ms, 1000);
+        assert_eq!(policy.max_backoff_ms, 30000);
+    }
+
+    #[test]
+    fn on_peer_lost() {
+        let peer_info = PeerInfo::try_create("peer_id", OLD_TOKEN).unwrap();
+
+        // recoveryが指定されていればRECOVERINGにする
+        let mut state = MockGlobalState::new();
+        state
+            .expect_find_peer()
+            .returning(|_| Some(record(PeerRecordStatusDto::Open, true)));
+        state
+            .expect_set_peer_status()
+            .times(1)
+            .returning(|_, status| assert_eq!(status, PeerRecordStatusDto::Recovering));
+        let module = create_module(MockRepository::new(), state, MockCallbackFunctions::new());
+        let recovery: &dyn PeerRecovery = module.resolve_ref();
+        assert!(recovery.on_peer_lost(&peer_info, false));
+
+        // recoveryが指定されていなければ、CLOSEの場合のみCLOSEDにする
+        let mut state = MockGlobalState::new();
+        state
+            .expect_find_peer()
+            .returning(|_| Some(record(PeerRecordStatusDto::Open, false)));
+        state
+            .expect_set_peer_status()
+            .times(1)
+            .returning(|_, status| assert_eq!(status, PeerRecordStatusDto::Closed));
+        let module = create_module(MockRepository::new(), state, MockCallbackFunctions::new());
+        let recovery: &dyn PeerRecovery = module.resolve_ref();
+        assert!(!recovery.on_peer_lost(&peer_info, true));
+        assert!(!recovery.on_peer_lost(&peer_info, false));
+
+        // 再生成中のPeerや、再生成前のtokenに対するイベントは無視する
+        let mut state = MockGlobalState::new();
+        state
+            .expect_find_peer()
+            .returning(|_| Some(record(PeerRecordStatusDto::Recovering, true)));
+        state.expect_set_peer_status().times(0);
+        let module = create_module(MockRepository::new(), state, MockCallbackFunctions::new());
+        let recovery: &dyn PeerRecovery = module.resolve_ref();
+        assert!(!recovery.on_peer_lost(&peer_info, true));
+        let stale = PeerInfo::try_create("peer_id", NEW_TOKEN).unwrap();
+        assert!(!recovery.on_peer_lost(&stale, true));
+    }
+
+    #[tokio::test]
+    async fn recover() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let hub = HUB.get_or_init(EventHub::new);
+        let subscriber_id = hub.subscribe(EventFilter::default(), 10);
+        let config = CONFIG.get_or_init(|| Config {
+            peer_open_timeout_ms: 100,
+            ..Config::default()
+        });
+
+        // 1回目はCREATEに失敗し、2回目でOPENする
+        let mut repository = MockRepository::new();
+        let mut create_count = 0;
+        repository
+            .expect_register()
+            .returning(move |request| match request {
+                Request::Peer(PeerRequest::Create { .. }) => {
+                    create_count += 1;
+                    if create_count == 1 {
+                        return Ok(ResponseResult::Error("peer_id is in use".to_string()));
+                    }
+                    Ok(ResponseResult::Success(Response::Peer(
+                        PeerResponse::Create(PeerInfo::try_create("peer_id", NEW_TOKEN).unwrap()),
+                    )))
+                }
+                Request::Peer(PeerRequest::Delete { params }) => Ok(ResponseResult::Success(
+                    Response::Peer(PeerResponse::Delete(params)),
+                )),
+                _ => unreachable!(),
+            });
+        let (tx, _) = broadcast::channel::<String>(10);
+        let sender = tx.clone();
+        repository.expect_subscribe_events().returning(move || {
+            let rx = sender.subscribe();
+            sender.send(open_event(NEW_TOKEN)).unwrap();
+            EventSubscription::new(rx)
+        });
+
+        // 新しいtokenがC++側に通知される
+        let mut callback = MockCallbackFunctions::new();
+        callback
+            .expect_create_peer_callback()
+            .times(1)
+            .returning(|peer_id, token| {
+                assert_eq!(peer_id, "peer_id");
+                assert_eq!(token, NEW_TOKEN);
+            });
+
+        let (state, shared) =
+            stateful_mock(record(PeerRecordStatusDto::Recovering, true), hub, config);
+        let module = create_module(repository, state, callback);
+        let recovery: &dyn PeerRecovery = module.resolve_ref();
+        recovery.recover(PeerId::new("peer_id")).await;
+
+        // 一覧上は新しいtokenでOPENとなる
+        let record = shared.lock().unwrap().clone().unwrap();
+        assert_eq!(record.status, PeerRecordStatusDto::Open);
+        assert_eq!(record.token.as_str(), NEW_TOKEN);
+
+        let events: Vec<_> = received_events(hub, subscriber_id)
+            .await
+            .into_iter()
+            .map(|event| event["result"].clone())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                json!({"request_type": "PEER", "command": "EVENT", "event": "RECOVERING", "params": {"peer_id": "peer_id", "token": OLD_TOKEN}, "attempt": 1, "max_retries": 3, "backoff_ms": 1}),
+                json!({"request_type": "PEER", "command": "EVENT", "event": "RECOVERING", "params": {"peer_id": "peer_id", "token": OLD_TOKEN}, "attempt": 2, "max_retries": 3, "backoff_ms": 2}),
+                json!({"request_type": "PEER", "command": "EVENT", "event": "RECOVERED", "params": {"peer_id": "peer_id", "token": NEW_TOKEN}, "attempt": 2}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn recovery_failed() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let hub = HUB.get_or_init(EventHub::new);
+        let subscriber_id = hub.subscribe(EventFilter::default(), 10);
+        let config = CONFIG.get_or_init(Config::default);
+
+        // 全ての試行でCREATEに失敗する
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .returning(|request| match request {
+                Request::Peer(PeerRequest::Create { .. }) => {
+                    Ok(ResponseResult::Error("peer_id is in use".to_string()))
+                }
+                Request::Peer(PeerRequest::Delete { params }) => Ok(ResponseResult::Success(
+                    Response::Peer(PeerResponse::Delete(params)),
+                )),
+                _ => unreachable!(),
+            });
+        repository
+            .expect_subscribe_events()
+            .times(3)
+            .returning(|| EventSubscription::new(broadcast::channel(1).1));
+
+        let mut callback = MockCallbackFunctions::new();
+        callback.expect_create_peer_callback().times(0);
+
+        let (state, shared) =
+            stateful_mock(record(PeerRecordStatusDto::Recovering, true), hub, config);
+        let module = create_module(repository, state, callback);
+        let recovery: &dyn PeerRecovery = module.resolve_ref();
+        recovery.recover(PeerId::new("peer_id")).await;
+
+        // 諦めたPeerはCLOSEDとして残る
+        let record = shared.lock().unwrap().clone().unwrap();
+        assert_eq!(record.status, PeerRecordStatusDto::Closed);
+
+        let events = received_events(hub, subscriber_id).await;
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[3]["result"],
+            json!({"request_type": "PEER", "command": "EVENT", "event": "RECOVERY_FAILED", "params": {"peer_id": "peer_id", "token": OLD_TOKEN}, "attempts": 3})
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled() {
+        static HUB: OnceCell<EventHub> = OnceCell::new();
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let hub = HUB.get_or_init(EventHub::new);
+        let config = CONFIG.get_or_init(Config::default);
+
+        // 待機中にPEER DELETEで一覧から削除された場合は、CREATEを送らない
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(1)
+            .returning(|request| match request {
+                Request::Peer(PeerRequest::Delete { params }) => Ok(ResponseResult::Success(
+                    Response::Peer(PeerResponse::Delete(params)),
+                )),
+                _ => unreachable!(),
+            });
+        repository.expect_subscribe_events().times(0);
+
+        let (state, shared) =
+            stateful_mock(record(PeerRecordStatusDto::Recovering, true), hub, config);
+        let module = create_module(repository, state, MockCallbackFunctions::new());
+        let recovery: &dyn PeerRecovery = module.resolve_ref();
+        let peer_id = PeerId::new("peer_id");
+        let recover = recovery.recover(peer_id);
+        // 最初の待機が終わる前に削除する
+        let remove = async {
+            *shared.lock().unwrap() = None;
+        };
+        tokio::join!(recover, remove);
+    }
+}
@@ -0,0 +1,221 @@
+/// PEER CREATEで登録したPeer Objectの一覧を管理するService
+/// - DELETE: WebRTC Gateway上のPeer Objectを削除し、一覧からも削除する。再生成中のPeerは再生成を中止する
+/// - LIST: 登録されている全てのPeer Objectと、各Peerが確立しているConnectionを返す
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use shaku::Component;
+
+use crate::application::dto::request::{PeerRequestDto, RequestDto};
+use crate::application::dto::response::{
+    PeerListResponseDto, PeerResponseDto, ResponseDto, ResponseDtoResult,
+};
+use crate::application::usecase::Service;
+use crate::domain::entity::request::{PeerRequest, Request};
+use crate::domain::entity::response::{PeerResponse, Response, ResponseResult};
+use crate::domain::entity::PeerInfo;
+use crate::domain::repository::Repository;
+use crate::error;
+use crate::ffi::rust_to_c_bridge::state_objects::GlobalState;
+
+#[derive(Component)]
+#[shaku(interface = Service)]
+pub(crate) struct Registry {
+    #[shaku(inject)]
+    repository: Arc<dyn Repository>,
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+}
+
+#[async_trait]
+impl Service for Registry {
+    async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
+        match request {
+            RequestDto::Peer(PeerRequestDto::Delete { params }) => self.delete(params).await,
+            RequestDto::Peer(PeerRequestDto::List) => Ok(self.list()),
+            _ => Err(error::Error::create_local_error(
+                "invalid parameter for Registry",
+            )),
+        }
+    }
+}
+
+impl Registry {
+    async fn delete(&self, params: PeerInfo) -> Result<ResponseDtoResult, error::Error> {
+        // 削除によって発火するCLOSEイベントで再生成が始まらないよう、先に一覧から外しておく
+        // 再生成中のPeerも、一覧から外れた時点で再生成を中止する
+        let record = self.state.remove_peer(&params.peer_id());
+
+        let request = Request::Peer(PeerRequest::Delete { params });
+        let result = match self.repository.register(request).await {
+            Ok(ResponseResult::Success(Response::Peer(PeerResponse::Delete(peer_info)))) => {
+                return Ok(ResponseDtoResult::Success(ResponseDto::Peer(
+                    PeerResponseDto::Delete(peer_info),
+                )));
+            }
+            Ok(ResponseResult::Error(message)) => error::Error::create_gateway_error(&message),
+            Ok(result) => {
+                let message = format!("unexpected response for DELETE: {:?}", result);
+                error::Error::create_local_error(&message)
+            }
+            Err(e) => e,
+        };
+
+        // 削除に失敗した場合は一覧に戻す
+        if let Some(record) = record {
+            self.state.store_peer(record);
+        }
+        Err(result)
+    }
+
+    fn list(&self) -> ResponseDtoResult {
+        let mut peers = self.state.list_peers();
+        peers.sort_by(|a, b| a.peer_id.as_str().cmp(b.peer_id.as_str()));
+
+        ResponseDtoResult::Success(ResponseDto::Peer(PeerResponseDto::List(
+            PeerListResponseDto { peers },
+        )))
+    }
+}
+
+#[cfg(test)]
+mod peer_registry_test {
+    use serde_json::json;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::application::dto::response::{PeerRecordDto, PeerRecordStatusDto};
+    use crate::di::PeerRegistryService;
+    use crate::domain::entity::{DataConnectionId, MediaConnectionId};
+    use crate::domain::repository::MockRepository;
+    use crate::ffi::rust_to_c_bridge::state_objects::MockGlobalState;
+
+    const TOKEN: &str = "pt-9749250e-d157-4f80-9ee2-359ce8524308";
+
+    async fn execute(
+        repository: MockRepository,
+        state: MockGlobalState,
+        message: &str,
+    ) -> Result<ResponseDtoResult, error::Error> {
+        let module = PeerRegistryService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+        service
+            .execute(RequestDto::from_str(message).unwrap())
+            .await
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let mut repository = MockRepository::new();
+        repository.expect_register().times(1).returning(|request| {
+            if let Request::Peer(PeerRequest::Delete { params }) = request {
+                return Ok(ResponseResult::Success(Response::Peer(
+                    PeerResponse::Delete(params),
+                )));
+            }
+            unreachable!()
+        });
+        // 削除したPeerは一覧からも削除される
+        let mut state = MockGlobalState::new();
+        state.expect_remove_peer().times(1).returning(|peer_id| {
+            assert_eq!(peer_id.as_str(), "peer_1");
+            None
+        });
+
+        let message = format!(
+            r#"{{"request_type": "PEER", "command": "DELETE", "params": {{"peer_id": "peer_1", "token": "{}"}}}}"#,
+            TOKEN
+        );
+        let result = execute(repository, state, &message).await.unwrap();
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "PEER",
+                "command": "DELETE",
+                "peer_id": "peer_1",
+                "token": TOKEN
+            }
+        });
+        assert_eq!(serde_json::to_value(&result).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn delete_failed() {
+        // 削除に失敗した場合は一覧に残す
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(1)
+            .returning(|_| Ok(ResponseResult::Error("peer not found".to_string())));
+        let mut state = MockGlobalState::new();
+        state.expect_remove_peer().times(1).returning(|peer_id| {
+            Some(PeerRecordDto::new(
+                &PeerInfo::try_create(peer_id.as_str(), TOKEN).unwrap(),
+            ))
+        });
+        state.expect_store_peer().times(1).returning(|record| {
+            assert_eq!(record.peer_id.as_str(), "peer_1");
+        });
+
+        let message = format!(
+            r#"{{"request_type": "PEER", "command": "DELETE", "params": {{"peer_id": "peer_1", "token": "{}"}}}}"#,
+            TOKEN
+        );
+        let result = execute(repository, state, &message).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn list() {
+        let mut state = MockGlobalState::new();
+        state.expect_list_peers().times(1).returning(|| {
+            let peer = |peer_id: &str, status| PeerRecordDto {
+                status,
+                ..PeerRecordDto::new(&PeerInfo::try_create(peer_id, TOKEN).unwrap())
+            };
+            let mut peer_2 = peer("peer_2", PeerRecordStatusDto::Open);
+            peer_2.data_connections =
+                vec![
+                    DataConnectionId::try_create("dc-8bdef7a1-65c8-46be-a82e-37d51c776309")
+                        .unwrap(),
+                ];
+            peer_2.media_connections =
+                vec![
+                    MediaConnectionId::try_create("mc-102127d9-30de-413b-93f7-41a33e39d82b")
+                        .unwrap(),
+                ];
+            vec![peer_2, peer("peer_1", PeerRecordStatusDto::Closed)]
+        });
+        let message = r#"{"request_type": "PEER", "command": "LIST"}"#;
+        let result = execute(MockRepository::new(), state, message)
+            .await
+            .unwrap();
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "PEER",
+                "command": "LIST",
+                "peers": [
+                    {
+                        "peer_id": "peer_1",
+                        "token": TOKEN,
+                        "status": "CLOSED",
+                        "data_connections": [],
+                        "media_connections": []
+                    },
+                    {
+                        "peer_id": "peer_2",
+                        "token": TOKEN,
+                        "status": "OPEN",
+                        "data_connections": ["dc-8bdef7a1-65c8-46be-a82e-37d51c776309"],
+                        "media_connections": ["mc-102127d9-30de-413b-93f7-41a33e39d82b"]
+                    }
+                ]
+            }
+        });
+        assert_eq!(serde_json::to_value(&result).unwrap(), expected);
+    }
+}
@@ -1,38 +1,267 @@
 /// 終了命令など、WebRTC Gateway自体の操作に関係ない指示がClientから来たときに呼ばれる
+/// commandごとに以下の処理を行う
+/// - PING: WebRTC Gatewayとの疎通を確認し、往復にかかった時間を返す
+/// - VERSION: rust_moduleとWebRTC Gatewayのバージョンを返す
+/// - STATE: GlobalStateで保持しているDataConnection, MediaConnectionの情報を返す
+/// - SHUTDOWN: レスポンスを返した後にプログラムを終了させる
+///
+/// ROS終了時のリソースの開放は、shutdown moduleで行う
+pub(crate) mod shutdown;
+
+use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use shaku::*;
 
-use crate::application::dto::response::ResponseDto;
-use crate::application::dto::response::SystemResponseDto;
+use crate::application::dto::request::SystemRequestDto;
+use crate::application::dto::response::{
+    PingResponseDto, ResponseDto, StateResponseDto, SystemResponseDto, VersionResponseDto,
+};
 use crate::application::usecase::ResponseDtoResult;
 use crate::application::usecase::Service;
 use crate::application::RequestDto;
-use crate::di::CppObjctsModule;
+use crate::domain::repository::Repository;
 use crate::error;
-use crate::ffi::rust_to_c_bridge::state_objects::ProgramState;
+use crate::ffi::rust_to_c_bridge::state_objects::{GlobalState, ProgramState};
+
+// SHUTDOWNのレスポンスをClientに返す猶予
+const SHUTDOWN_DELAY: Duration = Duration::from_millis(100);
 
 #[derive(Component)]
 #[shaku(interface = Service)]
-pub(crate) struct System {}
+pub(crate) struct System {
+    #[shaku(inject)]
+    repository: Arc<dyn Repository>,
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+    #[shaku(inject)]
+    program_state: Arc<dyn ProgramState>,
+}
 
 #[async_trait]
 impl Service for System {
     async fn execute(&self, request: RequestDto) -> Result<ResponseDtoResult, error::Error> {
-        if let RequestDto::System(_system) = request {
-            std::thread::spawn(|| {
-                sleep(Duration::from_millis(100));
-                let module = CppObjctsModule::builder().build();
-                let state: &dyn ProgramState = module.resolve_ref();
-                state.shutdown();
-            });
-            return Ok(ResponseDtoResult::Success(ResponseDto::System(
-                SystemResponseDto { is_success: true },
-            )));
-        }
-
-        return Err(error::Error::create_local_error("invalid parameters"));
+        let response = match request {
+            RequestDto::System(SystemRequestDto::Ping) => self.ping().await?,
+            RequestDto::System(SystemRequestDto::Version) => self.version().await,
+            RequestDto::System(SystemRequestDto::State) => self.dump_state(),
+            RequestDto::System(SystemRequestDto::Shutdown) => self.shutdown(),
+            _ => return Err(error::Error::create_local_error("invalid parameters")),
+        };
+        Ok(ResponseDtoResult::Success(ResponseDto::System(response)))
+    }
+}
+
+impl System {
+    async fn ping(&self) -> Result<SystemResponseDto, error::Error> {
+        let probe = self.repository.ping().await?;
+        Ok(SystemResponseDto::Ping(PingResponseDto {
+            gateway_url: self.state.config().gateway_url.clone(),
+            round_trip_ms: probe.round_trip.as_secs_f64() * 1000.0,
+        }))
+    }
+
+    // WebRTC Gatewayに接続できない場合も、rust_moduleのバージョンは返す
+    async fn version(&self) -> SystemResponseDto {
+        let gateway_version = match self.repository.ping().await {
+            Ok(probe) => probe.version,
+            Err(_) => None,
+        };
+        SystemResponseDto::Version(VersionResponseDto {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            gateway_url: self.state.config().gateway_url.clone(),
+            gateway_version,
+        })
+    }
+
+    fn dump_state(&self) -> SystemResponseDto {
+        // HashMapの順序に依存しないよう、IDで並べ替える
+        let mut data_connections = self.state.list_topics();
+        data_connections.sort_by(|a, b| {
+            a.data_connection_id
+                .as_str()
+                .cmp(b.data_connection_id.as_str())
+        });
+        let mut media_connections = self.state.list_call_responses();
+        media_connections.sort_by(|a, b| {
+            a.media_connection_id
+                .as_str()
+                .cmp(b.media_connection_id.as_str())
+        });
+
+        SystemResponseDto::State(StateResponseDto {
+            data_connections,
+            media_connections,
+        })
+    }
+
+    fn shutdown(&self) -> SystemResponseDto {
+        let program_state = self.program_state.clone();
+        std::thread::spawn(move || {
+            sleep(SHUTDOWN_DELAY);
+            program_state.shutdown();
+        });
+        SystemResponseDto::Shutdown
+    }
+}
+
+#[cfg(test)]
+mod system_test {
+    use once_cell::sync::OnceCell;
+    use serde_json::json;
+
+    use super::*;
+    use crate::application::dto::response::{CallResponseDto, SendParams};
+    use crate::config::Config;
+    use crate::di::SystemService;
+    use crate::domain::entity::{DataConnectionId, DataId, MediaConnectionId, SerializableId};
+    use crate::domain::repository::{GatewayProbe, MockRepository};
+    use crate::ffi::rust_to_c_bridge::c_functions_wrapper::DataPipeInfo;
+    use crate::ffi::rust_to_c_bridge::state_objects::{MockGlobalState, MockProgramState};
+
+    static CONFIG: OnceCell<Config> = OnceCell::new();
+
+    async fn execute(
+        repository: MockRepository,
+        state: MockGlobalState,
+        message: &str,
+    ) -> Result<serde_json::Value, error::Error> {
+        let module = SystemService::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn ProgramState>(Box::new(MockProgramState::new()))
+            .build();
+        let service: &dyn Service = module.resolve_ref();
+        let result = service.execute(RequestDto::from_str(message)?).await?;
+        Ok(serde_json::to_value(&result).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let mut repository = MockRepository::new();
+        repository.expect_ping().times(1).returning(|| {
+            Ok(GatewayProbe {
+                round_trip: Duration::from_micros(1500),
+                version: None,
+            })
+        });
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .return_const(CONFIG.get_or_init(Config::default));
+
+        let message = r#"{"request_type": "SYSTEM", "command": "PING"}"#;
+        let result = execute(repository, state, message).await.unwrap();
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "SYSTEM",
+                "command": "PING",
+                "gateway_url": "http://localhost:8000",
+                "round_trip_ms": 1.5
+            }
+        });
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test]
+    async fn ping_failed() {
+        // 疎通できない場合はエラーを返す
+        let mut repository = MockRepository::new();
+        repository.expect_ping().times(1).returning(|| {
+            Err(error::Error::create_local_error(
+                "WebRTC Gateway http://localhost:8000 is unreachable",
+            ))
+        });
+
+        let message = r#"{"request_type": "SYSTEM", "command": "PING"}"#;
+        let result = execute(repository, MockGlobalState::new(), message).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn version() {
+        // WebRTC Gatewayに接続できなくても、rust_moduleのバージョンは返す
+        let mut repository = MockRepository::new();
+        repository
+            .expect_ping()
+            .times(1)
+            .returning(|| Err(error::Error::create_local_error("unreachable")));
+        let mut state = MockGlobalState::new();
+        state
+            .expect_config()
+            .return_const(CONFIG.get_or_init(Config::default));
+
+        let message = r#"{"request_type": "SYSTEM", "command": "VERSION"}"#;
+        let result = execute(repository, state, message).await.unwrap();
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "SYSTEM",
+                "command": "VERSION",
+                "version": env!("CARGO_PKG_VERSION"),
+                "gateway_url": "http://localhost:8000"
+            }
+        });
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test]
+    async fn state() {
+        let mut state = MockGlobalState::new();
+        state.expect_list_topics().times(1).returning(|| {
+            vec![DataPipeInfo {
+                data_connection_id: DataConnectionId::try_create(
+                    "dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+                )
+                .unwrap(),
+                data_id: DataId::try_create("da-06cf1d26-0ef0-4b03-aca6-933027d434c2").unwrap(),
+                data_pipe_port_num: 60000,
+            }]
+        });
+        state.expect_list_call_responses().times(1).returning(|| {
+            vec![CallResponseDto {
+                send_params: SendParams {
+                    video: None,
+                    audio: None,
+                },
+                redirect_params: None,
+                media_connection_id: MediaConnectionId::try_create(
+                    "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+                )
+                .unwrap(),
+            }]
+        });
+
+        let message = r#"{"request_type": "SYSTEM", "command": "STATE"}"#;
+        let result = execute(MockRepository::new(), state, message)
+            .await
+            .unwrap();
+        let expected = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "SYSTEM",
+                "command": "STATE",
+                "data_connections": [{
+                    "data_connection_id": "dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+                    "data_id": "da-06cf1d26-0ef0-4b03-aca6-933027d434c2",
+                    "data_pipe_port_num": 60000
+                }],
+                "media_connections": [{
+                    "send_params": {},
+                    "media_connection_id": "mc-102127d9-30de-413b-93f7-41a33e39d82b"
+                }]
+            }
+        });
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn unknown_command() {
+        // 未定義のcommandはパースの時点で拒否され、SHUTDOWNとして扱われることはない
+        let message = r#"{"request_type": "SYSTEM", "command": "SHUTDOWM"}"#;
+        assert!(RequestDto::from_str(message).is_err());
     }
 }
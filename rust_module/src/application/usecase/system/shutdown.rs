@@ -0,0 +1,554 @@
+// ROS終了時に、Rust側とWebRTC Gateway上で確保した全てのリソースを開放する
+// 責務は以下の通りである
+// 0. WebRTC Gatewayの応答を待っている処理中のリクエストを中断する
+// 1. 保存している全てのDataConnectionを切断し、Dataポートを削除し、Pluginを開放する
+// 2. 保存している全てのMediaConnectionを切断し、Media, RTCPポートを削除する
+// 3. PEER CREATEで登録した全てのPeerを削除し、C++側に通知する
+// 4. 各手順の結果をまとめてログに出力し、返す
+//
+// WebRTC Gatewayが応答しなくなっていてもROSの終了を妨げないよう、各手順には期限を設ける
+// 期限切れや失敗した手順があっても、残りの手順は続行する
+// CLOSEイベントの処理と二重に開放しないよう、保存した情報は切断前に取り出しておく
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use shaku::{Component, Interface};
+
+use crate::application::dto::response::{PeerRecordStatusDto, ShutdownSummaryDto};
+use crate::domain::entity::request::{DataRequest, MediaRequest, PeerRequest, Request};
+use crate::domain::entity::response::ResponseResult;
+use crate::domain::entity::{
+    DataConnectionIdWrapper, DataIdWrapper, MediaConnectionIdWrapper, MediaIdWrapper, PeerInfo,
+    RtcpIdWrapper, SerializableId, SerializableSocket,
+};
+use crate::domain::repository::Repository;
+use crate::ffi::rust_to_c_bridge::state_objects::{CallbackFunctions, GlobalState, Logger};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub(crate) trait ShutdownProcess: Interface {
+    /// 全てのDataConnection, MediaConnectionと、登録済みの全てのPeer及び与えられたPeerを開放し、結果を返す
+    /// 与えられたPeerは、Rust側に登録されていないPeerを削除するために利用する
+    async fn shutdown(&self, peers: Vec<PeerInfo>) -> ShutdownSummaryDto;
+}
+
+#[derive(Component)]
+#[shaku(interface = ShutdownProcess)]
+pub(crate) struct ShutdownProcessImpl {
+    #[shaku(inject)]
+    repository: Arc<dyn Repository>,
+    #[shaku(inject)]
+    state: Arc<dyn GlobalState>,
+    #[shaku(inject)]
+    callback: Arc<dyn CallbackFunctions>,
+    #[shaku(inject)]
+    logger: Arc<dyn Logger>,
+}
+
+#[async_trait]
+impl ShutdownProcess for ShutdownProcessImpl {
+    async fn shutdown(&self, peers: Vec<PeerInfo>) -> ShutdownSummaryDto {
+        let mut summary = ShutdownSummaryDto::default();
+
+        // 0. 処理中のリクエストの中断
+        // 中断されたUseCaseは、確保済みのリソースを自身で開放する
+        self.state.request_canceller().cancel_all();
+
+        // 1. DataConnectionの開放
+        let mut data_connections = self.state.list_topics();
+        data_connections.sort_by(|a, b| {
+            a.data_connection_id
+                .as_str()
+                .cmp(b.data_connection_id.as_str())
+        });
+        for info in data_connections {
+            // CLOSEイベントで既に開放済みの場合は何もしない
+            self.state.detach_data_connection(&info.data_connection_id);
+            let info = match self.state.remove_topic(&info.data_connection_id) {
+                Some(info) => info,
+                None => continue,
+            };
+
+            let name = format!("DATA DISCONNECT {}", info.data_connection_id.as_str());
+            let request = Request::Data(DataRequest::Disconnect {
+                params: DataConnectionIdWrapper {
+                    data_connection_id: info.data_connection_id.clone(),
+                },
+            });
+            self.run_step(name, request, &mut summary).await;
+
+            let name = format!("DATA DELETE {}", info.data_id.as_str());
+            let request = Request::Data(DataRequest::Delete {
+                params: DataIdWrapper {
+                    data_id: info.data_id.clone(),
+                },
+            });
+            self.run_step(name, request, &mut summary).await;
+
+            // PluginはC++側で開放するため、WebRTC Gatewayの状態に関わらず開放する
+            self.callback
+                .data_connection_deleted_callback(info.data_pipe_port_num);
+            summary
+                .completed
+                .push(format!("UNLOAD_PLUGIN {}", info.data_pipe_port_num));
+        }
+
+        // 2. MediaConnectionの開放
+        let mut media_connections = self.state.list_call_responses();
+        media_connections.sort_by(|a, b| {
+            a.media_connection_id
+                .as_str()
+                .cmp(b.media_connection_id.as_str())
+        });
+        for call_response in media_connections {
+            self.state
+                .detach_media_connection(&call_response.media_connection_id);
+            let call_response = match self
+                .state
+                .remove_call_response(&call_response.media_connection_id)
+            {
+                Some(call_response) => call_response,
+                None => continue,
+            };
+
+            let name = format!(
+                "MEDIA DISCONNECT {}",
+                call_response.media_connection_id.as_str()
+            );
+            let request = Request::Media(MediaRequest::Disconnect {
+                params: MediaConnectionIdWrapper {
+                    media_connection_id: call_response.media_connection_id.clone(),
+                },
+            });
+            self.run_step(name, request, &mut summary).await;
+
+            let pairs = [
+                call_response.send_params.video,
+                call_response.send_params.audio,
+            ];
+            for pair in pairs.into_iter().flatten() {
+                let media_id = pair.media.get_id().unwrap();
+                let name = format!("MEDIA CONTENT_DELETE {}", media_id.as_str());
+                let request = Request::Media(MediaRequest::ContentDelete {
+                    params: MediaIdWrapper { media_id },
+                });
+                self.run_step(name, request, &mut summary).await;
+
+                let rtcp_id = pair.rtcp.get_id().unwrap();
+                let name = format!("MEDIA RTCP_DELETE {}", rtcp_id.as_str());
+                let request = Request::Media(MediaRequest::RtcpDelete {
+                    params: RtcpIdWrapper { rtcp_id },
+                });
+                self.run_step(name, request, &mut summary).await;
+            }
+        }
+
+        // 3. Peerの削除
+        // Peerを削除するとWebRTC Gateway上の接続も全て閉じられるため、最後に行う
+        // CLOSEイベントを受信済みのPeerはWebRTC Gateway上に存在しないので、一覧から消すだけで良い
+        let mut targets = vec![];
+        for record in self.state.list_peers() {
+            self.state.remove_peer(&record.peer_id);
+            if record.status == PeerRecordStatusDto::Open {
+                targets.push(record.peer_info());
+            }
+        }
+        for peer_info in peers {
+            if !targets.iter().any(|p| p.peer_id() == peer_info.peer_id()) {
+                targets.push(peer_info);
+            }
+        }
+        targets.sort_by(|a, b| a.peer_id().as_str().cmp(b.peer_id().as_str()));
+        for peer_info in targets {
+            let name = format!("PEER DELETE {}", peer_info.peer_id().as_str());
+            let request = Request::Peer(PeerRequest::Delete { params: peer_info });
+            self.run_step(name, request, &mut summary).await;
+        }
+        self.callback.peer_deleted_callback();
+
+        // 4. 結果の報告
+        let message = format!(
+            "shutdown finished: {}",
+            serde_json::to_string(&summary).unwrap()
+        );
+        if summary.failed.is_empty() && summary.timed_out.is_empty() {
+            self.logger.info(&message);
+        } else {
+            self.logger.warn(&message);
+        }
+
+        summary
+    }
+}
+
+impl ShutdownProcessImpl {
+    // 期限付きでWebRTC Gatewayにリクエストを送り、結果をsummaryに記録する
+    async fn run_step(&self, name: String, request: Request, summary: &mut ShutdownSummaryDto) {
+        let timeout = Duration::from_millis(self.state.config().shutdown_step_timeout_ms);
+        match tokio::time::timeout(timeout, self.repository.register(request)).await {
+            Ok(Ok(ResponseResult::Success(_))) => summary.completed.push(name),
+            Ok(Ok(ResponseResult::Error(message))) => {
+                summary.failed.push(format!("{}: {}", name, message))
+            }
+            Ok(Err(e)) => summary.failed.push(format!("{}: {}", name, e.message())),
+            Err(_) => summary.timed_out.push(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod shutdown_test {
+    use once_cell::sync::OnceCell;
+    use shaku::HasComponent;
+
+    use super::*;
+    use crate::application::dto::response::{
+        CallResponseDto, MediaPair, PeerRecordDto, SendParams,
+    };
+    use crate::config::Config;
+    use crate::di::ShutdownModule;
+    use crate::domain::entity::response::{DataResponse, MediaResponse, PeerResponse, Response};
+    use crate::domain::entity::{
+        DataConnectionId, DataId, MediaConnectionId, MediaId, RtcpId, SocketInfo,
+    };
+    use crate::domain::repository::MockRepository;
+    use crate::ffi::rust_to_c_bridge::c_functions_wrapper::DataPipeInfo;
+    use crate::ffi::rust_to_c_bridge::state_objects::{
+        MockCallbackFunctions, MockGlobalState, MockLogger, RequestCanceller,
+    };
+
+    static CONFIG: OnceCell<Config> = OnceCell::new();
+
+    fn config() -> &'static Config {
+        CONFIG.get_or_init(|| Config {
+            shutdown_step_timeout_ms: 50,
+            ..Config::default()
+        })
+    }
+
+    fn canceller() -> &'static RequestCanceller {
+        static CANCELLER: OnceCell<RequestCanceller> = OnceCell::new();
+        CANCELLER.get_or_init(RequestCanceller::new)
+    }
+
+    fn create_data_pipe_info() -> DataPipeInfo {
+        DataPipeInfo {
+            data_connection_id: DataConnectionId::try_create(
+                "dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+            )
+            .unwrap(),
+            data_id: DataId::try_create("da-06cf1d26-0ef0-4b03-aca6-933027d434c2").unwrap(),
+            data_pipe_port_num: 60000,
+        }
+    }
+
+    fn create_call_response() -> CallResponseDto {
+        CallResponseDto {
+            send_params: SendParams {
+                video: Some(MediaPair {
+                    media: SocketInfo::<MediaId>::try_create(
+                        Some("vi-4d053831-5dc2-461b-a358-d062d6115216".to_string()),
+                        "127.0.0.1",
+                        10000,
+                    )
+                    .unwrap(),
+                    rtcp: SocketInfo::<RtcpId>::try_create(
+                        Some("rc-4d053831-5dc2-461b-a358-d062d6115216".to_string()),
+                        "127.0.0.1",
+                        10001,
+                    )
+                    .unwrap(),
+                }),
+                audio: None,
+            },
+            redirect_params: None,
+            media_connection_id: MediaConnectionId::try_create(
+                "mc-102127d9-30de-413b-93f7-41a33e39d82b",
+            )
+            .unwrap(),
+        }
+    }
+
+    fn create_state() -> MockGlobalState {
+        let mut state = MockGlobalState::new();
+        state.expect_config().return_const(config());
+        state
+            .expect_request_canceller()
+            .times(1)
+            .return_const(canceller());
+        state
+            .expect_list_topics()
+            .returning(|| vec![create_data_pipe_info()]);
+        state
+            .expect_detach_data_connection()
+            .times(1)
+            .return_const(());
+        state
+            .expect_remove_topic()
+            .times(1)
+            .returning(|_| Some(create_data_pipe_info()));
+        state
+            .expect_list_call_responses()
+            .returning(|| vec![create_call_response()]);
+        state
+            .expect_detach_media_connection()
+            .times(1)
+            .return_const(());
+        state
+            .expect_remove_call_response()
+            .times(1)
+            .returning(|_| Some(create_call_response()));
+        state.expect_list_peers().returning(Vec::new);
+        state
+    }
+
+    fn create_callback() -> MockCallbackFunctions {
+        let mut callback = MockCallbackFunctions::new();
+        callback
+            .expect_data_connection_deleted_callback()
+            .times(1)
+            .returning(|port| assert_eq!(port, 60000));
+        callback
+            .expect_peer_deleted_callback()
+            .times(1)
+            .returning(|| ());
+        callback
+    }
+
+    fn create_peer() -> PeerInfo {
+        PeerInfo::try_create("peer_id", "pt-9749250e-d157-4f80-9ee2-359ce8524308").unwrap()
+    }
+
+    async fn execute(
+        repository: MockRepository,
+        state: MockGlobalState,
+        callback: MockCallbackFunctions,
+    ) -> ShutdownSummaryDto {
+        let mut logger = MockLogger::new();
+        logger.expect_info().returning(|_| ());
+        logger.expect_warn().returning(|_| ());
+        let module = ShutdownModule::builder()
+            .with_component_override::<dyn Repository>(Box::new(repository))
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(callback))
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .build();
+        let process: &dyn ShutdownProcess = module.resolve_ref();
+        process.shutdown(vec![create_peer()]).await
+    }
+
+    fn success_response(request: Request) -> ResponseResult {
+        let response = match request {
+            Request::Data(DataRequest::Disconnect { params }) => {
+                Response::Data(DataResponse::Disconnect(params))
+            }
+            Request::Data(DataRequest::Delete { params }) => {
+                Response::Data(DataResponse::Delete(params))
+            }
+            Request::Media(MediaRequest::Disconnect { .. }) => {
+                Response::Media(MediaResponse::Disconnect(None))
+            }
+            Request::Media(MediaRequest::ContentDelete { params }) => {
+                Response::Media(MediaResponse::ContentDelete(params))
+            }
+            Request::Media(MediaRequest::RtcpDelete { params }) => {
+                Response::Media(MediaResponse::RtcpDelete(params))
+            }
+            Request::Peer(PeerRequest::Delete { params }) => {
+                Response::Peer(PeerResponse::Delete(params))
+            }
+            _ => unreachable!(),
+        };
+        ResponseResult::Success(response)
+    }
+
+    #[tokio::test]
+    async fn release_all() {
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(6)
+            .returning(|request| Ok(success_response(request)));
+
+        let summary = execute(repository, create_state(), create_callback()).await;
+        assert_eq!(
+            summary.completed,
+            vec![
+                "DATA DISCONNECT dc-8bdef7a1-65c8-46be-a82e-37d51c776309",
+                "DATA DELETE da-06cf1d26-0ef0-4b03-aca6-933027d434c2",
+                "UNLOAD_PLUGIN 60000",
+                "MEDIA DISCONNECT mc-102127d9-30de-413b-93f7-41a33e39d82b",
+                "MEDIA CONTENT_DELETE vi-4d053831-5dc2-461b-a358-d062d6115216",
+                "MEDIA RTCP_DELETE rc-4d053831-5dc2-461b-a358-d062d6115216",
+                "PEER DELETE peer_id",
+            ]
+        );
+        assert!(summary.failed.is_empty());
+        assert!(summary.timed_out.is_empty());
+    }
+
+    #[tokio::test]
+    async fn continue_after_failure() {
+        // 切断に失敗しても、ポートの削除とPluginの開放は続行する
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(6)
+            .returning(|request| match request {
+                Request::Data(DataRequest::Disconnect { .. }) => Ok(ResponseResult::Error(
+                    "data_connection_id is not found".to_string(),
+                )),
+                Request::Media(MediaRequest::Disconnect { .. }) => Err(
+                    crate::error::Error::create_local_error("connection refused"),
+                ),
+                request => Ok(success_response(request)),
+            });
+
+        let summary = execute(repository, create_state(), create_callback()).await;
+        assert_eq!(summary.completed.len(), 5);
+        assert_eq!(
+            summary.failed,
+            vec![
+                "DATA DISCONNECT dc-8bdef7a1-65c8-46be-a82e-37d51c776309: data_connection_id is not found",
+                "MEDIA DISCONNECT mc-102127d9-30de-413b-93f7-41a33e39d82b: connection refused",
+            ]
+        );
+    }
+
+    // PEER DELETEに応答しないWebRTC Gatewayを模擬する
+    struct HungRepository {}
+
+    #[async_trait]
+    impl Repository for HungRepository {
+        async fn register(&self, request: Request) -> Result<ResponseResult, crate::error::Error> {
+            if let Request::Peer(_) = request {
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+            }
+            Ok(success_response(request))
+        }
+
+        async fn receive_event(&self) -> Result<ResponseResult, crate::error::Error> {
+            Err(crate::error::Error::create_local_error("not supported"))
+        }
+
+        fn subscribe_events(&self) -> crate::domain::repository::EventSubscription {
+            unreachable!()
+        }
+
+        async fn ping(
+            &self,
+        ) -> Result<crate::domain::repository::GatewayProbe, crate::error::Error> {
+            Err(crate::error::Error::create_local_error("not supported"))
+        }
+    }
+
+    #[tokio::test]
+    async fn hung_gateway() {
+        // WebRTC Gatewayが応答しなくても、期限切れとして扱い終了処理を完了させる
+        let mut logger = MockLogger::new();
+        logger.expect_warn().times(1).returning(|_| ());
+        let module = ShutdownModule::builder()
+            .with_component_override::<dyn Repository>(Box::new(HungRepository {}))
+            .with_component_override::<dyn GlobalState>(Box::new(create_state()))
+            .with_component_override::<dyn CallbackFunctions>(Box::new(create_callback()))
+            .with_component_override::<dyn Logger>(Box::new(logger))
+            .build();
+        let process: &dyn ShutdownProcess = module.resolve_ref();
+        let summary = process.shutdown(vec![create_peer()]).await;
+
+        assert_eq!(summary.completed.len(), 6);
+        assert_eq!(summary.timed_out, vec!["PEER DELETE peer_id"]);
+    }
+
+    #[tokio::test]
+    async fn delete_all_peers() {
+        // 登録済みのPeerとC++側から与えられたPeerを、重複なく全て削除する
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(2)
+            .returning(|request| Ok(success_response(request)));
+        let mut state = MockGlobalState::new();
+        state.expect_config().return_const(config());
+        state
+            .expect_request_canceller()
+            .times(1)
+            .return_const(canceller());
+        state.expect_list_topics().returning(Vec::new);
+        state.expect_list_call_responses().returning(Vec::new);
+        state.expect_list_peers().times(1).returning(|| {
+            let token = "pt-9749250e-d157-4f80-9ee2-359ce8524308";
+            let closed = PeerRecordDto {
+                status: PeerRecordStatusDto::Closed,
+                ..PeerRecordDto::new(&PeerInfo::try_create("closed_peer", token).unwrap())
+            };
+            vec![
+                PeerRecordDto::new(&PeerInfo::try_create("peer_id", token).unwrap()),
+                PeerRecordDto::new(&PeerInfo::try_create("another_peer", token).unwrap()),
+                closed,
+            ]
+        });
+        state.expect_remove_peer().times(3).returning(|_| None);
+        let mut callback = MockCallbackFunctions::new();
+        callback
+            .expect_peer_deleted_callback()
+            .times(1)
+            .returning(|| ());
+
+        let summary = execute(repository, state, callback).await;
+        assert_eq!(
+            summary.completed,
+            vec!["PEER DELETE another_peer", "PEER DELETE peer_id"]
+        );
+    }
+
+    #[tokio::test]
+    async fn already_released() {
+        // CLOSEイベントで既に開放済みのConnectionには何もしない
+        let mut repository = MockRepository::new();
+        repository
+            .expect_register()
+            .times(1)
+            .returning(|request| Ok(success_response(request)));
+        let mut state = MockGlobalState::new();
+        state.expect_config().return_const(config());
+        state
+            .expect_request_canceller()
+            .times(1)
+            .return_const(canceller());
+        state
+            .expect_list_topics()
+            .returning(|| vec![create_data_pipe_info()]);
+        state
+            .expect_detach_data_connection()
+            .times(1)
+            .return_const(());
+        state.expect_remove_topic().times(1).returning(|_| None);
+        state
+            .expect_list_call_responses()
+            .returning(|| vec![create_call_response()]);
+        state
+            .expect_detach_media_connection()
+            .times(1)
+            .return_const(());
+        state
+            .expect_remove_call_response()
+            .times(1)
+            .returning(|_| None);
+        state.expect_list_peers().returning(Vec::new);
+        let mut callback = MockCallbackFunctions::new();
+        callback.expect_data_connection_deleted_callback().times(0);
+        callback
+            .expect_peer_deleted_callback()
+            .times(1)
+            .returning(|| ());
+
+        let summary = execute(repository, state, callback).await;
+        assert_eq!(summary.completed, vec!["PEER DELETE peer_id"]);
+    }
+}
@@ -0,0 +1,26 @@
+// benches/以下のベンチマークから、crate内部の処理を呼び出すための関数群
+// "bench" featureを指定した場合のみビルドされ、通常の利用者には公開しない
+use std::hint::black_box;
+
+use crate::application::dto::request::RequestDto;
+use crate::application::factory::{ServiceContainer, ServiceKind};
+
+/// ベンチマークで振り分けるリクエスト
+/// JSONのパースは計測対象に含めないよう、事前に行っておく
+pub struct Request(RequestDto);
+
+impl Request {
+    pub fn parse(message: &str) -> Self {
+        Request(serde_json::from_str(message).expect("invalid request"))
+    }
+}
+
+/// 従来の方式。リクエストごとにDIコンテナを組み立ててServiceを生成する
+pub fn build_per_request(request: &Request) {
+    black_box(ServiceKind::from_request(&request.0).build());
+}
+
+/// 現在の方式。共有のServiceContainerからServiceを取得する
+pub fn lookup_shared(request: &Request) {
+    black_box(ServiceContainer::global().service(&request.0));
+}
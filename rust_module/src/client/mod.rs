@@ -0,0 +1,486 @@
+//! Rustのプログラムから、JSONやROSを介さずにWebRTC Gatewayを操作するためのAPI
+//! call_serviceと同じUseCaseを、型付きのリクエストで直接呼び出す
+//!
+//! ```no_run
+//! use skyway::client::{CreatePeerParams, EventFilter, SkyWayClient};
+//!
+//! # async fn example(params: CreatePeerParams) -> Result<(), skyway::client::ClientError> {
+//! let client = SkyWayClient::builder()
+//!     .gateway_url("http://127.0.0.1:8000")
+//!     .start()
+//!     .await?;
+//! let peer_info = client.create_peer(params).await?;
+//! let mut events = client.events(EventFilter::default());
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event);
+//! }
+//! client.delete_peer(peer_info).await?;
+//! client.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::application;
+use crate::application::dto::request::{
+    AnswerParametersDto, ConnectDtoParams, DataRequestDto, MediaRequestDto, PeerRequestDto,
+    RedirectDtoParams, RequestDto,
+};
+use crate::application::dto::response::{
+    DataResponseDto, MediaResponseDto, PeerResponseDto, ResponseDto, ResponseDtoResult,
+};
+use crate::config::REGISTERED_GATEWAY_URL;
+use crate::domain::entity::{DataConnectionIdWrapper, MediaConnectionIdWrapper};
+use crate::error;
+use crate::error::ErrorCode as ErrorCodeEntity;
+use crate::ffi::rust_to_c_bridge::state_objects::CHANNELS;
+use crate::host;
+use crate::host::Host;
+
+pub use crate::application::dto::request::{
+    AnswerQueryDto as AnswerQuery, CallQueryDto as CallQuery, ConstraintsDto as Constraints,
+    MediaParamsDto as MediaParams, PluginInfo,
+};
+pub use crate::application::dto::response::{
+    CallResponseDto as CallResponse, DataConnectionEventDto as DataEvent,
+    DataDisconnectDto as DataDisconnect, ErrorDto as ClientError,
+    MediaConnectionEventEnumDto as MediaEvent, PeerEventEnumDto as PeerEvent,
+    ShutdownSummaryDto as ShutdownSummary,
+};
+pub use crate::application::usecase::event::hub::EventFilter;
+pub use crate::domain::entity::{
+    AnswerResult, ConnectQueryOption, CreatePeerParams, DataConnectionId, MediaConnectionId,
+    PeerId, PeerInfo, RedirectParameters, Token,
+};
+pub use crate::error::ErrorCode;
+pub use crate::host::{file_logger, stderr_logger, DataPipe, InProcessDataPipe, LogLevel, LogSink};
+
+// wait_for_shutdownで終了を確認する間隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// SkyWayClientを起動するための設定
+pub struct SkyWayClientBuilder {
+    gateway_url: Option<String>,
+    host: Host,
+}
+
+impl Default for SkyWayClientBuilder {
+    fn default() -> Self {
+        SkyWayClientBuilder {
+            gateway_url: None,
+            // ライブラリとして使う場合、シグナルの扱いは呼び出し側に任せる
+            host: Host::default().shutdown_on_signal(false),
+        }
+    }
+}
+
+impl SkyWayClientBuilder {
+    /// WebRTC GatewayのURL。環境変数や設定ファイルの値より優先される
+    pub fn gateway_url(mut self, gateway_url: impl Into<String>) -> Self {
+        self.gateway_url = Some(gateway_url.into());
+        self
+    }
+
+    /// ログの出力先。指定しない場合は設定項目log_level, log_fileに従って出力する
+    pub fn logger(mut self, logger: impl Fn(LogLevel, &str) + Send + Sync + 'static) -> Self {
+        self.host = self.host.logger(logger);
+        self
+    }
+
+    /// DataConnectionのデータを扱う処理。指定しない場合、受信したデータはDEBUGログに出すだけで破棄する
+    pub fn data_pipe(mut self, data_pipe: impl DataPipe + 'static) -> Self {
+        self.host = self.host.data_pipe(data_pipe);
+        self
+    }
+
+    /// SIGINT/SIGTERMを受信した際に、shutdownと同じ終了処理を行う。既定では行わない
+    pub fn shutdown_on_signal(mut self, shutdown_on_signal: bool) -> Self {
+        self.host = self.host.shutdown_on_signal(shutdown_on_signal);
+        self
+    }
+
+    /// WebRTC Gatewayとのやり取りを開始する
+    /// tokioのRuntime上で呼ぶ必要がある。1つのプロセスで起動できるのは1度だけ
+    pub async fn start(self) -> Result<SkyWayClient, ClientError> {
+        if CHANNELS.get().is_some() {
+            return Err(client_error(
+                ErrorCodeEntity::InvalidRequest,
+                "SkyWayClient is already started",
+            ));
+        }
+
+        if let Some(gateway_url) = self.gateway_url {
+            let _ = REGISTERED_GATEWAY_URL.set(gateway_url);
+        }
+        host::install(self.host);
+        crate::start().await.map_err(|e| ClientError::from(&e))?;
+        Ok(SkyWayClient {
+            peers: Arc::new(Mutex::new(vec![])),
+            timeout: None,
+        })
+    }
+}
+
+/// WebRTC Gatewayを操作するためのクライアント
+/// 状態はプロセス内で共有されるため、cloneしたクライアントは同じPeer, Connectionを操作する
+#[derive(Debug, Clone)]
+pub struct SkyWayClient {
+    // shutdown時に開放するため、このクライアントで生成したPeerを保持する
+    peers: Arc<Mutex<Vec<PeerInfo>>>,
+    timeout: Option<Duration>,
+}
+
+impl SkyWayClient {
+    pub fn builder() -> SkyWayClientBuilder {
+        SkyWayClientBuilder::default()
+    }
+
+    /// 各リクエスト全体の期限を設定したクライアントを返す
+    /// call_serviceのtimeout_msと同様に、設定ファイルの期限より短い場合のみ有効
+    pub fn with_timeout(&self, timeout: Duration) -> SkyWayClient {
+        SkyWayClient {
+            peers: self.peers.clone(),
+            timeout: Some(timeout),
+        }
+    }
+
+    /// PEER CREATE
+    pub async fn create_peer(&self, params: CreatePeerParams) -> Result<PeerInfo, ClientError> {
+        let request = RequestDto::Peer(PeerRequestDto::Create {
+            params,
+            recovery: None,
+        });
+        match self.execute(request).await? {
+            ResponseDto::Peer(PeerResponseDto::Create(peer_info)) => {
+                self.lock_peers().push(peer_info.clone());
+                Ok(peer_info)
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// PEER DELETE
+    /// 削除に失敗した場合は、shutdown時に開放できるよう保持し続ける
+    pub async fn delete_peer(&self, peer_info: PeerInfo) -> Result<(), ClientError> {
+        let request = RequestDto::Peer(PeerRequestDto::Delete {
+            params: peer_info.clone(),
+        });
+        match self.execute(request).await? {
+            ResponseDto::Peer(PeerResponseDto::Delete(_)) => {
+                self.lock_peers().retain(|peer| peer != &peer_info);
+                Ok(())
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// DATA CONNECT
+    /// pluginはSkyWayClientBuilder::data_pipeで登録したDataPipeに渡される
+    pub async fn connect_data(
+        &self,
+        peer_info: &PeerInfo,
+        target_id: PeerId,
+        options: Option<ConnectQueryOption>,
+        plugin: PluginInfo,
+    ) -> Result<DataConnectionId, ClientError> {
+        let request = RequestDto::Data(DataRequestDto::Connect {
+            params: ConnectDtoParams {
+                peer_id: peer_info.peer_id(),
+                token: peer_info.token(),
+                target_id,
+                options,
+                params: None,
+                redirect_params: None,
+                plugin_info: plugin,
+            },
+        });
+        match self.execute(request).await? {
+            ResponseDto::Data(DataResponseDto::Connect(wrapper)) => Ok(wrapper.data_connection_id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// DATA REDIRECT
+    /// 着信したDataConnectionを、登録したDataPipeで扱う
+    pub async fn redirect_data(
+        &self,
+        data_connection_id: DataConnectionId,
+        plugin: PluginInfo,
+    ) -> Result<(), ClientError> {
+        let request = RequestDto::Data(DataRequestDto::Redirect {
+            params: RedirectDtoParams {
+                data_connection_id,
+                plugin_info: plugin,
+            },
+        });
+        match self.execute(request).await? {
+            ResponseDto::Data(DataResponseDto::Redirect(_)) => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// DATA DISCONNECT
+    pub async fn disconnect_data(
+        &self,
+        data_connection_id: DataConnectionId,
+    ) -> Result<DataDisconnect, ClientError> {
+        let request = RequestDto::Data(DataRequestDto::Disconnect {
+            params: DataConnectionIdWrapper { data_connection_id },
+        });
+        match self.execute(request).await? {
+            ResponseDto::Data(DataResponseDto::Disconnect(result)) => Ok(result),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// MEDIA CALL
+    pub async fn call(&self, query: CallQuery) -> Result<MediaConnectionId, ClientError> {
+        let request = RequestDto::Media(MediaRequestDto::Call { params: query });
+        match self.execute(request).await? {
+            ResponseDto::Media(MediaResponseDto::Call(wrapper)) => Ok(wrapper.media_connection_id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// MEDIA ANSWER
+    pub async fn answer(
+        &self,
+        media_connection_id: MediaConnectionId,
+        query: AnswerQuery,
+    ) -> Result<AnswerResult, ClientError> {
+        let request = RequestDto::Media(MediaRequestDto::Answer {
+            params: AnswerParametersDto {
+                media_connection_id,
+                answer_query: query,
+            },
+        });
+        match self.execute(request).await? {
+            ResponseDto::Media(MediaResponseDto::Answer(result)) => Ok(result),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// MEDIA DISCONNECT
+    pub async fn disconnect_media(
+        &self,
+        media_connection_id: MediaConnectionId,
+    ) -> Result<(), ClientError> {
+        let request = RequestDto::Media(MediaRequestDto::Disconnect {
+            params: MediaConnectionIdWrapper {
+                media_connection_id,
+            },
+        });
+        match self.execute(request).await? {
+            ResponseDto::Media(MediaResponseDto::Disconnect(_)) => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// filterに一致するイベントを購読する
+    /// EventStreamをdropすると購読を解除する
+    pub fn events(&self, filter: EventFilter) -> EventStream {
+        EventStream {
+            subscriber_id: application::subscribe(filter),
+        }
+    }
+
+    /// 保持している全てのConnectionとPeerを開放し、イベントの配信を止める
+    /// シグナルによる終了処理などで既に終了している場合は、何もせずに空の結果を返す
+    pub async fn shutdown(self) -> ShutdownSummary {
+        if host::is_shutdown_started() {
+            return ShutdownSummary::default();
+        }
+        let peers = self.lock_peers().drain(..).collect();
+        let summary = application::shutdown(peers).await;
+        host::begin_shutdown();
+        summary
+    }
+
+    fn lock_peers(&self) -> std::sync::MutexGuard<'_, Vec<PeerInfo>> {
+        self.peers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// shutdownが呼ばれるか、shutdown_on_signalを指定した場合にシグナルによる終了処理が完了するまで待つ
+    pub async fn wait_for_shutdown(&self) {
+        while !host::is_shutdown_started() {
+            tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+        }
+    }
+
+    async fn execute(&self, request: RequestDto) -> Result<ResponseDto, ClientError> {
+        application::execute(request, self.timeout)
+            .await
+            .map_err(|e| ClientError::from(&e))
+    }
+}
+
+/// SkyWayClient::eventsで購読したイベントを受け取る
+pub struct EventStream {
+    subscriber_id: u64,
+}
+
+impl EventStream {
+    /// 次のイベントを待つ。SkyWayClientが終了した場合はNoneを返す
+    pub async fn next(&mut self) -> Option<Event> {
+        application::next_event(self.subscriber_id)
+            .await
+            .ok()
+            .map(Event::from_value)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        application::unsubscribe_events(self.subscriber_id);
+    }
+}
+
+/// WebRTC Gatewayから届いたイベント
+// 受信したイベントは1件ずつ受け渡すだけなので、Mediaイベントの大きさに合わせたままにする
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Peer(PeerEvent),
+    Data(DataEvent),
+    Media(MediaEvent),
+    /// イベントの処理に失敗した
+    Error(ClientError),
+    /// 上記に分類されないイベント。receive_eventsが返すJSONと同じ形式
+    Unknown(Value),
+}
+
+impl Event {
+    fn from_value(value: Value) -> Event {
+        match ResponseDtoResult::from_str(&value.to_string()) {
+            Ok(ResponseDtoResult::Success(ResponseDto::Peer(PeerResponseDto::Event(event)))) => {
+                Event::Peer(event)
+            }
+            Ok(ResponseDtoResult::Success(ResponseDto::Data(DataResponseDto::Event(event)))) => {
+                Event::Data(event)
+            }
+            Ok(ResponseDtoResult::Success(ResponseDto::Media(MediaResponseDto::Event(event)))) => {
+                Event::Media(event)
+            }
+            Ok(ResponseDtoResult::Error(error)) => Event::Error(error),
+            _ => Event::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.step {
+            Some(step) => write!(f, "{:?} at {}: {}", self.code, step, self.message),
+            None => write!(f, "{:?}: {}", self.code, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+fn client_error(code: ErrorCodeEntity, message: &str) -> ClientError {
+    ClientError::from(&error::Error::create_error(code, message))
+}
+
+// UseCaseがリクエストと対応しないレスポンスを返した場合
+fn unexpected(response: ResponseDto) -> ClientError {
+    let message = format!("unexpected response {:?}", response);
+    client_error(ErrorCodeEntity::Internal, &message)
+}
+
+#[cfg(test)]
+mod client_test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn event_from_value() {
+        let value = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "DATA",
+                "command": "EVENT",
+                "event": "OPEN",
+                "data_connection_id": "dc-4995f372-fb6a-4196-b30a-ce11e5c7f56c",
+                "request_id": "req-1"
+            }
+        });
+        match Event::from_value(value) {
+            Event::Data(DataEvent::OPEN(wrapper)) => assert_eq!(
+                wrapper.data_connection_id.as_str(),
+                "dc-4995f372-fb6a-4196-b30a-ce11e5c7f56c"
+            ),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let value = json!({
+            "is_success": true,
+            "result": {
+                "request_type": "MEDIA",
+                "command": "EVENT",
+                "event": "CLOSE",
+                "media_connection_id": "mc-102127d9-30de-413b-93f7-41a33e39d82b"
+            }
+        });
+        assert!(matches!(
+            Event::from_value(value),
+            Event::Media(MediaEvent::Close(_))
+        ));
+
+        let value = json!({
+            "is_success": false,
+            "result": {
+                "request_type": null,
+                "command": null,
+                "code": "INTERNAL",
+                "message": "invalid message in receive_events"
+            }
+        });
+        match Event::from_value(value) {
+            Event::Error(error) => assert_eq!(error.code, ErrorCode::Internal),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let value = json!({"is_success": true, "result": {"request_type": "UNKNOWN"}});
+        assert!(matches!(Event::from_value(value), Event::Unknown(_)));
+    }
+
+    #[tokio::test]
+    async fn delete_peer_failed() {
+        use crate::ffi::rust_to_c_bridge::state_objects::PEER_STATE_INSTANCE;
+
+        // 削除に失敗したPeerは、shutdown時に開放できるよう保持し続ける
+        let _ = PEER_STATE_INSTANCE.set(Mutex::new(Default::default()));
+        let peer_info =
+            PeerInfo::try_create("peer_id", "pt-9749250e-d157-4f80-9ee2-359ce8524308").unwrap();
+        let client = SkyWayClient {
+            peers: Arc::new(Mutex::new(vec![peer_info.clone()])),
+            timeout: None,
+        };
+
+        // 期限切れのため、WebRTC Gatewayに送らずにTIMEOUTとなる
+        let result = client
+            .with_timeout(Duration::ZERO)
+            .delete_peer(peer_info.clone())
+            .await;
+        assert_eq!(result.unwrap_err().code, ErrorCode::Timeout);
+        assert_eq!(*client.lock_peers(), vec![peer_info]);
+    }
+
+    #[test]
+    fn display_error() {
+        let error = ClientError::from(
+            &error::Error::create_error(ErrorCodeEntity::PluginLoadFailed, "no plugin")
+                .with_step("LOAD_PLUGIN"),
+        );
+        assert_eq!(
+            error.to_string(),
+            "PluginLoadFailed at LOAD_PLUGIN: no plugin"
+        );
+    }
+}
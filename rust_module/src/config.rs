@@ -0,0 +1,365 @@
+// Rust側モジュールの設定値を管理する
+// 値は以下の優先順位で決定される
+// 1. C++側から`register_gateway_url`等のFFI関数経由で与えられた値
+// 2. 環境変数
+// 3. 環境変数`SKYWAY_CONFIG_PATH`で指定されたJSONファイル
+// 4. デフォルト値
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::application::dto::request::{DataPolicyDto, MediaPolicyDto};
+use crate::error;
+use crate::error::ErrorCode;
+use crate::ffi::rust_to_c_bridge::c_functions_wrapper::LoggerHolder;
+
+// WebRTC GatewayのURLの環境変数名
+pub(crate) const GATEWAY_URL_ENV: &str = "SKYWAY_GATEWAY_URL";
+// DataChannelのデータをC++側のPluginに転送する際のアドレスの環境変数名
+pub(crate) const DATA_REDIRECT_ADDRESS_ENV: &str = "SKYWAY_DATA_REDIRECT_ADDRESS";
+// イベント受信時のポーリング間隔の環境変数名
+pub(crate) const EVENT_POLL_INTERVAL_ENV: &str = "SKYWAY_EVENT_POLL_INTERVAL_MS";
+// PEER CREATE時にOPENイベントを待つ時間の環境変数名
+pub(crate) const PEER_OPEN_TIMEOUT_ENV: &str = "SKYWAY_PEER_OPEN_TIMEOUT_MS";
+// 終了処理の各手順でWebRTC Gatewayの応答を待つ時間の環境変数名
+pub(crate) const SHUTDOWN_STEP_TIMEOUT_ENV: &str = "SKYWAY_SHUTDOWN_STEP_TIMEOUT_MS";
+// WebRTC Gatewayへの各リクエストの応答を待つ時間の環境変数名
+pub(crate) const REQUEST_TIMEOUT_ENV: &str = "SKYWAY_REQUEST_TIMEOUT_MS";
+// CONNECT, REDIRECT, CALL, ANSWERの全体の期限の環境変数名
+pub(crate) const COMPOSITE_TIMEOUT_ENV: &str = "SKYWAY_COMPOSITE_TIMEOUT_MS";
+// C++側がロガーを登録しない場合に、Rust側で出力するログの最低レベルの環境変数名
+pub(crate) const LOG_LEVEL_ENV: &str = "SKYWAY_LOG_LEVEL";
+// C++側がロガーを登録しない場合に、Rust側でログを出力するファイルの環境変数名
+pub(crate) const LOG_FILE_ENV: &str = "SKYWAY_LOG_FILE";
+// 設定ファイルのパスを与える環境変数名
+pub(crate) const CONFIG_PATH_ENV: &str = "SKYWAY_CONFIG_PATH";
+
+// C++側から与えられたWebRTC GatewayのURLを保持する
+pub(crate) static REGISTERED_GATEWAY_URL: OnceCell<String> = OnceCell::new();
+// 起動時に一度だけ読み込んだ設定値を保持する
+pub(crate) static CONFIG_INSTANCE: OnceCell<Config> = OnceCell::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// WebRTC GatewayのURL
+    pub gateway_url: String,
+    /// WebRTC GatewayからC++側のPluginへデータを転送する際の宛先アドレス
+    pub data_redirect_address: String,
+    /// イベント受信時に終了状態を確認する間隔
+    pub event_poll_interval_ms: u64,
+    /// PEER CREATE時にOPENイベントを待つ時間
+    pub peer_open_timeout_ms: u64,
+    /// 終了処理の各手順でWebRTC Gatewayの応答を待つ時間
+    pub shutdown_step_timeout_ms: u64,
+    /// WebRTC Gatewayへの各リクエストの応答を待つ時間
+    pub request_timeout_ms: u64,
+    /// CONNECT, REDIRECT, CALL, ANSWERの全体の期限。超えた場合は確保したリソースを開放する
+    pub composite_timeout_ms: u64,
+    /// 起動時に設定する、着信したDataConnectionを自動的に処理するためのpolicy
+    /// 起動後はDATA POLICYで変更できる
+    pub data_connection_policy: Option<DataPolicyDto>,
+    /// 起動時に設定する、着信したMediaConnectionを自動的に処理するためのpolicy
+    /// 起動後はMEDIA POLICYで変更できる
+    pub media_connection_policy: Option<MediaPolicyDto>,
+    /// C++側がロガーを登録しない場合に出力するログの最低レベル(debug, info, warn, error)
+    pub log_level: String,
+    /// C++側がロガーを登録しない場合にログを出力するファイル。Noneの場合は標準エラー出力に出す
+    pub log_file: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            gateway_url: "http://localhost:8000".to_string(),
+            data_redirect_address: "127.0.0.1".to_string(),
+            event_poll_interval_ms: 1000,
+            peer_open_timeout_ms: 10000,
+            shutdown_step_timeout_ms: 3000,
+            request_timeout_ms: 10000,
+            composite_timeout_ms: 30000,
+            data_connection_policy: None,
+            media_connection_policy: None,
+            log_level: "info".to_string(),
+            log_file: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn global() -> &'static Config {
+        CONFIG_INSTANCE.get_or_init(Config::load)
+    }
+
+    // 設定ファイル、環境変数、C++側から与えられた値を順に読み込む
+    // 読み込みに失敗した値はデフォルト値のまま利用する
+    fn load() -> Config {
+        let file = std::env::var(CONFIG_PATH_ENV)
+            .ok()
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        let (config, errors) = Config::from_sources(
+            file.as_deref(),
+            |key| std::env::var(key).ok(),
+            REGISTERED_GATEWAY_URL.get().cloned(),
+        );
+        for error in errors {
+            let message = format!("failed to load config: {}", error.message());
+            if LoggerHolder::is_allocated() {
+                LoggerHolder::global().warn(message);
+            } else {
+                // ロガーの設定自体を読み込む場合は、ロガーの登録前に呼ばれる
+                eprintln!("{}", message);
+            }
+        }
+        config
+    }
+
+    // 読み込みに失敗した値はエラーとして返し、その値だけデフォルト値もしくは優先度の低い値のままにする
+    pub(crate) fn from_sources(
+        file: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+        registered_gateway_url: Option<String>,
+    ) -> (Config, Vec<error::Error>) {
+        let mut config = Config::default();
+        let mut errors = vec![];
+
+        // 設定ファイルには必要な項目だけ記述すれば良い
+        match file.map(serde_json::from_str::<Map<String, Value>>) {
+            Some(Ok(file)) => config.apply_file(&file, &mut errors),
+            Some(Err(e)) => errors.push(error::Error::SerdeError { error: e }),
+            None => {}
+        }
+        config.apply_env(env, &mut errors);
+
+        if let Some(gateway_url) = registered_gateway_url {
+            config.gateway_url = gateway_url;
+        }
+
+        (config, errors)
+    }
+
+    fn apply_file(&mut self, file: &Map<String, Value>, errors: &mut Vec<error::Error>) {
+        if let Some(gateway_url) = file_value(file, "gateway_url", errors) {
+            self.gateway_url = gateway_url;
+        }
+        if let Some(address) = file_value(file, "data_redirect_address", errors) {
+            self.data_redirect_address = address;
+        }
+        if let Some(interval) = file_value(file, "event_poll_interval_ms", errors) {
+            self.event_poll_interval_ms = interval;
+        }
+        if let Some(timeout) = file_value(file, "peer_open_timeout_ms", errors) {
+            self.peer_open_timeout_ms = timeout;
+        }
+        if let Some(timeout) = file_value(file, "shutdown_step_timeout_ms", errors) {
+            self.shutdown_step_timeout_ms = timeout;
+        }
+        if let Some(timeout) = file_value(file, "request_timeout_ms", errors) {
+            self.request_timeout_ms = timeout;
+        }
+        if let Some(timeout) = file_value(file, "composite_timeout_ms", errors) {
+            self.composite_timeout_ms = timeout;
+        }
+        if let Some(policy) = file_value(file, "data_connection_policy", errors) {
+            self.data_connection_policy = Some(policy);
+        }
+        if let Some(policy) = file_value(file, "media_connection_policy", errors) {
+            self.media_connection_policy = Some(policy);
+        }
+        if let Some(level) = file_value(file, "log_level", errors) {
+            self.log_level = level;
+        }
+        if let Some(path) = file_value(file, "log_file", errors) {
+            self.log_file = Some(path);
+        }
+    }
+
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>, errors: &mut Vec<error::Error>) {
+        let mut number = |key: &str| {
+            let value = env(key)?;
+            parse_number(key, &value).map_err(|e| errors.push(e)).ok()
+        };
+        if let Some(interval) = number(EVENT_POLL_INTERVAL_ENV) {
+            self.event_poll_interval_ms = interval;
+        }
+        if let Some(timeout) = number(PEER_OPEN_TIMEOUT_ENV) {
+            self.peer_open_timeout_ms = timeout;
+        }
+        if let Some(timeout) = number(SHUTDOWN_STEP_TIMEOUT_ENV) {
+            self.shutdown_step_timeout_ms = timeout;
+        }
+        if let Some(timeout) = number(REQUEST_TIMEOUT_ENV) {
+            self.request_timeout_ms = timeout;
+        }
+        if let Some(timeout) = number(COMPOSITE_TIMEOUT_ENV) {
+            self.composite_timeout_ms = timeout;
+        }
+
+        if let Some(gateway_url) = env(GATEWAY_URL_ENV) {
+            self.gateway_url = gateway_url;
+        }
+        if let Some(address) = env(DATA_REDIRECT_ADDRESS_ENV) {
+            self.data_redirect_address = address;
+        }
+        if let Some(level) = env(LOG_LEVEL_ENV) {
+            self.log_level = level;
+        }
+        if let Some(path) = env(LOG_FILE_ENV) {
+            self.log_file = Some(path);
+        }
+    }
+}
+
+// 設定ファイルの項目を1つ読み込む。記述されていない場合やnullの場合はNoneを返す
+fn file_value<T: DeserializeOwned>(
+    file: &Map<String, Value>,
+    key: &str,
+    errors: &mut Vec<error::Error>,
+) -> Option<T> {
+    match file.get(key) {
+        None | Some(Value::Null) => None,
+        Some(value) => serde_json::from_value(value.clone())
+            .map_err(|e| {
+                let message = format!("{} in config file is invalid: {}", key, e);
+                errors.push(error::Error::create_error(
+                    ErrorCode::InvalidRequest,
+                    &message,
+                ));
+            })
+            .ok(),
+    }
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u64, error::Error> {
+    value.parse::<u64>().map_err(|_| {
+        let message = format!("{} is not a number: {}", key, value);
+        error::Error::create_error(ErrorCode::InvalidRequest, &message)
+    })
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+
+    #[test]
+    fn default_values() {
+        let (config, errors) = Config::from_sources(None, |_| None, None);
+        assert_eq!(config, Config::default());
+        assert!(errors.is_empty());
+        assert_eq!(config.gateway_url, "http://localhost:8000");
+    }
+
+    #[test]
+    fn priority() {
+        let file = r#"{
+            "gateway_url": "http://file:8000",
+            "data_redirect_address": "10.0.0.1",
+            "event_poll_interval_ms": 10,
+            "shutdown_step_timeout_ms": 100,
+            "request_timeout_ms": 200,
+            "composite_timeout_ms": 300,
+            "log_level": "debug",
+            "log_file": "/var/log/skyway.log"
+        }"#;
+        let env = |key: &str| match key {
+            GATEWAY_URL_ENV => Some("http://env:8000".to_string()),
+            EVENT_POLL_INTERVAL_ENV => Some("500".to_string()),
+            COMPOSITE_TIMEOUT_ENV => Some("1000".to_string()),
+            LOG_LEVEL_ENV => Some("warn".to_string()),
+            _ => None,
+        };
+
+        // ファイルより環境変数、環境変数よりC++側から与えられた値が優先される
+        let (config, errors) =
+            Config::from_sources(Some(file), env, Some("http://ffi:8000".to_string()));
+        assert!(errors.is_empty());
+        assert_eq!(config.gateway_url, "http://ffi:8000");
+        assert_eq!(config.data_redirect_address, "10.0.0.1");
+        assert_eq!(config.event_poll_interval_ms, 500);
+        assert_eq!(config.shutdown_step_timeout_ms, 100);
+        assert_eq!(config.request_timeout_ms, 200);
+        assert_eq!(config.composite_timeout_ms, 1000);
+        assert_eq!(config.log_level, "warn");
+        assert_eq!(config.log_file.as_deref(), Some("/var/log/skyway.log"));
+    }
+
+    #[test]
+    fn connection_policy() {
+        // 起動時のpolicyは設定ファイルでのみ与えられる
+        let file = r#"{
+            "data_connection_policy": {
+                "rules": [{
+                    "peer_id": {"glob": "robot_*"},
+                    "plugin_info": {"type": "string", "plugins": []}
+                }]
+            },
+            "media_connection_policy": {
+                "rules": [{
+                    "peer_id": {"exact": "camera"},
+                    "answer_query": {"constraints": {}}
+                }]
+            }
+        }"#;
+        let (config, _) = Config::from_sources(Some(file), |_| None, None);
+        let policy = config.data_connection_policy.unwrap();
+        assert!(policy.find_rule("robot_1", "").is_some());
+        assert!(policy.find_rule("operator", "").is_none());
+        let policy = config.media_connection_policy.unwrap();
+        assert!(policy.find_rule("camera", "").is_some());
+        assert!(policy.find_rule("camera_1", "").is_none());
+    }
+
+    #[test]
+    fn invalid_file() {
+        // JSONとして読めない場合はファイル全体を無視し、他の値は読み込む
+        let env = |key: &str| match key {
+            GATEWAY_URL_ENV => Some("http://env:8000".to_string()),
+            _ => None,
+        };
+        let (config, errors) = Config::from_sources(Some("invalid json"), env, None);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(config.gateway_url, "http://env:8000");
+        assert_eq!(config.event_poll_interval_ms, 1000);
+    }
+
+    #[test]
+    fn invalid_file_value() {
+        // 不正な項目だけをデフォルト値のままにし、他の項目は読み込む
+        let file = r#"{
+            "gateway_url": "http://file:8000",
+            "event_poll_interval_ms": "fast",
+            "request_timeout_ms": 200
+        }"#;
+        let (config, errors) = Config::from_sources(Some(file), |_| None, None);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), ErrorCode::InvalidRequest);
+        assert!(errors[0]
+            .message()
+            .starts_with("event_poll_interval_ms in config file is invalid"));
+        assert_eq!(config.gateway_url, "http://file:8000");
+        assert_eq!(config.event_poll_interval_ms, 1000);
+        assert_eq!(config.request_timeout_ms, 200);
+    }
+
+    #[test]
+    fn invalid_interval() {
+        // 不正な環境変数の値は無視し、設定ファイルの値やC++側から与えられた値は利用する
+        let file = r#"{"event_poll_interval_ms": 10, "request_timeout_ms": 200}"#;
+        let env = |key: &str| match key {
+            EVENT_POLL_INTERVAL_ENV => Some("fast".to_string()),
+            REQUEST_TIMEOUT_ENV => Some("500".to_string()),
+            _ => None,
+        };
+        let (config, errors) =
+            Config::from_sources(Some(file), env, Some("http://ffi:8000".to_string()));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), ErrorCode::InvalidRequest);
+        assert_eq!(
+            errors[0].message(),
+            "SKYWAY_EVENT_POLL_INTERVAL_MS is not a number: fast"
+        );
+        assert_eq!(config.gateway_url, "http://ffi:8000");
+        assert_eq!(config.event_poll_interval_ms, 10);
+        assert_eq!(config.request_timeout_ms, 500);
+    }
+}
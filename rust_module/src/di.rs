@@ -1,13 +1,24 @@
 use shaku::module;
 
 use crate::application::factory::FactoryImpl;
+use crate::application::usecase::data::auto_accept::DataAutoAcceptImpl;
 use crate::application::usecase::data::connect::Connect;
+use crate::application::usecase::data::disconnect::Disconnect;
+use crate::application::usecase::data::policy::Policy;
 use crate::application::usecase::data::redirect::Redirect;
 use crate::application::usecase::event;
+use crate::application::usecase::event::subscription::Subscription;
 use crate::application::usecase::general::service::General;
 use crate::application::usecase::media::answer::AnswerService;
+use crate::application::usecase::media::auto_answer::MediaAutoAnswerImpl;
 use crate::application::usecase::media::call::Call;
+use crate::application::usecase::media::disconnect::DisconnectService;
+use crate::application::usecase::media::lifecycle::MediaLifecycleImpl;
+use crate::application::usecase::media::policy::PolicyService;
 use crate::application::usecase::peer::create::Create;
+use crate::application::usecase::peer::recovery::PeerRecoveryImpl;
+use crate::application::usecase::peer::registry::Registry;
+use crate::application::usecase::system::shutdown::ShutdownProcessImpl;
 use crate::application::usecase::system::System;
 use crate::ffi::rust_to_c_bridge::state_objects::{
     CallbackFunctionsImpl, GlobalStateImpl, LoggerImpl, ProgramStateImpl,
@@ -21,13 +32,6 @@ module! {
     }
 }
 
-module! {
-    pub(crate) GeneralFactory {
-        components = [FactoryImpl],
-        providers = []
-    }
-}
-
 module! {
     pub(crate) RepositoryModule {
         components = [RepositoryImpl, GlobalStateImpl],
@@ -44,7 +48,14 @@ module! {
 
 module! {
     pub(crate) SystemService {
-        components = [System],
+        components = [System, GlobalStateImpl, RepositoryImpl, ProgramStateImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) ShutdownModule {
+        components = [ShutdownProcessImpl, GlobalStateImpl, RepositoryImpl, CallbackFunctionsImpl, LoggerImpl],
         providers = []
     }
 }
@@ -56,6 +67,13 @@ module! {
     }
 }
 
+module! {
+    pub(crate) PeerRegistryService {
+        components = [Registry, GlobalStateImpl, RepositoryImpl],
+        providers = []
+    }
+}
+
 module! {
     pub(crate) DataConnectService {
         components = [Connect, GlobalStateImpl, RepositoryImpl, FactoryImpl, CallbackFunctionsImpl],
@@ -70,6 +88,13 @@ module! {
     }
 }
 
+module! {
+    pub(crate) DataDisconnectService {
+        components = [Disconnect, GlobalStateImpl, RepositoryImpl, CallbackFunctionsImpl],
+        providers = []
+    }
+}
+
 module! {
     pub(crate) MediaCallService {
         components = [Call, GlobalStateImpl, RepositoryImpl, FactoryImpl, CallbackFunctionsImpl],
@@ -84,9 +109,65 @@ module! {
     }
 }
 
+module! {
+    pub(crate) MediaDisconnectService {
+        components = [DisconnectService, MediaLifecycleImpl, GlobalStateImpl, RepositoryImpl, LoggerImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) MediaLifecycleModule {
+        components = [MediaLifecycleImpl, GlobalStateImpl, RepositoryImpl, LoggerImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) PeerRecoveryModule {
+        components = [PeerRecoveryImpl, GlobalStateImpl, RepositoryImpl, CallbackFunctionsImpl, LoggerImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) DataPolicyService {
+        components = [Policy, GlobalStateImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) DataAutoAcceptModule {
+        components = [DataAutoAcceptImpl, GlobalStateImpl, FactoryImpl, LoggerImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) MediaPolicyService {
+        components = [PolicyService, GlobalStateImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) MediaAutoAnswerModule {
+        components = [MediaAutoAnswerImpl, GlobalStateImpl, FactoryImpl, LoggerImpl],
+        providers = []
+    }
+}
+
 module! {
     pub(crate) EventReceiveService {
-        components = [event::EventReceiveImpl, CallbackFunctionsImpl, GlobalStateImpl, RepositoryImpl, LoggerImpl],
+        components = [event::EventReceiveImpl, MediaLifecycleImpl, PeerRecoveryImpl, DataAutoAcceptImpl, MediaAutoAnswerImpl, FactoryImpl, CallbackFunctionsImpl, GlobalStateImpl, RepositoryImpl, LoggerImpl],
+        providers = []
+    }
+}
+
+module! {
+    pub(crate) EventSubscriptionService {
+        components = [Subscription, GlobalStateImpl],
         providers = []
     }
 }
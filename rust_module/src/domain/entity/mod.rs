@@ -15,6 +15,14 @@ pub(crate) use skyway_webrtc_gateway_caller::prelude::media::*;
 #[allow(unused_imports)]
 pub(crate) use skyway_webrtc_gateway_caller::prelude::peer::*;
 
+// client moduleからcrateの外へ公開するもの
+pub use skyway_webrtc_gateway_caller::prelude::common::{PeerId, Token};
+pub use skyway_webrtc_gateway_caller::prelude::data::{ConnectQueryOption, DataConnectionId};
+pub use skyway_webrtc_gateway_caller::prelude::media::{
+    AnswerResult, MediaConnectionId, RedirectParameters,
+};
+pub use skyway_webrtc_gateway_caller::prelude::peer::{CreatePeerParams, PeerInfo};
+
 // メッセージを自然にStringに変換できるようにする
 pub(crate) trait Stringify {
     fn to_string(&self) -> Result<String, error::Error>;
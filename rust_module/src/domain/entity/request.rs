@@ -78,6 +78,19 @@ pub(crate) enum Request {
     Data(DataRequest),
 }
 
+impl Request {
+    /// ログ出力用に、"DATA CONNECT"のようなリクエストの種類を返す
+    pub fn kind(&self) -> String {
+        // Request型である時点でシリアライズには失敗しない
+        let value = serde_json::to_value(self).unwrap();
+        format!(
+            "{} {}",
+            value["type"].as_str().unwrap_or_default(),
+            value["command"].as_str().unwrap_or_default()
+        )
+    }
+}
+
 #[allow(dead_code)]
 impl Stringify for Request {
     fn to_string(&self) -> Result<String, error::Error> {
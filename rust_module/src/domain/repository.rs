@@ -1,5 +1,8 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
 use shaku::Interface;
+use tokio::sync::broadcast;
 
 use crate::domain::entity::request::Request;
 use crate::domain::entity::response::ResponseResult;
@@ -16,4 +19,45 @@ pub(crate) trait Repository: Interface {
     async fn register(&self, params: Request) -> Result<ResponseResult, error::Error>;
     /// イベントを監視するためのメソッド
     async fn receive_event(&self) -> Result<ResponseResult, error::Error>;
+    /// receive_eventの利用者からイベントを奪わずに、UseCase内でイベントを監視するためのメソッド
+    /// 呼び出し以降に発生したイベントを受け取ることができる
+    fn subscribe_events(&self) -> EventSubscription;
+    /// SkyWay Crateを介さずにWebRTC GatewayへHTTPリクエストを送り、疎通を確認するためのメソッド
+    async fn ping(&self) -> Result<GatewayProbe, error::Error>;
+}
+
+/// WebRTC Gatewayへの疎通確認の結果
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GatewayProbe {
+    /// リクエストを送ってからレスポンスを受け取るまでの時間
+    pub round_trip: Duration,
+    /// WebRTC Gatewayのバージョン。バージョンを返すエンドポイントがない場合はNone
+    pub version: Option<String>,
+}
+
+/// subscribe_eventsで得られるイベントの購読者
+pub(crate) struct EventSubscription {
+    receiver: broadcast::Receiver<String>,
+}
+
+impl EventSubscription {
+    pub fn new(receiver: broadcast::Receiver<String>) -> Self {
+        Self { receiver }
+    }
+
+    /// 次のイベントを待つ
+    /// 処理が追いつかずに取りこぼしたイベントは読み飛ばす
+    pub async fn recv(&mut self) -> Result<ResponseResult, error::Error> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(message) => {
+                    return ResponseResult::from_str(&message).map_err(error::Error::from)
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(error::Error::create_local_error("event channel is closed"))
+                }
+            }
+        }
+    }
 }
@@ -1 +1,269 @@
-pub(crate) use skyway_webrtc_gateway_caller::error::Error;
+// Rust側の処理で発生するエラーを定義する
+// ユーザにはErrorCodeで分類した上で返し、ユーザはメッセージの文面ではなくcodeでエラーの種類を判別する
+use serde::{Deserialize, Serialize};
+
+/// SkyWay Crateが返すエラー
+pub(crate) use skyway_webrtc_gateway_caller::error::Error as GatewayError;
+
+/// ユーザに返すエラーの分類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// リクエストの形式や値が不正
+    InvalidRequest,
+    /// WebRTC Gatewayに到達できない
+    GatewayUnreachable,
+    /// WebRTC Gatewayがリクエストを受け付けなかった
+    GatewayRejected,
+    /// C++側でのPluginのロードに失敗した
+    PluginLoadFailed,
+    /// 処理が時間内に完了しなかった
+    Timeout,
+    /// 対象のPeer, Connection等が存在しない
+    NotFound,
+    /// 終了処理によって中断された
+    Cancelled,
+    /// 上記に分類されないエラー
+    Internal,
+}
+
+// ログにはユーザに返すcodeと同じ表記で出力する
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(code)) => f.write_str(&code),
+            _ => Err(std::fmt::Error),
+        }
+    }
+}
+
+// SerdeError, LocalErrorはSkyWay Crateのエラーと名前を揃えている
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// JSONのパースもしくはシリアライズに失敗した
+    SerdeError { error: serde_json::Error },
+    /// 分類されていないエラー。ユーザにはINTERNALとして返す
+    LocalError(String),
+    /// 分類済みのエラー
+    Failure {
+        code: ErrorCode,
+        message: String,
+        /// 複数の手順からなるUseCaseで、失敗した手順
+        step: Option<String>,
+        /// WebRTC Gatewayが返したエラー
+        gateway_error: Option<serde_json::Value>,
+    },
+}
+
+impl Error {
+    pub fn create_local_error(message: &str) -> Error {
+        Error::LocalError(message.into())
+    }
+
+    pub fn create_error(code: ErrorCode, message: &str) -> Error {
+        Error::Failure {
+            code,
+            message: message.into(),
+            step: None,
+            gateway_error: None,
+        }
+    }
+
+    /// WebRTC Gatewayが返したエラーから生成する
+    /// SkyWay Crateはエラーを{"reason", "message"}形式のJSON文字列か、平文で返す
+    pub fn create_gateway_error(gateway_error: &str) -> Error {
+        #[derive(Deserialize)]
+        struct GatewayErrorMessage {
+            reason: String,
+            message: String,
+        }
+
+        let (code, message, gateway_error) =
+            match serde_json::from_str::<GatewayErrorMessage>(gateway_error) {
+                Ok(error) => {
+                    let code = match (error.reason.as_str(), error.message.as_str()) {
+                        ("NetworkError", _) | ("IoError", _) => ErrorCode::GatewayUnreachable,
+                        ("InvalidAddressError", _) => ErrorCode::InvalidRequest,
+                        (_, "recv Not Found") => ErrorCode::NotFound,
+                        (_, "recv RequestTimeout") => ErrorCode::Timeout,
+                        _ => ErrorCode::GatewayRejected,
+                    };
+                    // JSONとしてパースできているので、Valueへの変換には失敗しない
+                    let value = serde_json::from_str(gateway_error).unwrap();
+                    (code, error.message, value)
+                }
+                Err(_) => (
+                    ErrorCode::GatewayRejected,
+                    gateway_error.to_string(),
+                    serde_json::Value::String(gateway_error.to_string()),
+                ),
+            };
+
+        Error::Failure {
+            code,
+            message,
+            step: None,
+            gateway_error: Some(gateway_error),
+        }
+    }
+
+    /// 失敗した手順を記録する
+    /// より内側の手順で既に記録されている場合は、そちらを優先する
+    pub fn with_step(self, step: &str) -> Error {
+        match self {
+            Error::Failure {
+                code,
+                message,
+                step: None,
+                gateway_error,
+            } => Error::Failure {
+                code,
+                message,
+                step: Some(step.into()),
+                gateway_error,
+            },
+            Error::Failure { .. } => self,
+            e => Error::Failure {
+                code: e.code(),
+                message: e.message(),
+                step: Some(step.into()),
+                gateway_error: None,
+            },
+        }
+    }
+
+    /// 分類やstepはそのままに、メッセージだけを差し替える
+    pub fn with_message(self, message: String) -> Error {
+        match self {
+            Error::Failure {
+                code,
+                step,
+                gateway_error,
+                ..
+            } => Error::Failure {
+                code,
+                message,
+                step,
+                gateway_error,
+            },
+            _ => Error::LocalError(message),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Failure { code, .. } => *code,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::SerdeError { error } => error.to_string(),
+            Error::LocalError(message) => message.clone(),
+            Error::Failure { message, .. } => message.clone(),
+        }
+    }
+
+    pub fn step(&self) -> Option<&str> {
+        match self {
+            Error::Failure { step, .. } => step.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn gateway_error(&self) -> Option<&serde_json::Value> {
+        match self {
+            Error::Failure { gateway_error, .. } => gateway_error.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+// SkyWay Crateの型(IDの生成等)が返すエラーを変換する
+impl From<GatewayError> for Error {
+    fn from(error: GatewayError) -> Self {
+        match error {
+            GatewayError::SerdeError { error } => Error::SerdeError { error },
+            GatewayError::LocalError(message) => Error::LocalError(message),
+            GatewayError::AddrParseError(e) => {
+                Error::create_error(ErrorCode::InvalidRequest, &e.to_string())
+            }
+            e => Error::create_error(ErrorCode::GatewayUnreachable, &format!("{:?}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod error_test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn gateway_error() {
+        let error = Error::create_gateway_error(
+            r#"{"reason":"NetworkError","message":"error sending request"}"#,
+        );
+        assert_eq!(error.code(), ErrorCode::GatewayUnreachable);
+        assert_eq!(error.message(), "error sending request");
+        assert_eq!(
+            error.gateway_error(),
+            Some(&json!({"reason": "NetworkError", "message": "error sending request"}))
+        );
+
+        let error =
+            Error::create_gateway_error(r#"{"reason":"InternalError","message":"recv Not Found"}"#);
+        assert_eq!(error.code(), ErrorCode::NotFound);
+
+        let error = Error::create_gateway_error(
+            r#"{"reason":"InternalError","message":"recv RequestTimeout"}"#,
+        );
+        assert_eq!(error.code(), ErrorCode::Timeout);
+
+        let error =
+            Error::create_gateway_error(r#"{"reason":"InternalError","message":"recv Forbidden"}"#);
+        assert_eq!(error.code(), ErrorCode::GatewayRejected);
+
+        // 平文のエラーはそのまま保持する
+        let error = Error::create_gateway_error("MediaConnection has been already opened.");
+        assert_eq!(error.code(), ErrorCode::GatewayRejected);
+        assert_eq!(
+            error.gateway_error(),
+            Some(&json!("MediaConnection has been already opened."))
+        );
+    }
+
+    #[test]
+    fn code_display() {
+        // ユーザに返すJSONと同じ表記になる
+        assert_eq!(
+            ErrorCode::GatewayUnreachable.to_string(),
+            "GATEWAY_UNREACHABLE"
+        );
+        assert_eq!(ErrorCode::Timeout.to_string(), "TIMEOUT");
+    }
+
+    #[test]
+    fn step() {
+        // 最初に記録された手順を保持する
+        let error = Error::create_local_error("failed")
+            .with_step("DATA CREATE")
+            .with_step("DATA CONNECT");
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.step(), Some("DATA CREATE"));
+
+        // メッセージを差し替えても分類と手順は変わらない
+        let error = error.with_message("failed. rolled back: []".to_string());
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.step(), Some("DATA CREATE"));
+        assert_eq!(error.message(), "failed. rolled back: []");
+    }
+
+    #[test]
+    fn from_gateway_error() {
+        let error: Error = GatewayError::create_local_error("invalid id").into();
+        assert!(matches!(error, Error::LocalError(message) if message == "invalid id"));
+    }
+}
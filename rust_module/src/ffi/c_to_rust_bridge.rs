@@ -1,19 +1,99 @@
 // 当面はユニットテストは行わず、結合試験だけ行うことにする
 // Fixme: Unit Test
-use std::ffi::{c_void, CStr, CString};
+// C++側へpanicがunwindしないよう、全ての関数はguard::catchの中で処理を行う
+use std::ffi::c_void;
+use std::future::Future;
 use std::os::raw::c_char;
+use std::sync::Mutex;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
-use shaku::HasComponent;
+use tokio::runtime::{Handle, Runtime};
 
-use crate::application::dto::request::RequestDto;
-use crate::application::usecase::Service;
-use crate::di::GeneralService;
-use crate::domain::entity::request::PeerRequest;
+use crate::config::REGISTERED_GATEWAY_URL;
 use crate::domain::entity::PeerInfo;
+use crate::error;
+use crate::ffi::guard;
+use crate::ffi::ownership::RustOwnedString;
 use crate::ffi::rust_to_c_bridge::c_functions_wrapper::*;
 
+// FFI経由で呼ばれる全ての処理は、run()で起動したこのRuntime上で実行する
+// join_handlerで開放されるまで保持し続ける
+static RUNTIME: Mutex<Option<Runtime>> = Mutex::new(None);
+
+// Runtimeの開放時に、実行中のタスクの終了を待つ時間
+const RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// run()で起動したRuntime上でfutureを実行し、完了まで待機する
+// Runtimeが起動していない、もしくは既に開放されている場合はNoneを返す
+fn block_on<F: Future>(future: F) -> Option<F::Output> {
+    // block_on中はlockを保持しないよう、handleだけ取り出しておく
+    let handle: Handle = RUNTIME.lock().ok()?.as_ref()?.handle().clone();
+    Some(handle.block_on(future))
+}
+
+// エラーをcall_serviceの戻り値と同じ形式のJSONにして、C++側へ渡す文字列を生成する
+fn error_string(error: &error::Error) -> *mut c_char {
+    RustOwnedString::new(crate::application::error_message(error)).into_raw()
+}
+
+// Runtimeが起動していない場合のエラー
+fn runtime_error() -> error::Error {
+    error::Error::create_local_error("runtime is not running")
+}
+
+// C++側へ返すエラー値を持たない関数で、エラーをログに出力する
+fn log_error(error: &error::Error) {
+    if LoggerHolder::is_allocated() {
+        LoggerHolder::global().error(error.message());
+    }
+}
+
+// 起動に失敗した場合の戻り値
+fn run_failed() -> RunResponse {
+    RunResponse {
+        flag: false,
+        handler: std::ptr::null_mut(),
+    }
+}
+
+// run()で起動したRuntime上でfutureを実行し、完了を待たずに戻る
+// Runtimeが起動していない、もしくは既に開放されている場合はfalseを返す
+fn spawn<F>(future: F) -> bool
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let handle: Option<Handle> = RUNTIME
+        .lock()
+        .ok()
+        .and_then(|runtime| runtime.as_ref().map(|runtime| runtime.handle().clone()));
+    match handle {
+        Some(handle) => {
+            // 完了はcallbackで通知するため、JoinHandleは保持しない
+            drop(handle.spawn(future));
+            true
+        }
+        None => false,
+    }
+}
+
 //========== 起動時用 ==========
+// WebRTC GatewayのURLをC++側から与える
+// runより前に呼ばれた場合のみ有効で、環境変数や設定ファイルの値より優先される
+#[no_mangle]
+pub extern "C" fn register_gateway_url(gateway_url: *const c_char) {
+    guard::catch(
+        "register_gateway_url",
+        || match guard::from_c_str("gateway_url", gateway_url) {
+            Ok(gateway_url) => {
+                let _ = REGISTERED_GATEWAY_URL.set(gateway_url);
+            }
+            Err(e) => log_error(&e),
+        },
+        |e| log_error(&e),
+    )
+}
+
 // 起動に成功した場合、Rust側でWebRTC Gateawyから生じるイベントのリスナースレッドが回り続ける
 // 終了時にそれを終了するため、起動に成功したというフラグとともにhandlerを一緒に返す
 #[repr(C)]
@@ -24,27 +104,48 @@ pub struct RunResponse {
 
 #[no_mangle]
 pub extern "C" fn run() -> RunResponse {
-    if !LoggerHolder::is_allocated() {
-        return RunResponse {
-            flag: false,
-            handler: std::ptr::null_mut(),
-        };
-    }
+    guard::catch("run", run_inner, |e| {
+        log_error(&e);
+        run_failed()
+    })
+}
 
-    if !ProgramStateHolder::is_allocated() {
-        LoggerHolder::global().error(
-            "ProgramState object is not allocated. Please call the register_program_state function",
-        );
-        return RunResponse {
-            flag: false,
-            handler: std::ptr::null_mut(),
-        };
+fn run_inner() -> RunResponse {
+    // C++側がregister_*関数で登録しなかった機能は、Rust側の既定の実装で補う
+    crate::host::install(crate::host::Host::default());
+
+    let mut runtime_slot = match RUNTIME.lock() {
+        Ok(runtime_slot) => runtime_slot,
+        Err(_) => {
+            LoggerHolder::global().error("runtime lock is poisoned");
+            return run_failed();
+        }
+    };
+    if runtime_slot.is_some() {
+        LoggerHolder::global().error("run is called twice");
+        return run_failed();
     }
 
+    // 全てのFFI呼び出しで共有するRuntimeを起動する
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("skyway-runtime")
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            LoggerHolder::global().error(format!("failed to start tokio runtime: {:?}", e));
+            return run_failed();
+        }
+    };
+    let runtime_handle = runtime.handle().clone();
+    *runtime_slot = Some(runtime);
+    drop(runtime_slot);
+
     // SkyWay Crateを開始する
-    let handle: JoinHandle<()> = std::thread::spawn(|| {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
+    // スレッド内でpanicした場合も、join_handlerでのjoinがErrを返すだけでC++側へは伝播しない
+    let handle: JoinHandle<()> = std::thread::spawn(move || {
+        runtime_handle.block_on(async {
             crate::rust_main().await;
         });
     });
@@ -61,71 +162,267 @@ pub extern "C" fn run() -> RunResponse {
 #[no_mangle]
 pub extern "C" fn call_service(message_char: *const c_char) -> *mut c_char {
     // C文字列とRust文字列の変換だけ行って、中身の処理はapplicationメソッドに任せる
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let message: String = rt.block_on(async {
-        let c_str: &CStr = unsafe { CStr::from_ptr(message_char) };
-        let message = c_str.to_str().unwrap().to_string();
-
-        crate::application::call_service(message).await
-    });
-    return CString::new(message.as_str()).unwrap().into_raw();
+    guard::catch(
+        "call_service",
+        || {
+            let message = match guard::from_c_str("message", message_char) {
+                Ok(message) => message,
+                Err(e) => return error_string(&e),
+            };
+            match block_on(crate::application::call_service(message)) {
+                Some(message) => RustOwnedString::new(message).into_raw(),
+                None => error_string(&runtime_error()),
+            }
+        },
+        |e| error_string(&e),
+    )
 }
 
 #[no_mangle]
 pub extern "C" fn receive_events() -> *mut c_char {
     // C文字列とRust文字列の変換だけ行って、中身の処理はapplicationメソッドに任せる
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let result = rt.block_on(async { crate::application::receive_events().await });
-    return CString::new(result).unwrap().into_raw();
+    guard::catch(
+        "receive_events",
+        || match block_on(crate::application::receive_events()) {
+            Some(result) => RustOwnedString::new(result).into_raw(),
+            None => error_string(&runtime_error()),
+        },
+        |e| error_string(&e),
+    )
+}
+
+// イベントの購読を登録し、購読者のIDを返す
+// filterはEVENT SUBSCRIBEのfilterと同じ形式のJSONで、"{}"を与えると全てのイベントを受け取る
+// 登録に失敗した場合は0を返す
+#[no_mangle]
+pub extern "C" fn subscribe_events(filter: *const c_char) -> u64 {
+    guard::catch(
+        "subscribe_events",
+        || {
+            let result = guard::from_c_str("filter", filter)
+                .and_then(|filter| crate::application::subscribe_events(&filter));
+            match result {
+                Ok(subscriber_id) => subscriber_id,
+                Err(e) => {
+                    log_error(&e.with_step("subscribe events"));
+                    0
+                }
+            }
+        },
+        |e| {
+            log_error(&e);
+            0
+        },
+    )
+}
+
+// subscribe_eventsで登録した購読者宛のイベントを1つ取り出す
+// receive_eventsと同様に、イベントが届くまで待機する
+#[no_mangle]
+pub extern "C" fn poll_events(subscriber_id: u64) -> *mut c_char {
+    guard::catch(
+        "poll_events",
+        || match block_on(crate::application::poll_events(subscriber_id)) {
+            Some(result) => RustOwnedString::new(result).into_raw(),
+            None => error_string(&runtime_error()),
+        },
+        |e| error_string(&e),
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn unsubscribe_events(subscriber_id: u64) -> bool {
+    guard::catch(
+        "unsubscribe_events",
+        || crate::application::unsubscribe_events(subscriber_id),
+        |e| {
+            log_error(&e);
+            false
+        },
+    )
+}
+
+//========== 非同期API ==========
+// 以下の関数は処理の完了を待たずに戻り、完了時にcallbackを呼ぶ
+// callbackはRuntimeのスレッドから呼ばれるため、C++側で必要に応じて排他制御を行うこと
+// callbackに渡される文字列はrelease_stringで開放する必要がある
+// shutdown_serviceの開始以降はcallbackは呼ばれない。shutdown_serviceは実行中のcallbackの完了を待つため、
+// callbackの中からshutdown_serviceを呼んではならない
+
+// call_serviceの非同期版
+// 結果のJSONはcall_serviceと同じ形式でcallbackに渡される。処理中にpanicした場合もエラーのJSONが渡される
+// Runtimeが起動していない場合や終了処理の開始後は、受け付けずにfalseを返す。この場合callbackは呼ばれない
+#[no_mangle]
+pub extern "C" fn call_service_async(
+    message_char: *const c_char,
+    user_data: *mut c_void,
+    callback: AsyncCallbackFn,
+) -> bool {
+    guard::catch(
+        "call_service_async",
+        || {
+            if CALLBACK_GATE.is_closed() {
+                return false;
+            }
+
+            let message = guard::from_c_str("message", message_char);
+            let callback = AsyncCallback::new(user_data, callback);
+            spawn(async move {
+                let result = match message {
+                    Ok(message) => {
+                        guard::catch_async(
+                            "call_service_async",
+                            crate::application::call_service(message),
+                            |e| crate::application::error_message(&e),
+                        )
+                        .await
+                    }
+                    Err(e) => crate::application::error_message(&e),
+                };
+                callback.fire(result);
+            })
+        },
+        |e| {
+            log_error(&e);
+            false
+        },
+    )
+}
+
+// subscribe_eventsの非同期版
+// 購読者宛のイベントが届くたびにcallbackを呼ぶ。購読者のIDを返し、登録に失敗した場合は0を返す
+// unsubscribe_eventsで購読を解除するか、終了処理が始まるとcallbackの呼び出しは止まる
+#[no_mangle]
+pub extern "C" fn subscribe_events_async(
+    filter: *const c_char,
+    user_data: *mut c_void,
+    callback: AsyncCallbackFn,
+) -> u64 {
+    guard::catch(
+        "subscribe_events_async",
+        || {
+            if CALLBACK_GATE.is_closed() {
+                return 0;
+            }
+
+            let subscriber_id = subscribe_events(filter);
+            if subscriber_id == 0 {
+                return 0;
+            }
+
+            let callback = AsyncCallback::new(user_data, callback);
+            let is_spawned = spawn(async move {
+                let push =
+                    crate::application::push_events(subscriber_id, |event| callback.fire(event));
+                // panicした場合は購読を解除し、以降のイベントを溜め込まないようにする
+                let is_panicked = guard::catch_async(
+                    "subscribe_events_async",
+                    async {
+                        push.await;
+                        false
+                    },
+                    |_| true,
+                )
+                .await;
+                if is_panicked {
+                    crate::application::unsubscribe_events(subscriber_id);
+                }
+            });
+            if !is_spawned {
+                crate::application::unsubscribe_events(subscriber_id);
+                return 0;
+            }
+            subscriber_id
+        },
+        |e| {
+            log_error(&e);
+            0
+        },
+    )
 }
 
 //========== 開放処理 ==========
 // ros終了時にC++側から呼ばれる
 // Rust側オブジェクトの開放処理と、WebRTC Gateway上のオブジェクトの開放処理を行う
+// 各手順には期限が設けられているため、WebRTC Gatewayが応答しなくても終了処理は完了する
 #[no_mangle]
 pub extern "C" fn shutdown_service(peer_id: *const c_char, token: *const c_char) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    rt.block_on(async {
-        let c_str: &CStr = unsafe { CStr::from_ptr(peer_id) };
-        let peer_id = c_str.to_str().unwrap().to_string();
-
-        let c_str: &CStr = unsafe { CStr::from_ptr(token) };
-        let token = c_str.to_str().unwrap().to_string();
-
-        let param = RequestDto::Peer(PeerRequest::Delete {
-            params: PeerInfo::try_create(peer_id, token).expect("peer_info is invalid"),
-        });
-
-        let module = GeneralService::builder().build();
-        let service: &dyn Service = module.resolve_ref();
+    guard::catch(
+        "shutdown_service",
+        || {
+            // 以降は非同期APIのcallbackを呼ばない。実行中のcallbackがあれば完了を待つ
+            CALLBACK_GATE.close();
 
-        if let Err(e) = service.execute(param).await {
-            let error_message = format!("peer close error: {:?}", e);
-            LoggerHolder::global().error(error_message);
-        }
+            // PeerObjectの情報が不正な場合でも、Peer以外のリソースは開放する
+            let peer_id = guard::from_c_str("peer_id", peer_id);
+            let token = guard::from_c_str("token", token);
+            let peers = match (peer_id, token) {
+                (Ok(peer_id), Ok(token)) => match PeerInfo::try_create(peer_id, token) {
+                    Ok(peer_info) => vec![peer_info],
+                    Err(_) => vec![],
+                },
+                _ => vec![],
+            };
 
-        CallbackFunctionsHolder::global().peer_deleted_callback();
-    });
+            let result = block_on(crate::application::shutdown(peers));
+            if result.is_none() {
+                LoggerHolder::global()
+                    .error("shutdown_service is called while runtime is not running");
+            }
+        },
+        |e| log_error(&e),
+    )
 }
 
 // C++側のプログラム終了時に、Rust側が全て開放されるまで待機するために呼ばれる関数
+// rust_mainの終了を待ったあと、Runtimeを停止する
 #[no_mangle]
 pub extern "C" fn join_handler(handler: *mut c_void) {
-    let handle = unsafe { Box::from_raw(handler as *mut JoinHandle<()>) };
-    let _ = handle.join();
+    guard::catch(
+        "join_handler",
+        || {
+            if !handler.is_null() {
+                let handle = unsafe { Box::from_raw(handler as *mut JoinHandle<()>) };
+                if handle.join().is_err() {
+                    log_error(&error::Error::create_local_error("rust_main panicked"));
+                }
+            }
+
+            // shutdown_serviceを経ずに終了する場合も、Runtimeの停止後にcallbackが呼ばれないようにする
+            CALLBACK_GATE.close();
+
+            // 他の呼び出しがpanicしてlockがpoisonedになっていても、Runtimeは停止する
+            let runtime = match RUNTIME.lock() {
+                Ok(mut runtime) => runtime.take(),
+                Err(poisoned) => poisoned.into_inner().take(),
+            };
+            if let Some(runtime) = runtime {
+                runtime.shutdown_timeout(RUNTIME_SHUTDOWN_TIMEOUT);
+            }
+        },
+        |e| log_error(&e),
+    )
 }
 
 // Rust側で生成した文字列はRust側で開放するため、C++側から文字列を返す
+// 対象はcall_service等の戻り値と、非同期APIのcallbackに渡された文字列のみ
+// Rust側からC++側の関数に引数として渡した文字列は借用なので、この関数で開放してはならない
 #[no_mangle]
 pub extern "C" fn release_string(message: *mut c_char) {
-    unsafe {
-        let _ = CString::from_raw(message);
-    }
+    unsafe { RustOwnedString::release(message) };
 }
 
 #[no_mangle]
 pub extern "C" fn print_string(message: *const c_char) {
-    let str = unsafe { CStr::from_ptr(message) }.to_str().unwrap();
-    println!("{}", str);
-    CallbackFunctionsHolder::global().release_str(message);
+    guard::catch(
+        "print_string",
+        || {
+            // messageは借用なので、Rust側では開放しない
+            match guard::from_c_str("message", message) {
+                Ok(message) => println!("{}", message),
+                Err(e) => log_error(&e),
+            }
+        },
+        |e| log_error(&e),
+    )
 }
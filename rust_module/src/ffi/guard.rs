@@ -0,0 +1,158 @@
+// FFI境界でpanicを止めるための関数群
+// C++側へunwindするとundefined behaviorになるため、extern "C"関数は全てcatchを経由して処理を行う
+#[cfg(any(feature = "ffi", test))]
+use std::any::Any;
+use std::ffi::{CStr, CString};
+#[cfg(any(feature = "ffi", test))]
+use std::future::Future;
+use std::os::raw::c_char;
+#[cfg(any(feature = "ffi", test))]
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+#[cfg(any(feature = "ffi", test))]
+use futures::FutureExt;
+
+use crate::error;
+use crate::error::ErrorCode;
+#[cfg(any(feature = "ffi", test))]
+use crate::ffi::rust_to_c_bridge::c_functions_wrapper::LoggerHolder;
+
+// fを実行し、panicした場合はログを出力してfallbackが返す値を返す
+// nameはログに出力する関数名
+#[cfg(any(feature = "ffi", test))]
+pub(crate) fn catch<T>(
+    name: &str,
+    f: impl FnOnce() -> T,
+    fallback: impl FnOnce(error::Error) -> T,
+) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => fallback(panic_error(name, payload)),
+    }
+}
+
+// catchの非同期版。spawnしたタスク内のpanicを捕捉するために使う
+#[cfg(any(feature = "ffi", test))]
+pub(crate) async fn catch_async<T>(
+    name: &str,
+    future: impl Future<Output = T>,
+    fallback: impl FnOnce(error::Error) -> T,
+) -> T {
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(value) => value,
+        Err(payload) => fallback(panic_error(name, payload)),
+    }
+}
+
+// panicの内容をINTERNALエラーに変換し、ログに出力する
+#[cfg(any(feature = "ffi", test))]
+fn panic_error(name: &str, payload: Box<dyn Any + Send>) -> error::Error {
+    let reason = if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    };
+    let message = format!("{} panicked: {}", name, reason);
+
+    // Loggerの呼び出し自体がpanicしても、C++側へunwindさせない
+    let _ = catch_unwind(|| {
+        if LoggerHolder::is_allocated() {
+            LoggerHolder::global().error(message.as_str());
+        } else {
+            eprintln!("{}", message);
+        }
+    });
+    error::Error::create_error(ErrorCode::Internal, &message)
+}
+
+// C++側から渡された文字列をRustの文字列に変換する
+// nullやUTF-8として不正な文字列はINVALID_REQUESTエラーとする
+pub(crate) fn from_c_str(name: &str, c_str: *const c_char) -> Result<String, error::Error> {
+    if c_str.is_null() {
+        let message = format!("{} is null", name);
+        return Err(error::Error::create_error(
+            ErrorCode::InvalidRequest,
+            &message,
+        ));
+    }
+
+    let c_str: &CStr = unsafe { CStr::from_ptr(c_str) };
+    c_str.to_str().map(|s| s.to_string()).map_err(|e| {
+        let message = format!("{} is not a valid UTF-8 string: {}", name, e);
+        error::Error::create_error(ErrorCode::InvalidRequest, &message)
+    })
+}
+
+// C++側へ渡す文字列を生成する
+// C文字列は途中にNULを含められないため、NULは"\0"にエスケープする
+pub(crate) fn to_c_string(message: impl Into<String>) -> CString {
+    let message = message.into();
+    match CString::new(message) {
+        Ok(c_string) => c_string,
+        Err(e) => {
+            let message = String::from_utf8_lossy(&e.into_vec()).replace('\0', "\\0");
+            // NULは全てエスケープ済みなので失敗しない
+            CString::new(message).unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod guard_test {
+    use super::*;
+
+    #[test]
+    fn catch() {
+        assert_eq!(super::catch("test", || 1, |_| 0), 1);
+
+        let error = super::catch(
+            "call_service",
+            || -> Result<(), error::Error> { panic!("broken") },
+            Err,
+        )
+        .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Internal);
+        assert_eq!(error.message(), "call_service panicked: broken");
+    }
+
+    #[tokio::test]
+    async fn catch_async() {
+        let result = super::catch_async(
+            "call_service_async",
+            async { panic!("{}", "broken".to_string()) },
+            |e| e.message(),
+        )
+        .await;
+        assert_eq!(result, "call_service_async panicked: broken");
+    }
+
+    #[test]
+    fn from_c_str() {
+        let c_string = CString::new("message").unwrap();
+        assert_eq!(
+            super::from_c_str("message", c_string.as_ptr()).unwrap(),
+            "message"
+        );
+
+        let error = super::from_c_str("message", std::ptr::null()).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
+
+        let invalid = CString::new(vec![0x66, 0xff, 0x6f]).unwrap();
+        let error = super::from_c_str("message", invalid.as_ptr()).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
+        assert!(error
+            .message()
+            .starts_with("message is not a valid UTF-8 string"));
+    }
+
+    #[test]
+    fn to_c_string() {
+        assert_eq!(super::to_c_string("message").to_str().unwrap(), "message");
+        assert_eq!(
+            super::to_c_string("mes\0sage").to_str().unwrap(),
+            "mes\\0sage"
+        );
+    }
+}
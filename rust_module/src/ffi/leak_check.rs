@@ -0,0 +1,58 @@
+// テスト時にメモリリークを検出するためのallocator
+// スレッドごとに確保中のバイト数を数え、処理の前後の差分でリークを検出する
+// 他のテストは並列に別スレッドで実行されるため、計測には影響しない
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+}
+
+struct CountingAllocator;
+
+// スレッドの終了処理中はthread localにアクセスできないため、計測しない
+fn count(size: isize) {
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + size));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            count(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        count(-(layout.size() as isize));
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            count(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            count(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// fを実行し、実行後も開放されずに残ったバイト数を返す
+// fの中で別スレッドに渡って開放されるメモリは正しく計測できないため、同期的な処理にのみ使う
+pub(crate) fn allocated_bytes_during(f: impl FnOnce()) -> isize {
+    let before = ALLOCATED.with(|allocated| allocated.get());
+    f();
+    ALLOCATED.with(|allocated| allocated.get()) - before
+}
@@ -1,2 +1,8 @@
+// C++側から呼ばれる関数群。SkyWayClientのみを使う場合は不要
+#[cfg(feature = "ffi")]
 pub(crate) mod c_to_rust_bridge;
+pub(crate) mod guard;
+#[cfg(test)]
+pub(crate) mod leak_check;
+pub(crate) mod ownership;
 pub(crate) mod rust_to_c_bridge;
@@ -0,0 +1,194 @@
+// Rust/C++間で受け渡す文字列の所有権の規約
+// 1. 関数の引数として渡す文字列は借用とし、呼び出しの間だけ有効とする。
+//    受け取った側は開放してはならず、呼び出し後も使う場合はコピーする。Rust側からC++側へ渡す場合はBorrowedCStringを使う
+// 2. Rust側からC++側へ戻り値や非同期APIのcallbackで渡す文字列はRust側の持ち物とし、C++側はrelease_stringで開放する。
+//    Rust側ではRustOwnedStringを使う
+// 3. C++側からRust側へ戻り値で渡す文字列(PluginLoadResult::error_message)はC++側の持ち物とし、
+//    Rust側はtake_cpp_stringでコピーした後、release_string_callbackで開放する
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+use crate::ffi::guard;
+
+// Rust側からC++側の関数へ引数として貸し出す文字列
+// C++側の関数から戻った後、dropする際にRust側で開放する
+pub(crate) struct BorrowedCString(CString);
+
+impl BorrowedCString {
+    pub fn new(message: impl Into<String>) -> Self {
+        BorrowedCString(guard::to_c_string(message))
+    }
+
+    // 戻り値のポインタは、このオブジェクトがdropされるまで有効
+    pub fn as_ptr(&self) -> *const c_char {
+        self.0.as_ptr()
+    }
+}
+
+// Rust側からC++側へ所有権ごと渡す文字列
+// C++側はrelease_stringを呼んでRust側に開放させる
+pub(crate) struct RustOwnedString(CString);
+
+impl RustOwnedString {
+    pub fn new(message: impl Into<String>) -> Self {
+        RustOwnedString(guard::to_c_string(message))
+    }
+
+    // 以降はC++側の持ち物になり、releaseが呼ばれるまで開放されない
+    pub fn into_raw(self) -> *mut c_char {
+        self.0.into_raw()
+    }
+
+    // into_rawでC++側に渡した文字列を開放する。nullの場合は何もしない
+    // Safety: messageはinto_rawが返したポインタで、まだ開放されていないこと
+    pub unsafe fn release(message: *mut c_char) {
+        if !message.is_null() {
+            let _ = CString::from_raw(message);
+        }
+    }
+}
+
+// C++側が確保した文字列をコピーした上で、releaseを呼んでC++側に開放させる
+// nullの場合は空文字列を返し、releaseは呼ばない
+// Safety: messageはnullか、releaseで開放すべき有効なC文字列であること
+pub(crate) unsafe fn take_cpp_string(
+    message: *const c_char,
+    release: impl FnOnce(*const c_char),
+) -> String {
+    if message.is_null() {
+        return String::new();
+    }
+    let copied = CStr::from_ptr(message).to_string_lossy().into_owned();
+    release(message);
+    copied
+}
+
+#[cfg(test)]
+mod ownership_test {
+    use std::ffi::c_void;
+    use std::os::raw::c_char;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    #[cfg(feature = "ffi")]
+    use crate::ffi::c_to_rust_bridge::release_string;
+    use crate::ffi::leak_check::allocated_bytes_during;
+    use crate::ffi::rust_to_c_bridge::c_functions_wrapper::{
+        AsyncCallback, CallbackFunctionsHolder, LoggerHolder, PluginLoadResult,
+    };
+
+    // ffi featureなしでビルドする場合は、release_stringと同じ処理で代用する
+    #[cfg(not(feature = "ffi"))]
+    extern "C" fn release_string(message: *mut c_char) {
+        unsafe { RustOwnedString::release(message) };
+    }
+
+    // C++側の関数の代わり。規約通り、引数の文字列は読むだけで開放しない
+    static READ_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+    fn read(message: *const c_char) {
+        let length = unsafe { CStr::from_ptr(message) }.to_bytes().len();
+        READ_BYTES.fetch_add(length, Ordering::SeqCst);
+    }
+
+    extern "C" fn log(message: *const c_char) {
+        read(message);
+    }
+
+    extern "C" fn create_peer(peer_id: *const c_char, token: *const c_char) {
+        read(peer_id);
+        read(token);
+    }
+
+    extern "C" fn peer_deleted() {}
+
+    extern "C" fn create_data(
+        target_ip: *const c_char,
+        _target_port: u16,
+        plugin_type: *const c_char,
+        plugin_param: *const c_char,
+    ) -> PluginLoadResult {
+        read(target_ip);
+        read(plugin_type);
+        read(plugin_param);
+        PluginLoadResult {
+            is_success: true,
+            port: 60000,
+            error_message: std::ptr::null_mut(),
+        }
+    }
+
+    extern "C" fn data_connection_deleted(_port_num: u16) {}
+
+    extern "C" fn release_cpp_string(_message: *const c_char) {}
+
+    // C++側の非同期APIのcallbackの代わり。規約通り、結果の文字列はrelease_stringで開放する
+    extern "C" fn on_complete(_user_data: *mut c_void, message: *mut c_char) {
+        read(message);
+        release_string(message);
+    }
+
+    #[test]
+    fn borrowed_strings_are_released_after_call() {
+        let logger = LoggerHolder::new(log, log, log, log);
+        let functions = CallbackFunctionsHolder::new(
+            create_peer,
+            peer_deleted,
+            create_data,
+            data_connection_deleted,
+            release_cpp_string,
+        );
+
+        let allocated = allocated_bytes_during(|| {
+            logger.debug("debug message");
+            logger.info("info message");
+            logger.warn("warn message");
+            logger.error("error message");
+            functions.create_peer_callback("peer_id", "pt-token");
+            let _ = functions.data_callback("127.0.0.1", 50000, "string", "[]");
+        });
+        assert_eq!(allocated, 0);
+        assert!(READ_BYTES.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn owned_strings_are_released_by_release_string() {
+        let allocated = allocated_bytes_during(|| {
+            let message = RustOwnedString::new(r#"{"is_success":true}"#).into_raw();
+            release_string(message);
+            release_string(std::ptr::null_mut());
+        });
+        assert_eq!(allocated, 0);
+
+        let callback = AsyncCallback::new(std::ptr::null_mut(), on_complete);
+        let allocated = allocated_bytes_during(|| {
+            assert!(callback.fire(r#"{"is_success":true}"#.to_string()));
+        });
+        assert_eq!(allocated, 0);
+    }
+
+    #[test]
+    fn leak_is_detected() {
+        // release_stringを呼ばなければ、harnessがリークとして検出する
+        let mut message = std::ptr::null_mut();
+        let allocated = allocated_bytes_during(|| {
+            message = RustOwnedString::new("leaked").into_raw();
+        });
+        assert!(allocated > 0);
+        release_string(message);
+    }
+
+    #[test]
+    fn cpp_strings_are_released_after_copy() {
+        // C++側で確保された文字列の代わり
+        let cpp_string = CString::new("plugin load error").unwrap().into_raw();
+        let mut released = std::ptr::null();
+        let message = unsafe { take_cpp_string(cpp_string, |message| released = message) };
+        assert_eq!(message, "plugin load error");
+        assert_eq!(released, cpp_string as *const c_char);
+        unsafe { RustOwnedString::release(cpp_string) };
+
+        let message = unsafe { take_cpp_string(std::ptr::null(), |_| unreachable!()) };
+        assert_eq!(message, "");
+    }
+}
@@ -1,11 +1,18 @@
 // 当面はユニットテストは行わず、結合試験だけ行うことにする
 // Fixme: Unit Test
-use std::ffi::CString;
+#[cfg(any(feature = "ffi", test))]
+use std::ffi::c_void;
 use std::os::raw::{c_char, c_double};
+#[cfg(any(feature = "ffi", test))]
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
 
 use serde::{Deserialize, Serialize};
 
-use crate::domain::entity::DataConnectionId;
+use crate::application::request_id;
+use crate::domain::entity::{DataConnectionId, DataId};
+use crate::ffi::ownership::BorrowedCString;
+#[cfg(any(feature = "ffi", test))]
+use crate::ffi::ownership::RustOwnedString;
 use crate::ffi::rust_to_c_bridge::state_objects::{
     CALLBACK_FUNCTIONS, LOGGER_INSTANCE, PROGRAM_STATE_INSTANCE,
 };
@@ -13,13 +20,13 @@ use crate::ffi::rust_to_c_bridge::state_objects::{
 // Rust側でイベントが発生した際にC++側に通知するためのコールバック関数を保持する
 #[repr(C)]
 pub struct CallbackFunctionsHolder {
-    create_peer_callback_c: extern "C" fn(peer_id: *mut c_char, token: *mut c_char),
+    create_peer_callback_c: extern "C" fn(peer_id: *const c_char, token: *const c_char),
     peer_deleted_callback: extern "C" fn(),
     data_callback_c: extern "C" fn(
-        target_ip: *mut c_char,
+        target_ip: *const c_char,
         target_port: u16,
-        plugin_type: *mut c_char,
-        plugin_param: *mut c_char,
+        plugin_type: *const c_char,
+        plugin_param: *const c_char,
     ) -> PluginLoadResult,
     data_connection_deleted_callback_c: extern "C" fn(data_connection_id: u16),
     release_str_c: extern "C" fn(data_connection_id: *const c_char),
@@ -27,13 +34,13 @@ pub struct CallbackFunctionsHolder {
 
 impl CallbackFunctionsHolder {
     pub fn new(
-        create_peer_callback_c: extern "C" fn(peer_id: *mut c_char, token: *mut c_char),
+        create_peer_callback_c: extern "C" fn(peer_id: *const c_char, token: *const c_char),
         peer_deleted_callback: extern "C" fn(),
         data_callback_c: extern "C" fn(
-            target_ip: *mut c_char,
+            target_ip: *const c_char,
             target_port: u16,
-            plugin_type: *mut c_char,
-            plugin_param: *mut c_char,
+            plugin_type: *const c_char,
+            plugin_param: *const c_char,
         ) -> PluginLoadResult,
         data_connection_deleted_callback_c: extern "C" fn(data_connection_id: u16),
         release_str_c: extern "C" fn(message: *const c_char),
@@ -47,17 +54,22 @@ impl CallbackFunctionsHolder {
         }
     }
 
+    // 未登録の場合はpanicする。FFI境界ではguard::catchで捕捉される
     pub fn global() -> &'static CallbackFunctionsHolder {
         CALLBACK_FUNCTIONS
             .get()
             .expect("functions is not initialized")
     }
 
+    pub fn is_allocated() -> bool {
+        CALLBACK_FUNCTIONS.get().is_some()
+    }
+
+    // 文字列は呼び出しの間だけ貸し出し、戻った後にRust側で開放する
     pub fn create_peer_callback(&self, peer_id: &str, token: &str) {
-        (self.create_peer_callback_c)(
-            CString::new(peer_id).unwrap().into_raw(),
-            CString::new(token).unwrap().into_raw(),
-        );
+        let peer_id = BorrowedCString::new(peer_id);
+        let token = BorrowedCString::new(token);
+        (self.create_peer_callback_c)(peer_id.as_ptr(), token.as_ptr());
     }
 
     pub fn peer_deleted_callback(&self) {
@@ -71,11 +83,16 @@ impl CallbackFunctionsHolder {
         plugin_type: &str,
         plugin_parameter: &str,
     ) -> PluginLoadResult {
+        // 文字列は呼び出しの間だけ貸し出し、戻った後にRust側で開放する
+        // 戻り値のerror_messageはC++側の持ち物なので、release_strで開放する必要がある
+        let target_ip = BorrowedCString::new(target_ip);
+        let plugin_type = BorrowedCString::new(plugin_type);
+        let plugin_parameter = BorrowedCString::new(plugin_parameter);
         (self.data_callback_c)(
-            CString::new(target_ip).unwrap().into_raw(),
+            target_ip.as_ptr(),
             target_port,
-            CString::new(plugin_type).unwrap().into_raw(),
-            CString::new(plugin_parameter).unwrap().into_raw(),
+            plugin_type.as_ptr(),
+            plugin_parameter.as_ptr(),
         )
     }
 
@@ -89,7 +106,7 @@ impl CallbackFunctionsHolder {
 }
 
 // Rust側でイベントが発生した際にC++側に通知するためのコールバック関数の実体をC++側から受け取る
-#[no_mangle]
+#[cfg_attr(feature = "ffi", no_mangle)]
 pub extern "C" fn register_callbacks(param: &CallbackFunctionsHolder) {
     let functions = CallbackFunctionsHolder::new(
         param.create_peer_callback_c,
@@ -99,12 +116,13 @@ pub extern "C" fn register_callbacks(param: &CallbackFunctionsHolder) {
         param.release_str_c,
     );
 
-    if CALLBACK_FUNCTIONS.set(functions).is_err() {
-        return;
-    }
+    // 2回目以降の登録は無視する
+    let _ = CALLBACK_FUNCTIONS.set(functions);
 }
 
 // ROSの機能でロギングするための関数を保持する
+// リクエストの処理中に出力するログには、そのリクエストのrequest_idを付与する
+// ログの文字列は呼び出しの間だけ貸し出し、戻った後にRust側で開放する
 #[derive(Debug)]
 pub struct LoggerHolder {
     debug_c: extern "C" fn(*const c_char) -> (),
@@ -129,6 +147,7 @@ impl LoggerHolder {
         }
     }
 
+    // 未登録の場合はpanicする。FFI境界ではguard::catchで捕捉される
     pub fn global() -> &'static LoggerHolder {
         LOGGER_INSTANCE.get().expect("logger is not initialized")
     }
@@ -138,42 +157,46 @@ impl LoggerHolder {
     }
 
     pub fn debug(&self, message: impl Into<String>) {
-        let message_raw = CString::new(message.into()).unwrap().into_raw();
-        (self.debug_c)(message_raw);
+        let message = BorrowedCString::new(request_id::tag_message(message.into()));
+        (self.debug_c)(message.as_ptr());
     }
 
     pub fn info(&self, message: impl Into<String>) {
-        let message_raw = CString::new(message.into()).unwrap().into_raw();
-        (self.info_c)(message_raw);
+        let message = BorrowedCString::new(request_id::tag_message(message.into()));
+        (self.info_c)(message.as_ptr());
     }
 
     pub fn warn(&self, message: impl Into<String>) {
-        let message_raw = CString::new(message.into()).unwrap().into_raw();
-        (self.warn_c)(message_raw);
+        let message = BorrowedCString::new(request_id::tag_message(message.into()));
+        (self.warn_c)(message.as_ptr());
     }
 
     pub fn error(&self, message: impl Into<String>) {
-        let message_raw = CString::new(message.into()).unwrap().into_raw();
-        (self.error_c)(message_raw);
+        let message = BorrowedCString::new(request_id::tag_message(message.into()));
+        (self.error_c)(message.as_ptr());
     }
 }
 
 // ROSの機能でロギングするための関数の実体を受け取るための関数
-#[no_mangle]
+#[cfg_attr(feature = "ffi", no_mangle)]
 pub extern "C" fn register_logger(
     debug_c: extern "C" fn(*const c_char),
     info_c: extern "C" fn(*const c_char),
     warn_c: extern "C" fn(*const c_char),
     error_c: extern "C" fn(*const c_char),
 ) {
-    LOGGER_INSTANCE
+    // 2回目以降の登録は無視する。C++側へpanicを伝播させないため、unwrapはしない
+    let is_registered = LOGGER_INSTANCE
         .set(LoggerHolder {
             debug_c,
             info_c,
             warn_c,
             error_c,
         })
-        .unwrap();
+        .is_err();
+    if is_registered {
+        LoggerHolder::global().warn("register_logger is called twice");
+    }
 }
 
 // ROSの機能を制御するための関数を保持する
@@ -204,6 +227,7 @@ impl ProgramStateHolder {
         }
     }
 
+    // 未登録の場合はpanicする。FFI境界ではguard::catchで捕捉される
     pub fn global() -> &'static ProgramStateHolder {
         PROGRAM_STATE_INSTANCE
             .get()
@@ -236,7 +260,7 @@ impl ProgramStateHolder {
 }
 
 // ROSの機能を制御するための関数の実体を受け取るための関数
-#[no_mangle]
+#[cfg_attr(feature = "ffi", no_mangle)]
 pub extern "C" fn register_program_state(
     is_running_c: extern "C" fn() -> bool,
     is_shutting_down_c: extern "C" fn() -> bool,
@@ -244,7 +268,8 @@ pub extern "C" fn register_program_state(
     wait_for_shutdown_c: extern "C" fn() -> (),
     shutdown_c: extern "C" fn() -> (),
 ) {
-    PROGRAM_STATE_INSTANCE
+    // 2回目以降の登録は無視する。C++側へpanicを伝播させないため、unwrapはしない
+    let is_registered = PROGRAM_STATE_INSTANCE
         .set(ProgramStateHolder {
             is_running_c,
             is_shutting_down_c,
@@ -252,13 +277,132 @@ pub extern "C" fn register_program_state(
             wait_for_shutdown_c,
             shutdown_c,
         })
-        .unwrap();
+        .is_err();
+    if is_registered && LoggerHolder::is_allocated() {
+        LoggerHolder::global().warn("register_program_state is called twice");
+    }
+}
+
+// call_service_async等の非同期APIの完了時に呼ぶC++側の関数
+// 第1引数は呼び出し時に渡されたuser_data、第2引数は結果のJSONで、release_stringで開放する必要がある
+#[cfg(any(feature = "ffi", test))]
+pub type AsyncCallbackFn = extern "C" fn(user_data: *mut c_void, message: *mut c_char);
+
+// 非同期APIの呼び出し時に渡されたコールバックを、完了まで保持する
+#[cfg(any(feature = "ffi", test))]
+pub(crate) struct AsyncCallback {
+    user_data: *mut c_void,
+    callback: AsyncCallbackFn,
+}
+
+// user_dataはC++側の持ち物で、Rust側では触らずにcallbackへ渡すだけなので、スレッドを跨いでも問題ない
+#[cfg(any(feature = "ffi", test))]
+unsafe impl Send for AsyncCallback {}
+#[cfg(any(feature = "ffi", test))]
+unsafe impl Sync for AsyncCallback {}
+
+#[cfg(any(feature = "ffi", test))]
+impl AsyncCallback {
+    pub fn new(user_data: *mut c_void, callback: AsyncCallbackFn) -> Self {
+        AsyncCallback {
+            user_data,
+            callback,
+        }
+    }
+
+    // CALLBACK_GATEが閉じられていなければ、messageを渡してコールバックを呼ぶ
+    // 呼ばなかった場合はmessageをRust側で開放し、falseを返す
+    pub fn fire(&self, message: String) -> bool {
+        let message_raw = RustOwnedString::new(message).into_raw();
+        let is_fired = CALLBACK_GATE.run(|| (self.callback)(self.user_data, message_raw));
+        if !is_fired {
+            unsafe { RustOwnedString::release(message_raw) };
+        }
+        is_fired
+    }
+}
+
+// 非同期APIのコールバックが、終了処理の開始後に呼ばれないことを保証する
+// 閉じた後はコールバックを呼ばず、閉じる際には実行中のコールバックの完了を待つ
+#[cfg(any(feature = "ffi", test))]
+pub(crate) static CALLBACK_GATE: CallbackGate = CallbackGate::new();
+
+#[cfg(any(feature = "ffi", test))]
+struct CallbackGateState {
+    is_closed: bool,
+    running: usize,
+}
+
+#[cfg(any(feature = "ffi", test))]
+pub(crate) struct CallbackGate {
+    state: Mutex<CallbackGateState>,
+    idle: Condvar,
+}
+
+#[cfg(any(feature = "ffi", test))]
+impl CallbackGate {
+    const fn new() -> Self {
+        CallbackGate {
+            state: Mutex::new(CallbackGateState {
+                is_closed: false,
+                running: 0,
+            }),
+            idle: Condvar::new(),
+        }
+    }
+
+    // callbackはlockの外で実行するため、poisonedになっても状態は壊れていない
+    fn lock(&self) -> MutexGuard<'_, CallbackGateState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "ffi")]
+    pub fn is_closed(&self) -> bool {
+        self.lock().is_closed
+    }
+
+    // 閉じられていなければcallbackを実行してtrueを返す
+    // callbackの実行中はlockを保持しないので、複数のcallbackが並行して実行されうる
+    pub fn run(&self, callback: impl FnOnce()) -> bool {
+        {
+            let mut state = self.lock();
+            if state.is_closed {
+                return false;
+            }
+            state.running += 1;
+        }
+
+        callback();
+
+        let mut state = self.lock();
+        state.running -= 1;
+        if state.running == 0 {
+            self.idle.notify_all();
+        }
+        true
+    }
+
+    // 以降のcallbackの実行を止め、実行中のcallbackが全て完了するまで待機する
+    // callbackの中から呼ぶとデッドロックする
+    #[cfg(feature = "ffi")]
+    pub fn close(&self) {
+        let mut state = self.lock();
+        state.is_closed = true;
+        while state.running > 0 {
+            state = self
+                .idle
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
 }
 
 // Pluginがロードされた場合、この構造体に格納して使用中のPluginを管理する
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct DataPipeInfo {
     pub data_connection_id: DataConnectionId,
+    // DataChannelのSourceとして開放したDataポート。切断時に削除する
+    pub data_id: DataId,
     pub data_pipe_port_num: u16,
 }
 
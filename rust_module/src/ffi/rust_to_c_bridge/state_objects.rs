@@ -5,10 +5,14 @@ use std::sync::Arc;
 
 use once_cell::sync::OnceCell;
 use shaku::{Component, Interface};
-use tokio::sync::{mpsc, oneshot, Mutex};
-
-use crate::application::dto::response::CallResponseDto;
-use crate::domain::entity::{DataConnectionId, MediaConnectionId};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex};
+
+use crate::application::dto::request::{DataPolicyDto, MediaPolicyDto};
+use crate::application::dto::response::{CallResponseDto, PeerRecordDto, PeerRecordStatusDto};
+use crate::application::request_id;
+use crate::application::usecase::event::hub::EventHub;
+use crate::config::Config;
+use crate::domain::entity::{DataConnectionId, MediaConnectionId, PeerId};
 use crate::ffi::rust_to_c_bridge::c_functions_wrapper::{
     CallbackFunctionsHolder, DataPipeInfo, LoggerHolder, PluginLoadResult, ProgramStateHolder,
 };
@@ -24,6 +28,10 @@ pub(crate) static LOGGER_INSTANCE: OnceCell<LoggerHolder> = OnceCell::new();
 pub(crate) static PROGRAM_STATE_INSTANCE: OnceCell<ProgramStateHolder> = OnceCell::new();
 // WebRTC Crate起動時に生成されたSender, Receiverを破棄すると通信できなくなるので、保持し続ける
 pub(crate) static CHANNELS: OnceCell<Arc<dyn Channels>> = OnceCell::new();
+// 処理済みのイベントを、receive_eventsやEVENT SUBSCRIBEで登録された購読者に配信する
+pub(crate) static EVENT_HUB: OnceCell<EventHub> = OnceCell::new();
+// 終了処理の開始時に、WebRTC Gatewayの応答を待っているリクエストを中断する
+pub(crate) static REQUEST_CANCELLER: OnceCell<RequestCanceller> = OnceCell::new();
 // Event処理やDisconnect時に利用するため、DataConnection確立時に
 // Source Topic とDestination Topicの情報を集めておく
 pub(crate) static DATA_CONNECTION_STATE_INSTANCE: OnceCell<
@@ -34,6 +42,47 @@ pub(crate) static DATA_CONNECTION_STATE_INSTANCE: OnceCell<
 pub(crate) static MEDIA_CONNECTION_STATE_INSTANCE: OnceCell<
     std::sync::Mutex<HashMap<MediaConnectionId, CallResponseDto>>,
 > = OnceCell::new();
+// DATA POLICYで設定された、着信したDataConnectionを自動的に処理するためのpolicy
+// 起動時に設定ファイルの値で初期化される
+pub(crate) static DATA_POLICY_INSTANCE: OnceCell<std::sync::Mutex<Option<DataPolicyDto>>> =
+    OnceCell::new();
+// MEDIA POLICYで設定された、着信したMediaConnectionを自動的に処理するためのpolicy
+// 起動時に設定ファイルの値で初期化される
+pub(crate) static MEDIA_POLICY_INSTANCE: OnceCell<std::sync::Mutex<Option<MediaPolicyDto>>> =
+    OnceCell::new();
+// PEER LISTやshutdown時に利用するため、PEER CREATEで生成したPeer Objectと
+// そのPeerが確立したConnectionを集めておく
+pub(crate) static PEER_STATE_INSTANCE: OnceCell<std::sync::Mutex<HashMap<PeerId, PeerRecordDto>>> =
+    OnceCell::new();
+
+/// WebRTC Gatewayの応答を待っているリクエストをまとめて中断するための通知
+/// 中断されるのはcancel_allの時点で応答を待っているリクエストだけで、以降のリクエストは影響を受けない
+pub(crate) struct RequestCanceller {
+    // 値は中断した回数で、変化したことだけを利用する
+    sender: watch::Sender<u64>,
+}
+
+impl Default for RequestCanceller {
+    fn default() -> Self {
+        RequestCanceller::new()
+    }
+}
+
+impl RequestCanceller {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(0);
+        RequestCanceller { sender }
+    }
+
+    /// リクエストを送る前に呼び出し、返されたreceiverの変化を中断の通知として扱う
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.sender.subscribe()
+    }
+
+    pub fn cancel_all(&self) {
+        self.sender.send_modify(|count| *count += 1);
+    }
+}
 
 #[cfg_attr(test, automock)]
 pub(crate) trait CallbackFunctions: Interface {
@@ -87,6 +136,7 @@ impl CallbackFunctions for CallbackFunctionsImpl {
 #[shaku(interface = CallbackFunctions)]
 pub(crate) struct CallbackFunctionsImpl {}
 
+#[cfg_attr(test, automock)]
 pub(crate) trait Logger: Interface {
     fn debug(&self, message: &str);
     fn info(&self, message: &str);
@@ -116,6 +166,7 @@ impl Logger for LoggerImpl {
     }
 }
 
+#[cfg_attr(test, automock)]
 pub(crate) trait ProgramState: Interface {
     fn is_running(&self) -> bool;
     fn is_shutting_down(&self) -> bool;
@@ -153,19 +204,26 @@ impl ProgramState for ProgramStateImpl {
 pub(crate) trait Channels: Interface {
     fn sender(&self) -> &mpsc::Sender<(oneshot::Sender<String>, String)>;
     fn receiver(&self) -> &Mutex<mpsc::Receiver<String>>;
+    fn subscribe(&self) -> broadcast::Receiver<String>;
 }
 
 pub(crate) struct ChannelsImpl {
     sender: mpsc::Sender<(oneshot::Sender<String>, String)>,
     receiver: Mutex<mpsc::Receiver<String>>,
+    notifier: broadcast::Sender<String>,
 }
 
 impl ChannelsImpl {
     pub fn new(
         sender: mpsc::Sender<(oneshot::Sender<String>, String)>,
         receiver: Mutex<mpsc::Receiver<String>>,
+        notifier: broadcast::Sender<String>,
     ) -> Self {
-        Self { sender, receiver }
+        Self {
+            sender,
+            receiver,
+            notifier,
+        }
     }
 }
 
@@ -177,15 +235,23 @@ impl Channels for ChannelsImpl {
     fn receiver(&self) -> &Mutex<mpsc::Receiver<String>> {
         &self.receiver
     }
+
+    fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.notifier.subscribe()
+    }
 }
 
 #[cfg_attr(test, automock)]
 pub(crate) trait GlobalState: Interface {
     fn channels(&self) -> &'static Arc<dyn Channels>;
     fn program_state(&self) -> &'static ProgramStateHolder;
+    fn config(&self) -> &'static Config;
+    fn event_hub(&self) -> &'static EventHub;
+    fn request_canceller(&self) -> &'static RequestCanceller;
     fn store_topic(&self, data_connection_id: DataConnectionId, response: DataPipeInfo);
     fn find_topic(&self, data_connection_id: &DataConnectionId) -> Option<DataPipeInfo>;
     fn remove_topic(&self, data_connection_id: &DataConnectionId) -> Option<DataPipeInfo>;
+    fn list_topics(&self) -> Vec<DataPipeInfo>;
     fn store_call_response(
         &self,
         media_connection_id: MediaConnectionId,
@@ -195,12 +261,44 @@ pub(crate) trait GlobalState: Interface {
         &self,
         media_connection_id: &MediaConnectionId,
     ) -> Option<CallResponseDto>;
+    fn remove_call_response(
+        &self,
+        media_connection_id: &MediaConnectionId,
+    ) -> Option<CallResponseDto>;
+    fn list_call_responses(&self) -> Vec<CallResponseDto>;
+    fn store_peer(&self, record: PeerRecordDto);
+    fn find_peer(&self, peer_id: &PeerId) -> Option<PeerRecordDto>;
+    fn remove_peer(&self, peer_id: &PeerId) -> Option<PeerRecordDto>;
+    fn list_peers(&self) -> Vec<PeerRecordDto>;
+    fn set_peer_status(&self, peer_id: &PeerId, status: PeerRecordStatusDto);
+    /// 登録されていないPeerの場合は何もしない
+    fn attach_data_connection(&self, peer_id: &PeerId, data_connection_id: DataConnectionId);
+    /// 登録されていないPeerの場合は何もしない
+    fn attach_media_connection(&self, peer_id: &PeerId, media_connection_id: MediaConnectionId);
+    /// どのPeerにも紐付いていないConnectionの場合は何もしない
+    fn detach_data_connection(&self, data_connection_id: &DataConnectionId);
+    /// どのPeerにも紐付いていないConnectionの場合は何もしない
+    fn detach_media_connection(&self, media_connection_id: &MediaConnectionId);
+    fn data_policy(&self) -> Option<DataPolicyDto>;
+    fn set_data_policy(&self, policy: Option<DataPolicyDto>);
+    fn media_policy(&self) -> Option<MediaPolicyDto>;
+    fn set_media_policy(&self, policy: Option<MediaPolicyDto>);
 }
 
 #[derive(Component)]
 #[shaku(interface = GlobalState)]
 pub(crate) struct GlobalStateImpl {}
 
+impl GlobalStateImpl {
+    // リクエストの処理中にPeer, Connectionの情報を保存した場合は、そのリクエストを起因として記録する
+    // 以降のイベントにはこのrequest_idが付与される
+    fn record_origin(&self, id: &str) {
+        if let Some(request_id) = request_id::current() {
+            self.event_hub().record_origin(id, &request_id);
+        }
+    }
+}
+
 impl GlobalState for GlobalStateImpl {
     fn channels(&self) -> &'static Arc<dyn Channels> {
         CHANNELS.get().expect("CHANNELS is not initialized")
@@ -212,7 +310,20 @@ impl GlobalState for GlobalStateImpl {
             .expect("PROGRAM_STATE is not initialized")
     }
 
+    fn config(&self) -> &'static Config {
+        Config::global()
+    }
+
+    fn event_hub(&self) -> &'static EventHub {
+        EVENT_HUB.get_or_init(EventHub::new)
+    }
+
+    fn request_canceller(&self) -> &'static RequestCanceller {
+        REQUEST_CANCELLER.get_or_init(RequestCanceller::new)
+    }
+
     fn store_topic(&self, data_connection_id: DataConnectionId, response: DataPipeInfo) {
+        self.record_origin(data_connection_id.as_str());
         let hash = DATA_CONNECTION_STATE_INSTANCE.get().unwrap();
         hash.lock().unwrap().insert(data_connection_id, response);
     }
@@ -236,11 +347,21 @@ impl GlobalState for GlobalStateImpl {
         return hash.remove(data_connection_id);
     }
 
+    fn list_topics(&self) -> Vec<DataPipeInfo> {
+        let hash = DATA_CONNECTION_STATE_INSTANCE
+            .get()
+            .unwrap()
+            .lock()
+            .unwrap();
+        hash.values().cloned().collect()
+    }
+
     fn store_call_response(
         &self,
         media_connection_id: MediaConnectionId,
         response: CallResponseDto,
     ) {
+        self.record_origin(media_connection_id.as_str());
         let hash = MEDIA_CONNECTION_STATE_INSTANCE.get().unwrap();
         hash.lock().unwrap().insert(media_connection_id, response);
     }
@@ -257,4 +378,105 @@ impl GlobalState for GlobalStateImpl {
         let item = hash.get(media_connection_id);
         item.map(|item| item.clone())
     }
+
+    fn remove_call_response(
+        &self,
+        media_connection_id: &MediaConnectionId,
+    ) -> Option<CallResponseDto> {
+        let mut hash = MEDIA_CONNECTION_STATE_INSTANCE
+            .get()
+            .unwrap()
+            .lock()
+            .unwrap();
+        hash.remove(media_connection_id)
+    }
+
+    fn list_call_responses(&self) -> Vec<CallResponseDto> {
+        let hash = MEDIA_CONNECTION_STATE_INSTANCE
+            .get()
+            .unwrap()
+            .lock()
+            .unwrap();
+        hash.values().cloned().collect()
+    }
+
+    fn store_peer(&self, record: PeerRecordDto) {
+        self.record_origin(record.peer_id.as_str());
+        let hash = PEER_STATE_INSTANCE.get().unwrap();
+        hash.lock().unwrap().insert(record.peer_id.clone(), record);
+    }
+
+    fn find_peer(&self, peer_id: &PeerId) -> Option<PeerRecordDto> {
+        let hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        hash.get(peer_id).cloned()
+    }
+
+    fn remove_peer(&self, peer_id: &PeerId) -> Option<PeerRecordDto> {
+        let mut hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        hash.remove(peer_id)
+    }
+
+    fn list_peers(&self) -> Vec<PeerRecordDto> {
+        let hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        hash.values().cloned().collect()
+    }
+
+    fn set_peer_status(&self, peer_id: &PeerId, status: PeerRecordStatusDto) {
+        let mut hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        if let Some(record) = hash.get_mut(peer_id) {
+            record.status = status;
+        }
+    }
+
+    fn attach_data_connection(&self, peer_id: &PeerId, data_connection_id: DataConnectionId) {
+        let mut hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        if let Some(record) = hash.get_mut(peer_id) {
+            if !record.data_connections.contains(&data_connection_id) {
+                record.data_connections.push(data_connection_id);
+            }
+        }
+    }
+
+    fn attach_media_connection(&self, peer_id: &PeerId, media_connection_id: MediaConnectionId) {
+        let mut hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        if let Some(record) = hash.get_mut(peer_id) {
+            if !record.media_connections.contains(&media_connection_id) {
+                record.media_connections.push(media_connection_id);
+            }
+        }
+    }
+
+    fn detach_data_connection(&self, data_connection_id: &DataConnectionId) {
+        let mut hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        for record in hash.values_mut() {
+            record
+                .data_connections
+                .retain(|id| id != data_connection_id);
+        }
+    }
+
+    fn detach_media_connection(&self, media_connection_id: &MediaConnectionId) {
+        let mut hash = PEER_STATE_INSTANCE.get().unwrap().lock().unwrap();
+        for record in hash.values_mut() {
+            record
+                .media_connections
+                .retain(|id| id != media_connection_id);
+        }
+    }
+
+    fn data_policy(&self) -> Option<DataPolicyDto> {
+        DATA_POLICY_INSTANCE.get().unwrap().lock().unwrap().clone()
+    }
+
+    fn set_data_policy(&self, policy: Option<DataPolicyDto>) {
+        *DATA_POLICY_INSTANCE.get().unwrap().lock().unwrap() = policy;
+    }
+
+    fn media_policy(&self) -> Option<MediaPolicyDto> {
+        MEDIA_POLICY_INSTANCE.get().unwrap().lock().unwrap().clone()
+    }
+
+    fn set_media_policy(&self, policy: Option<MediaPolicyDto>) {
+        *MEDIA_POLICY_INSTANCE.get().unwrap().lock().unwrap() = policy;
+    }
 }
@@ -0,0 +1,182 @@
+// C++側(ROS)のPluginの代わりに、DataConnectionのデータをプロセス内で扱う
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::application::dto::request::PluginInfo;
+
+/// DataConnectionで送受信するデータを扱う処理
+/// ROSではC++側のPluginが担う役割を、Rustのプログラム側で実装する
+pub trait DataPipe: Send + Sync {
+    /// DataConnectionの確立時に呼ばれる
+    /// targetはWebRTC GatewayのDataポートで、ここに送ったデータが相手側に届く
+    /// 相手側から届いたデータを受け取るポートを開放し、そのポート番号を返す
+    fn open(&self, target: SocketAddr, plugin: &PluginInfo) -> Result<u16, String>;
+
+    /// DataConnectionの切断時に、openで開放したポートを閉じる
+    fn close(&self, port: u16);
+}
+
+// 受信待ちのスレッドが、closeされたかを確認する間隔
+const RECV_INTERVAL: Duration = Duration::from_millis(100);
+// WebRTC Gatewayから届くデータの最大長
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+type DataHandler = dyn Fn(u16, &[u8]) + Send + Sync;
+
+/// 受信したデータをコールバックに渡し、sendで相手側にデータを送るDataPipe
+/// PluginInfoは参照せず、データはバイト列のまま扱う
+/// cloneしたものは同じポートを共有するため、1つをSkyWayClientに登録し、もう1つでsendできる
+#[derive(Clone)]
+pub struct InProcessDataPipe {
+    bind_address: String,
+    on_data: Arc<DataHandler>,
+    pipes: Arc<Mutex<HashMap<u16, Pipe>>>,
+}
+
+struct Pipe {
+    socket: Arc<UdpSocket>,
+    target: SocketAddr,
+    is_closed: Arc<AtomicBool>,
+    receiver: JoinHandle<()>,
+}
+
+impl InProcessDataPipe {
+    /// bind_addressはWebRTC Gatewayがデータを転送してくるアドレスで、通常は設定項目data_redirect_addressと同じ値にする
+    /// 相手側からデータが届くたびに、on_dataにopenが返したポート番号とデータを渡す
+    pub fn new(
+        bind_address: impl Into<String>,
+        on_data: impl Fn(u16, &[u8]) + Send + Sync + 'static,
+    ) -> Self {
+        InProcessDataPipe {
+            bind_address: bind_address.into(),
+            on_data: Arc::new(on_data),
+            pipes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// openが返したポートに対応するDataConnectionで、相手側にデータを送る
+    pub fn send(&self, port: u16, data: &[u8]) -> std::io::Result<usize> {
+        let pipes = self.lock_pipes();
+        let pipe = pipes.get(&port).ok_or_else(|| {
+            std::io::Error::new(ErrorKind::NotFound, format!("port {} is not open", port))
+        })?;
+        pipe.socket.send_to(data, pipe.target)
+    }
+
+    /// 開いているポートの一覧
+    pub fn ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.lock_pipes().keys().copied().collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    fn lock_pipes(&self) -> MutexGuard<'_, HashMap<u16, Pipe>> {
+        self.pipes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl DataPipe for InProcessDataPipe {
+    fn open(&self, target: SocketAddr, _plugin: &PluginInfo) -> Result<u16, String> {
+        let socket = UdpSocket::bind((self.bind_address.as_str(), 0)).map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(RECV_INTERVAL))
+            .map_err(|e| e.to_string())?;
+        let port = socket.local_addr().map_err(|e| e.to_string())?.port();
+        let socket = Arc::new(socket);
+        let is_closed = Arc::new(AtomicBool::new(false));
+
+        let receiver = {
+            let socket = socket.clone();
+            let is_closed = is_closed.clone();
+            let on_data = self.on_data.clone();
+            std::thread::Builder::new()
+                .name(format!("skyway-data-{}", port))
+                .spawn(move || receive(port, &socket, &is_closed, &*on_data))
+                .map_err(|e| e.to_string())?
+        };
+
+        self.lock_pipes().insert(
+            port,
+            Pipe {
+                socket,
+                target,
+                is_closed,
+                receiver,
+            },
+        );
+        Ok(port)
+    }
+
+    fn close(&self, port: u16) {
+        let pipe = self.lock_pipes().remove(&port);
+        if let Some(pipe) = pipe {
+            pipe.is_closed.store(true, Ordering::SeqCst);
+            // 受信待ちはRECV_INTERVALで抜けるため、長くは待たない
+            let _ = pipe.receiver.join();
+        }
+    }
+}
+
+// closeされるまでデータを受信し、on_dataに渡し続ける
+fn receive(port: u16, socket: &UdpSocket, is_closed: &AtomicBool, on_data: &DataHandler) {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    while !is_closed.load(Ordering::SeqCst) {
+        match socket.recv(&mut buffer) {
+            Ok(size) => on_data(port, &buffer[..size]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(_) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod data_pipe_test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn send_and_receive() {
+        // WebRTC GatewayのDataポートの代わり
+        let gateway = UdpSocket::bind("127.0.0.1:0").unwrap();
+        gateway
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let pipe = InProcessDataPipe::new("127.0.0.1", move |port, data: &[u8]| {
+            let _ = sender.lock().unwrap().send((port, data.to_vec()));
+        });
+        let plugin = PluginInfo {
+            r#type: "string".to_string(),
+            plugins: vec![],
+        };
+        let port = pipe.open(gateway.local_addr().unwrap(), &plugin).unwrap();
+        assert_eq!(pipe.ports(), vec![port]);
+
+        // 相手側から届いたデータはon_dataに渡される
+        gateway.send_to(b"from peer", ("127.0.0.1", port)).unwrap();
+        let (received_port, data) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(received_port, port);
+        assert_eq!(data, b"from peer");
+
+        // sendしたデータはWebRTC GatewayのDataポートに届く
+        pipe.clone().send(port, b"to peer").unwrap();
+        let mut buffer = [0u8; 16];
+        let size = gateway.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"to peer");
+
+        pipe.close(port);
+        assert!(pipe.ports().is_empty());
+        assert_eq!(
+            pipe.send(port, b"closed").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
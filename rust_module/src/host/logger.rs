@@ -0,0 +1,106 @@
+// C++側(ROS)のロガーの代わりに、標準エラー出力やファイルにログを出力する
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// ログの重要度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// 設定ファイルや環境変数で指定されたレベル名を変換する。大文字小文字は区別しない
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name.to_ascii_lowercase().as_str() {
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
+
+/// ログの出力先
+pub type LogSink = Box<dyn Fn(LogLevel, &str) + Send + Sync>;
+
+/// min_level以上のログを標準エラー出力に出す
+pub fn stderr_logger(min_level: LogLevel) -> LogSink {
+    Box::new(move |level, message| {
+        if level >= min_level {
+            eprintln!("{}", format_line(level, message));
+        }
+    })
+}
+
+/// min_level以上のログをpathのファイルに追記する
+pub fn file_logger(path: impl AsRef<Path>, min_level: LogLevel) -> std::io::Result<LogSink> {
+    let file: Mutex<File> = Mutex::new(OpenOptions::new().create(true).append(true).open(path)?);
+    Ok(Box::new(move |level, message| {
+        if level >= min_level {
+            let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+            // ログの書き込みに失敗しても、処理は継続する
+            let _ = writeln!(file, "{}", format_line(level, message));
+        }
+    }))
+}
+
+// 1行の形式は"[UNIX時刻(秒.ミリ秒)] [LEVEL] message"
+fn format_line(level: LogLevel, message: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "[{}.{:03}] [{}] {}",
+        now.as_secs(),
+        now.subsec_millis(),
+        level.name(),
+        message
+    )
+}
+
+#[cfg(test)]
+mod logger_test {
+    use super::*;
+
+    #[test]
+    fn from_name() {
+        assert_eq!(LogLevel::from_name("debug"), Some(LogLevel::Debug));
+        assert_eq!(LogLevel::from_name("WARN"), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::from_name("verbose"), None);
+        assert!(LogLevel::Debug < LogLevel::Error);
+    }
+
+    #[test]
+    fn file_logger() {
+        let path = std::env::temp_dir().join(format!("skyway_log_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let logger = super::file_logger(&path, LogLevel::Info).unwrap();
+        logger(LogLevel::Debug, "debug message");
+        logger(LogLevel::Info, "info message");
+        logger(LogLevel::Error, "error message");
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("[INFO] info message"));
+        assert!(lines[1].ends_with("[ERROR] error message"));
+    }
+}
@@ -0,0 +1,345 @@
+// Rust側モジュールを動かすホスト(ROSのC++ノードやRustのプログラム)が提供する機能
+// ロガー、プログラムの状態管理、Pluginのロードは、通常C++側がregister_*関数で登録する
+// C++側が登録しなかったものは、ここで定義するRust側の既定の実装で補う
+// これによりROSなしでも、通常のLinuxのデーモンやテストとして動かせる
+mod data_pipe;
+mod logger;
+
+use std::net::SocketAddr;
+use std::os::raw::{c_char, c_double};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+
+use crate::application::dto::request::PluginInfo;
+use crate::config::Config;
+use crate::ffi::guard;
+use crate::ffi::ownership::RustOwnedString;
+use crate::ffi::rust_to_c_bridge::c_functions_wrapper::{
+    register_callbacks, register_logger, register_program_state, CallbackFunctionsHolder,
+    LoggerHolder, PluginLoadResult, ProgramStateHolder,
+};
+
+pub use data_pipe::{DataPipe, InProcessDataPipe};
+pub use logger::{file_logger, stderr_logger, LogLevel, LogSink};
+
+static LOG_SINK: OnceCell<LogSink> = OnceCell::new();
+static DATA_PIPE: OnceCell<Box<dyn DataPipe>> = OnceCell::new();
+// shutdownやシグナルの受信で立て、イベントの配信などを止める
+static IS_SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+// プログラムの状態をRust側で管理し、かつシグナルで終了する設定の場合に立てる
+static SHUTDOWN_ON_SIGNAL: AtomicBool = AtomicBool::new(false);
+// wait_for_shutdownで終了を待つ際の確認間隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// C++側が登録しなかった機能を補う、Rust側の実装
+/// 既定では設定項目log_level, log_fileに従ってログを出力し、SIGINT/SIGTERMで終了処理を行い、
+/// DataConnectionのデータは受信したことをDEBUGログに出すInProcessDataPipeで扱う
+pub struct Host {
+    logger: Option<LogSink>,
+    data_pipe: Option<Box<dyn DataPipe>>,
+    shutdown_on_signal: bool,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Host {
+            logger: None,
+            data_pipe: None,
+            shutdown_on_signal: true,
+        }
+    }
+}
+
+impl Host {
+    /// ログの出力先
+    pub fn logger(mut self, logger: impl Fn(LogLevel, &str) + Send + Sync + 'static) -> Self {
+        self.logger = Some(Box::new(logger));
+        self
+    }
+
+    /// DataConnectionのデータを扱う処理
+    pub fn data_pipe(mut self, data_pipe: impl DataPipe + 'static) -> Self {
+        self.data_pipe = Some(Box::new(data_pipe));
+        self
+    }
+
+    /// SIGINT/SIGTERMを受信した際に、全てのリソースを開放して終了するか
+    pub fn shutdown_on_signal(mut self, shutdown_on_signal: bool) -> Self {
+        self.shutdown_on_signal = shutdown_on_signal;
+        self
+    }
+}
+
+/// C++側が登録していないロガー、Programの状態、コールバックを、hostの実装で登録する
+/// 既に登録済みのものはC++側の実装を優先し、置き換えない
+pub(crate) fn install(host: Host) {
+    let mut installed = vec![];
+
+    if !LoggerHolder::is_allocated() {
+        let _ = LOG_SINK.set(host.logger.unwrap_or_else(default_logger));
+        register_logger(log_debug, log_info, log_warn, log_error);
+        installed.push("logger");
+    }
+
+    if !ProgramStateHolder::is_allocated() {
+        SHUTDOWN_ON_SIGNAL.store(host.shutdown_on_signal, Ordering::SeqCst);
+        register_program_state(
+            is_running,
+            is_shutting_down,
+            sleep,
+            wait_for_shutdown,
+            shutdown,
+        );
+        installed.push("program state");
+    }
+
+    if !CallbackFunctionsHolder::is_allocated() {
+        let _ = DATA_PIPE.set(host.data_pipe.unwrap_or_else(default_data_pipe));
+        register_callbacks(&CallbackFunctionsHolder::new(
+            create_peer,
+            peer_deleted,
+            open_data_pipe,
+            close_data_pipe,
+            release_string,
+        ));
+        installed.push("data pipe");
+    }
+
+    if !installed.is_empty() {
+        let message = format!("use built-in host for {}", installed.join(", "));
+        LoggerHolder::global().info(message);
+    }
+}
+
+/// Rust側がプログラムの状態を管理している場合に、SIGINT/SIGTERMを待ち受けるタスクを開始する
+/// 受信すると全てのリソースを開放し、wait_for_shutdownで待機している処理を終了させる
+/// tokioのRuntime上で呼ぶ必要がある
+pub(crate) fn spawn_signal_handler() {
+    if !SHUTDOWN_ON_SIGNAL.load(Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async {
+        let signal = match wait_for_signal().await {
+            Ok(signal) => signal,
+            Err(e) => {
+                let message = format!("failed to listen for signals: {:?}", e);
+                LoggerHolder::global().error(message);
+                return;
+            }
+        };
+        if is_shutdown_started() {
+            return;
+        }
+
+        LoggerHolder::global().info(format!("received {}. shutting down", signal));
+        // 各Peerは状態として保持されているので、ここで渡す必要はない
+        crate::application::shutdown(vec![]).await;
+        begin_shutdown();
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = interrupt.recv() => Ok("SIGINT"),
+        _ = terminate.recv() => Ok("SIGTERM"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    tokio::signal::ctrl_c().await.map(|_| "Ctrl-C")
+}
+
+/// 以降のイベントの配信などを止める
+pub(crate) fn begin_shutdown() {
+    IS_SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+/// shutdownやシグナルによる終了処理が始まっているか
+pub(crate) fn is_shutdown_started() -> bool {
+    IS_SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+// 設定項目log_level, log_fileに従ったロガー
+fn default_logger() -> LogSink {
+    let config = Config::global();
+    let level = LogLevel::from_name(&config.log_level).unwrap_or_else(|| {
+        eprintln!("unknown log_level {}. use info", config.log_level);
+        LogLevel::Info
+    });
+    match &config.log_file {
+        Some(path) => file_logger(path, level).unwrap_or_else(|e| {
+            eprintln!("failed to open log_file {}: {}. use stderr", path, e);
+            stderr_logger(level)
+        }),
+        None => stderr_logger(level),
+    }
+}
+
+// 受信したデータはDEBUGログに出すだけで、相手側への送信は行わない
+fn default_data_pipe() -> Box<dyn DataPipe> {
+    let address = Config::global().data_redirect_address.clone();
+    Box::new(InProcessDataPipe::new(address, |port, data| {
+        let message = format!("received {} bytes on data port {}", data.len(), port);
+        LoggerHolder::global().debug(message);
+    }))
+}
+
+fn log(level: LogLevel, message: *const c_char) {
+    if let (Some(sink), Ok(message)) = (LOG_SINK.get(), guard::from_c_str("message", message)) {
+        sink(level, &message);
+    }
+}
+
+extern "C" fn log_debug(message: *const c_char) {
+    log(LogLevel::Debug, message);
+}
+
+extern "C" fn log_info(message: *const c_char) {
+    log(LogLevel::Info, message);
+}
+
+extern "C" fn log_warn(message: *const c_char) {
+    log(LogLevel::Warn, message);
+}
+
+extern "C" fn log_error(message: *const c_char) {
+    log(LogLevel::Error, message);
+}
+
+extern "C" fn is_running() -> bool {
+    !is_shutdown_started()
+}
+
+extern "C" fn is_shutting_down() -> bool {
+    is_shutdown_started()
+}
+
+extern "C" fn sleep(duration: c_double) {
+    std::thread::sleep(Duration::from_secs_f64(duration.max(0.0)));
+}
+
+extern "C" fn wait_for_shutdown() {
+    while !is_shutdown_started() {
+        std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+}
+
+extern "C" fn shutdown() {
+    begin_shutdown();
+}
+
+// Peerの生成、削除はイベントで通知されるため、ここでは何もしない
+extern "C" fn create_peer(_peer_id: *const c_char, _token: *const c_char) {}
+
+extern "C" fn peer_deleted() {}
+
+// Pluginのロードの代わりに、DataPipeを開く
+// 失敗時のerror_messageはrelease_stringで開放される
+extern "C" fn open_data_pipe(
+    target_ip: *const c_char,
+    target_port: u16,
+    plugin_type: *const c_char,
+    plugin_param: *const c_char,
+) -> PluginLoadResult {
+    match open(target_ip, target_port, plugin_type, plugin_param) {
+        Ok(port) => PluginLoadResult {
+            is_success: true,
+            port,
+            error_message: std::ptr::null_mut(),
+        },
+        Err(message) => PluginLoadResult {
+            is_success: false,
+            port: 0,
+            error_message: RustOwnedString::new(message).into_raw(),
+        },
+    }
+}
+
+fn open(
+    target_ip: *const c_char,
+    target_port: u16,
+    plugin_type: *const c_char,
+    plugin_param: *const c_char,
+) -> Result<u16, String> {
+    let data_pipe = DATA_PIPE.get().ok_or("no DataPipe is registered")?;
+    let target_ip = guard::from_c_str("target_ip", target_ip).map_err(|e| e.message())?;
+    let target = format!("{}:{}", target_ip, target_port)
+        .parse::<SocketAddr>()
+        .map_err(|e| e.to_string())?;
+    let plugin = PluginInfo {
+        r#type: guard::from_c_str("plugin_type", plugin_type).map_err(|e| e.message())?,
+        plugins: guard::from_c_str("plugin_param", plugin_param)
+            .map_err(|e| e.message())
+            .and_then(|param| serde_json::from_str(&param).map_err(|e| e.to_string()))?,
+    };
+    data_pipe.open(target, &plugin)
+}
+
+extern "C" fn close_data_pipe(port: u16) {
+    if let Some(data_pipe) = DATA_PIPE.get() {
+        data_pipe.close(port);
+    }
+}
+
+extern "C" fn release_string(message: *const c_char) {
+    unsafe { RustOwnedString::release(message as *mut c_char) };
+}
+
+#[cfg(test)]
+mod host_test {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::ffi::ownership::take_cpp_string;
+
+    struct EchoPipe;
+
+    impl DataPipe for EchoPipe {
+        fn open(&self, target: SocketAddr, plugin: &PluginInfo) -> Result<u16, String> {
+            match plugin.r#type.as_str() {
+                "echo" => Ok(target.port() + 1),
+                _ => Err(format!("unknown plugin {}", plugin.r#type)),
+            }
+        }
+
+        fn close(&self, _port: u16) {}
+    }
+
+    #[test]
+    fn data_pipe() {
+        let _ = DATA_PIPE.set(Box::new(EchoPipe));
+
+        let target_ip = CString::new("127.0.0.1").unwrap();
+        let param = CString::new("[]").unwrap();
+
+        let plugin_type = CString::new("echo").unwrap();
+        let result = open_data_pipe(
+            target_ip.as_ptr(),
+            10000,
+            plugin_type.as_ptr(),
+            param.as_ptr(),
+        );
+        assert!(result.is_success);
+        assert_eq!(result.port, 10001);
+
+        let plugin_type = CString::new("string").unwrap();
+        let result = open_data_pipe(
+            target_ip.as_ptr(),
+            10000,
+            plugin_type.as_ptr(),
+            param.as_ptr(),
+        );
+        assert!(!result.is_success);
+        let message = unsafe { take_cpp_string(result.error_message, |m| release_string(m)) };
+        assert_eq!(message, "unknown plugin string");
+    }
+}
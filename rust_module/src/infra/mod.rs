@@ -1,16 +1,50 @@
 // skyway_webrtc_gateway_callerをInfra層として利用するための薄いラッパー
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use shaku::Component;
+use tokio::sync::{broadcast, mpsc};
 
+use crate::application::deadline;
 use crate::domain::entity::request::Request;
 use crate::domain::entity::response::ResponseResult;
 use crate::domain::entity::Stringify;
-use crate::domain::repository::Repository;
+use crate::domain::repository::{EventSubscription, GatewayProbe, Repository};
 use crate::error;
+use crate::error::ErrorCode;
+use crate::ffi::rust_to_c_bridge::c_functions_wrapper::LoggerHolder;
 use crate::ffi::rust_to_c_bridge::state_objects::GlobalState;
 
+// UseCase内でイベントを監視するためのbroadcast channelの容量
+const EVENT_NOTIFIER_CAPACITY: usize = 100;
+// WebRTC Gatewayへの疎通確認を諦めるまでの時間
+const GATEWAY_PING_TIMEOUT: Duration = Duration::from_secs(3);
+// WebRTC Gatewayのバージョンを返すエンドポイント
+const GATEWAY_VERSION_PATH: &str = "/version";
+
+/// SkyWay Crateから受け取ったイベントを、receive_event用のreceiverと、
+/// UseCase内でイベントを監視するためのnotifierの両方に分配する
+pub(crate) fn dispatch_events(
+    mut event_rx: mpsc::Receiver<String>,
+) -> (mpsc::Receiver<String>, broadcast::Sender<String>) {
+    let (tx, rx) = mpsc::channel::<String>(1000);
+    let (notifier, _) = broadcast::channel::<String>(EVENT_NOTIFIER_CAPACITY);
+    let notifier_clone = notifier.clone();
+
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            // 購読者がいない場合はエラーになるが、問題ない
+            let _ = notifier_clone.send(event.clone());
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    (rx, notifier)
+}
+
 #[derive(Component)]
 #[shaku(interface = Repository)]
 pub(crate) struct RepositoryImpl {
@@ -18,14 +52,18 @@ pub(crate) struct RepositoryImpl {
     state: Arc<dyn GlobalState>,
 }
 
-#[async_trait]
-impl Repository for RepositoryImpl {
-    async fn register(&self, params: Request) -> Result<ResponseResult, error::Error> {
+impl RepositoryImpl {
+    // SkyWay Crateにリクエストを送り、応答を待つ
+    async fn send(&self, params: Request) -> Result<ResponseResult, error::Error> {
         // SkyWay Crateからの戻り値を得るためのoneshot channelを生成
         let (channel_message_tx, channel_message_rx) = tokio::sync::oneshot::channel();
 
         // Request型である時点でto_stringには失敗しない
         let message = params.to_string().unwrap();
+        // リクエストの処理中であれば、request_idが付与された状態で出力される
+        if LoggerHolder::is_allocated() {
+            LoggerHolder::global().debug(format!("register {}", params.kind()));
+        }
 
         let sender = self.state.channels().sender();
 
@@ -45,20 +83,56 @@ impl Repository for RepositoryImpl {
             )),
         }
     }
+}
+
+#[async_trait]
+impl Repository for RepositoryImpl {
+    async fn register(&self, params: Request) -> Result<ResponseResult, error::Error> {
+        // 終了処理が始まった場合は、応答を待たずに中断する
+        let mut cancelled = self.state.request_canceller().subscribe();
+
+        // 既定の待ち時間を、リクエスト全体の期限までの残り時間で切り詰める
+        let timeout = Duration::from_millis(self.state.config().request_timeout_ms);
+        let timeout = deadline::timeout_for(timeout);
+        let kind = params.kind();
+        let timeout_error = || {
+            let message = format!(
+                "no response from WebRTC Gateway for {} within {}ms",
+                kind,
+                timeout.as_millis()
+            );
+            error::Error::create_error(ErrorCode::Timeout, &message)
+        };
+        // 期限が過ぎている場合は、WebRTC Gatewayに送らずにエラーとする
+        if timeout.is_zero() {
+            return Err(timeout_error());
+        }
+
+        tokio::select! {
+            result = tokio::time::timeout(timeout, self.send(params)) => {
+                result.unwrap_or_else(|_| Err(timeout_error()))
+            }
+            _ = cancelled.changed() => {
+                let message = format!("{} is cancelled by shutdown", kind);
+                Err(error::Error::create_error(ErrorCode::Cancelled, &message))
+            }
+        }
+    }
 
     async fn receive_event(&self) -> Result<ResponseResult, error::Error> {
         use std::time::Duration;
 
         use tokio::time;
         let state = self.state.program_state();
+        let interval = Duration::from_millis(self.state.config().event_poll_interval_ms);
         let channels = self.state.channels();
         let receiver = channels.receiver();
 
         while !state.is_shutting_down() {
             let mut rx = receiver.lock().await;
-            match time::timeout(Duration::from_millis(1000), rx.recv()).await {
+            match time::timeout(interval, rx.recv()).await {
                 Ok(Some(response_string)) => {
-                    return ResponseResult::from_str(&response_string);
+                    return ResponseResult::from_str(&response_string).map_err(error::Error::from);
                 }
                 Ok(None) => {
                     // closed
@@ -73,19 +147,96 @@ impl Repository for RepositoryImpl {
 
         return Err(error::Error::create_local_error("ros has been shut down"));
     }
+
+    fn subscribe_events(&self) -> EventSubscription {
+        EventSubscription::new(self.state.channels().subscribe())
+    }
+
+    async fn ping(&self) -> Result<GatewayProbe, error::Error> {
+        let gateway_url = &self.state.config().gateway_url;
+        match tokio::time::timeout(GATEWAY_PING_TIMEOUT, send_version_request(gateway_url)).await {
+            Ok(result) => result,
+            Err(_) => {
+                let message = format!("no response from WebRTC Gateway {}", gateway_url);
+                Err(error::Error::create_error(ErrorCode::Timeout, &message))
+            }
+        }
+    }
+}
+
+// WebRTC GatewayのバージョンのエンドポイントにGETリクエストを送り、レスポンスを受け取るまでの時間を計測する
+// ステータスコードに関わらず、HTTPのレスポンスが返ってくれば疎通できているとみなす
+// エンドポイントがバージョンを返した場合のみ、GatewayProbe.versionに格納する
+async fn send_version_request(gateway_url: &str) -> Result<GatewayProbe, error::Error> {
+    // skyway_webrtc_gateway_callerと同様に、gateway_urlの後ろにパスを繋げる
+    let url = reqwest::Url::parse(gateway_url)
+        .and_then(|_| {
+            let url = gateway_url.trim_end_matches('/');
+            reqwest::Url::parse(&format!("{}{}", url, GATEWAY_VERSION_PATH))
+        })
+        .map_err(|e| {
+            let message = format!("invalid gateway url {}: {}", gateway_url, e);
+            error::Error::create_error(ErrorCode::InvalidRequest, &message)
+        })?;
+
+    let start = Instant::now();
+    let response = reqwest::Client::new().get(url).send().await.map_err(|e| {
+        let (code, reason) = match e.is_connect() {
+            true => (ErrorCode::GatewayUnreachable, "is unreachable"),
+            false => (ErrorCode::GatewayRejected, "returns invalid response"),
+        };
+        let message = format!("WebRTC Gateway {} {}: {}", gateway_url, reason, e);
+        error::Error::create_error(code, &message)
+    })?;
+    let round_trip = start.elapsed();
+
+    let version = match response.status().is_success() {
+        true => response
+            .text()
+            .await
+            .ok()
+            .and_then(|body| parse_version(&body)),
+        false => None,
+    };
+    Ok(GatewayProbe {
+        round_trip,
+        version,
+    })
+}
+
+// {"version": "..."}の形式のレスポンスボディからバージョンを取り出す
+fn parse_version(body: &str) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_str(body).ok()?;
+    body["version"].as_str().map(|version| version.to_string())
 }
 
 #[cfg(test)]
 mod infra_send_message_test {
     use once_cell::sync::OnceCell;
     use shaku::HasComponent;
-    use tokio::sync::{mpsc, oneshot, Mutex};
+    use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
 
     use super::*;
+    use crate::config::Config;
     use crate::di::RepositoryModule;
     use crate::domain::entity::request::PeerRequest;
     use crate::domain::entity::{CreatePeerParams, FromStr, PeerId};
-    use crate::ffi::rust_to_c_bridge::state_objects::{Channels, ChannelsImpl, MockGlobalState};
+    use crate::ffi::rust_to_c_bridge::state_objects::{
+        Channels, ChannelsImpl, MockGlobalState, RequestCanceller,
+    };
+
+    fn config() -> &'static Config {
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        CONFIG.get_or_init(|| Config {
+            request_timeout_ms: 100,
+            ..Config::default()
+        })
+    }
+
+    fn canceller() -> &'static RequestCanceller {
+        static CANCELLER: OnceCell<RequestCanceller> = OnceCell::new();
+        CANCELLER.get_or_init(RequestCanceller::new)
+    }
 
     fn create_request() -> Request {
         let inner = PeerRequest::Create {
@@ -111,6 +262,7 @@ mod infra_send_message_test {
         let _ = CHANNELS.set(Arc::new(ChannelsImpl::new(
             message_tx,
             Mutex::new(event_rx),
+            broadcast::channel(10).0,
         )));
 
         // GlobalStateのMockを生成
@@ -120,6 +272,8 @@ mod infra_send_message_test {
             .expect_channels()
             .times(1)
             .returning(move || CHANNELS.get().unwrap());
+        state.expect_config().return_const(config());
+        state.expect_request_canceller().return_const(canceller());
 
         // サービスを生成
         let module = RepositoryModule::builder()
@@ -173,6 +327,7 @@ mod infra_send_message_test {
         let _ = CHANNELS.set(Arc::new(ChannelsImpl::new(
             message_tx,
             Mutex::new(event_rx),
+            broadcast::channel(10).0,
         )));
 
         // GlobalStateのMockを生成
@@ -182,6 +337,8 @@ mod infra_send_message_test {
             .expect_channels()
             .times(1)
             .returning(move || CHANNELS.get().unwrap());
+        state.expect_config().return_const(config());
+        state.expect_request_canceller().return_const(canceller());
 
         // サービスを生成
         let module = RepositoryModule::builder()
@@ -228,6 +385,7 @@ mod infra_send_message_test {
         let _ = CHANNELS.set(Arc::new(ChannelsImpl::new(
             message_tx,
             Mutex::new(event_rx),
+            broadcast::channel(10).0,
         )));
 
         // GlobalStateのMockを生成
@@ -237,6 +395,8 @@ mod infra_send_message_test {
             .expect_channels()
             .times(1)
             .returning(move || CHANNELS.get().unwrap());
+        state.expect_config().return_const(config());
+        state.expect_request_canceller().return_const(canceller());
 
         // サービスを生成
         let module = RepositoryModule::builder()
@@ -277,15 +437,111 @@ mod infra_send_message_test {
             _ => assert!(false),
         }
     }
+
+    // 応答を返さずにリクエストを保持し続けるWebRTC Gatewayを模擬する
+    fn create_hung_state(
+        channels: &'static OnceCell<Arc<dyn Channels>>,
+        canceller: &'static RequestCanceller,
+    ) -> MockGlobalState {
+        let (message_tx, mut message_rx) = mpsc::channel::<(oneshot::Sender<String>, String)>(10);
+        let (_event_tx, event_rx) = mpsc::channel::<String>(1000);
+        let _ = channels.set(Arc::new(ChannelsImpl::new(
+            message_tx,
+            Mutex::new(event_rx),
+            broadcast::channel(10).0,
+        )));
+        tokio::spawn(async move {
+            let mut pending = vec![];
+            while let Some((response_message_tx, _)) = message_rx.recv().await {
+                pending.push(response_message_tx);
+            }
+        });
+
+        let mut state = MockGlobalState::new();
+        state
+            .expect_channels()
+            .returning(move || channels.get().unwrap());
+        state.expect_config().return_const(config());
+        state.expect_request_canceller().return_const(canceller);
+        state
+    }
+
+    #[tokio::test]
+    // 期限内にresponseが帰ってこないケース
+    async fn error_timeout() {
+        static CHANNELS: OnceCell<Arc<dyn Channels>> = OnceCell::new();
+        let module = RepositoryModule::builder()
+            .with_component_override::<dyn GlobalState>(Box::new(create_hung_state(
+                &CHANNELS,
+                canceller(),
+            )))
+            .build();
+        let repository_impl: &dyn Repository = module.resolve_ref();
+
+        let result = repository_impl.register(create_request()).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Timeout);
+        assert_eq!(
+            error.message(),
+            "no response from WebRTC Gateway for PEER CREATE within 100ms"
+        );
+
+        // リクエスト全体の期限の方が短い場合は、そちらに合わせる
+        let deadline = std::time::Instant::now() + Duration::from_millis(10);
+        let result =
+            deadline::scope(Some(deadline), repository_impl.register(create_request())).await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::Timeout);
+
+        // 期限が過ぎている場合は送信しない
+        let result = deadline::scope(
+            Some(std::time::Instant::now()),
+            repository_impl.register(create_request()),
+        )
+        .await;
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.message(),
+            "no response from WebRTC Gateway for PEER CREATE within 0ms"
+        );
+    }
+
+    #[tokio::test]
+    // 応答待ちの間に終了処理が始まるケース
+    async fn cancelled_by_shutdown() {
+        static CHANNELS: OnceCell<Arc<dyn Channels>> = OnceCell::new();
+        // 他のテストのリクエストを中断しないよう、専用のものを使う
+        static CANCELLER: OnceCell<RequestCanceller> = OnceCell::new();
+        let canceller = CANCELLER.get_or_init(RequestCanceller::new);
+        let module = RepositoryModule::builder()
+            .with_component_override::<dyn GlobalState>(Box::new(create_hung_state(
+                &CHANNELS, canceller,
+            )))
+            .build();
+        let repository_impl: &dyn Repository = module.resolve_ref();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            canceller.cancel_all();
+        });
+        let result = repository_impl.register(create_request()).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Cancelled);
+        assert_eq!(error.message(), "PEER CREATE is cancelled by shutdown");
+
+        // 中断後のリクエストは影響を受けない
+        let result = repository_impl.register(create_request()).await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::Timeout);
+    }
 }
 
 #[cfg(test)]
 mod infra_receive_event_test {
     use once_cell::sync::OnceCell;
     use shaku::HasComponent;
-    use tokio::sync::{mpsc, oneshot, Mutex};
+    use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
 
     use super::*;
+    use crate::config::Config;
     use crate::di::RepositoryModule;
     use crate::ffi::rust_to_c_bridge::c_functions_wrapper::{helper, ProgramStateHolder};
     use crate::ffi::rust_to_c_bridge::state_objects::{Channels, ChannelsImpl, MockGlobalState};
@@ -301,6 +557,7 @@ mod infra_receive_event_test {
         let _ = CHANNELS.set(Arc::new(ChannelsImpl::new(
             message_tx,
             Mutex::new(event_rx),
+            broadcast::channel(10).0,
         )));
 
         static PROGRAM_STATE_INSTANCE: OnceCell<ProgramStateHolder> = OnceCell::new();
@@ -322,6 +579,11 @@ mod infra_receive_event_test {
             .expect_program_state()
             .times(1)
             .returning(move || PROGRAM_STATE_INSTANCE.get().unwrap());
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(move || CONFIG.get_or_init(Config::default));
 
         // サービスを生成
         let module = RepositoryModule::builder()
@@ -361,6 +623,7 @@ mod infra_receive_event_test {
         let _ = CHANNELS.set(Arc::new(ChannelsImpl::new(
             message_tx,
             Mutex::new(event_rx),
+            broadcast::channel(10).0,
         )));
 
         static PROGRAM_STATE_INSTANCE: OnceCell<ProgramStateHolder> = OnceCell::new();
@@ -382,6 +645,11 @@ mod infra_receive_event_test {
             .expect_program_state()
             .times(1)
             .returning(move || PROGRAM_STATE_INSTANCE.get().unwrap());
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        state
+            .expect_config()
+            .times(1)
+            .returning(move || CONFIG.get_or_init(Config::default));
 
         // サービスを生成
         let module = RepositoryModule::builder()
@@ -403,3 +671,145 @@ mod infra_receive_event_test {
         }
     }
 }
+
+#[cfg(test)]
+mod infra_ping_test {
+    use once_cell::sync::OnceCell;
+    use shaku::HasComponent;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::di::RepositoryModule;
+    use crate::ffi::rust_to_c_bridge::state_objects::MockGlobalState;
+
+    fn create_repository(config: &'static Config) -> RepositoryModule {
+        let mut state = MockGlobalState::new();
+        state.expect_config().return_const(config);
+        RepositoryModule::builder()
+            .with_component_override::<dyn GlobalState>(Box::new(state))
+            .build()
+    }
+
+    // WebRTC Gateway相当のHTTPサーバを立て、受け取ったリクエストの先頭をsenderに渡した上でresponseを返す
+    async fn serve(response: &'static str) -> (String, oneshot::Receiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; 1024];
+            let length = stream.read(&mut buffer).await.unwrap();
+            let _ = sender.send(buffer[..length].to_vec());
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+        (address, receiver)
+    }
+
+    #[test]
+    fn version_body() {
+        assert_eq!(
+            parse_version(r#"{"version": "0.4.1"}"#),
+            Some("0.4.1".to_string())
+        );
+        assert_eq!(parse_version(r#"{"peer_id": "foo"}"#), None);
+        assert_eq!(parse_version("404 page not found"), None);
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let (address, request) = serve(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 19\r\n\r\n{\"version\":\"0.4.1\"}",
+        )
+        .await;
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let config = CONFIG.get_or_init(|| Config {
+            gateway_url: format!("http://{}", address),
+            ..Config::default()
+        });
+        let module = create_repository(config);
+        let repository: &dyn Repository = module.resolve_ref();
+
+        let probe = repository.ping().await.unwrap();
+        assert_eq!(probe.version, Some("0.4.1".to_string()));
+        let request = request.await.unwrap();
+        assert!(request.starts_with(b"GET /version HTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn ping_without_version() {
+        // 404でもHTTPのレスポンスが返れば疎通できている
+        let (address, _request) =
+            serve("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").await;
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let config = CONFIG.get_or_init(|| Config {
+            gateway_url: format!("http://{}/", address),
+            ..Config::default()
+        });
+        let module = create_repository(config);
+        let repository: &dyn Repository = module.resolve_ref();
+
+        let probe = repository.ping().await.unwrap();
+        assert_eq!(probe.version, None);
+    }
+
+    #[tokio::test]
+    async fn ping_over_https() {
+        // https://のURLにはTLSで接続する
+        // 平文のHTTPサーバはTLSのハンドシェイクに応じられないため、疎通できないとみなされる
+        let (address, request) = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let config = CONFIG.get_or_init(|| Config {
+            gateway_url: format!("https://{}", address),
+            ..Config::default()
+        });
+        let module = create_repository(config);
+        let repository: &dyn Repository = module.resolve_ref();
+
+        let error = repository.ping().await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::GatewayUnreachable);
+        // 平文のリクエストではなく、TLSのClientHello(Handshake record)が届いている
+        let request = request.await.unwrap();
+        assert_eq!(request[0], 0x16);
+        assert!(!request.starts_with(b"GET"));
+    }
+
+    #[tokio::test]
+    async fn invalid_url() {
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let config = CONFIG.get_or_init(|| Config {
+            gateway_url: "http://".to_string(),
+            ..Config::default()
+        });
+        let module = create_repository(config);
+        let repository: &dyn Repository = module.resolve_ref();
+
+        let error = repository.ping().await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn unreachable() {
+        // 一度bindしたポートを閉じ、接続できないアドレスを作る
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        static CONFIG: OnceCell<Config> = OnceCell::new();
+        let config = CONFIG.get_or_init(|| Config {
+            gateway_url: format!("http://{}", address),
+            ..Config::default()
+        });
+        let module = create_repository(config);
+        let repository: &dyn Repository = module.resolve_ref();
+
+        let error = repository.ping().await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::GatewayUnreachable);
+        assert!(error
+            .message()
+            .starts_with("WebRTC Gateway http://127.0.0.1"));
+    }
+}
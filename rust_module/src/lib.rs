@@ -2,34 +2,74 @@
 // rust_module以下はApplicationの一部とDomain、Infra層に相当する
 // skyway_webrtc_gateway_controller crate(以下SkyWay Crate)をInfra層として利用し、
 // ROS側で持つべきDomain知識を定義し、サービスを提供するのが主な目的である
+// RustのプログラムからはSkyWayClientを通じて同じサービスを利用できる。C++側へのC ABIは"ffi" featureで提供する
+// JSONでのリクエストの受付など、C ABI経由でのみ使う処理はffi featureなしではビルドしない
 mod application;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+pub mod client;
+mod config;
 mod di;
 mod domain;
 mod error;
 mod ffi;
+pub mod host;
 mod infra;
 mod utils;
 
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::ffi::rust_to_c_bridge::c_functions_wrapper::{LoggerHolder, ProgramStateHolder};
+pub use crate::client::SkyWayClient;
+use crate::config::Config;
+use crate::ffi::rust_to_c_bridge::c_functions_wrapper::LoggerHolder;
 use crate::ffi::rust_to_c_bridge::state_objects::{
-    ChannelsImpl, CHANNELS, DATA_CONNECTION_STATE_INSTANCE, MEDIA_CONNECTION_STATE_INSTANCE,
+    ChannelsImpl, CHANNELS, DATA_CONNECTION_STATE_INSTANCE, DATA_POLICY_INSTANCE,
+    MEDIA_CONNECTION_STATE_INSTANCE, MEDIA_POLICY_INSTANCE, PEER_STATE_INSTANCE,
 };
 
-/// C++側から、 `crate::ffi::c_to_rust_bridge::run` 経由で呼ばれる
-pub(crate) async fn rust_main() {
+/// Rust側の各モジュールを初期化し、WebRTC Gatewayとのやり取りとイベントの配信を開始する
+/// rust_mainとclient::SkyWayClientの起動時に一度だけ呼ばれる
+pub(crate) async fn start() -> Result<(), error::Error> {
     let _ = DATA_CONNECTION_STATE_INSTANCE.set(std::sync::Mutex::new(HashMap::new()));
     let _ = MEDIA_CONNECTION_STATE_INSTANCE.set(std::sync::Mutex::new(HashMap::new()));
+    let _ = PEER_STATE_INSTANCE.set(std::sync::Mutex::new(HashMap::new()));
 
-    let (sender, receiver) = skyway_webrtc_gateway_caller::run("http://localhost:8000").await;
+    let config = Config::global();
+    // 起動時のpolicyは設定ファイルから読み込む
+    let _ = DATA_POLICY_INSTANCE.set(std::sync::Mutex::new(config.data_connection_policy.clone()));
+    let _ = MEDIA_POLICY_INSTANCE.set(std::sync::Mutex::new(
+        config.media_connection_policy.clone(),
+    ));
+    LoggerHolder::global().info(format!("WebRTC Gateway: {}", config.gateway_url));
+    let (sender, receiver) = skyway_webrtc_gateway_caller::run(&config.gateway_url).await;
+    // receive_eventsの利用者とUseCase内部の両方でイベントを受け取れるように分配する
+    let (receiver, notifier) = crate::infra::dispatch_events(receiver);
     // SkyWay Crateにアクセスするためのsender, receiverを保持する
     // Channels objectに入れた上でOnceCellで保持する
-    let channels = ChannelsImpl::new(sender, tokio::sync::Mutex::new(receiver));
-    let result = CHANNELS.set(Arc::new(channels));
-    if result.is_err() {
-        LoggerHolder::global().error("CHANNELS set error");
+    let channels = ChannelsImpl::new(sender, tokio::sync::Mutex::new(receiver), notifier);
+    if CHANNELS.set(Arc::new(channels)).is_err() {
+        return Err(error::Error::create_local_error("CHANNELS set error"));
+    }
+
+    // 全てのServiceをここで一度だけ生成し、以降のリクエストで使い回す
+    crate::application::factory::ServiceContainer::global();
+
+    // イベントを処理し、receive_eventsやEVENT SUBSCRIBEの購読者に配信し続ける
+    tokio::spawn(crate::application::publish_events());
+    // Rust側でプログラムの状態を管理している場合は、SIGINT/SIGTERMで終了処理を行う
+    crate::host::spawn_signal_handler();
+    Ok(())
+}
+
+/// C++側から、 `crate::ffi::c_to_rust_bridge::run` 経由で呼ばれる
+#[cfg(feature = "ffi")]
+pub(crate) async fn rust_main() {
+    use crate::ffi::rust_to_c_bridge::c_functions_wrapper::ProgramStateHolder;
+
+    if let Err(e) = start().await {
+        LoggerHolder::global().error(e.message());
         ProgramStateHolder::global().shutdown();
     }
 
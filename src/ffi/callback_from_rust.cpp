@@ -8,17 +8,19 @@
 
 #include "rust_functions.h"
 
+// Rust側から引数として渡される文字列は呼び出しの間だけ有効なので、C++側では開放しない
 extern "C" {
-void create_peer_callback_ffi(char* peer_id, char* token) {
+void create_peer_callback_ffi(const char* peer_id, const char* token) {
   create_peer_callback_handler(peer_id, token);
 }
 
 // Peer Closeイベントが発火したときにプログラム全体を終了する
 void peer_deleted_callback_ffi() { ros::shutdown(); }
 
-PluginLoadResult create_data_callback_ffi(char* target_ip, uint16_t target_port,
-                                          char* plugin_type,
-                                          char* plugin_param) {
+PluginLoadResult create_data_callback_ffi(const char* target_ip,
+                                          uint16_t target_port,
+                                          const char* plugin_type,
+                                          const char* plugin_param) {
   return create_data_callback_handler(target_ip, target_port, plugin_type,
                                       plugin_param);
 }
@@ -53,20 +55,16 @@ CallbackFromRustImpl::CallbackFromRustImpl(std::shared_ptr<Router> router)
   register_callbacks(functions);
 }
 
-void CallbackFromRustImpl::create_peer_callback(char* peer_id, char* token) {
+void CallbackFromRustImpl::create_peer_callback(const char* peer_id,
+                                                const char* token) {
   router_->OnCreatePeer(peer_id, token);
-
-  release_string(peer_id);
-  release_string(token);
 }
 
 PluginLoadResult CallbackFromRustImpl::create_data_connection_callback(
-    char* target_ip, uint16_t port, char* plugin_type, char* plugin_param) {
+    const char* target_ip, uint16_t port, const char* plugin_type,
+    const char* plugin_param) {
   auto result =
       router_->OnConnectData(target_ip, port, plugin_type, plugin_param);
-  release_string(target_ip);
-  release_string(plugin_type);
-  release_string(plugin_param);
 
   struct PluginLoadResult response = {.is_success = result.is_success,
                                       .port = result.port,
@@ -18,8 +18,9 @@ using fruit::Injector;
 // callback body
 namespace {
 std::function<void(int)> shutdown_handler;
-std::function<void(char*, char*)> create_peer_callback_handler;
-std::function<PluginLoadResult(char*, uint16_t, char*, char*)>
+std::function<void(const char*, const char*)> create_peer_callback_handler;
+std::function<PluginLoadResult(const char*, uint16_t, const char*,
+                               const char*)>
     create_data_callback_handler;
 std::function<void(uint16_t)> data_connection_close_event_callback_handler;
 }  // namespace
@@ -28,9 +29,9 @@ class CallbackFromRust {};
 
 class CallbackFromRustImpl : public CallbackFromRust {
  private:
-  void create_peer_callback(char* peer_id, char* token);
-  PluginLoadResult create_data_connection_callback(char*, uint16_t, char*,
-                                                   char*);
+  void create_peer_callback(const char* peer_id, const char* token);
+  PluginLoadResult create_data_connection_callback(const char*, uint16_t,
+                                                   const char*, const char*);
   void delete_data_connection_callback(uint16_t);
 
   std::shared_ptr<Router> router_;
@@ -19,13 +19,17 @@ using void_double_func = void (*)(double);
 using void_void_func = void (*)();
 using bool_void_func = bool (*)();
 using void_char_char_func = void (*)(char*, char*);
+// Rust側から引数として渡される文字列は呼び出しの間だけ有効で、C++側で開放してはならない
+using void_cchar_func = void (*)(const char*);
+using void_cchar_cchar_func = void (*)(const char*, const char*);
+using void_ptr_char_func = void (*)(void*, char*);
 using void_char_func = void (*)(char*);
 using void_void_func = void (*)();
-using plugin_topicparam_func = PluginLoadResult (*)(char*, uint16_t, char*,
-                                                    char*);
+using plugin_topicparam_func = PluginLoadResult (*)(const char*, uint16_t,
+                                                    const char*, const char*);
 
 struct Function {
-  void_char_char_func create_peer_callback;
+  void_cchar_cchar_func create_peer_callback;
   void_void_func peer_deleted_callback;
   plugin_topicparam_func create_data_callback;
   void_uint16_func data_connection_deleted_callback;
@@ -9,21 +9,18 @@
 // Rust側から呼び出されるC++側関数の実体
 extern "C" {
 // loggers
-void log_debug_c(char* message) {
+// messageはRust側の持ち物で、呼び出しの間だけ有効なので開放しない
+void log_debug_c(const char* message) {
   ROS_DEBUG("%s", message);
-  release_string(message);
 }
-void log_info_c(char* message) {
+void log_info_c(const char* message) {
   ROS_INFO("%s", message);
-  release_string(message);
 }
-void log_warn_c(char* message) {
+void log_warn_c(const char* message) {
   ROS_WARN("%s", message);
-  release_string(message);
 }
-void log_err_c(char* message) {
+void log_err_c(const char* message) {
   ROS_ERROR("%s", message);
-  release_string(message);
 }
 
 // ros control functions
@@ -11,10 +11,10 @@
 // Rust側から呼び出されるC++側関数の定義
 extern "C" {
 // loggers
-void log_debug_c(char* message);
-void log_info_c(char* message);
-void log_warn_c(char* message);
-void log_err_c(char* message);
+void log_debug_c(const char* message);
+void log_info_c(const char* message);
+void log_warn_c(const char* message);
+void log_err_c(const char* message);
 
 // ros control functions
 bool is_ok_c();
@@ -8,27 +8,38 @@
 #include "common.h"
 
 // C++側から呼び出されるRust側関数の定義
+// 戻り値のchar*と、非同期APIのcallbackに渡されるchar*はRust側の持ち物で、release_stringで開放する
 extern "C" {
 void register_callbacks(Function& functions);
 char* call_service(const char* message);
 char* receive_events();
+uint64_t subscribe_events(const char* filter);
+char* poll_events(uint64_t subscriber_id);
+bool call_service_async(const char* message, void* user_data,
+                        void_ptr_char_func callback);
+uint64_t subscribe_events_async(const char* filter, void* user_data,
+                                void_ptr_char_func callback);
+bool unsubscribe_events(uint64_t subscriber_id);
 void release_string(char* message);
 void create_peer_callback(char* peer_id, char* token);
 void peer_deleted_callback();
 PluginLoadResult create_data_callback(char* parameter);
 void data_connection_close_event_callback(char* data_connection_id);
 
-void register_logger(void_char_func debug, void_char_func info,
-                     void_char_func warn, void_char_func error);
+void register_logger(void_cchar_func debug, void_cchar_func info,
+                     void_cchar_func warn, void_cchar_func error);
 void register_program_state(bool_void_func is_running_c,
                             bool_void_func is_shutting_down_c,
                             void_double_func sleep_c,
                             void_void_func wait_for_shutdown_c,
                             void_void_func shutdown_c);
+void register_gateway_url(const char* gateway_url);
+// register_callbacks, register_logger, register_program_stateを呼ばずにrunした場合、
+// 未登録の機能はRust側の既定の実装(標準エラー出力へのログ、SIGINT/SIGTERMでの終了等)で補われる
 run_response_t run();
 void join_handler(void* handler);
 
-void print_string(char* message);
+void print_string(const char* message);
 };
 
 #endif  // SKYWAY_RUST_FUNCTIONS_H
@@ -14,6 +14,11 @@ int main(int argc, char** argv) {
   register_logger(log_debug_c, log_info_c, log_warn_c, log_err_c);
   register_program_state(is_ok_c, is_shutting_down_c, ros_sleep_c,
                          wait_for_shutdown_c, shutdown_c);
+  // WebRTC GatewayのURLが指定されていればRust側に渡す
+  std::string gateway_url;
+  if (ros::NodeHandle("~").getParam("gateway_url", gateway_url)) {
+    register_gateway_url(gateway_url.c_str());
+  }
   // Rust側の処理開始
   run_response_t response = run();
 
@@ -42,7 +42,7 @@ BinaryPluginRouter::~BinaryPluginRouter() {
 PluginResult BinaryPluginRouter::TryStart() {
   // plugin情報の配列を与えられていない場合は開始できない
   if (!config_->IsArray()) {
-    return {false, 0, "invalid config parameters"};
+    return {false, 0, strdup("invalid config parameters")};
   }
 
   // try startにして、errorを返せるようにする
@@ -60,7 +60,7 @@ JsonPluginRouter::~JsonPluginRouter() {
 PluginResult JsonPluginRouter::TryStart() {
   // plugin情報の配列を与えられていない場合は開始できない
   if (!config_->IsArray()) {
-    return {false, 0, "invalid config parameters"};
+    return {false, 0, strdup("invalid config parameters")};
   }
 
   auto callback = std::make_shared<
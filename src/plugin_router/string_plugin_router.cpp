@@ -46,7 +46,7 @@ StringPluginRouter::~StringPluginRouter() {
 PluginResult StringPluginRouter::TryStart() {
   // plugin情報の配列を与えられていない場合は開始できない
   if (!config_->IsArray()) {
-    return {false, 0, "invalid config parameters"};
+    return {false, 0, strdup("invalid config parameters")};
   }
 
   auto callback = std::make_shared<std::function<void(std::string)>>(std::bind(
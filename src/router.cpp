@@ -58,7 +58,7 @@ void RouterImpl::shutdown(int signal) {
   }
 }
 
-void RouterImpl::OnCreatePeer(char* peer_id, char* token) {
+void RouterImpl::OnCreatePeer(const char* peer_id, const char* token) {
   // Peer Objectの生成に成功したら、peer_idとtokenを保持しておく
   // これは終了時に開放するためだけに利用する
   peer_id_ = peer_id;
@@ -19,7 +19,7 @@ using fruit::Injector;
 class Router {
  public:
   virtual ~Router() = default;
-  virtual void OnCreatePeer(char* peer_id, char* token) {}
+  virtual void OnCreatePeer(const char* peer_id, const char* token) {}
   virtual PluginResult OnConnectData(std::string target_ip,
                                      uint16_t target_port,
                                      std::string plugin_type,
@@ -58,7 +58,7 @@ class RouterImpl : public Router {
     event_service_->Shutdown();
   }
 
-  virtual void OnCreatePeer(char* peer_id, char* token) override;
+  virtual void OnCreatePeer(const char* peer_id, const char* token) override;
   virtual PluginResult OnConnectData(std::string target_ip, uint16_t,
                                      std::string, std::string) override;
   virtual void OnDeleteData(uint16_t port_num) override;